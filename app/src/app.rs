@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::cli::Config;
 
@@ -6,7 +8,7 @@ pub use {{crate_name}} as lib;
 use lib::{
     bip300301::{self, bitcoin, jsonrpsee, MainClient},
     miner::{self, Miner},
-    node::{self, Node, THIS_SIDECHAIN},
+    node::{self, ChainEvent, Node, NodeConfig, THIS_SIDECHAIN},
     types::{self, OutPoint, Output, Transaction},
     wallet::{self, Wallet},
     format_deposit_address,
@@ -16,39 +18,75 @@ use lib::{
 pub struct App {
     pub node: Node,
     pub wallet: Wallet,
+    /// Name of `wallet`, see `Wallet::path`.
+    pub wallet_name: String,
     pub miner: Miner,
     pub utxos: HashMap<OutPoint, Output>,
     pub transaction: Transaction,
+    /// Error of the last failed GUI action.
+    pub error: ErrorState,
     runtime: tokio::runtime::Runtime,
+    chain_events: tokio::sync::broadcast::Receiver<ChainEvent>,
+    mining: Option<tokio::task::JoinHandle<Result<(), Error>>>,
+    /// Tells the mining task to abandon its block.
+    abandon: Option<tokio::sync::oneshot::Sender<()>>,
+    generate: bool,
+    bmm_poll_interval: Duration,
+    datadir: PathBuf,
+    address_policy: wallet::AddressPolicy,
+    account_path: wallet::AccountPath,
 }
 
 impl App {
+    /// Most mempool transactions included in a mined block.
+    pub const MAX_BLOCK_TRANSACTIONS: usize = 1000;
+
     pub fn new(config: &Config) -> Result<Self, Error> {
         // Node launches some tokio tasks for p2p networking, that is why we need a tokio runtime
         // here.
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()?;
-        let wallet = Wallet::new(&config.datadir.join("wallet.mdb"))?;
+        let wallet = Wallet::new(
+            &Wallet::path(&config.datadir, &config.wallet)?,
+            config.address_policy,
+            config.account_path,
+            Wallet::DEFAULT_MAP_SIZE,
+        )?;
         let miner = Miner::new(
             THIS_SIDECHAIN,
             config.main_addr,
             &config.main_user,
             &config.main_password,
         )?;
-        let node = runtime.block_on(async {
+        let mut node = runtime.block_on(async {
             let node = match Node::new(
                 &config.datadir,
                 config.net_addr,
                 config.main_addr,
                 &config.main_user,
                 &config.main_password,
+                NodeConfig {
+                    prune_depth: config.prune_depth,
+                    compression_level: config.compression_level,
+                    min_relay_fee_rate: config.min_relay_fee_rate,
+                    mempool_ttl: config.mempool_ttl,
+                },
             ) {
                 Ok(node) => node,
                 Err(err) => return Err(err),
             };
             Ok(node)
         })?;
+        if let Some(num_threads) = config.verification_threads {
+            node.set_verification_threads(num_threads)?;
+        }
+        node.set_finality_depth(config.finality_depth);
+        node.set_push_transaction_limit(config.push_transaction_limit);
+        node.set_health_addr(config.health_addr);
+        node.set_request_timeout(config.request_timeout);
+        node.set_compact_blocks(config.compact_blocks);
+        let chain_events = node.subscribe();
         let utxos = {
             let mut utxos = wallet.get_utxos()?;
             let transactions = node.get_all_transactions()?;
@@ -62,13 +100,23 @@ impl App {
         Ok(Self {
             node,
             wallet,
+            wallet_name: config.wallet.clone(),
             miner,
             utxos,
             transaction: Transaction {
                 inputs: vec![],
                 outputs: vec![],
             },
+            error: ErrorState::default(),
             runtime,
+            chain_events,
+            mining: None,
+            abandon: None,
+            generate: config.generate,
+            bmm_poll_interval: config.bmm_poll_interval,
+            datadir: config.datadir.clone(),
+            address_policy: config.address_policy,
+            account_path: config.account_path,
         })
     }
 
@@ -76,6 +124,10 @@ impl App {
         let authorized_transaction = self.wallet.authorize(self.transaction.clone())?;
         self.runtime
             .block_on(self.node.submit_transaction(&authorized_transaction))?;
+        // Addresses from the "generate" button are only persisted once they're sent to.
+        for output in &self.transaction.outputs {
+            self.wallet.claim_address(&output.address)?;
+        }
         self.transaction = Transaction {
             inputs: vec![],
             outputs: vec![],
@@ -84,65 +136,251 @@ impl App {
         Ok(())
     }
 
+    /// Fees the next mined block would collect from the mempool.
+    pub fn available_fees(&self) -> Result<u64, Error> {
+        Ok(self.node.get_available_fees(Self::MAX_BLOCK_TRANSACTIONS)?)
+    }
+
+    /// Names of the wallets in the data directory.
+    pub fn list_wallets(&self) -> Result<Vec<String>, Error> {
+        Ok(Wallet::list(&self.datadir)?)
+    }
+
+    /// Open the wallet called `name`, creating it if it doesn't exist, and make it the active
+    /// one. Mining started before the switch keeps paying the previous wallet.
+    pub fn switch_wallet(&mut self, name: &str) -> Result<(), Error> {
+        let wallet = Wallet::new(
+            &Wallet::path(&self.datadir, name)?,
+            self.address_policy,
+            self.account_path,
+            Wallet::DEFAULT_MAP_SIZE,
+        )?;
+        self.wallet = wallet;
+        self.wallet_name = name.to_owned();
+        self.transaction = Transaction {
+            inputs: vec![],
+            outputs: vec![],
+        };
+        // Catch up on blocks connected while the wallet wasn't active.
+        self.update_wallet()?;
+        self.update_utxos()?;
+        Ok(())
+    }
+
+    /// Combine up to `max_inputs` of the smallest wallet UTXOs into one, paying `fee`.
+    pub fn consolidate_utxos(&mut self, max_inputs: usize, fee: u64) -> Result<(), Error> {
+        let transaction = self.wallet.create_consolidation_transaction(max_inputs, fee)?;
+        let transaction = self.wallet.authorize(transaction)?;
+        self.runtime.block_on(self.node.submit_transaction(&transaction))?;
+        self.update_utxos()?;
+        Ok(())
+    }
+
+    /// Check which of the mainchain RPC methods we depend on are supported.
+    pub fn doctor(&self) -> Vec<(lib::mainchain::RpcMethod, lib::mainchain::Check)> {
+        self.runtime.block_on(lib::mainchain::doctor(&self.miner.mainchain))
+    }
+
+    /// Disconnect blocks down to `height` and update the wallet accordingly.
+    pub fn rollback_to_height(&mut self, height: u32) -> Result<(), Error> {
+        self.runtime.block_on(self.node.rollback_to_height(height))?;
+        self.update_utxos()?;
+        Ok(())
+    }
+
+    /// Delete the node's chain data, and the wallet's UTXOs if `reset_wallet` is set.
+    pub fn reset_chain(&mut self, reset_wallet: bool) -> Result<(), Error> {
+        self.runtime.block_on(self.node.reset_chain())?;
+        if reset_wallet {
+            self.wallet.clear_utxos()?;
+        }
+        self.update_utxos()?;
+        Ok(())
+    }
+
     pub fn get_new_main_address(
         &self,
     ) -> Result<bitcoin::Address<bitcoin::address::NetworkChecked>, Error> {
         let address = self
             .runtime
-            .block_on(self.miner.drivechain.client.getnewaddress("", "legacy"))?;
+            .block_on(self.miner.mainchain.client.getnewaddress("", "legacy"))?;
         let address: bitcoin::Address<bitcoin::address::NetworkChecked> = address
-            .require_network(bitcoin::Network::Regtest)
+            .require_network(lib::consensus::network())
             .unwrap();
         Ok(address)
     }
 
     const EMPTY_BLOCK_BMM_BRIBE: u64 = 1000;
+    /// How long to wait for a BMM request to land when mainchain blocks aren't generated.
+    const BMM_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+    pub fn is_syncing(&self) -> Result<bool, Error> {
+        Ok(self.runtime.block_on(self.node.is_syncing())?)
+    }
+
+    /// Start mining a block in the background, `poll_mining` reports when it's done.
     pub fn mine(&mut self) -> Result<(), Error> {
-        self.runtime.block_on(async {
-            const NUM_TRANSACTIONS: usize = 1000;
-            let (transactions, fee) = self.node.get_transactions(NUM_TRANSACTIONS)?;
-            let coinbase = match fee {
-                0 => vec![],
-                _ => vec![types::Output {
-                    address: self.wallet.get_new_address()?,
-                    content: types::Content::Value(fee),
-                }],
-            };
-            let body = types::Body::new(transactions, coinbase);
-            let prev_side_hash = self.node.get_best_hash()?;
-            let prev_main_hash = self.miner.drivechain.get_mainchain_tip().await?;
-            let header = types::Header {
-                merkle_root: body.compute_merkle_root(),
-                prev_side_hash,
-                prev_main_hash,
-            };
-            let bribe = if fee > 0 {
-                fee
-            } else {
-                Self::EMPTY_BLOCK_BMM_BRIBE
-            };
-            let bribe = bitcoin::Amount::from_sat(bribe);
-            self.miner
-                .attempt_bmm(bribe.to_sat(), 0, header, body)
-                .await?;
-            self.miner.generate().await?;
-            if let Ok(Some((header, body))) = self.miner.confirm_bmm().await {
-                self.node.submit_block(&header, &body).await?;
-            }
+        if self.mining.is_some() {
+            return Err(Error::AlreadyMining);
+        }
+        if self.is_syncing()? {
+            return Err(Error::Syncing);
+        }
+        if !self.wallet.has_seed()? {
+            return Err(Error::NoCoinbaseAddress);
+        }
+        let (abandon, abandoned) = tokio::sync::oneshot::channel();
+        let task = Self::mine_block(
+            self.node.clone(),
+            self.wallet.clone(),
+            self.miner.clone(),
+            self.generate,
+            self.bmm_poll_interval,
+            abandoned,
+        );
+        self.mining = Some(self.runtime.spawn(task));
+        self.abandon = Some(abandon);
+        Ok(())
+    }
 
-            Ok::<(), Error>(())
-        })?;
+    /// Tell the mining task to stop mining the current block, `poll_mining` reports when it has
+    /// stopped. The block's BMM request is left to expire on mainchain.
+    pub fn abandon_block(&mut self) {
+        if let Some(abandon) = self.abandon.take() {
+            let _ = abandon.send(());
+        }
+    }
+
+    /// Finish the background mining task if it's done. Returns whether it's still running.
+    pub fn poll_mining(&mut self) -> Result<bool, Error> {
+        match &self.mining {
+            Some(mining) if mining.is_finished() => {}
+            Some(_) => return Ok(true),
+            None => return Ok(false),
+        }
+        self.abandon = None;
+        if let Some(mining) = self.mining.take() {
+            self.runtime.block_on(mining)??;
+        }
         self.update_wallet()?;
         self.update_utxos()?;
+        Ok(false)
+    }
+
+    /// Mine a block with `miner`, which the task owns, until it's connected or `abandoned`.
+    async fn mine_block(
+        node: Node,
+        wallet: Wallet,
+        mut miner: Miner,
+        generate: bool,
+        bmm_poll_interval: Duration,
+        abandoned: tokio::sync::oneshot::Receiver<()>,
+    ) -> Result<(), Error> {
+        let bmm_block = Self::bmm_block(&node, &wallet, &mut miner, generate, bmm_poll_interval);
+        let block = tokio::select! {
+            block = bmm_block => block?,
+            Ok(()) = abandoned => None,
+        };
+        match block {
+            Some((header, body)) => node.submit_block(&header, &body).await?,
+            None => miner.abandon_block(),
+        }
         Ok(())
     }
 
+    /// Make a BMM request for a new block and wait for it to land on mainchain.
+    async fn bmm_block(
+        node: &Node,
+        wallet: &Wallet,
+        miner: &mut Miner,
+        generate: bool,
+        bmm_poll_interval: Duration,
+    ) -> Result<Option<(types::Header, types::Body)>, Error> {
+        let (transactions, fee) = node.get_transactions(Self::MAX_BLOCK_TRANSACTIONS)?;
+        let coinbase = Self::coinbase(wallet, fee)?;
+        let body = types::Body::new(transactions, coinbase);
+        let (prev_side_hash, height, commitment) = {
+            let snapshot = node.snapshot()?;
+            (
+                snapshot.get_best_hash()?,
+                snapshot.get_height()? + 1,
+                snapshot.get_utxo_set_hash()?,
+            )
+        };
+        let prev_main_hash = miner.mainchain.get_mainchain_tip().await?;
+        let header = types::Header {
+            merkle_root: body.compute_merkle_root(),
+            prev_side_hash,
+            prev_main_hash,
+            height,
+            commitment: Some(commitment),
+        };
+        let bribe = if fee > 0 {
+            fee
+        } else {
+            Self::EMPTY_BLOCK_BMM_BRIBE
+        };
+        let bribe = bitcoin::Amount::from_sat(bribe);
+        miner.attempt_bmm(bribe.to_sat(), 0, header, body).await?;
+        let block = if generate {
+            miner.generate().await?;
+            miner.confirm_bmm().await.ok().flatten()
+        } else {
+            miner.wait_for_bmm(bmm_poll_interval, Self::BMM_TIMEOUT).await?
+        };
+        Ok(block)
+    }
+
+    /// Coinbase outputs paying `fee` to the wallet, fails with `Error::NoCoinbaseAddress` if the
+    /// wallet has no seed to derive an address from.
+    fn coinbase(wallet: &Wallet, fee: u64) -> Result<Vec<types::Output>, Error> {
+        if fee == 0 {
+            return Ok(vec![]);
+        }
+        let address = match wallet.get_change_address() {
+            Ok(address) => address,
+            Err(wallet::Error::NoSeed) => return Err(Error::NoCoinbaseAddress),
+            Err(err) => return Err(err.into()),
+        };
+        // A single value output, well within the consensus limit on coinbase outputs.
+        Ok(vec![types::Output {
+            address,
+            content: types::Content::Value(fee),
+        }])
+    }
+
     fn update_wallet(&mut self) -> Result<(), Error> {
+        use tokio::sync::broadcast::error::TryRecvError;
+        loop {
+            match self.chain_events.try_recv() {
+                Ok(ChainEvent::Connect { header, body }) => {
+                    self.wallet.connect_block(&body, header.height)?;
+                }
+                Ok(ChainEvent::Disconnect {
+                    body, spent_utxos, ..
+                }) => {
+                    self.wallet.disconnect_block(&body, &spent_utxos)?;
+                }
+                // Missed events are covered by the scan of the UTXO set below.
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
         let addresses = self.wallet.get_addresses()?;
-        let utxos = self.node.get_utxos_by_addresses(&addresses)?;
         let outpoints: Vec<_> = self.wallet.get_utxos()?.into_keys().collect();
-        let spent = self.node.get_spent_utxos(&outpoints)?;
+        // A single snapshot, so that a block connected in between can't make a UTXO show up as
+        // both new and spent.
+        let (utxos, spent, heights, height) = {
+            let snapshot = self.node.snapshot()?;
+            let utxos = snapshot.get_utxos_by_addresses(&addresses)?;
+            let spent = snapshot.get_spent_utxos(&outpoints)?;
+            let new_outpoints: Vec<_> = utxos.keys().copied().collect();
+            let heights = snapshot.get_utxo_heights(&new_outpoints)?;
+            (utxos, spent, heights, snapshot.get_height()?)
+        };
+        self.wallet.set_height(height)?;
         self.wallet.put_utxos(&utxos)?;
+        self.wallet.put_utxo_heights(&heights)?;
         self.wallet.delete_utxos(&spent)?;
         Ok(())
     }
@@ -164,7 +402,7 @@ impl App {
             let address = self.wallet.get_new_address()?;
             let address = format_deposit_address(THIS_SIDECHAIN, &format!("{address}"));
             self.miner
-                .drivechain
+                .mainchain
                 .client
                 .createsidechaindeposit(THIS_SIDECHAIN, &address, amount.into(), fee.into())
                 .await?;
@@ -173,6 +411,42 @@ impl App {
     }
 }
 
+pub type AppResult<T> = Result<T, Error>;
+
+/// Error of a failed GUI action, kept until the user dismisses it.
+#[derive(Debug, Default)]
+pub struct ErrorState {
+    message: Option<String>,
+}
+
+impl ErrorState {
+    /// Record the error of `result` if there is one, returns the value otherwise.
+    pub fn capture<T>(&mut self, result: AppResult<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                // Include the whole source chain, the top level message rarely says enough.
+                let mut message = err.to_string();
+                let mut source = std::error::Error::source(&err);
+                while let Some(err) = source {
+                    message.push_str(&format!(": {err}"));
+                    source = err.source();
+                }
+                self.message = Some(message);
+                None
+            }
+        }
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn dismiss(&mut self) {
+        self.message = None;
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("node error")]
@@ -185,6 +459,50 @@ pub enum Error {
     Drivechain(#[from] bip300301::Error),
     #[error("io error")]
     Io(#[from] std::io::Error),
+    #[error("node is syncing, refusing to mine on a stale tip")]
+    Syncing,
+    #[error("already mining a block")]
+    AlreadyMining,
+    #[error("wallet has no seed to derive a coinbase address from")]
+    NoCoinbaseAddress,
+    #[error("mining task failed")]
+    Join(#[from] tokio::task::JoinError),
     #[error("jsonrpsee error")]
     Jsonrpsee(#[from] jsonrpsee::core::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_populates_error_state() {
+        let mut error = ErrorState::default();
+        assert_eq!(error.capture(Ok(1)), Some(1));
+        assert!(error.message().is_none());
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, "disk full");
+        assert!(error.capture::<()>(Err(io_error.into())).is_none());
+        assert_eq!(error.message(), Some("io error: disk full"));
+        error.dismiss();
+        assert!(error.message().is_none());
+    }
+
+    #[test]
+    fn coinbase_requires_seed() {
+        let dir = tempfile::tempdir().unwrap();
+        let wallet = Wallet::new(
+            dir.path(),
+            wallet::AddressPolicy::Fresh,
+            wallet::AccountPath::default(),
+            Wallet::DEFAULT_MAP_SIZE,
+        )
+        .unwrap();
+        assert!(matches!(
+            App::coinbase(&wallet, 1_000),
+            Err(Error::NoCoinbaseAddress)
+        ));
+        wallet.set_seed(&[1; 64]).unwrap();
+        let coinbase = App::coinbase(&wallet, 1_000).unwrap();
+        assert_eq!(coinbase.len(), 1);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::app::lib;
 use clap::Parser;
-use std::{net::SocketAddr, path::PathBuf};
+use lib::bip300301::bitcoin;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -10,7 +12,12 @@ pub struct Cli {
     /// address to use for P2P networking, defaults to 127.0.0.1:4000
     #[arg(short, long)]
     pub net_addr: Option<String>,
-    /// address to connect to mainchain node RPC server, defaults to 127.0.0.1:18443
+    /// mainchain network the sidechain is deployed on: mainnet, testnet, signet or regtest,
+    /// defaults to regtest
+    #[arg(long)]
+    pub network: Option<String>,
+    /// address to connect to mainchain node RPC server, defaults to 127.0.0.1 on the network's
+    /// RPC port, 18443 for regtest
     #[arg(short, long)]
     pub main_addr: Option<String>,
     /// mainchain node RPC user, defaults to "user"
@@ -19,14 +26,104 @@ pub struct Cli {
     /// mainchain node RPC password, defaults to "password"
     #[arg(short, long)]
     pub password_main: Option<String>,
+    /// prune block bodies older than this many blocks, disabled by default
+    #[arg(long)]
+    pub prune: Option<u32>,
+    /// zstd compression level for stored block bodies, 0 disables compression, defaults to 3
+    #[arg(long)]
+    pub compression_level: Option<i32>,
+    /// minimum fee rate in sats per byte for relaying transactions, defaults to 1
+    #[arg(long)]
+    pub min_relay_fee_rate: Option<u64>,
+    /// evict transactions that weren't mined within this many blocks, disabled by default
+    #[arg(long)]
+    pub mempool_ttl: Option<u32>,
+    /// verify the integrity of the local block archive and exit
+    #[arg(long)]
+    pub verify: bool,
+    /// check that the mainchain node is reachable and supports every RPC method we need, and exit
+    #[arg(long)]
+    pub doctor: bool,
+    /// disconnect blocks from the tip down to this height and exit
+    #[arg(long, value_name = "HEIGHT")]
+    pub rollback: Option<u32>,
+    /// delete all blocks, chain state and mempool transactions after confirmation and exit
+    #[arg(long)]
+    pub reset: bool,
+    /// with --reset, also forget the wallet's UTXOs, keys and addresses are kept
+    #[arg(long, requires = "reset")]
+    pub reset_wallet: bool,
+    /// address to serve Prometheus metrics on, disabled by default
+    #[arg(long)]
+    pub metrics_addr: Option<String>,
+    /// address to serve /health and /ready checks on over HTTP, disabled by default
+    #[arg(long)]
+    pub health_addr: Option<String>,
+    /// send change and mining rewards to a single address instead of a new one every time
+    #[arg(long)]
+    pub reuse_change_address: bool,
+    /// don't generate mainchain blocks when mining, wait for BMM requests to be included instead
+    #[arg(long)]
+    pub no_generate: bool,
+    /// seconds between checks for BMM inclusion with --no-generate, defaults to 5
+    #[arg(long)]
+    pub bmm_poll_interval: Option<u64>,
+    /// hardened path of the wallet account key, defaults to m/1'/0'/0'
+    #[arg(long)]
+    pub account_path: Option<String>,
+    /// name of the wallet to open, stored in wallet-<NAME>.mdb in the data directory, defaults to
+    /// the wallet in wallet.mdb
+    #[arg(long, value_name = "NAME")]
+    pub wallet: Option<String>,
+    /// threads used for signature verification, defaults to one per core
+    #[arg(long)]
+    pub verification_threads: Option<usize>,
+    /// confirmations after which blocks are final and never disconnected, defaults to 100, 0
+    /// allows disconnecting blocks of any depth
+    #[arg(long)]
+    pub finality_depth: Option<u32>,
+    /// transactions per second accepted from each peer, defaults to 10
+    #[arg(long)]
+    pub peer_tx_rate: Option<f64>,
+    /// transactions a peer may push in a burst above --peer-tx-rate, defaults to 50
+    #[arg(long)]
+    pub peer_tx_burst: Option<u32>,
+    /// seconds peers have to respond to a request, defaults to 10
+    #[arg(long)]
+    pub request_timeout: Option<u64>,
+    /// download blocks as compact blocks, fetching only transactions missing from the mempool
+    #[arg(long)]
+    pub compact_blocks: bool,
 }
 
 pub struct Config {
     pub datadir: PathBuf,
+    pub network: bitcoin::Network,
     pub net_addr: SocketAddr,
     pub main_addr: SocketAddr,
     pub main_user: String,
     pub main_password: String,
+    pub prune_depth: Option<u32>,
+    pub compression_level: Option<i32>,
+    pub min_relay_fee_rate: u64,
+    pub mempool_ttl: Option<u32>,
+    pub verify: bool,
+    pub doctor: bool,
+    pub rollback: Option<u32>,
+    pub reset: bool,
+    pub reset_wallet: bool,
+    pub metrics_addr: Option<SocketAddr>,
+    pub health_addr: Option<SocketAddr>,
+    pub address_policy: lib::wallet::AddressPolicy,
+    pub account_path: lib::wallet::AccountPath,
+    pub wallet: String,
+    pub generate: bool,
+    pub bmm_poll_interval: Duration,
+    pub verification_threads: Option<usize>,
+    pub finality_depth: Option<u32>,
+    pub push_transaction_limit: lib::net::RateLimit,
+    pub request_timeout: Duration,
+    pub compact_blocks: bool,
 }
 
 impl Cli {
@@ -37,12 +134,23 @@ impl Cli {
             .clone()
             .unwrap_or(DEFAULT_NET_ADDR.to_string())
             .parse()?;
-        const DEFAULT_MAIN_ADDR: &str = "127.0.0.1:18443";
-        let main_addr: SocketAddr = self
-            .main_addr
-            .clone()
-            .unwrap_or(DEFAULT_MAIN_ADDR.to_string())
-            .parse()?;
+        let network = match self.network.as_deref() {
+            None => bitcoin::Network::Regtest,
+            Some("mainnet") => bitcoin::Network::Bitcoin,
+            Some(network) => network.parse()?,
+        };
+        let main_addr: SocketAddr = match &self.main_addr {
+            Some(main_addr) => main_addr.parse()?,
+            None => {
+                let port = match network {
+                    bitcoin::Network::Bitcoin => 8332,
+                    bitcoin::Network::Testnet => 18332,
+                    bitcoin::Network::Signet => 38332,
+                    _ => 18443,
+                };
+                SocketAddr::from(([127, 0, 0, 1], port))
+            }
+        };
         let datadir = self
             .datadir
             .clone()
@@ -50,6 +158,27 @@ impl Cli {
                 dirs::data_dir().expect("couldn't get default datadir, specify --datadir")
             })
             .join("{{crate_name}}");
+        let compression_level = match self.compression_level {
+            Some(0) => None,
+            Some(level) => Some(level),
+            None => Some(lib::compression::DEFAULT_COMPRESSION_LEVEL),
+        };
+        let metrics_addr = self
+            .metrics_addr
+            .as_ref()
+            .map(|metrics_addr| metrics_addr.parse())
+            .transpose()?;
+        let health_addr = self
+            .health_addr
+            .as_ref()
+            .map(|health_addr| health_addr.parse())
+            .transpose()?;
+        let account_path = self
+            .account_path
+            .as_ref()
+            .map(|account_path| account_path.parse())
+            .transpose()?
+            .unwrap_or_default();
         let main_user = self.user_main.clone().unwrap_or_else(|| "user".into());
         let main_password = self
             .password_main
@@ -57,10 +186,55 @@ impl Cli {
             .unwrap_or_else(|| "password".into());
         Ok(Config {
             datadir,
+            network,
             net_addr,
             main_addr,
             main_user,
             main_password,
+            prune_depth: self.prune,
+            compression_level,
+            min_relay_fee_rate: self
+                .min_relay_fee_rate
+                .unwrap_or(lib::mempool::MemPool::DEFAULT_MIN_RELAY_FEE_RATE),
+            mempool_ttl: self.mempool_ttl,
+            verify: self.verify,
+            doctor: self.doctor,
+            rollback: self.rollback,
+            reset: self.reset,
+            reset_wallet: self.reset_wallet,
+            metrics_addr,
+            health_addr,
+            address_policy: if self.reuse_change_address {
+                lib::wallet::AddressPolicy::Reuse
+            } else {
+                lib::wallet::AddressPolicy::Fresh
+            },
+            account_path,
+            wallet: self
+                .wallet
+                .clone()
+                .unwrap_or_else(|| lib::wallet::Wallet::DEFAULT_NAME.into()),
+            generate: !self.no_generate,
+            bmm_poll_interval: Duration::from_secs(self.bmm_poll_interval.unwrap_or(5)),
+            verification_threads: self.verification_threads,
+            finality_depth: match self.finality_depth {
+                Some(0) => None,
+                Some(finality_depth) => Some(finality_depth),
+                None => Some(<lib::node::Node>::DEFAULT_FINALITY_DEPTH),
+            },
+            push_transaction_limit: lib::net::RateLimit {
+                per_second: self
+                    .peer_tx_rate
+                    .unwrap_or(lib::net::RateLimit::DEFAULT_PUSH_TRANSACTION.per_second),
+                burst: self
+                    .peer_tx_burst
+                    .unwrap_or(lib::net::RateLimit::DEFAULT_PUSH_TRANSACTION.burst),
+            },
+            request_timeout: self
+                .request_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(lib::net::DEFAULT_REQUEST_TIMEOUT),
+            compact_blocks: self.compact_blocks,
         })
     }
 }
@@ -0,0 +1,25 @@
+use crate::app::App;
+use eframe::egui;
+
+#[derive(Default)]
+pub struct Activity {}
+
+impl Activity {
+    pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        ui.heading("Activity");
+        let events = app.wallet.get_events(0).unwrap_or_default();
+        if events.is_empty() {
+            ui.label("No activity yet");
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("activity").striped(true).show(ui, |ui| {
+                for (id, event) in events.iter().rev() {
+                    ui.monospace(format!("{id}"));
+                    ui.monospace(format!("{event}"));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
@@ -2,21 +2,62 @@ use crate::app::lib;
 use crate::app::App;
 use eframe::egui;
 use human_size::{Byte, Kibibyte, Mebibyte, SpecificSize};
-use lib::{bip300301::bitcoin, types::GetValue};
+use lib::{
+    bip300301::bitcoin,
+    types::{self, Body, GetValue, Transaction},
+};
+
+use super::{truncate_hex, utxo_selector::show_utxo};
 
 pub struct BlockExplorer {
     height: u32,
+    search: String,
+    not_found: bool,
+    selected: Option<Selected>,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Selected {
+    Coinbase,
+    Transaction(usize),
 }
 
 impl BlockExplorer {
     pub fn new(height: u32) -> Self {
-        Self { height }
+        Self {
+            height,
+            search: "".into(),
+            not_found: false,
+            selected: None,
+        }
+    }
+
+    /// Find the height of a block by its hash or by the txid of a transaction it includes.
+    fn find_height(app: &App, search: &str) -> Option<u32> {
+        let search = search.trim();
+        if let Ok(hash) = search.parse::<types::BlockHash>() {
+            if let Some(header) = app.node.get_header_by_hash(&hash).ok().flatten() {
+                return Some(header.height);
+            }
+        }
+        if let Ok(txid) = search.parse::<types::Txid>() {
+            if let Some((_, height)) = app.node.get_transaction(&txid).ok().flatten() {
+                return Some(height);
+            }
+        }
+        None
     }
 
     pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
-        let max_height = app.node.get_height().unwrap_or(0);
-        let header = app.node.get_header(self.height).ok().flatten();
-        let body = app.node.get_body(self.height).ok().flatten();
+        // Read the height and the block from the same snapshot, so that they agree even if a
+        // block is connected in between.
+        let (max_height, block) = match app.node.snapshot() {
+            Ok(snapshot) => (
+                snapshot.get_height().unwrap_or(0),
+                snapshot.get_block(self.height).ok().flatten(),
+            ),
+            Err(_) => (0, None),
+        };
         egui::CentralPanel::default().show_inside(ui, |ui| {
             ui.heading("Block");
             ui.horizontal(|ui| {
@@ -31,15 +72,38 @@ impl BlockExplorer {
                     self.height = max_height;
                 }
             });
-            if let (Some(header), Some(body)) = (header, body) {
-                let hash = &format!("{}", header.hash());
+            ui.horizontal(|ui| {
+                let search_edit = egui::TextEdit::singleline(&mut self.search)
+                    .hint_text("block hash or txid")
+                    .desired_width(450.);
+                ui.add(search_edit);
+                if ui.button("go").clicked() {
+                    match Self::find_height(app, &self.search) {
+                        Some(height) => {
+                            self.height = height;
+                            self.not_found = false;
+                        }
+                        None => self.not_found = true,
+                    }
+                }
+                if self.not_found {
+                    ui.label("not found");
+                }
+            });
+            if let Some(block) = block {
+                let header = &block.header;
+                let body = &block.body;
+                let hash = &format!("{}", block.hash);
                 let merkle_root = &format!("{}", header.merkle_root);
                 let prev_side_hash = &format!("{}", header.prev_side_hash);
                 let prev_main_hash = &format!("{}", header.prev_main_hash);
-                let body_size = bincode::serialize(&body).unwrap_or(vec![]).len();
-                let coinbase_value: u64 = body.coinbase.iter().map(GetValue::get_value).sum();
-                let coinbase_value = bitcoin::Amount::from_sat(coinbase_value);
-                let num_transactions = body.transactions.len();
+                let commitment = match header.commitment {
+                    Some(commitment) => types::blake3::Hash::from(commitment).to_hex().to_string(),
+                    None => "none".into(),
+                };
+                let body_size = block.body_size;
+                let coinbase_value = bitcoin::Amount::from_sat(block.coinbase_value);
+                let num_transactions = block.num_transactions;
                 let body_size = if let Ok(body_size) = SpecificSize::new(body_size as f64, Byte) {
                     let bytes = body_size.to_bytes();
                     if bytes < 1024 {
@@ -56,14 +120,127 @@ impl BlockExplorer {
                 };
                 let num_sigops = body.authorizations.len();
                 ui.monospace(format!("Block hash:       {hash}"));
+                ui.monospace(format!("Height:           {}", header.height));
                 ui.monospace(format!("Merkle root:      {merkle_root}"));
                 ui.monospace(format!("Prev side:        {prev_side_hash}"));
                 ui.monospace(format!("Prev main:        {prev_main_hash}"));
+                ui.monospace(format!("Commitment:       {commitment}"));
                 ui.monospace(format!("Num transactions: {num_transactions}"));
                 ui.monospace(format!("Coinbase value:   {coinbase_value}"));
                 ui.monospace(format!("Body size:        {body_size}"));
                 ui.monospace(format!("Num sigops:       {num_sigops}"));
+                ui.separator();
+                self.show_transactions(app, ui, body);
             }
         });
     }
+
+    fn show_transactions(&mut self, app: &App, ui: &mut egui::Ui, body: &Body) {
+        ui.heading("Transactions");
+        egui::ScrollArea::vertical()
+            .id_source("block_transactions")
+            .max_height(200.)
+            .show(ui, |ui| {
+                egui::Grid::new("block_transactions")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.monospace("txid");
+                        ui.monospace("inputs");
+                        ui.monospace("outputs");
+                        ui.monospace("fee");
+                        ui.end_row();
+                        ui.selectable_value(
+                            &mut self.selected,
+                            Some(Selected::Coinbase),
+                            "coinbase",
+                        );
+                        ui.monospace("0");
+                        ui.monospace(format!("{}", body.coinbase.len()));
+                        ui.monospace("-");
+                        ui.end_row();
+                        for (index, transaction) in body.transactions.iter().enumerate() {
+                            let txid = format!("{}", transaction.txid());
+                            let txid = truncate_hex(&txid, 8);
+                            let fee = Self::get_fee(app, transaction)
+                                .map(|fee| format!("{}", bitcoin::Amount::from_sat(fee)))
+                                .unwrap_or("unknown".into());
+                            ui.selectable_value(
+                                &mut self.selected,
+                                Some(Selected::Transaction(index)),
+                                txid,
+                            );
+                            ui.monospace(format!("{}", transaction.inputs.len()));
+                            ui.monospace(format!("{}", transaction.outputs.len()));
+                            ui.monospace(fee);
+                            ui.end_row();
+                        }
+                    });
+            });
+        let (inputs, outputs) = match self.selected {
+            Some(Selected::Coinbase) => (&[][..], &body.coinbase),
+            Some(Selected::Transaction(index)) => match body.transactions.get(index) {
+                Some(transaction) => (transaction.inputs.as_slice(), &transaction.outputs),
+                None => return,
+            },
+            None => return,
+        };
+        ui.separator();
+        ui.horizontal_top(|ui| {
+            ui.vertical(|ui| {
+                ui.heading("Inputs");
+                egui::Grid::new("block_transaction_inputs")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.monospace("kind");
+                        ui.monospace("outpoint");
+                        ui.monospace("value");
+                        ui.end_row();
+                        let spent_outputs = app.node.get_outputs(inputs).unwrap_or_default();
+                        for input in inputs {
+                            match spent_outputs.get(input) {
+                                Some(output) => show_utxo(ui, input, output),
+                                None => {
+                                    ui.monospace(format!("{input}"));
+                                    ui.monospace("");
+                                    ui.monospace("unknown");
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+            ui.separator();
+            ui.vertical(|ui| {
+                ui.heading("Outputs");
+                egui::Grid::new("block_transaction_outputs")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.monospace("vout");
+                        ui.monospace("address");
+                        ui.monospace("value");
+                        ui.end_row();
+                        for (vout, output) in outputs.iter().enumerate() {
+                            let address = output.address.short();
+                            let value = bitcoin::Amount::from_sat(output.get_value());
+                            ui.monospace(format!("{vout}"));
+                            ui.monospace(format!("{address}"));
+                            ui.monospace(format!("{value}"));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+    }
+
+    /// Fee of a confirmed transaction, `None` if one of the spent outputs can't be looked up.
+    fn get_fee(app: &App, transaction: &Transaction) -> Option<u64> {
+        let spent_outputs = app.node.get_outputs(&transaction.inputs).ok()?;
+        let value_in: u64 = transaction
+            .inputs
+            .iter()
+            .map(|input| spent_outputs.get(input).map(GetValue::get_value))
+            .sum::<Option<u64>>()?;
+        let value_out: u64 = transaction.outputs.iter().map(GetValue::get_value).sum();
+        value_in.checked_sub(value_out)
+    }
 }
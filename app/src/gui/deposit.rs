@@ -41,10 +41,11 @@ impl Deposit {
             .add_enabled(amount.is_ok() && fee.is_ok(), egui::Button::new("deposit"))
             .clicked()
         {
-            app.deposit(
+            let result = app.deposit(
                 amount.expect("should not happen"),
                 fee.expect("should not happen"),
             );
+            app.error.capture(result);
         }
     }
 }
@@ -7,6 +7,8 @@ use lib::{
     types::{GetValue, OutPoint},
 };
 
+use super::truncate_hex;
+
 pub struct MemPoolExplorer {
     current: usize,
 }
@@ -20,7 +22,34 @@ impl Default for MemPoolExplorer {
 impl MemPoolExplorer {
     pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
         let transactions = app.node.get_all_transactions().unwrap_or(vec![]);
-        let utxos = app.wallet.get_utxos().unwrap_or_default();
+        // Spent outputs are looked up in the node's UTXO set, so fees are known for any
+        // transaction and not only the wallet's own.
+        let filled: Vec<_> = {
+            let snapshot = app.node.snapshot();
+            transactions
+                .iter()
+                .map(|transaction| {
+                    let snapshot = snapshot.as_ref().ok()?;
+                    snapshot.fill_transaction(&transaction.transaction).ok()
+                })
+                .collect()
+        };
+        let info = app.node.get_mempool_info().unwrap_or_default();
+        egui::TopBottomPanel::top("mempool_info").show_inside(ui, |ui| {
+            let total_fees = bitcoin::Amount::from_sat(info.total_fees);
+            let fee_rate = |fee_rate: Option<u64>| match fee_rate {
+                Some(fee_rate) => format!("{fee_rate}"),
+                None => "-".into(),
+            };
+            ui.monospace(format!(
+                "{} transactions, {} bytes, total fees {total_fees}, fee rate (sat/byte) min {} median {} max {}",
+                info.count,
+                info.total_size,
+                fee_rate(info.min_fee_rate),
+                fee_rate(info.median_fee_rate),
+                fee_rate(info.max_fee_rate),
+            ));
+        });
         egui::SidePanel::left("transaction_picker")
             .resizable(false)
             .show_inside(ui, |ui| {
@@ -33,44 +62,32 @@ impl MemPoolExplorer {
                         ui.monospace("value out");
                         ui.monospace("fee");
                         ui.end_row();
-                        for (index, transaction) in transactions.iter().enumerate() {
+                        for (index, (transaction, filled)) in
+                            transactions.iter().zip(&filled).enumerate()
+                        {
                             let value_out: u64 = transaction
                                 .transaction
                                 .outputs
                                 .iter()
                                 .map(GetValue::get_value)
                                 .sum();
-                            let value_in: u64 = transaction
-                                .transaction
-                                .inputs
-                                .iter()
-                                .map(|input| utxos.get(input).map(GetValue::get_value))
-                                .sum::<Option<u64>>()
-                                .unwrap_or(0);
-                            let txid = &format!("{}", transaction.transaction.txid())[0..8];
-                            if value_in >= value_out {
-                                let fee = value_in - value_out;
-                                ui.selectable_value(&mut self.current, index, format!("{txid}"));
-                                ui.with_layout(
-                                    egui::Layout::right_to_left(egui::Align::Max),
-                                    |ui| {
-                                        let value_out = bitcoin::Amount::from_sat(value_out);
-                                        ui.monospace(format!("{value_out}"));
-                                    },
-                                );
-                                ui.with_layout(
-                                    egui::Layout::right_to_left(egui::Align::Max),
-                                    |ui| {
-                                        let fee = bitcoin::Amount::from_sat(fee);
-                                        ui.monospace(format!("{fee}"));
-                                    },
-                                );
-                                ui.end_row();
-                            } else {
-                                ui.selectable_value(&mut self.current, index, format!("{txid}"));
-                                ui.monospace("invalid");
-                                ui.end_row();
-                            }
+                            let fee = match filled {
+                                Some(filled) => {
+                                    format!("{}", filled.fee_display(transaction.serialized_size()))
+                                }
+                                None => "unknown".into(),
+                            };
+                            let txid = format!("{}", transaction.transaction.txid());
+                            let txid = truncate_hex(&txid, 8);
+                            ui.selectable_value(&mut self.current, index, format!("{txid}"));
+                            ui.with_layout(egui::Layout::right_to_left(egui::Align::Max), |ui| {
+                                let value_out = bitcoin::Amount::from_sat(value_out);
+                                ui.monospace(format!("{value_out}"));
+                            });
+                            ui.with_layout(egui::Layout::right_to_left(egui::Align::Max), |ui| {
+                                ui.monospace(fee);
+                            });
+                            ui.end_row();
                         }
                     });
             });
@@ -85,7 +102,10 @@ impl MemPoolExplorer {
                         ui.monospace("outpoint");
                         ui.monospace("value");
                         ui.end_row();
-                        for input in &transaction.transaction.inputs {
+                        let spent_utxos = filled[self.current]
+                            .as_ref()
+                            .map(|filled| filled.spent_utxos.as_slice());
+                        for (vin, input) in transaction.transaction.inputs.iter().enumerate() {
                             let (kind, hash, vout) = match input {
                                 OutPoint::Regular { txid, vout } => {
                                     ("regular", format!("{txid}"), *vout)
@@ -97,9 +117,13 @@ impl MemPoolExplorer {
                                     ("coinbase", format!("{merkle_root}"), *vout)
                                 }
                             };
-                            let output = &utxos[input];
-                            let hash = &hash[0..8];
-                            let value = bitcoin::Amount::from_sat(output.get_value());
+                            let hash = truncate_hex(&hash, 8);
+                            let value = match spent_utxos.and_then(|spent| spent.get(vin)) {
+                                Some(output) => {
+                                    format!("{}", bitcoin::Amount::from_sat(output.get_value()))
+                                }
+                                None => "unknown".into(),
+                            };
                             ui.monospace(format!("{kind}",));
                             ui.monospace(format!("{hash}:{vout}",));
                             ui.monospace(format!("{value}",));
@@ -118,7 +142,7 @@ impl MemPoolExplorer {
                         ui.monospace("value");
                         ui.end_row();
                         for (vout, output) in transaction.transaction.outputs.iter().enumerate() {
-                            let address = &format!("{}", output.address)[0..8];
+                            let address = output.address.short();
                             let value = bitcoin::Amount::from_sat(output.get_value());
                             ui.monospace(format!("{vout}"));
                             ui.monospace(format!("{address}"));
@@ -132,7 +156,7 @@ impl MemPoolExplorer {
                 ui.separator();
                 let txid = transaction.transaction.txid();
                 ui.monospace(format!("Txid:             {txid}"));
-                let transaction_size = bincode::serialize(&transaction).unwrap_or(vec![]).len();
+                let transaction_size = transaction.serialized_size();
                 let transaction_size = if let Ok(transaction_size) =
                     SpecificSize::new(transaction_size as f64, Byte)
                 {
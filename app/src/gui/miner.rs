@@ -1,5 +1,8 @@
-use crate::app::App;
+use crate::app::{lib, App};
 use eframe::egui;
+use lib::bip300301::bitcoin;
+
+use super::truncate_hex;
 
 pub struct Miner;
 
@@ -11,15 +14,39 @@ impl Default for Miner {
 
 impl Miner {
     pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
-        let block_height = app.node.get_height().unwrap_or(0);
-        let best_hash = app.node.get_best_hash().unwrap_or([0; 32].into());
+        let (block_height, best_hash) = match app.node.snapshot() {
+            Ok(snapshot) => (
+                snapshot.get_height().unwrap_or(0),
+                snapshot.get_best_hash().unwrap_or_default(),
+            ),
+            Err(_) => (0, Default::default()),
+        };
         ui.label("Block height: ");
         ui.monospace(format!("{block_height}"));
         ui.label("Best hash: ");
-        let best_hash = &format!("{best_hash}")[0..8];
+        let best_hash = format!("{best_hash}");
+        let best_hash = truncate_hex(&best_hash, 8);
         ui.monospace(format!("{best_hash}..."));
-        if ui.button("mine").clicked() {
-            app.mine();
+        ui.label("Fees: ");
+        let fees = app.available_fees().unwrap_or(0);
+        ui.monospace(format!("{}", bitcoin::Amount::from_sat(fees)));
+        let syncing = app.is_syncing().unwrap_or(false);
+        let mining = app.poll_mining();
+        let mining = app.error.capture(mining).unwrap_or(false);
+        if ui
+            .add_enabled(!syncing && !mining, egui::Button::new("mine"))
+            .clicked()
+        {
+            let result = app.mine();
+            app.error.capture(result);
+        }
+        if syncing {
+            ui.label("syncing...");
+        } else if mining {
+            ui.label("mining...");
+            if ui.button("abandon").clicked() {
+                app.abandon_block();
+            }
         }
     }
 }
@@ -2,8 +2,12 @@ use std::collections::HashSet;
 
 use crate::app::{lib, App};
 use eframe::egui;
-use lib::{bip300301::bitcoin, types::GetValue};
+use lib::{
+    bip300301::bitcoin,
+    types::{FeeDisplay, GetValue},
+};
 
+mod activity;
 mod block_explorer;
 mod deposit;
 mod mempool_explorer;
@@ -11,8 +15,10 @@ mod miner;
 mod seed;
 mod utxo_creator;
 mod utxo_selector;
+mod wallet_selector;
 mod withdrawals;
 
+use activity::Activity;
 use block_explorer::BlockExplorer;
 use deposit::Deposit;
 use mempool_explorer::MemPoolExplorer;
@@ -20,7 +26,15 @@ use miner::Miner;
 use seed::SetSeed;
 use utxo_selector::{show_utxo, UtxoSelector};
 
-use self::{utxo_creator::UtxoCreator, withdrawals::Withdrawals};
+use self::{utxo_creator::UtxoCreator, wallet_selector::WalletSelector, withdrawals::Withdrawals};
+
+/// First `n` characters of `s`, or all of `s` if it is shorter.
+fn truncate_hex(s: &str, n: usize) -> &str {
+    match s.char_indices().nth(n) {
+        Some((index, _)) => &s[..index],
+        None => s,
+    }
+}
 
 pub struct EguiApp {
     app: App,
@@ -33,6 +47,8 @@ pub struct EguiApp {
     mempool_explorer: MemPoolExplorer,
     block_explorer: BlockExplorer,
     withdrawals: Withdrawals,
+    wallet_selector: WalletSelector,
+    activity: Activity,
 }
 
 #[derive(Eq, PartialEq)]
@@ -41,6 +57,7 @@ enum Tab {
     MemPoolExplorer,
     BlockExplorer,
     Withdrawals,
+    Activity,
 }
 
 impl EguiApp {
@@ -61,12 +78,35 @@ impl EguiApp {
             block_explorer: BlockExplorer::new(height),
             tab: Tab::TransactionBuilder,
             withdrawals: Withdrawals::default(),
+            wallet_selector: WalletSelector::default(),
+            activity: Activity::default(),
         }
     }
+
+    /// Modal with the error of the last failed action, until it's dismissed.
+    fn show_error(&mut self, ctx: &egui::Context) {
+        let Some(message) = self.app.error.message().map(str::to_owned) else {
+            return;
+        };
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .show(ctx, |ui| {
+                ui.label(message);
+                if ui.button("dismiss").clicked() {
+                    self.app.error.dismiss();
+                }
+            });
+    }
 }
 
 impl eframe::App for EguiApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.show_error(ctx);
+        egui::TopBottomPanel::top("wallets").show(ctx, |ui| {
+            self.wallet_selector.show(&mut self.app, ui);
+        });
         if self.app.wallet.has_seed().unwrap_or(false) {
             egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
                 ui.horizontal(|ui| {
@@ -78,6 +118,7 @@ impl eframe::App for EguiApp {
                     ui.selectable_value(&mut self.tab, Tab::MemPoolExplorer, "mempool explorer");
                     ui.selectable_value(&mut self.tab, Tab::BlockExplorer, "block explorer");
                     ui.selectable_value(&mut self.tab, Tab::Withdrawals, "withdrawals");
+                    ui.selectable_value(&mut self.tab, Tab::Activity, "activity");
                 });
             });
             egui::TopBottomPanel::bottom("util").show(ctx, |ui| {
@@ -85,6 +126,20 @@ impl eframe::App for EguiApp {
                     self.miner.show(&mut self.app, ui);
                     ui.separator();
                     self.deposit.show(&mut self.app, ui);
+                    ui.separator();
+                    let height = self.app.node.get_height().unwrap_or(0);
+                    let balance = self
+                        .app
+                        .wallet
+                        .get_balance_detailed(height)
+                        .unwrap_or_default();
+                    ui.monospace(format!(
+                        "Available: {}, pending withdrawal: {}, immature: {}, frozen: {}",
+                        bitcoin::Amount::from_sat(balance.available),
+                        bitcoin::Amount::from_sat(balance.pending_withdrawal),
+                        bitcoin::Amount::from_sat(balance.immature),
+                        bitcoin::Amount::from_sat(balance.frozen),
+                    ));
                 });
             });
             egui::CentralPanel::default().show(ctx, |ui| match self.tab {
@@ -167,7 +222,7 @@ impl eframe::App for EguiApp {
                                 for (vout, output) in
                                     self.app.transaction.outputs.iter().enumerate()
                                 {
-                                    let address = &format!("{}", output.address)[0..8];
+                                    let address = output.address.short();
                                     let value = bitcoin::Amount::from_sat(output.get_value());
                                     ui.monospace(format!("{vout}"));
                                     ui.monospace(format!("{address}"));
@@ -195,17 +250,18 @@ impl eframe::App for EguiApp {
                             self.utxo_creator.show(&mut self.app, ui);
                             ui.separator();
                             ui.heading("Transaction");
-                            let txid = &format!("{}", self.app.transaction.txid())[0..8];
+                            let txid = format!("{}", self.app.transaction.txid());
+                            let txid = truncate_hex(&txid, 8);
                             ui.monospace(format!("txid: {txid}"));
-                            if value_in >= value_out {
-                                let fee = value_in - value_out;
-                                let fee = bitcoin::Amount::from_sat(fee);
-                                ui.monospace(format!("fee:  {fee}"));
-                                if ui.button("sign and send").clicked() {
-                                    self.app.sign_and_send().unwrap_or(());
-                                }
-                            } else {
-                                ui.label("Not Enough Value In");
+                            let fee = FeeDisplay::new(
+                                value_in,
+                                value_out,
+                                self.app.transaction.serialized_size(),
+                            );
+                            ui.monospace(format!("fee:  {fee}"));
+                            if fee.is_sufficient() && ui.button("sign and send").clicked() {
+                                let result = self.app.sign_and_send();
+                                self.app.error.capture(result);
                             }
                         });
                 }
@@ -218,6 +274,9 @@ impl eframe::App for EguiApp {
                 Tab::Withdrawals => {
                     self.withdrawals.show(&mut self.app, ui);
                 }
+                Tab::Activity => {
+                    self.activity.show(&mut self.app, ui);
+                }
             });
         } else {
             egui::CentralPanel::default().show(ctx, |_ui| {
@@ -228,3 +287,17 @@ impl eframe::App for EguiApp {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::truncate_hex;
+
+    #[test]
+    fn truncate_hex_clamps_to_length() {
+        assert_eq!(truncate_hex("0123456789abcdef", 8), "01234567");
+        assert_eq!(truncate_hex("0123", 8), "0123");
+        assert_eq!(truncate_hex("", 8), "");
+        // Never splits a multibyte character.
+        assert_eq!(truncate_hex("ab…cd", 3), "ab…");
+    }
+}
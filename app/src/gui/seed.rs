@@ -1,4 +1,4 @@
-use crate::app::App;
+use crate::app::{App, Error};
 use eframe::egui;
 
 pub struct SetSeed {
@@ -16,7 +16,7 @@ impl Default for SetSeed {
 }
 
 impl SetSeed {
-    pub fn show(&mut self, app: &App, ui: &mut egui::Ui) {
+    pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             let seed_edit = egui::TextEdit::singleline(&mut self.seed)
                 .hint_text("seed")
@@ -40,9 +40,10 @@ impl SetSeed {
         {
             let mnemonic = mnemonic.expect("should never happen");
             let seed = bip39::Seed::new(&mnemonic, &self.passphrase);
-            app.wallet
-                .set_seed(seed.as_bytes().try_into().expect("seed it not 64 bytes"))
-                .expect("failed to set HD wallet seed");
+            let result = app
+                .wallet
+                .set_seed(seed.as_bytes().try_into().expect("seed it not 64 bytes"));
+            app.error.capture(result.map_err(Error::from));
         }
     }
 }
@@ -65,7 +65,7 @@ impl UtxoCreator {
             if ui.button("generate").clicked() {
                 self.address = app
                     .wallet
-                    .get_new_address()
+                    .peek_next_address()
                     .map(|address| format!("{address}"))
                     .unwrap_or("".into());
             }
@@ -1,5 +1,5 @@
 use crate::app::lib;
-use crate::app::App;
+use crate::app::{App, Error};
 use eframe::egui;
 use lib::{
     bip300301::bitcoin,
@@ -7,14 +7,24 @@ use lib::{
 };
 use std::collections::HashSet;
 
+use super::truncate_hex;
+
 #[derive(Default)]
-pub struct UtxoSelector;
+pub struct UtxoSelector {
+    consolidation_fee: String,
+}
 
 impl UtxoSelector {
+    /// UTXOs combined by a single click of "consolidate".
+    const CONSOLIDATION_MAX_INPUTS: usize = 100;
+
     pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
         ui.heading("Spend UTXO");
         let selected: HashSet<_> = app.transaction.inputs.iter().cloned().collect();
         let utxos = &app.utxos;
+        let height = app.node.get_height().unwrap_or(0);
+        let utxo_heights = app.wallet.get_utxos_with_height().unwrap_or_default();
+        let frozen = app.wallet.get_frozen().unwrap_or_default();
         let total: u64 = utxos
             .iter()
             .filter(|(outpoint, _)| !selected.contains(outpoint))
@@ -24,28 +34,78 @@ impl UtxoSelector {
         utxos.sort_by_key(|(outpoint, _)| format!("{outpoint}"));
         ui.separator();
         ui.monospace(format!("Total: {}", bitcoin::Amount::from_sat(total)));
+        let consolidate = ui
+            .horizontal(|ui| {
+                let fee_edit = egui::TextEdit::singleline(&mut self.consolidation_fee)
+                    .hint_text("fee")
+                    .desired_width(80.);
+                ui.add(fee_edit);
+                ui.label("BTC");
+                let fee = bitcoin::Amount::from_str_in(
+                    &self.consolidation_fee,
+                    bitcoin::Denomination::Bitcoin,
+                );
+                let clicked = ui
+                    .add_enabled(fee.is_ok(), egui::Button::new("consolidate"))
+                    .on_hover_text("combine the smallest UTXOs into one")
+                    .clicked();
+                fee.ok().filter(|_| clicked)
+            })
+            .inner;
         ui.separator();
-        egui::Grid::new("utxos").striped(true).show(ui, |ui| {
-            ui.monospace("kind");
-            ui.monospace("outpoint");
-            ui.monospace("value");
-            ui.end_row();
-            for (outpoint, output) in utxos {
-                if selected.contains(outpoint) {
-                    continue;
-                }
-                //ui.horizontal(|ui| {});
-                show_utxo(ui, outpoint, output);
+        let toggle_frozen = egui::Grid::new("utxos")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.monospace("kind");
+                ui.monospace("outpoint");
+                ui.monospace("value");
+                ui.monospace("confirmations");
+                ui.end_row();
+                let mut toggle_frozen = None;
+                for (outpoint, output) in utxos {
+                    if selected.contains(outpoint) {
+                        continue;
+                    }
+                    //ui.horizontal(|ui| {});
+                    show_utxo(ui, outpoint, output);
+                    let confirmations = match utxo_heights.get(outpoint) {
+                        Some((_, Some(utxo_height))) if *utxo_height <= height => {
+                            format!("{}", height - utxo_height + 1)
+                        }
+                        _ => "unknown".into(),
+                    };
+                    ui.monospace(confirmations);
 
-                if ui
-                    .add_enabled(!selected.contains(outpoint), egui::Button::new("spend"))
-                    .clicked()
-                {
-                    app.transaction.inputs.push(*outpoint);
+                    if ui
+                        .add_enabled(!selected.contains(outpoint), egui::Button::new("spend"))
+                        .clicked()
+                    {
+                        app.transaction.inputs.push(*outpoint);
+                    }
+                    let is_frozen = frozen.contains(outpoint);
+                    if ui
+                        .button(if is_frozen { "unfreeze" } else { "freeze" })
+                        .clicked()
+                    {
+                        toggle_frozen = Some((*outpoint, is_frozen));
+                    }
+                    ui.end_row();
                 }
-                ui.end_row();
-            }
-        });
+                toggle_frozen
+            })
+            .inner;
+        if let Some((outpoint, is_frozen)) = toggle_frozen {
+            let result = if is_frozen {
+                app.wallet.unfreeze(&outpoint)
+            } else {
+                app.wallet.freeze(&outpoint)
+            };
+            app.error.capture(result.map_err(Error::from));
+        }
+        if let Some(fee) = consolidate {
+            let result = app.consolidate_utxos(Self::CONSOLIDATION_MAX_INPUTS, fee.to_sat());
+            app.error.capture(result);
+        }
     }
 }
 
@@ -55,7 +115,7 @@ pub fn show_utxo(ui: &mut egui::Ui, outpoint: &OutPoint, output: &Output) {
         OutPoint::Deposit(outpoint) => ("deposit", format!("{}", outpoint.txid), outpoint.vout),
         OutPoint::Coinbase { merkle_root, vout } => ("coinbase", format!("{merkle_root}"), *vout),
     };
-    let hash = &hash[0..8];
+    let hash = truncate_hex(&hash, 8);
     let value = bitcoin::Amount::from_sat(output.get_value());
     ui.monospace(format!("{kind}",));
     ui.monospace(format!("{hash}:{vout}",));
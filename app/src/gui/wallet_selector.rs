@@ -0,0 +1,42 @@
+use crate::app::App;
+use eframe::egui;
+
+#[derive(Default)]
+pub struct WalletSelector {
+    new_wallet: String,
+}
+
+impl WalletSelector {
+    pub fn show(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Wallet:");
+            let mut selected = app.wallet_name.clone();
+            let names = app.list_wallets().unwrap_or_default();
+            egui::ComboBox::from_id_source("wallet")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in names {
+                        ui.selectable_value(&mut selected, name.clone(), name);
+                    }
+                });
+            if selected != app.wallet_name {
+                let result = app.switch_wallet(&selected);
+                app.error.capture(result);
+            }
+            ui.separator();
+            let new_wallet_edit = egui::TextEdit::singleline(&mut self.new_wallet)
+                .hint_text("new wallet name")
+                .desired_width(120.);
+            ui.add(new_wallet_edit);
+            if ui
+                .add_enabled(!self.new_wallet.is_empty(), egui::Button::new("create"))
+                .clicked()
+            {
+                let result = app.switch_wallet(&self.new_wallet);
+                if app.error.capture(result).is_some() {
+                    self.new_wallet.clear();
+                }
+            }
+        });
+    }
+}
@@ -17,6 +17,22 @@ impl Withdrawals {
         ui.heading("Pending withdrawals");
         let bundle = app.node.get_pending_withdrawal_bundle().ok().flatten();
         if let Some(bundle) = bundle {
+            let txid = bundle.transaction.txid();
+            let mainchain_fee = match bundle.mainchain_fee() {
+                Some(fee) => format!("{}", bitcoin::Amount::from_sat(fee)),
+                None => "unknown".into(),
+            };
+            let inputs_commitment = match bundle.inputs_commitment() {
+                Some(commitment) => commitment
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect(),
+                None => "unknown".into(),
+            };
+            ui.monospace(format!("Txid:              {txid}"));
+            ui.monospace(format!("Mainchain fee:     {mainchain_fee}"));
+            ui.monospace(format!("Inputs commitment: {inputs_commitment}"));
+            ui.separator();
             let mut spent_utxos: Vec<_> = bundle.spent_utxos.iter().collect();
             spent_utxos.sort_by_key(|(outpoint, _)| format!("{outpoint}"));
             egui::Grid::new("bundle_utxos")
@@ -7,7 +7,63 @@ mod gui;
 fn main() -> anyhow::Result<()> {
     let cli = cli::Cli::parse();
     let config = cli.get_config()?;
-    let app = app::App::new(&config)?;
+    if let Some(metrics_addr) = config.metrics_addr {
+        metrics_exporter_prometheus::PrometheusBuilder::new()
+            .with_http_listener(metrics_addr)
+            .install()?;
+    }
+    // Selected before the node opens, the chain id and consensus parameters depend on it.
+    if let Err(selected) = app::lib::consensus::select_network(config.network) {
+        anyhow::bail!("network {selected} is already in use");
+    }
+    let mut app = app::App::new(&config)?;
+    if config.verify {
+        app.node.verify_chain()?;
+        println!("chain verified up to height {}", app.node.get_height()?);
+        return Ok(());
+    }
+    if config.doctor {
+        let mut compatible = true;
+        for (method, check) in app.doctor() {
+            use app::lib::mainchain::Check;
+            compatible &= check.is_supported();
+            match check {
+                Check::Supported => println!("{method}: ok"),
+                Check::Missing => println!("{method}: not supported"),
+                Check::Failed(err) => println!("{method}: {err}"),
+            }
+        }
+        if !compatible {
+            anyhow::bail!("mainchain node at {} is not compatible", config.main_addr);
+        }
+        println!("mainchain node at {} is compatible", config.main_addr);
+        return Ok(());
+    }
+    if let Some(height) = config.rollback {
+        app.rollback_to_height(height)?;
+        println!("rolled back to height {}", app.node.get_height()?);
+        return Ok(());
+    }
+    if config.reset {
+        println!(
+            "this deletes all blocks and chain state in {}{}, type \"yes\" to continue",
+            config.datadir.display(),
+            if config.reset_wallet {
+                " and the wallet's UTXOs"
+            } else {
+                ""
+            },
+        );
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if answer.trim() != "yes" {
+            println!("reset cancelled");
+            return Ok(());
+        }
+        app.reset_chain(config.reset_wallet)?;
+        println!("chain data reset");
+        return Ok(());
+    }
 
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
@@ -0,0 +1,74 @@
+use {{crate_name}}::{
+    authorization::{authorize, get_address},
+    consensus::ConsensusParams,
+    heed,
+    state::State,
+    types::{Body, Content, OutPoint, Output, Transaction},
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+fn keypair(index: u32) -> Keypair {
+    let mut bytes = [0; 32];
+    bytes[..4].copy_from_slice(&index.to_be_bytes());
+    let secret = SecretKey::from_bytes(&bytes).expect("failed to create secret key");
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// Fill the state with `num_transactions` UTXOs and return a body spending all of them.
+fn setup(env: &heed::Env, state: &State, num_transactions: u32) -> Body {
+    let mut txn = env.write_txn().unwrap();
+    let mut transactions = vec![];
+    for index in 0..num_transactions {
+        let keypair = keypair(index);
+        let address = get_address(&keypair.public);
+        let outpoint = OutPoint::Regular {
+            txid: [0; 32].into(),
+            vout: index,
+        };
+        let output = Output {
+            address,
+            content: Content::Value(100),
+        };
+        state.utxos.put(&mut txn, &outpoint, &output).unwrap();
+        let transaction = Transaction {
+            inputs: vec![outpoint],
+            outputs: vec![Output {
+                address,
+                content: Content::Value(90),
+            }],
+        };
+        transactions.push(authorize(&[(address, &keypair)], transaction).unwrap());
+    }
+    txn.commit().unwrap();
+    Body::new(transactions, vec![])
+}
+
+fn validate_body(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate_body");
+    for num_transactions in [1, 10, 100, 1000] {
+        let dir = tempfile::tempdir().unwrap();
+        let env = heed::EnvOpenOptions::new()
+            .map_size(100 * 1024 * 1024) // 100MB
+            .max_dbs(State::NUM_DBS)
+            .open(dir.path())
+            .unwrap();
+        let state = State::new(&env, ConsensusParams::default()).unwrap();
+        let body = setup(&env, &state, num_transactions);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_transactions),
+            &body,
+            |b, body| {
+                b.iter(|| {
+                    let txn = env.read_txn().unwrap();
+                    state.validate_body(&txn, black_box(body)).unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, validate_body);
+criterion_main!(benches);
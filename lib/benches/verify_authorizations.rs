@@ -0,0 +1,78 @@
+use {{crate_name}}::{
+    authorization::{authorize, get_address, verify_authorizations, verify_authorized_transaction},
+    types::{AuthorizedTransaction, Body, Content, OutPoint, Output, Transaction},
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+const NUM_AUTHORIZATIONS: [u32; 4] = [1, 10, 100, 1000];
+
+fn keypair(index: u32) -> Keypair {
+    let mut bytes = [0; 32];
+    bytes[..4].copy_from_slice(&index.to_be_bytes());
+    let secret = SecretKey::from_bytes(&bytes).expect("failed to create secret key");
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// Create a transaction with `num_inputs` inputs, each authorized by a different keypair.
+fn authorized_transaction(seed: u32, num_inputs: u32) -> AuthorizedTransaction {
+    let keypairs: Vec<_> = (0..num_inputs).map(|i| keypair(seed + i)).collect();
+    let addresses_keypairs: Vec<_> = keypairs
+        .iter()
+        .map(|keypair| (get_address(&keypair.public), keypair))
+        .collect();
+    let inputs = (0..num_inputs)
+        .map(|vout| OutPoint::Regular {
+            txid: [0; 32].into(),
+            vout: seed + vout,
+        })
+        .collect();
+    let transaction = Transaction {
+        inputs,
+        outputs: vec![Output {
+            address: addresses_keypairs[0].0,
+            content: Content::Value(seed as u64),
+        }],
+    };
+    authorize(&addresses_keypairs, transaction).unwrap()
+}
+
+fn bench_verify_authorizations(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify_authorizations");
+    for num_authorizations in NUM_AUTHORIZATIONS {
+        // One single input transaction per authorization.
+        let transactions = (0..num_authorizations)
+            .map(|i| authorized_transaction(i, 1))
+            .collect();
+        let body = Body::new(transactions, vec![]);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_authorizations),
+            &body,
+            |b, body| b.iter(|| verify_authorizations(black_box(body)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn bench_verify_authorized_transaction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify_authorized_transaction");
+    for num_authorizations in NUM_AUTHORIZATIONS {
+        let transaction = authorized_transaction(0, num_authorizations);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_authorizations),
+            &transaction,
+            |b, transaction| {
+                b.iter(|| verify_authorized_transaction(black_box(transaction)).unwrap())
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_verify_authorizations,
+    bench_verify_authorized_transaction
+);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+use {{crate_name}}::wallet::{AccountPath, AddressPolicy, Wallet};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const NUM_ADDRESSES: u32 = 1000;
+
+fn wallet() -> (tempfile::TempDir, Wallet) {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = Wallet::new(
+        dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    (dir, wallet)
+}
+
+fn bench_get_new_address(c: &mut Criterion) {
+    c.bench_function("get_new_address x1000", |b| {
+        b.iter_batched(
+            wallet,
+            |(_dir, wallet)| {
+                for _ in 0..NUM_ADDRESSES {
+                    wallet.get_new_address().unwrap();
+                }
+            },
+            criterion::BatchSize::PerIteration,
+        )
+    });
+}
+
+fn bench_get_new_addresses(c: &mut Criterion) {
+    c.bench_function("get_new_addresses(1000)", |b| {
+        b.iter_batched(
+            wallet,
+            |(_dir, wallet)| wallet.get_new_addresses(NUM_ADDRESSES).unwrap(),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+}
+
+criterion_group!(benches, bench_get_new_address, bench_get_new_addresses);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+//! Run a node from async code without blocking the runtime.
+//!
+//! Read methods open their own short lived read transactions, and async methods never hold a
+//! transaction across an `.await`, so both can be called directly from tasks.
+//!
+//! Usage: `cargo run --example async_node -- <datadir> <main_addr> <user> <password>`
+use {{crate_name}}::node::{Node, NodeConfig};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let datadir: PathBuf = args.next().ok_or("missing datadir")?.into();
+    let main_addr: SocketAddr = args.next().ok_or("missing main_addr")?.parse()?;
+    let user = args.next().ok_or("missing user")?;
+    let password = args.next().ok_or("missing password")?;
+    let bind_addr: SocketAddr = "127.0.0.1:4000".parse()?;
+
+    let mut node = Node::new(
+        &datadir,
+        bind_addr,
+        main_addr,
+        &user,
+        &password,
+        NodeConfig::default(),
+    )?;
+    node.run()?;
+
+    loop {
+        let height = node.get_height()?;
+        let syncing = node.is_syncing().await?;
+        let mempool_info = node.get_mempool_info()?;
+        println!(
+            "height {height}, syncing {syncing}, {} transactions in mempool",
+            mempool_info.count
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
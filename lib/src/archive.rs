@@ -1,27 +1,40 @@
 use crate::types::*;
 use crate::types::{hash, BlockHash, Body};
+use crate::versioned::SerdeVersioned;
 use heed::byteorder::{BigEndian, ByteOrder};
 use heed::types::*;
 use heed::{Database, RoTxn, RwTxn};
+use std::ops::RangeInclusive;
 
 #[derive(Clone)]
 pub struct Archive {
-    headers: Database<OwnedType<[u8; 4]>, SerdeBincode<Header>>,
-    bodies: Database<OwnedType<[u8; 4]>, SerdeBincode<Body>>,
+    headers: Database<OwnedType<[u8; 4]>, SerdeVersioned<Header>>,
+    /// Bodies serialized with `compression::serialize_body`.
+    bodies: Database<OwnedType<[u8; 4]>, ByteSlice>,
     hash_to_height: Database<OwnedType<[u8; 32]>, OwnedType<[u8; 4]>>,
+    txid_to_height: Database<OwnedType<[u8; 32]>, OwnedType<[u8; 4]>>,
+    /// Bodies at this height and below have been pruned.
+    pruned_height: Database<OwnedType<u32>, OwnedType<u32>>,
+    /// zstd compression level for stored bodies, `None` stores them uncompressed.
+    compression_level: Option<i32>,
 }
 
 impl Archive {
-    pub const NUM_DBS: u32 = 3;
+    pub const NUM_DBS: u32 = 5;
 
-    pub fn new(env: &heed::Env) -> Result<Self, Error> {
+    pub fn new(env: &heed::Env, compression_level: Option<i32>) -> Result<Self, Error> {
         let headers = env.create_database(Some("headers"))?;
         let bodies = env.create_database(Some("bodies"))?;
         let hash_to_height = env.create_database(Some("hash_to_height"))?;
+        let txid_to_height = env.create_database(Some("txid_to_height"))?;
+        let pruned_height = env.create_database(Some("pruned_height"))?;
         Ok(Self {
             headers,
             bodies,
             hash_to_height,
+            txid_to_height,
+            pruned_height,
+            compression_level,
         })
     }
 
@@ -31,16 +44,119 @@ impl Archive {
         Ok(header)
     }
 
+    /// Returns `Error::Pruned` if the body existed but was pruned, and `None` if it never existed.
     pub fn get_body(&self, txn: &RoTxn, height: u32) -> Result<Option<Body>, Error> {
+        if height != 0 && height <= self.get_pruned_height(txn)? {
+            return Err(Error::Pruned { height });
+        }
         let height = height.to_be_bytes();
-        let header = self.bodies.get(txn, &height)?;
-        Ok(header)
+        let body = match self.bodies.get(txn, &height)? {
+            Some(bytes) => Some(crate::compression::deserialize_body(bytes)?),
+            None => None,
+        };
+        Ok(body)
+    }
+
+    /// Headers in `range` in ascending order of height, heights without a header are skipped.
+    pub fn iter_headers<'txn>(
+        &self,
+        txn: &'txn RoTxn,
+        range: RangeInclusive<u32>,
+    ) -> Result<impl Iterator<Item = Result<(u32, Header), Error>> + 'txn, Error> {
+        let range = range.start().to_be_bytes()..=range.end().to_be_bytes();
+        let headers = self.headers.range(txn, &range)?.map(|item| {
+            let (height, header) = item?;
+            Ok((BigEndian::read_u32(&height), header))
+        });
+        Ok(headers)
+    }
+
+    /// Bodies in `range` in ascending order of height, pruned bodies are skipped.
+    pub fn iter_bodies<'txn>(
+        &self,
+        txn: &'txn RoTxn,
+        range: RangeInclusive<u32>,
+    ) -> Result<impl Iterator<Item = Result<(u32, Body), Error>> + 'txn, Error> {
+        let range = range.start().to_be_bytes()..=range.end().to_be_bytes();
+        let bodies = self.bodies.range(txn, &range)?.map(|item| {
+            let (height, bytes) = item?;
+            let body = crate::compression::deserialize_body(bytes)?;
+            Ok((BigEndian::read_u32(&height), body))
+        });
+        Ok(bodies)
+    }
+
+    pub fn get_height_by_hash(&self, txn: &RoTxn, hash: &BlockHash) -> Result<Option<u32>, Error> {
+        let height = self
+            .hash_to_height
+            .get(txn, &(*hash).into())?
+            .map(|height| BigEndian::read_u32(&height));
+        Ok(height)
+    }
+
+    /// Height of the block that includes the transaction.
+    pub fn get_height_by_txid(&self, txn: &RoTxn, txid: &Txid) -> Result<Option<u32>, Error> {
+        let height = self
+            .txid_to_height
+            .get(txn, txid.into())?
+            .map(|height| BigEndian::read_u32(&height));
+        Ok(height)
+    }
+
+    /// Index the transactions of every stored body if `txid_to_height` is empty, for databases
+    /// written before the index existed. Transactions of pruned bodies can't be indexed. Returns
+    /// the number of indexed transactions.
+    pub fn backfill_txid_index(&self, txn: &mut RwTxn) -> Result<usize, Error> {
+        if !self.txid_to_height.is_empty(txn)? {
+            return Ok(0);
+        }
+        let mut txids = vec![];
+        for item in self.bodies.iter(txn)? {
+            let (height, bytes) = item?;
+            let body = crate::compression::deserialize_body(bytes)?;
+            for transaction in &body.transactions {
+                txids.push((transaction.txid(), height));
+            }
+        }
+        for (txid, height) in &txids {
+            self.txid_to_height.put(txn, &(*txid).into(), height)?;
+        }
+        Ok(txids.len())
+    }
+
+    /// Delete all headers, bodies and indexes.
+    pub fn clear(&self, txn: &mut RwTxn) -> Result<(), Error> {
+        self.headers.clear(txn)?;
+        self.bodies.clear(txn)?;
+        self.hash_to_height.clear(txn)?;
+        self.txid_to_height.clear(txn)?;
+        self.pruned_height.clear(txn)?;
+        Ok(())
+    }
+
+    pub fn get_pruned_height(&self, txn: &RoTxn) -> Result<u32, Error> {
+        Ok(self.pruned_height.get(txn, &0)?.unwrap_or(0))
+    }
+
+    /// Delete bodies older than `current_height - keep_depth`, headers are kept.
+    pub fn prune_bodies(&self, txn: &mut RwTxn, keep_depth: u32) -> Result<(), Error> {
+        let height = self.get_height(txn)?;
+        let pruned_height = self.get_pruned_height(txn)?;
+        let new_pruned_height = height.saturating_sub(keep_depth).saturating_sub(1);
+        if new_pruned_height <= pruned_height {
+            return Ok(());
+        }
+        for height in (pruned_height + 1)..=new_pruned_height {
+            self.bodies.delete(txn, &height.to_be_bytes())?;
+        }
+        self.pruned_height.put(txn, &0, &new_pruned_height)?;
+        Ok(())
     }
 
     pub fn get_best_hash(&self, txn: &RoTxn) -> Result<BlockHash, Error> {
         let best_hash = match self.headers.last(txn)? {
             Some((_, header)) => hash(&header).into(),
-            None => [0; 32].into(),
+            None => crate::consensus::genesis_hash(),
         };
         Ok(best_hash)
     }
@@ -53,6 +169,58 @@ impl Archive {
         Ok(height)
     }
 
+    /// Hashes of blocks going back from the tip, the 10 most recent ones and then exponentially
+    /// spaced, ending with the genesis hash. A peer on a different branch finds the fork point
+    /// from it with `find_fork_point`.
+    pub fn get_block_locator(&self, txn: &RoTxn) -> Result<Vec<BlockHash>, Error> {
+        let mut locator = vec![];
+        let mut height = self.get_height(txn)?;
+        let mut step = 1;
+        while height > 0 {
+            if let Some(header) = self.get_header(txn, height)? {
+                locator.push(header.hash());
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator.push(crate::consensus::genesis_hash());
+        Ok(locator)
+    }
+
+    /// Height of the most recent block of `locator` that is on this chain, 0 if only genesis is
+    /// shared.
+    pub fn find_fork_point(&self, txn: &RoTxn, locator: &[BlockHash]) -> Result<u32, Error> {
+        for hash in locator {
+            if let Some(height) = self.get_height_by_hash(txn, hash)? {
+                return Ok(height);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Up to `max_headers` headers after `height`, up to and including `stop`.
+    pub fn get_headers_after(
+        &self,
+        txn: &RoTxn,
+        height: u32,
+        stop: Option<BlockHash>,
+        max_headers: usize,
+    ) -> Result<Vec<Header>, Error> {
+        let mut headers = vec![];
+        let start = (height + 1).to_be_bytes();
+        for item in self.headers.range(txn, &(start..))?.take(max_headers) {
+            let (_, header) = item?;
+            let hash = header.hash();
+            headers.push(header);
+            if Some(hash) == stop {
+                break;
+            }
+        }
+        Ok(headers)
+    }
+
     pub fn put_body(&self, txn: &mut RwTxn, header: &Header, body: &Body) -> Result<(), Error> {
         if header.merkle_root != body.compute_merkle_root() {
             return Err(Error::InvalidMerkleRoot);
@@ -62,7 +230,12 @@ impl Archive {
             .hash_to_height
             .get(txn, &hash.into())?
             .ok_or(Error::NoHeader(hash))?;
-        self.bodies.put(txn, &height, body)?;
+        for transaction in &body.transactions {
+            self.txid_to_height
+                .put(txn, &transaction.txid().into(), &height)?;
+        }
+        let body = crate::compression::serialize_body(body, self.compression_level)?;
+        self.bodies.put(txn, &height, &body)?;
         Ok(())
     }
 
@@ -72,22 +245,57 @@ impl Archive {
         if header.prev_side_hash != best_hash {
             return Err(Error::InvalidPrevSideHash);
         }
+        if header.height != height + 1 {
+            return Err(Error::InvalidHeight {
+                expected: height + 1,
+                height: header.height,
+            });
+        }
         let new_height = (height + 1).to_be_bytes();
         self.headers.put(txn, &new_height, header)?;
         self.hash_to_height
             .put(txn, &header.hash().into(), &new_height)?;
         Ok(())
     }
+
+    /// Remove the tip header and body, returns them. Fails with `Error::Pruned` if the body was
+    /// pruned.
+    pub fn disconnect_tip(&self, txn: &mut RwTxn) -> Result<Option<(Header, Body)>, Error> {
+        let height = self.get_height(txn)?;
+        let header = match self.get_header(txn, height)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let body = self
+            .get_body(txn, height)?
+            .ok_or(Error::NoBody { height })?;
+        for transaction in &body.transactions {
+            self.txid_to_height
+                .delete(txn, &transaction.txid().into())?;
+        }
+        self.hash_to_height.delete(txn, &header.hash().into())?;
+        self.bodies.delete(txn, &height.to_be_bytes())?;
+        self.headers.delete(txn, &height.to_be_bytes())?;
+        Ok(Some((header, body)))
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("heed error")]
     Heed(#[from] heed::Error),
+    #[error("compression error")]
+    Compression(#[from] crate::compression::Error),
     #[error("invalid previous side hash")]
     InvalidPrevSideHash,
+    #[error("invalid height {height}, expected {expected}")]
+    InvalidHeight { expected: u32, height: u32 },
     #[error("invalid merkle root")]
     InvalidMerkleRoot,
+    #[error("body at height {height} was pruned")]
+    Pruned { height: u32 },
     #[error("no header with hash {0}")]
     NoHeader(BlockHash),
+    #[error("no body at height {height}")]
+    NoBody { height: u32 },
 }
@@ -1,5 +1,5 @@
 use crate::types::blake3;
-use crate::types::{Address, AuthorizedTransaction, Body, GetAddress, Transaction, Verify};
+use crate::types::{Address, AuthorizedTransaction, Body, GetAddress, Transaction, Txid, Verify};
 pub use ed25519_dalek::{Keypair, PublicKey, Signature, SignatureError, Signer, Verifier};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -37,14 +37,8 @@ pub fn get_address(public_key: &PublicKey) -> Address {
     Address(output)
 }
 
-struct Package<'a> {
-    messages: Vec<&'a [u8]>,
-    signatures: Vec<Signature>,
-    public_keys: Vec<PublicKey>,
-}
-
 pub fn verify_authorized_transaction(transaction: &AuthorizedTransaction) -> Result<(), Error> {
-    let serialized_transaction = bincode::serialize(&transaction.transaction)?;
+    let serialized_transaction = signing_message(&transaction.transaction)?;
     let messages: Vec<_> = std::iter::repeat(serialized_transaction.as_slice())
         .take(transaction.authorizations.len())
         .collect();
@@ -70,7 +64,7 @@ pub fn verify_authorizations(body: &Body) -> Result<(), Error> {
     let serialized_transactions: Vec<Vec<u8>> = body
         .transactions
         .par_iter()
-        .map(bincode::serialize)
+        .map(signing_message)
         .collect::<Result<_, _>>()?;
     let serialized_transactions = serialized_transactions.iter().map(Vec::as_slice);
     let messages = input_numbers.zip(serialized_transactions).flat_map(
@@ -81,60 +75,119 @@ pub fn verify_authorizations(body: &Body) -> Result<(), Error> {
 
     let pairs = body.authorizations.iter().zip(messages).collect::<Vec<_>>();
 
+    // Batch verification gets cheaper per signature as the batch grows, so chunks are kept large
+    // enough to amortize it while still spreading the work over all threads.
+    const MIN_CHUNK_SIZE: usize = 64;
     let num_threads = rayon::current_num_threads();
-    let num_authorizations = body.authorizations.len();
-    let package_size = num_authorizations / num_threads;
-    let mut packages: Vec<Package> = Vec::with_capacity(num_threads);
-    for i in 0..num_threads {
-        let mut package = Package {
-            messages: Vec::with_capacity(package_size),
-            signatures: Vec::with_capacity(package_size),
-            public_keys: Vec::with_capacity(package_size),
-        };
-        for (authorization, message) in &pairs[i * package_size..(i + 1) * package_size] {
-            package.messages.push(*message);
-            package.signatures.push(authorization.signature);
-            package.public_keys.push(authorization.public_key);
+    let chunk_size = std::cmp::max(
+        (pairs.len() + num_threads - 1) / num_threads,
+        MIN_CHUNK_SIZE,
+    );
+    let result = pairs.par_chunks(chunk_size).try_for_each(|chunk| {
+        let mut messages = Vec::with_capacity(chunk.len());
+        let mut signatures = Vec::with_capacity(chunk.len());
+        let mut public_keys = Vec::with_capacity(chunk.len());
+        for (authorization, message) in chunk {
+            messages.push(*message);
+            signatures.push(authorization.signature);
+            public_keys.push(authorization.public_key);
         }
-        packages.push(package);
-    }
-    for (authorization, message) in &pairs[num_threads * package_size..] {
-        packages[num_threads - 1].messages.push(*message);
-        packages[num_threads - 1]
-            .signatures
-            .push(authorization.signature);
-        packages[num_threads - 1]
-            .public_keys
-            .push(authorization.public_key);
+        ed25519_dalek::verify_batch(&messages, &signatures, &public_keys)
+    });
+    if let Err(err) = result {
+        // Batch verification doesn't say which signature is invalid, so find it one by one.
+        let inputs = body.transactions.iter().flat_map(|transaction| {
+            let txid = transaction.txid();
+            (0..transaction.inputs.len()).map(move |input| (txid, input))
+        });
+        for ((authorization, message), (txid, input)) in pairs.iter().zip(inputs) {
+            if authorization
+                .public_key
+                .verify(message, &authorization.signature)
+                .is_err()
+            {
+                return Err(Error::InvalidSignature { txid, input });
+            }
+        }
+        return Err(err.into());
     }
-    assert_eq!(
-        packages.iter().map(|p| p.signatures.len()).sum::<usize>(),
-        body.authorizations.len()
-    );
-    packages
-        .par_iter()
-        .map(
-            |Package {
-                 messages,
-                 signatures,
-                 public_keys,
-             }| ed25519_dalek::verify_batch(messages, signatures, public_keys),
-        )
-        .collect::<Result<(), SignatureError>>()?;
     Ok(())
 }
 
+/// Domain tag for transaction signatures, keeps them distinct from signatures over any other
+/// data that happens to serialize identically.
+const TRANSACTION_DOMAIN_TAG: &[u8] = b"sidechain transaction";
+/// Bumped whenever the format of the signed message changes.
+pub const SIGNATURE_SCHEME_VERSION: u8 = 1;
+
+/// Bytes that are signed to authorize a transaction. Commits to the domain tag, the signature
+/// scheme version and the chain id, used by both signing and verification.
+pub fn signing_message(transaction: &Transaction) -> Result<Vec<u8>, Error> {
+    let message = (
+        TRANSACTION_DOMAIN_TAG,
+        SIGNATURE_SCHEME_VERSION,
+        crate::consensus::chain_id(),
+        transaction,
+    );
+    Ok(bincode::serialize(&message)?)
+}
+
 pub fn sign(keypair: &Keypair, transaction: &Transaction) -> Result<Signature, Error> {
-    let message = bincode::serialize(&transaction)?;
+    let message = signing_message(transaction)?;
     Ok(keypair.sign(&message))
 }
 
+/// Signature over an arbitrary message proving control of an address.
+///
+/// Addresses are hashes of public keys, so the public key is needed to verify the signature.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedMessage {
+    pub public_key: PublicKey,
+    pub signature: Signature,
+}
+
+/// Domain separated hash of a message, so that message signatures can never be valid
+/// transaction signatures.
+fn hash_message(message: &[u8]) -> blake3::Hash {
+    const MESSAGE_PREFIX: &[u8] = b"sidechain signed message:";
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(MESSAGE_PREFIX);
+    hasher.update(message);
+    hasher.finalize()
+}
+
+pub fn sign_message(keypair: &Keypair, message: &[u8]) -> SignedMessage {
+    let hash = hash_message(message);
+    SignedMessage {
+        public_key: keypair.public,
+        signature: keypair.sign(hash.as_bytes()),
+    }
+}
+
+pub fn verify_message(
+    address: &Address,
+    message: &[u8],
+    public_key: &PublicKey,
+    signature: &Signature,
+) -> Result<(), Error> {
+    let hash_public_key = get_address(public_key);
+    if *address != hash_public_key {
+        return Err(Error::WrongKeypairForAddress {
+            address: *address,
+            hash_public_key,
+        });
+    }
+    let hash = hash_message(message);
+    public_key.verify(hash.as_bytes(), signature)?;
+    Ok(())
+}
+
 pub fn authorize(
     addresses_keypairs: &[(Address, &Keypair)],
     transaction: Transaction,
 ) -> Result<AuthorizedTransaction, Error> {
     let mut authorizations: Vec<Authorization> = Vec::with_capacity(addresses_keypairs.len());
-    let message = bincode::serialize(&transaction)?;
+    let message = signing_message(&transaction)?;
     for (address, keypair) in addresses_keypairs {
         let hash_public_key = get_address(&keypair.public);
         if *address != hash_public_key {
@@ -164,6 +217,8 @@ pub enum Error {
         address: Address,
         hash_public_key: Address,
     },
+    #[error("invalid signature for input {input} of transaction {txid}")]
+    InvalidSignature { txid: Txid, input: usize },
     #[error("ed25519_dalek error")]
     DalekError(#[from] SignatureError),
     #[error("bincode error")]
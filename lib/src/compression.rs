@@ -0,0 +1,77 @@
+use crate::types::Body;
+use std::io::Read as _;
+
+/// Body serialized with bincode and stored as is.
+const UNCOMPRESSED: u8 = 0xfe;
+/// Body serialized with bincode and compressed with zstd.
+const ZSTD: u8 = 0xff;
+
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+/// Maximum size of a decompressed body, larger bodies are rejected instead of being inflated.
+pub const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Serialize a body prefixed with a flag byte, `None` disables compression.
+pub fn serialize_body(body: &Body, compression_level: Option<i32>) -> Result<Vec<u8>, Error> {
+    let serialized = bincode::serialize(body)?;
+    let bytes = match compression_level {
+        Some(level) => {
+            let compressed = zstd::encode_all(serialized.as_slice(), level)?;
+            [vec![ZSTD], compressed].concat()
+        }
+        None => [vec![UNCOMPRESSED], serialized].concat(),
+    };
+    Ok(bytes)
+}
+
+/// Deserialize a body written by `serialize_body`, bodies written before the flag byte was
+/// introduced are plain bincode and still decode.
+pub fn deserialize_body(bytes: &[u8]) -> Result<Body, Error> {
+    let body = match bytes.split_first() {
+        Some((&ZSTD, compressed)) => {
+            decompress(compressed).and_then(|serialized| Ok(bincode::deserialize(&serialized)?))
+        }
+        Some((&UNCOMPRESSED, serialized)) => Ok(bincode::deserialize(serialized)?),
+        _ => return Ok(bincode::deserialize(bytes)?),
+    };
+    // A legacy body can start with a byte equal to one of the flags.
+    body.or_else(|err| bincode::deserialize(bytes).map_err(|_| err))
+}
+
+/// Decompress at most `MAX_BODY_SIZE` bytes.
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut serialized = vec![];
+    zstd::Decoder::new(compressed)?
+        .take(MAX_BODY_SIZE as u64 + 1)
+        .read_to_end(&mut serialized)?;
+    if serialized.len() > MAX_BODY_SIZE {
+        return Err(Error::TooLarge { max: MAX_BODY_SIZE });
+    }
+    Ok(serialized)
+}
+
+/// Serde adapter sending bodies compressed over the wire.
+pub mod body {
+    use super::*;
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &Body, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes =
+            serialize_body(body, Some(DEFAULT_COMPRESSION_LEVEL)).map_err(S::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Body, D::Error> {
+        let bytes: Vec<u8> = Deserialize::deserialize(deserializer)?;
+        deserialize_body(&bytes).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("bincode error")]
+    Bincode(#[from] bincode::Error),
+    #[error("decompressed body is larger than {max} bytes")]
+    TooLarge { max: usize },
+}
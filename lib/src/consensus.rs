@@ -0,0 +1,118 @@
+use crate::types::{hash, BlockHash, Hash};
+use bip300301::bitcoin;
+use std::sync::OnceLock;
+
+/// Mainchain network this sidechain is deployed on, see `network`.
+static NETWORK: OnceLock<bitcoin::Network> = OnceLock::new();
+
+/// Select the mainchain network this sidechain is deployed on, before anything is signed or
+/// validated. Fails with the network in use if another one was already selected or used, so that
+/// the chain id can't change while running.
+pub fn select_network(network: bitcoin::Network) -> Result<(), bitcoin::Network> {
+    let selected = *NETWORK.get_or_init(|| network);
+    if selected != network {
+        return Err(selected);
+    }
+    Ok(())
+}
+
+/// Mainchain network this sidechain is deployed on, regtest unless another one was selected with
+/// `select_network`.
+pub fn network() -> bitcoin::Network {
+    *NETWORK.get_or_init(|| bitcoin::Network::Regtest)
+}
+
+/// Blocks after which a coinbase output can be spent, an output created at height `h` can be
+/// spent from height `h + COINBASE_MATURITY` on.
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Whether a coinbase output created at `utxo_height` can be spent in the block at `height`.
+pub fn is_coinbase_mature(utxo_height: u32, height: u32) -> bool {
+    height.saturating_sub(utxo_height) >= COINBASE_MATURITY
+}
+
+/// Identifies this sidechain deployment. Every signature commits to it, so transactions can't be
+/// replayed on another sidechain or network.
+pub fn chain_id() -> Hash {
+    hash(&(crate::node::THIS_SIDECHAIN, network().to_string()))
+}
+
+/// Previous side hash of the first block.
+pub fn genesis_hash() -> BlockHash {
+    hash(&("genesis", chain_id())).into()
+}
+
+/// Consensus parameters shared by block validation, withdrawal bundle creation and mining.
+#[derive(Debug, Clone)]
+pub struct ConsensusParams {
+    /// Number of blocks to wait after a failed withdrawal bundle before creating a new one.
+    pub withdrawal_bundle_failure_gap: u32,
+    /// Weight of a withdrawal bundle with 0 outputs.
+    pub bundle_0_weight: u64,
+    /// Weight of a single withdrawal bundle output.
+    pub bundle_output_weight: u64,
+    /// Maximum weight of a withdrawal bundle transaction.
+    pub max_bundle_weight: u64,
+    /// Maximum number of coinbase outputs in a block.
+    pub max_coinbase_outputs: usize,
+}
+
+impl ConsensusParams {
+    /// Parameters of the deployment on `network`.
+    pub fn for_network(network: bitcoin::Network) -> Self {
+        match network {
+            bitcoin::Network::Bitcoin => Self::mainnet(),
+            bitcoin::Network::Testnet => Self::testnet(),
+            bitcoin::Network::Signet => Self::signet(),
+            // Regtest, and networks added to `bitcoin` later.
+            _ => Self::regtest(),
+        }
+    }
+
+    /// Waits about a week of blocks after a failed withdrawal bundle.
+    pub fn mainnet() -> Self {
+        Self {
+            withdrawal_bundle_failure_gap: 1008,
+            ..Self::regtest()
+        }
+    }
+
+    /// Waits about a day of blocks after a failed withdrawal bundle.
+    pub fn testnet() -> Self {
+        Self {
+            withdrawal_bundle_failure_gap: 144,
+            ..Self::regtest()
+        }
+    }
+
+    /// Waits about a day of blocks after a failed withdrawal bundle.
+    pub fn signet() -> Self {
+        Self {
+            withdrawal_bundle_failure_gap: 144,
+            ..Self::regtest()
+        }
+    }
+
+    /// Short failure gap, so that tests don't have to mine many blocks between bundles.
+    pub fn regtest() -> Self {
+        Self {
+            withdrawal_bundle_failure_gap: 4,
+            bundle_0_weight: 504,
+            bundle_output_weight: 128,
+            max_bundle_weight: bitcoin::policy::MAX_STANDARD_TX_WEIGHT as u64,
+            max_coinbase_outputs: 16,
+        }
+    }
+
+    /// Maximum number of outputs in a withdrawal bundle, 3121 with the regtest parameters.
+    pub fn max_bundle_outputs(&self) -> usize {
+        ((self.max_bundle_weight - self.bundle_0_weight) / self.bundle_output_weight) as usize
+    }
+}
+
+/// Parameters of the selected network, see `network`.
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self::for_network(network())
+    }
+}
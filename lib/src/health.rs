@@ -0,0 +1,62 @@
+//! Liveness and readiness checks over plain HTTP, served separately from RPC so that container
+//! orchestrators can restart a wedged node.
+use crate::mainchain::Mainchain;
+use crate::node::Node;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Responds 200 while the node is alive, see `Node::is_alive`, and 503 otherwise.
+pub const HEALTH_PATH: &str = "/health";
+/// Responds 200 while the node is alive and synced with its peers, see `Node::is_ready`, and 503
+/// otherwise.
+pub const READY_PATH: &str = "/ready";
+
+const REQUEST_LIMIT: usize = 1024;
+
+pub(crate) async fn serve<M: Mainchain>(node: Node<M>, listener: TcpListener) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                println!("failed to accept health check connection: {err:?}");
+                continue;
+            }
+        };
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(err) = respond(&node, stream).await {
+                println!("failed to respond to health check: {err:?}");
+            }
+        });
+    }
+}
+
+async fn respond<M: Mainchain>(node: &Node<M>, mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = [0; REQUEST_LIMIT];
+    let len = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..len]);
+    // Request line is `GET <path> HTTP/1.1`.
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split(' ').nth(1));
+    let status = match path {
+        Some(HEALTH_PATH) => node.is_alive(),
+        Some(READY_PATH) => node.is_ready().await,
+        _ => {
+            return write_status(&mut stream, "404 Not Found").await;
+        }
+    };
+    let status = if status {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    };
+    write_status(&mut stream, status).await
+}
+
+async fn write_status(stream: &mut TcpStream, status: &str) -> std::io::Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -1,15 +1,22 @@
 pub mod archive;
 pub mod authorization;
+pub mod compression;
+pub mod consensus;
+pub mod health;
+pub mod mainchain;
 pub mod mempool;
 pub mod miner;
 pub mod net;
 pub mod node;
 pub mod state;
+#[cfg(feature = "testutil")]
+pub mod testutil;
 pub mod types;
+pub mod versioned;
 pub mod wallet;
 
-pub use heed;
 pub use bip300301;
+pub use heed;
 
 /// Format `str_dest` with the proper `s{sidechain_number}_` prefix and a
 /// checksum postfix for calling createsidechaindeposit on mainchain.
@@ -0,0 +1,320 @@
+use crate::types::BlockHash;
+use bip300301::{bitcoin, Drivechain, MainClient, TwoWayPegData, WithdrawalBundleStatus};
+use std::collections::HashMap;
+use std::str::FromStr as _;
+
+/// Deposits keyed by mainchain outpoint, and the hash of the last mainchain block they were
+/// read from.
+pub type Deposits = (
+    HashMap<bitcoin::OutPoint, bip300301::Output>,
+    Option<bitcoin::BlockHash>,
+);
+
+/// Mainchain RPC methods the node depends on, checked by `doctor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RpcMethod {
+    GetBestBlockHash,
+    ListSidechainDepositsByBlock,
+    CreateBmmCriticalDataTx,
+    VerifyBmm,
+}
+
+impl RpcMethod {
+    pub const ALL: [Self; 4] = [
+        Self::GetBestBlockHash,
+        Self::ListSidechainDepositsByBlock,
+        Self::CreateBmmCriticalDataTx,
+        Self::VerifyBmm,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::GetBestBlockHash => "getbestblockhash",
+            Self::ListSidechainDepositsByBlock => "listsidechaindepositsbyblock",
+            Self::CreateBmmCriticalDataTx => "createbmmcriticaldatatx",
+            Self::VerifyBmm => "verifybmm",
+        }
+    }
+}
+
+impl std::fmt::Display for RpcMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Outcome of checking a single RPC method, see `doctor`.
+#[derive(Debug)]
+pub enum Check {
+    Supported,
+    /// The mainchain node doesn't know the method.
+    Missing,
+    Failed(Error),
+}
+
+impl Check {
+    fn new<T>(result: Result<T, Error>, expect_rejection: bool) -> Self {
+        match result {
+            Ok(_) => Self::Supported,
+            Err(Error::MethodNotFound(_)) => Self::Missing,
+            Err(_) if expect_rejection => Self::Supported,
+            Err(err) => Self::Failed(err),
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        matches!(self, Self::Supported)
+    }
+}
+
+/// Call every RPC method in `RpcMethod::ALL` with arguments that can't change mainchain state,
+/// and report which of them the mainchain node doesn't support.
+///
+/// `getbestblockhash` is checked first, if it fails the mainchain node is unreachable or rejected
+/// our credentials, and the other methods aren't checked. `createbmmcriticaldatatx` and
+/// `verifybmm` are called with arguments any compatible node rejects, so an error other than an
+/// unknown method counts as supported.
+pub async fn doctor<M: Mainchain>(mainchain: &M) -> Vec<(RpcMethod, Check)> {
+    use bitcoin::hashes::Hash as _;
+    let tip = match mainchain.get_mainchain_tip().await {
+        Ok(tip) => tip,
+        Err(err) => {
+            return vec![(
+                RpcMethod::GetBestBlockHash,
+                Check::new(Err::<(), _>(err), false),
+            )]
+        }
+    };
+    let deposits = mainchain.get_deposit_outputs(tip, Some(tip)).await;
+    // Zero amount, an invalid sidechain number and a previous block that isn't the tip.
+    let bmm_request = mainchain
+        .attempt_bmm(
+            0,
+            0,
+            BlockHash::default(),
+            u8::MAX,
+            bitcoin::BlockHash::all_zeros(),
+        )
+        .await;
+    let bmm_verification = mainchain.verify_bmm(&tip, BlockHash::default()).await;
+    vec![
+        (RpcMethod::GetBestBlockHash, Check::Supported),
+        (
+            RpcMethod::ListSidechainDepositsByBlock,
+            Check::new(deposits, false),
+        ),
+        (
+            RpcMethod::CreateBmmCriticalDataTx,
+            Check::new(bmm_request, true),
+        ),
+        (RpcMethod::VerifyBmm, Check::new(bmm_verification, true)),
+    ]
+}
+
+/// Mainchain operations needed by the node and the miner.
+///
+/// Implemented by `Drivechain`, which talks to a mainchain node over RPC, and by
+/// `testutil::MockMainchain`, which can be scripted in memory.
+#[async_trait::async_trait]
+pub trait Mainchain: Clone + Send + Sync + 'static {
+    async fn get_mainchain_tip(&self) -> Result<bitcoin::BlockHash, Error>;
+
+    /// Deposits since `start`, up to `end`.
+    async fn get_deposit_outputs(
+        &self,
+        end: bitcoin::BlockHash,
+        start: Option<bitcoin::BlockHash>,
+    ) -> Result<Deposits, Error>;
+
+    async fn get_withdrawal_bundle_statuses(
+        &self,
+    ) -> Result<HashMap<bitcoin::Txid, WithdrawalBundleStatus>, Error>;
+
+    /// Deposits and withdrawal bundle statuses since `start`, up to `end`.
+    ///
+    /// This is on the critical path of `Node::submit_block`, so the two independent queries run
+    /// concurrently.
+    async fn get_two_way_peg_data(
+        &self,
+        end: bitcoin::BlockHash,
+        start: Option<bitcoin::BlockHash>,
+    ) -> Result<TwoWayPegData, Error> {
+        let ((deposits, deposit_block_hash), bundle_statuses) = tokio::try_join!(
+            self.get_deposit_outputs(end, start),
+            self.get_withdrawal_bundle_statuses(),
+        )?;
+        Ok(TwoWayPegData {
+            deposits,
+            deposit_block_hash,
+            bundle_statuses,
+        })
+    }
+
+    async fn broadcast_withdrawal_bundle(
+        &self,
+        transaction: bitcoin::Transaction,
+    ) -> Result<(), Error>;
+
+    /// Create a BMM request committing to `critical_hash` on top of `prev_main_hash`.
+    async fn attempt_bmm(
+        &self,
+        amount: u64,
+        height: u32,
+        critical_hash: BlockHash,
+        sidechain_number: u8,
+        prev_main_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::Txid, Error>;
+
+    /// Check that the block with `critical_hash` was BMMed in the block after `prev_main_hash`.
+    async fn verify_bmm(
+        &self,
+        prev_main_hash: &bitcoin::BlockHash,
+        critical_hash: BlockHash,
+    ) -> Result<(), Error>;
+
+    /// Mine mainchain blocks, only works on regtest.
+    async fn generate(&self, blocks: u32) -> Result<(), Error>;
+
+    /// Confirmations of a mainchain block, 1 for the tip. `None` if it isn't on the best chain.
+    async fn get_block_confirmations(
+        &self,
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<Option<u32>, Error>;
+}
+
+#[async_trait::async_trait]
+impl Mainchain for Drivechain {
+    async fn get_mainchain_tip(&self) -> Result<bitcoin::BlockHash, Error> {
+        Ok(Drivechain::get_mainchain_tip(self).await?)
+    }
+
+    async fn get_deposit_outputs(
+        &self,
+        end: bitcoin::BlockHash,
+        start: Option<bitcoin::BlockHash>,
+    ) -> Result<Deposits, Error> {
+        Drivechain::get_deposit_outputs(self, end, start)
+            .await
+            .map_err(|err| Error::rpc(RpcMethod::ListSidechainDepositsByBlock, err))
+    }
+
+    async fn get_withdrawal_bundle_statuses(
+        &self,
+    ) -> Result<HashMap<bitcoin::Txid, WithdrawalBundleStatus>, Error> {
+        Ok(Drivechain::get_withdrawal_bundle_statuses(self).await?)
+    }
+
+    async fn broadcast_withdrawal_bundle(
+        &self,
+        transaction: bitcoin::Transaction,
+    ) -> Result<(), Error> {
+        Drivechain::broadcast_withdrawal_bundle(self, transaction).await?;
+        Ok(())
+    }
+
+    async fn attempt_bmm(
+        &self,
+        amount: u64,
+        height: u32,
+        critical_hash: BlockHash,
+        sidechain_number: u8,
+        prev_main_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::Txid, Error> {
+        use bitcoin::hashes::Hash as _;
+        let str_hash_prev = prev_main_hash.to_string();
+        let critical_hash: [u8; 32] = critical_hash.into();
+        let critical_hash = bitcoin::BlockHash::from_byte_array(critical_hash);
+        let value = self
+            .client
+            .createbmmcriticaldatatx(
+                bitcoin::Amount::from_sat(amount).into(),
+                height,
+                &critical_hash,
+                sidechain_number,
+                &str_hash_prev[str_hash_prev.len() - 8..],
+            )
+            .await
+            .map_err(|err| Error::rpc(RpcMethod::CreateBmmCriticalDataTx, err.into()))?;
+        let txid =
+            bitcoin::Txid::from_str(value["txid"]["txid"].as_str().ok_or(Error::InvalidJson)?)
+                .map_err(bip300301::Error::from)?;
+        Ok(txid)
+    }
+
+    async fn verify_bmm(
+        &self,
+        prev_main_hash: &bitcoin::BlockHash,
+        critical_hash: BlockHash,
+    ) -> Result<(), Error> {
+        let critical_hash = critical_hash.into();
+        Drivechain::verify_bmm(self, prev_main_hash, &critical_hash)
+            .await
+            .map_err(|err| match err {
+                bip300301::Error::NoNextBlock { prev_main_hash } => {
+                    Error::NoNextBlock { prev_main_hash }
+                }
+                err => Error::rpc(RpcMethod::VerifyBmm, err),
+            })?;
+        Ok(())
+    }
+
+    async fn generate(&self, blocks: u32) -> Result<(), Error> {
+        self.client
+            .generate(blocks)
+            .await
+            .map_err(bip300301::Error::from)?;
+        Ok(())
+    }
+
+    async fn get_block_confirmations(
+        &self,
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<Option<u32>, Error> {
+        let block = self
+            .client
+            .getblock(block_hash, Some(1))
+            .await
+            .map_err(bip300301::Error::from)?;
+        // Blocks that aren't on the best chain have -1 confirmations.
+        let confirmations = block["confirmations"].as_i64().ok_or(Error::InvalidJson)?;
+        Ok(u32::try_from(confirmations)
+            .ok()
+            .filter(|confirmations| *confirmations > 0))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("drivechain error")]
+    Drivechain(#[from] bip300301::Error),
+    #[error("invalid json")]
+    InvalidJson,
+    #[error("mainchain node doesn't support the {0} RPC method")]
+    MethodNotFound(RpcMethod),
+    /// Nothing has been mined on top of `prev_main_hash` yet, so BMM requests built on it can't
+    /// be confirmed yet either.
+    #[error("no mainchain block on top of {prev_main_hash} yet")]
+    NoNextBlock { prev_main_hash: bitcoin::BlockHash },
+    #[error("block {critical_hash} was not bmmed on top of {prev_main_hash}")]
+    NotBmmVerified {
+        prev_main_hash: bitcoin::BlockHash,
+        critical_hash: BlockHash,
+    },
+}
+
+impl Error {
+    /// Tell a method the mainchain node doesn't know apart from other failures of `method`.
+    fn rpc(method: RpcMethod, err: bip300301::Error) -> Self {
+        // JSON-RPC reports unknown methods with code -32601, which jsonrpsee calls
+        // `MethodNotFound`.
+        let debug = format!("{err:?}");
+        if debug.contains("-32601")
+            || debug.contains("MethodNotFound")
+            || debug.contains("Method not found")
+        {
+            Self::MethodNotFound(method)
+        } else {
+            Self::Drivechain(err)
+        }
+    }
+}
@@ -1,46 +1,245 @@
 use crate::types::{AuthorizedTransaction, OutPoint, Txid};
+use crate::versioned::SerdeVersioned;
 use heed::types::*;
 use heed::{Database, RoTxn, RwTxn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Clone)]
 pub struct MemPool {
-    pub transactions: Database<OwnedType<[u8; 32]>, SerdeBincode<AuthorizedTransaction>>,
+    pub transactions: Database<OwnedType<[u8; 32]>, SerdeVersioned<AuthorizedTransaction>>,
     pub spent_utxos: Database<SerdeBincode<OutPoint>, Unit>,
+    /// Fee and serialized size of each transaction, recorded when it is added.
+    pub fees: Database<OwnedType<[u8; 32]>, SerdeVersioned<(u64, u64)>>,
+    /// Height of the chain tip when each transaction was added.
+    pub heights: Database<OwnedType<[u8; 32]>, OwnedType<u32>>,
+    /// Minimum fee rate in sats per byte for accepting a transaction.
+    min_relay_fee_rate: u64,
 }
 
 impl MemPool {
-    pub const NUM_DBS: u32 = 2;
+    pub const NUM_DBS: u32 = 4;
 
-    pub fn new(env: &heed::Env) -> Result<Self, Error> {
+    pub const DEFAULT_MIN_RELAY_FEE_RATE: u64 = 1;
+
+    pub fn new(env: &heed::Env, min_relay_fee_rate: u64) -> Result<Self, Error> {
         let transactions = env.create_database(Some("transactions"))?;
         let spent_utxos = env.create_database(Some("spent_utxos"))?;
+        let fees = env.create_database(Some("fees"))?;
+        let heights = env.create_database(Some("heights"))?;
         Ok(Self {
             transactions,
             spent_utxos,
+            fees,
+            heights,
+            min_relay_fee_rate,
         })
     }
 
-    pub fn put(&self, txn: &mut RwTxn, transaction: &AuthorizedTransaction) -> Result<(), Error> {
+    /// Add a validated transaction paying `fee` to the mempool, `height` is the current chain
+    /// height.
+    pub fn put(
+        &self,
+        txn: &mut RwTxn,
+        transaction: &AuthorizedTransaction,
+        fee: u64,
+        height: u32,
+    ) -> Result<(), Error> {
+        let size = transaction.serialized_size();
+        let min_fee = self
+            .min_relay_fee_rate
+            .checked_mul(size)
+            .ok_or(Error::MinFeeOverflow { size })?;
+        if fee < min_fee {
+            return Err(Error::FeeTooLow { fee, min_fee });
+        }
         println!(
             "adding transaction {} to mempool",
             transaction.transaction.txid()
         );
+        let mut inputs = HashSet::with_capacity(transaction.transaction.inputs.len());
+        for input in &transaction.transaction.inputs {
+            if !inputs.insert(input) {
+                return Err(Error::DuplicateInput { outpoint: *input });
+            }
+        }
         for input in &transaction.transaction.inputs {
             if self.spent_utxos.get(txn, input)?.is_some() {
                 return Err(Error::UtxoDoubleSpent);
             }
             self.spent_utxos.put(txn, input, &())?;
         }
-        self.transactions
-            .put(txn, &transaction.transaction.txid().into(), &transaction)?;
+        let txid = transaction.transaction.txid().into();
+        self.transactions.put(txn, &txid, &transaction)?;
+        self.fees.put(txn, &txid, &(fee, size))?;
+        self.heights.put(txn, &txid, &height)?;
         Ok(())
     }
 
     pub fn delete(&self, txn: &mut RwTxn, txid: &Txid) -> Result<(), Error> {
+        if let Some(transaction) = self.transactions.get(txn, txid.into())? {
+            for input in &transaction.transaction.inputs {
+                self.spent_utxos.delete(txn, input)?;
+            }
+        }
         self.transactions.delete(txn, txid.into())?;
+        self.fees.delete(txn, txid.into())?;
+        self.heights.delete(txn, txid.into())?;
+        Ok(())
+    }
+
+    /// Delete transactions added more than `ttl` blocks before `height`, returns their txids.
+    /// Transactions without a recorded height are treated as added at height 0.
+    pub fn expire(&self, txn: &mut RwTxn, height: u32, ttl: u32) -> Result<Vec<Txid>, Error> {
+        let mut expired = vec![];
+        for item in self.transactions.iter(txn)? {
+            let (txid, _) = item?;
+            let added_height = self.heights.get(txn, &txid)?.unwrap_or(0);
+            if height.saturating_sub(added_height) > ttl {
+                expired.push(Txid::from(txid));
+            }
+        }
+        for txid in &expired {
+            self.delete(txn, txid)?;
+        }
+        Ok(expired)
+    }
+
+    /// Delete transactions spending utxos that are no longer in the UTXO set, returns their txids.
+    pub fn remove_conflicts(
+        &self,
+        txn: &mut RwTxn,
+        state: &crate::state::State,
+    ) -> Result<Vec<Txid>, Error> {
+        let mut conflicts = vec![];
+        for item in self.transactions.iter(txn)? {
+            let (_, transaction) = item?;
+            for input in &transaction.transaction.inputs {
+                // Read the database directly so that the UTXO cache isn't filled with values from
+                // an uncommitted write transaction.
+                if state.utxos.get(txn, input)?.is_none() {
+                    conflicts.push(transaction.transaction.txid());
+                    break;
+                }
+            }
+        }
+        for txid in &conflicts {
+            self.delete(txn, txid)?;
+        }
+        Ok(conflicts)
+    }
+
+    /// Delete all transactions.
+    pub fn clear(&self, txn: &mut RwTxn) -> Result<(), Error> {
+        self.transactions.clear(txn)?;
+        self.spent_utxos.clear(txn)?;
+        self.fees.clear(txn)?;
+        self.heights.clear(txn)?;
         Ok(())
     }
 
+    /// Rebuild `spent_utxos` from `transactions`, dropping markers left behind by a crash between
+    /// writes. Transactions spending outputs that are no longer UTXOs, or already spent by another
+    /// mempool transaction, are deleted and their txids returned.
+    pub fn reconcile(
+        &self,
+        txn: &mut RwTxn,
+        state: &crate::state::State,
+    ) -> Result<Vec<Txid>, Error> {
+        let mut transactions = vec![];
+        for item in self.transactions.iter(txn)? {
+            let (_, transaction) = item?;
+            transactions.push(transaction);
+        }
+        let mut spent_utxos = HashSet::new();
+        let mut removed = vec![];
+        for transaction in &transactions {
+            let mut valid = true;
+            for input in &transaction.transaction.inputs {
+                // Read the database directly, see `remove_conflicts`.
+                if state.utxos.get(txn, input)?.is_none() || spent_utxos.contains(input) {
+                    valid = false;
+                    break;
+                }
+            }
+            if valid {
+                spent_utxos.extend(transaction.transaction.inputs.iter().copied());
+            } else {
+                removed.push(transaction.transaction.txid());
+            }
+        }
+        for txid in &removed {
+            self.delete(txn, txid)?;
+        }
+        self.spent_utxos.clear(txn)?;
+        for outpoint in &spent_utxos {
+            self.spent_utxos.put(txn, outpoint, &())?;
+        }
+        Ok(removed)
+    }
+
+    /// Transaction with `txid`, `None` if it isn't in the mempool.
+    pub fn get(&self, txn: &RoTxn, txid: &Txid) -> Result<Option<AuthorizedTransaction>, Error> {
+        Ok(self.transactions.get(txn, txid.into())?)
+    }
+
+    /// Number of transactions in the mempool.
+    pub fn len(&self, txn: &RoTxn) -> Result<u64, Error> {
+        Ok(self.transactions.len(txn)?)
+    }
+
+    pub fn get_info(&self, txn: &RoTxn) -> Result<MempoolInfo, Error> {
+        let mut total_fees: u64 = 0;
+        let mut total_size: u64 = 0;
+        let mut fee_rates = vec![];
+        for item in self.fees.iter(txn)? {
+            let (_, (fee, size)) = item?;
+            total_fees += fee;
+            total_size += size;
+            fee_rates.push(fee / size);
+        }
+        fee_rates.sort_unstable();
+        Ok(MempoolInfo {
+            count: fee_rates.len() as u64,
+            total_fees,
+            total_size,
+            min_fee_rate: fee_rates.first().copied(),
+            max_fee_rate: fee_rates.last().copied(),
+            median_fee_rate: fee_rates.get(fee_rates.len() / 2).copied(),
+        })
+    }
+
+    /// Txids of all transactions, with their details if `verbose` is set. Fees and sizes are
+    /// read from the values recorded when each transaction was added.
+    pub fn get_raw(&self, txn: &RoTxn, verbose: bool) -> Result<RawMempool, Error> {
+        if !verbose {
+            let mut txids = vec![];
+            for item in self
+                .transactions
+                .remap_data_type::<DecodeIgnore>()
+                .iter(txn)?
+            {
+                let (txid, ()) = item?;
+                txids.push(Txid::from(txid));
+            }
+            return Ok(RawMempool::Txids(txids));
+        }
+        let mut entries = vec![];
+        for item in self.transactions.iter(txn)? {
+            let (txid, transaction) = item?;
+            let (fee, size) = self.fees.get(txn, &txid)?.unwrap_or_default();
+            entries.push(MempoolEntry {
+                txid: Txid::from(txid),
+                size,
+                fee,
+                fee_rate: fee.checked_div(size).unwrap_or(0),
+                inputs: transaction.transaction.inputs,
+                height: self.heights.get(txn, &txid)?,
+            });
+        }
+        Ok(RawMempool::Verbose(entries))
+    }
+
     pub fn take(&self, txn: &RoTxn, number: usize) -> Result<Vec<AuthorizedTransaction>, Error> {
         let mut transactions = vec![];
         for item in self.transactions.iter(txn)?.take(number) {
@@ -60,10 +259,47 @@ impl MemPool {
     }
 }
 
+/// Aggregate statistics over the mempool, fee rates are in sats per byte.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolInfo {
+    pub count: u64,
+    pub total_fees: u64,
+    pub total_size: u64,
+    pub min_fee_rate: Option<u64>,
+    pub max_fee_rate: Option<u64>,
+    pub median_fee_rate: Option<u64>,
+}
+
+/// Contents of the mempool as returned by `MemPool::get_raw`, ordered by txid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RawMempool {
+    Txids(Vec<Txid>),
+    Verbose(Vec<MempoolEntry>),
+}
+
+/// A mempool transaction, the fee rate is in sats per byte rounded down.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolEntry {
+    pub txid: Txid,
+    pub size: u64,
+    pub fee: u64,
+    pub fee_rate: u64,
+    pub inputs: Vec<OutPoint>,
+    /// Height of the chain tip when the transaction was added, `None` if it wasn't recorded.
+    pub height: Option<u32>,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("heed error")]
     Heed(#[from] heed::Error),
+    #[error("can't add transaction, fee {fee} is lower than minimum relay fee {min_fee}")]
+    FeeTooLow { fee: u64, min_fee: u64 },
+    #[error("can't add transaction, minimum relay fee for {size} bytes overflows")]
+    MinFeeOverflow { size: u64 },
     #[error("can't add transaction, utxo double spent")]
     UtxoDoubleSpent,
+    #[error("can't add transaction, utxo {outpoint} is spent more than once")]
+    DuplicateInput { outpoint: OutPoint },
 }
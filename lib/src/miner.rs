@@ -1,15 +1,14 @@
+use crate::mainchain::Mainchain;
 use crate::types::*;
-use bip300301::bitcoin;
 use bip300301::Drivechain;
-use bitcoin::hashes::Hash as _;
 use std::net::SocketAddr;
-use std::str::FromStr as _;
+use std::time::{Duration, Instant};
 
 pub use bip300301::MainClient;
 
 #[derive(Clone)]
-pub struct Miner {
-    pub drivechain: Drivechain,
+pub struct Miner<M = Drivechain> {
+    pub mainchain: M,
     block: Option<(Header, Body)>,
     sidechain_number: u8,
 }
@@ -22,19 +21,21 @@ impl Miner {
         password: &str,
     ) -> Result<Self, Error> {
         let drivechain = Drivechain::new(sidechain_number, main_addr, user, password)?;
-        Ok(Self {
-            drivechain,
+        Ok(Self::with_mainchain(sidechain_number, drivechain))
+    }
+}
+
+impl<M: Mainchain> Miner<M> {
+    pub fn with_mainchain(sidechain_number: u8, mainchain: M) -> Self {
+        Self {
+            mainchain,
             sidechain_number,
             block: None,
-        })
+        }
     }
 
     pub async fn generate(&self) -> Result<(), Error> {
-        self.drivechain
-            .client
-            .generate(1)
-            .await
-            .map_err(bip300301::Error::from)?;
+        self.mainchain.generate(1).await?;
         Ok(())
     }
 
@@ -45,44 +46,94 @@ impl Miner {
         header: Header,
         body: Body,
     ) -> Result<(), Error> {
-        let str_hash_prev = header.prev_main_hash.to_string();
-        let critical_hash: [u8; 32] = header.hash().into();
-        let critical_hash = bitcoin::BlockHash::from_byte_array(critical_hash);
-        let value = self
-            .drivechain
-            .client
-            .createbmmcriticaldatatx(
-                bitcoin::Amount::from_sat(amount).into(),
+        self.mainchain
+            .attempt_bmm(
+                amount,
                 height,
-                &critical_hash,
+                header.hash(),
                 self.sidechain_number,
-                &str_hash_prev[str_hash_prev.len() - 8..],
+                header.prev_main_hash,
             )
-            .await
-            .map_err(bip300301::Error::from)?;
-        bitcoin::Txid::from_str(value["txid"]["txid"].as_str().ok_or(Error::InvalidJson)?)
-            .map_err(bip300301::Error::from)?;
+            .await?;
         assert_eq!(header.merkle_root, body.compute_merkle_root());
         self.block = Some((header, body));
         Ok(())
     }
 
+    /// Returns the pending block once its BMM request is confirmed. Until a mainchain block is
+    /// mined on top of the one the request was built on, the block stays pending and `None` is
+    /// returned. A block built on a mainchain block that has been reorged out can never be
+    /// confirmed, so it's abandoned.
     pub async fn confirm_bmm(&mut self) -> Result<Option<(Header, Body)>, Error> {
         if let Some((header, body)) = self.block.clone() {
-            let block_hash = header.hash().into();
-            self.drivechain
-                .verify_bmm(&header.prev_main_hash, &block_hash)
-                .await?;
+            if let Err(err) = self
+                .mainchain
+                .verify_bmm(&header.prev_main_hash, header.hash())
+                .await
+            {
+                if let crate::mainchain::Error::NoNextBlock { .. } = err {
+                    return Ok(None);
+                }
+                let confirmations = self
+                    .mainchain
+                    .get_block_confirmations(header.prev_main_hash)
+                    .await?;
+                if confirmations.is_none() {
+                    println!(
+                        "abandoning block {}, mainchain block {} is no longer on the best chain",
+                        header.hash(),
+                        header.prev_main_hash
+                    );
+                    self.abandon_block();
+                    return Ok(None);
+                }
+                return Err(err.into());
+            }
             self.block = None;
             return Ok(Some((header, body)));
         }
         Ok(None)
     }
+
+    /// Forget the block waiting for BMM confirmation, so that a new one can be mined.
+    pub fn abandon_block(&mut self) {
+        self.block = None;
+    }
+
+    pub fn has_pending_block(&self) -> bool {
+        self.block.is_some()
+    }
+
+    /// Poll `confirm_bmm` every `poll_interval` until the BMM request lands in a mainchain block,
+    /// for networks where blocks can't be generated on demand. Returns `None` if it doesn't land
+    /// within `timeout`.
+    pub async fn wait_for_bmm(
+        &mut self,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<Option<(Header, Body)>, Error> {
+        let start = Instant::now();
+        loop {
+            match self.confirm_bmm().await {
+                Ok(Some(block)) => return Ok(Some(block)),
+                Ok(None) if !self.has_pending_block() => return Ok(None),
+                Ok(None) | Err(_) if start.elapsed() < timeout => {
+                    tokio::time::sleep(poll_interval).await;
+                }
+                Ok(None)
+                | Err(Error::Mainchain(crate::mainchain::Error::NotBmmVerified { .. })) => {
+                    self.block = None;
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
 }
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("drivechain error")]
     Drivechain(#[from] bip300301::Error),
-    #[error("invalid jaon")]
-    InvalidJson,
+    #[error("mainchain error")]
+    Mainchain(#[from] crate::mainchain::Error),
 }
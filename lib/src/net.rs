@@ -1,13 +1,42 @@
-use crate::types::{AuthorizedTransaction, Body, Header};
+use crate::types::{
+    AuthorizedTransaction, BlockHash, Body, Header, Output, Txid, MAX_TRANSACTION_ITEMS,
+};
 use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 pub use quinn;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 use std::{net::SocketAddr, sync::Arc};
 
-pub const READ_LIMIT: usize = 1024;
+/// Upper bound on the encoded size of one input, output and authorization of a transaction.
+/// Withdrawal outputs are the largest outputs, their mainchain address has at most 90 characters.
+const MAX_TRANSACTION_ITEM_SIZE: usize = 320;
+/// Maximum size of a request read from a peer, enough for pushing a transaction with
+/// [`MAX_TRANSACTION_ITEMS`] inputs, outputs and authorizations.
+pub const READ_LIMIT: usize = 1024 + MAX_TRANSACTION_ITEMS * MAX_TRANSACTION_ITEM_SIZE;
+/// Maximum size of a response read from a peer, responses carry blocks and batches of headers.
+pub const RESPONSE_READ_LIMIT: usize = 16 * 1024 * 1024;
+pub const PROTOCOL_VERSION: u32 = 8;
+/// Misbehavior score at which a peer is disconnected.
+pub const MAX_MISBEHAVIOR: u32 = 100;
+/// Misbehavior added for every transaction a peer pushes over its rate limit.
+pub const THROTTLE_PENALTY: u32 = 10;
+/// Misbehavior added for every request a peer doesn't respond to in time.
+pub const TIMEOUT_PENALTY: u32 = 10;
+/// Misbehavior added for a block or block transactions that fail validation, enough to disconnect.
+pub const INVALID_BLOCK_PENALTY: u32 = MAX_MISBEHAVIOR;
+/// Misbehavior added for a pushed transaction that fails validation, enough to disconnect.
+pub const INVALID_TRANSACTION_PENALTY: u32 = MAX_MISBEHAVIOR;
+/// Misbehavior added for a pushed transaction that is valid but doesn't meet our relay policy,
+/// such as the minimum relay fee.
+pub const POLICY_PENALTY: u32 = 1;
+/// Misbehavior added for a request over [`READ_LIMIT`] or that fails to decode, enough to
+/// disconnect.
+pub const INVALID_REQUEST_PENALTY: u32 = MAX_MISBEHAVIOR;
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 // State.
 // Archive.
@@ -27,14 +56,119 @@ pub struct Net {
     pub client: Endpoint,
     pub server: Endpoint,
     pub peers: Arc<RwLock<HashMap<usize, Peer>>>,
+    /// Addresses of peers we dialed ourselves, they are redialed if the connection drops.
+    pub outbound: Arc<RwLock<HashSet<SocketAddr>>>,
+    /// Hash of our certificate, presented to peers when dialing and when being dialed.
+    pub node_id: [u8; 32],
+    /// Stable ids of connections that have listener tasks running.
+    pub listening: Arc<RwLock<HashSet<usize>>>,
+    /// Time a peer has to respond to a request, for peers connected after it is set.
+    pub request_timeout: Duration,
 }
 
 #[derive(Clone)]
 pub struct Peer {
     pub state: Arc<RwLock<Option<PeerState>>>,
+    /// Protocol version negotiated in the handshake.
+    pub version: Arc<RwLock<Option<u32>>>,
+    /// Hash of the certificate the peer presented, recorded once the handshake succeeds.
+    pub node_id: Arc<RwLock<Option<[u8; 32]>>>,
+    /// Tokens for transactions pushed by the peer.
+    pub push_transaction_bucket: Arc<std::sync::Mutex<TokenBucket>>,
+    pub misbehavior: Arc<AtomicU32>,
+    pub request_timeout: Duration,
     pub connection: Connection,
 }
 
+impl Peer {
+    pub fn new(connection: Connection, request_timeout: Duration) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(None)),
+            version: Arc::new(RwLock::new(None)),
+            node_id: Arc::new(RwLock::new(None)),
+            push_transaction_bucket: Arc::new(std::sync::Mutex::new(TokenBucket::default())),
+            misbehavior: Arc::new(AtomicU32::new(0)),
+            request_timeout,
+            connection,
+        }
+    }
+
+    /// Add `penalty` to the peer's misbehavior score and close the connection with `reason` once
+    /// it reaches `MAX_MISBEHAVIOR`. Returns whether the connection was closed.
+    pub fn misbehaving(&self, penalty: u32, reason: &str) -> bool {
+        let misbehavior = self
+            .misbehavior
+            .fetch_add(penalty, Ordering::SeqCst)
+            .saturating_add(penalty);
+        if misbehavior < MAX_MISBEHAVIOR {
+            return false;
+        }
+        println!(
+            "disconnecting {}: {reason}",
+            self.connection.remote_address()
+        );
+        self.connection
+            .close(quinn::VarInt::from_u32(3), reason.as_bytes());
+        true
+    }
+
+    /// Hash of the certificate the peer presented in the TLS handshake. Both ends of a connection
+    /// present their certificate and prove they hold its key, so a peer can't claim the node id
+    /// of another node.
+    pub fn certified_node_id(&self) -> Result<[u8; 32], Error> {
+        let certs = self
+            .connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<rustls::Certificate>>().ok())
+            .ok_or(Error::NoPeerCertificate)?;
+        let cert = certs.first().ok_or(Error::NoPeerCertificate)?;
+        Ok(blake3::hash(&cert.0).into())
+    }
+}
+
+/// Sustained rate and burst size of messages accepted from a single peer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub const DEFAULT_PUSH_TRANSACTION: Self = Self {
+        per_second: 10.0,
+        burst: 50,
+    };
+}
+
+/// Token bucket that starts full and refills at `RateLimit::per_second` up to
+/// `RateLimit::burst` tokens.
+#[derive(Debug, Default)]
+pub struct TokenBucket {
+    /// Tokens left and the time they were counted, `None` before the first message.
+    tokens: Option<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Take a token, returns `false` if the bucket is empty.
+    pub fn try_take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        let burst = limit.burst as f64;
+        let tokens = match self.tokens {
+            Some((tokens, last)) => {
+                let elapsed = now.saturating_duration_since(last).as_secs_f64();
+                (tokens + elapsed * limit.per_second).min(burst)
+            }
+            None => burst,
+        };
+        if tokens >= 1.0 {
+            self.tokens = Some((tokens - 1.0, now));
+            true
+        } else {
+            self.tokens = Some((tokens, now));
+            false
+        }
+    }
+}
+
 impl Peer {
     pub fn heart_beat(&self, state: &PeerState) -> Result<(), Error> {
         let message = bincode::serialize(state)?;
@@ -42,51 +176,125 @@ impl Peer {
         Ok(())
     }
 
+    /// Send `message` and wait for the response, a peer that doesn't respond within
+    /// `request_timeout` is penalized.
     pub async fn request(&self, message: &Request) -> Result<Response, Error> {
-        let (mut send, mut recv) = self.connection.open_bi().await?;
-        let message = bincode::serialize(message)?;
-        send.write_all(&message).await?;
-        send.finish().await?;
-        let response = recv.read_to_end(READ_LIMIT).await?;
-        let response: Response = bincode::deserialize(&response)?;
-        Ok(response)
+        let request = async {
+            let (mut send, mut recv) = self.connection.open_bi().await?;
+            let message = bincode::serialize(message)?;
+            send.write_all(&message).await?;
+            send.finish().await?;
+            let response = recv.read_to_end(RESPONSE_READ_LIMIT).await?;
+            let response = deserialize_response(&response)?;
+            Ok(response)
+        };
+        match tokio::time::timeout(self.request_timeout, request).await {
+            Ok(response) => response,
+            Err(_) => {
+                self.misbehaving(TIMEOUT_PENALTY, "request timed out");
+                Err(Error::RequestTimeout {
+                    timeout: self.request_timeout,
+                })
+            }
+        }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
-    GetBlock { height: u32 },
-    PushTransaction { transaction: AuthorizedTransaction },
+    /// Sent by the dialing peer right after connecting.
+    Hello {
+        version: u32,
+        best_height: u32,
+    },
+    GetBlock {
+        height: u32,
+    },
+    /// Block at `height` with txids in place of transactions, answered with
+    /// `Response::CompactBlock`.
+    GetCompactBlock {
+        height: u32,
+    },
+    /// Transactions at `missing_indices` in the body of the block at `height`, for reconstructing
+    /// a compact block.
+    GetBlockTxs {
+        height: u32,
+        missing_indices: Vec<u32>,
+    },
+    PushTransaction {
+        transaction: AuthorizedTransaction,
+    },
+    /// Headers after the most recent block of `locator` that is on the responder's chain.
+    GetHeadersFromLocator {
+        locator: Vec<BlockHash>,
+        stop: Option<BlockHash>,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Response {
-    Block { header: Header, body: Body },
+    Hello {
+        version: u32,
+        best_height: u32,
+    },
+    Block {
+        header: Header,
+        #[serde(with = "crate::compression::body")]
+        body: Body,
+    },
     NoBlock,
+    CompactBlock {
+        header: Header,
+        coinbase: Vec<Output>,
+        txids: Vec<Txid>,
+    },
+    /// Transactions requested with `Request::GetBlockTxs`, in the requested order.
+    BlockTxs {
+        transactions: Vec<AuthorizedTransaction>,
+    },
+    Headers {
+        headers: Vec<Header>,
+    },
     TransactionAccepted,
     TransactionRejected,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PeerState {
+    /// Kept first so that it can be checked before the rest of the state is trusted.
+    pub version: u32,
     pub block_height: u32,
+    /// Number of transactions in the peer's mempool.
+    pub mempool_size: u64,
 }
 
 impl Default for PeerState {
     fn default() -> Self {
-        Self { block_height: 0 }
+        Self {
+            version: PROTOCOL_VERSION,
+            block_height: 0,
+            mempool_size: 0,
+        }
     }
 }
 
 impl Net {
     pub fn new(bind_addr: SocketAddr) -> Result<Self, Error> {
-        let (server, _) = make_server_endpoint(bind_addr)?;
-        let client = make_client_endpoint("0.0.0.0:0".parse()?)?;
+        let (cert, key) = generate_certificate()?;
+        let node_id = blake3::hash(&cert.0).into();
+        let server = make_server_endpoint(bind_addr, cert.clone(), key.clone())?;
+        let client = make_client_endpoint("0.0.0.0:0".parse()?, cert, key)?;
         let peers = Arc::new(RwLock::new(HashMap::new()));
+        let outbound = Arc::new(RwLock::new(HashSet::new()));
+        let listening = Arc::new(RwLock::new(HashSet::new()));
         Ok(Net {
             server,
             client,
             peers,
+            outbound,
+            node_id,
+            listening,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
         })
     }
     pub async fn connect(&self, addr: SocketAddr) -> Result<Peer, Error> {
@@ -96,10 +304,7 @@ impl Net {
             }
         }
         let connection = self.client.connect(addr, "localhost")?.await?;
-        let peer = Peer {
-            state: Arc::new(RwLock::new(None)),
-            connection,
-        };
+        let peer = Peer::new(connection, self.request_timeout);
         self.peers
             .write()
             .await
@@ -107,47 +312,130 @@ impl Net {
         Ok(peer)
     }
 
+    /// Record the node id of `peer` once the handshake succeeds. Fails if another connection to the same
+    /// node is already open, so that the newer one can be closed.
+    pub async fn set_node_id(&self, peer: &Peer, node_id: [u8; 32]) -> Result<(), Error> {
+        // Held while checking, so two connections to the same node can't both pass.
+        let peers = self.peers.write().await;
+        for other in peers.values() {
+            if other.connection.stable_id() != peer.connection.stable_id()
+                && *other.node_id.read().await == Some(node_id)
+            {
+                return Err(Error::AlreadyConnectedToNode { node_id });
+            }
+        }
+        *peer.node_id.write().await = Some(node_id);
+        Ok(())
+    }
+
     pub async fn disconnect(&self, stable_id: usize) -> Result<Option<Peer>, Error> {
         let peer = self.peers.write().await.remove(&stable_id);
         Ok(peer)
     }
 }
 
+/// Decode a message received from a peer.
+///
+/// Uses the same encoding as `bincode::deserialize`, but refuses to read more
+/// than [`READ_LIMIT`] bytes, so length prefixes claiming more data than a
+/// message can hold are rejected instead of allocated for.
+pub fn deserialize_message<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    deserialize_with_limit(bytes, READ_LIMIT)
+}
+
+/// Decode a response received from a peer, like [`deserialize_message`] but
+/// limited to [`RESPONSE_READ_LIMIT`] bytes.
+pub fn deserialize_response(bytes: &[u8]) -> Result<Response, Error> {
+    deserialize_with_limit(bytes, RESPONSE_READ_LIMIT)
+}
+
+fn deserialize_with_limit<T: serde::de::DeserializeOwned>(
+    bytes: &[u8],
+    limit: usize,
+) -> Result<T, Error> {
+    use bincode::Options as _;
+    let message = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit as u64)
+        .deserialize(bytes)?;
+    Ok(message)
+}
+
 #[allow(unused)]
-pub fn make_client_endpoint(bind_addr: SocketAddr) -> Result<Endpoint, Error> {
-    let client_cfg = configure_client();
+pub fn make_client_endpoint(
+    bind_addr: SocketAddr,
+    cert: rustls::Certificate,
+    key: rustls::PrivateKey,
+) -> Result<Endpoint, Error> {
+    let client_cfg = configure_client(cert, key)?;
     let mut endpoint = Endpoint::client(bind_addr)?;
     endpoint.set_default_client_config(client_cfg);
     Ok(endpoint)
 }
 
 /// Constructs a QUIC endpoint configured to listen for incoming connections on a certain address
-/// and port.
-///
-/// ## Returns
-///
-/// - a stream of incoming QUIC connections
-/// - server certificate serialized into DER format
+/// and port, presenting `cert` and requiring clients to present one too.
 #[allow(unused)]
-pub fn make_server_endpoint(bind_addr: SocketAddr) -> Result<(Endpoint, Vec<u8>), Error> {
-    let (server_config, server_cert) = configure_server()?;
+pub fn make_server_endpoint(
+    bind_addr: SocketAddr,
+    cert: rustls::Certificate,
+    key: rustls::PrivateKey,
+) -> Result<Endpoint, Error> {
+    let server_config = configure_server(cert, key)?;
     let endpoint = Endpoint::server(server_config, bind_addr)?;
-    Ok((endpoint, server_cert))
+    Ok(endpoint)
 }
 
-/// Returns default server configuration along with its certificate.
-fn configure_server() -> Result<(ServerConfig, Vec<u8>), Error> {
+/// Self-signed certificate a node presents both as a server and as a client, its hash is the node
+/// id.
+fn generate_certificate() -> Result<(rustls::Certificate, rustls::PrivateKey), Error> {
     let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
     let cert_der = cert.serialize_der()?;
     let priv_key = cert.serialize_private_key_der();
-    let priv_key = rustls::PrivateKey(priv_key);
-    let cert_chain = vec![rustls::Certificate(cert_der.clone())];
+    Ok((rustls::Certificate(cert_der), rustls::PrivateKey(priv_key)))
+}
+
+fn configure_server(
+    cert: rustls::Certificate,
+    key: rustls::PrivateKey,
+) -> Result<ServerConfig, Error> {
+    let crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(AcceptAnyClientCert::new())
+        .with_single_cert(vec![cert], key)?;
 
-    let mut server_config = ServerConfig::with_single_cert(cert_chain, priv_key)?;
+    let mut server_config = ServerConfig::with_crypto(Arc::new(crypto));
     let transport_config = Arc::get_mut(&mut server_config.transport).unwrap();
     transport_config.max_concurrent_uni_streams(1_u8.into());
 
-    Ok((server_config, cert_der))
+    Ok(server_config)
+}
+
+/// Client certificate verifier that treats any certificate as valid, like
+/// `SkipServerVerification`. Clients still have to sign the handshake with the certificate's key,
+/// which is all node ids rely on.
+struct AcceptAnyClientCert;
+
+impl AcceptAnyClientCert {
+    fn new() -> Arc<Self> {
+        Arc::new(Self)
+    }
+}
+
+impl rustls::server::ClientCertVerifier for AcceptAnyClientCert {
+    fn client_auth_root_subjects(&self) -> &[rustls::DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::server::ClientCertVerified, rustls::Error> {
+        Ok(rustls::server::ClientCertVerified::assertion())
+    }
 }
 
 /// Dummy certificate verifier that treats any certificate as valid.
@@ -174,13 +462,16 @@ impl rustls::client::ServerCertVerifier for SkipServerVerification {
     }
 }
 
-fn configure_client() -> ClientConfig {
+fn configure_client(
+    cert: rustls::Certificate,
+    key: rustls::PrivateKey,
+) -> Result<ClientConfig, Error> {
     let crypto = rustls::ClientConfig::builder()
         .with_safe_defaults()
         .with_custom_certificate_verifier(SkipServerVerification::new())
-        .with_no_client_auth();
+        .with_client_auth_cert(vec![cert], key)?;
 
-    ClientConfig::new(Arc::new(crypto))
+    Ok(ClientConfig::new(Arc::new(crypto)))
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -209,4 +500,20 @@ pub enum Error {
     Bincode(#[from] bincode::Error),
     #[error("already connected to peer at {0}")]
     AlreadyConnected(SocketAddr),
+    #[error("already connected to node {}", hex::encode(node_id))]
+    AlreadyConnectedToNode { node_id: [u8; 32] },
+    #[error("incompatible protocol version {version} != {}", PROTOCOL_VERSION)]
+    IncompatibleVersion { version: u32 },
+    #[error("unexpected response to handshake")]
+    UnexpectedHandshakeResponse,
+    #[error("peer didn't present a certificate")]
+    NoPeerCertificate,
+    #[error("peer sent a request before the handshake")]
+    HandshakeRequired,
+    #[error("peer exceeded its rate limit")]
+    RateLimited,
+    #[error("peer sent transactions that don't match the compact block")]
+    InvalidBlockTxs,
+    #[error("peer didn't respond within {timeout:?}")]
+    RequestTimeout { timeout: Duration },
 }
@@ -1,3 +1,4 @@
+use crate::mainchain::Mainchain;
 use crate::net::{PeerState, Request, Response};
 use crate::{authorization::Authorization, types::*};
 use heed::RoTxn;
@@ -5,21 +6,189 @@ use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
     net::SocketAddr,
+    ops::RangeInclusive,
     path::Path,
     sync::Arc,
+    time::{Duration, Instant},
 };
-use tokio::sync::RwLock;
 
+#[rustfmt::skip]
 pub const THIS_SIDECHAIN: u8 = {{slot_number}};
 
+/// Change to the best chain, sent to subscribers of `Node::subscribe`.
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    Connect {
+        header: Header,
+        body: Body,
+    },
+    /// `spent_utxos` are the outputs spent by the disconnected body, they are unspent again.
+    Disconnect {
+        header: Header,
+        body: Body,
+        spent_utxos: HashMap<OutPoint, Output>,
+    },
+}
+
+/// Reads sharing a single read transaction, see `Node::snapshot`.
+pub struct Snapshot<'a, M = bip300301::Drivechain> {
+    node: &'a Node<M>,
+    txn: RoTxn<'a>,
+}
+
+impl<M> Snapshot<'_, M> {
+    pub fn get_height(&self) -> Result<u32, Error> {
+        Ok(self.node.archive.get_height(&self.txn)?)
+    }
+
+    pub fn get_best_hash(&self) -> Result<BlockHash, Error> {
+        Ok(self.node.archive.get_best_hash(&self.txn)?)
+    }
+
+    pub fn get_header(&self, height: u32) -> Result<Option<Header>, Error> {
+        Ok(self.node.archive.get_header(&self.txn, height)?)
+    }
+
+    pub fn get_body(&self, height: u32) -> Result<Option<Body>, Error> {
+        Ok(self.node.archive.get_body(&self.txn, height)?)
+    }
+
+    pub fn get_block(&self, height: u32) -> Result<Option<BlockInfo>, Error> {
+        match (self.get_header(height)?, self.get_body(height)?) {
+            (Some(header), Some(body)) => Ok(Some(BlockInfo::new(header, body))),
+            (_, _) => Ok(None),
+        }
+    }
+
+    pub fn get_utxos_by_addresses(
+        &self,
+        addresses: &HashSet<Address>,
+    ) -> Result<HashMap<OutPoint, Output>, Error> {
+        Ok(self
+            .node
+            .state
+            .get_utxos_by_addresses(&self.txn, addresses)?)
+    }
+
+    /// See `Node::get_spent_utxos`.
+    pub fn get_spent_utxos(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        let utxos = self.node.state.get_utxos(&self.txn, outpoints)?;
+        let spent = outpoints
+            .iter()
+            .filter(|outpoint| matches!(utxos.get(outpoint), Some(None)))
+            .copied()
+            .collect();
+        Ok(spent)
+    }
+
+    /// See `Node::get_utxo_heights`.
+    pub fn get_utxo_heights(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<HashMap<OutPoint, u32>, Error> {
+        let mut heights = HashMap::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            if let Some(height) = self.node.state.get_utxo_height(&self.txn, outpoint)? {
+                heights.insert(*outpoint, height);
+            }
+        }
+        Ok(heights)
+    }
+
+    /// See `Node::get_utxo_set_hash`.
+    pub fn get_utxo_set_hash(&self) -> Result<Hash, Error> {
+        Ok(self.node.state.utxo_set_hash(&self.txn)?)
+    }
+
+    /// See `Node::fill_transaction`.
+    pub fn fill_transaction(&self, transaction: &Transaction) -> Result<FilledTransaction, Error> {
+        let mut spent_utxos = Vec::with_capacity(transaction.inputs.len());
+        let mut missing = vec![];
+        for input in &transaction.inputs {
+            match self.node.state.get_utxo(&self.txn, input)? {
+                Some(output) => spent_utxos.push(output),
+                None => missing.push(*input),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::MissingInputs {
+                txid: transaction.txid(),
+                missing,
+            });
+        }
+        Ok(FilledTransaction {
+            spent_utxos,
+            transaction: transaction.clone(),
+        })
+    }
+}
+
+/// Settings used when the node's databases are opened, see `Node::new`.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Number of recent block bodies to keep, `None` disables pruning.
+    pub prune_depth: Option<u32>,
+    /// zstd level block bodies are stored with, `None` stores them uncompressed.
+    pub compression_level: Option<i32>,
+    /// Minimum fee in sats per byte for transactions accepted into the mempool.
+    pub min_relay_fee_rate: u64,
+    /// Number of blocks after which unmined transactions are evicted from the mempool, `None`
+    /// keeps them until they are mined or conflict.
+    pub mempool_ttl: Option<u32>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            prune_depth: None,
+            compression_level: None,
+            min_relay_fee_rate: crate::mempool::MemPool::DEFAULT_MIN_RELAY_FEE_RATE,
+            mempool_ttl: None,
+        }
+    }
+}
+
+/// Sidechain node, cheap to clone and safe to share between tasks.
+///
+/// Read methods open their own read transaction, so they don't take one as an argument. Several
+/// reads that need a consistent view can share one transaction through `Node::snapshot`. Async
+/// methods never hold a transaction across an `.await`: LMDB transactions are tied to the thread
+/// that opened them, and a write transaction held while waiting would block every other writer.
+///
+/// Generic over the mainchain so that it can run against `testutil::MockMainchain`.
 #[derive(Clone)]
-pub struct Node {
+pub struct Node<M = bip300301::Drivechain> {
     net: crate::net::Net,
     state: crate::state::State,
     archive: crate::archive::Archive,
     mempool: crate::mempool::MemPool,
-    drivechain: bip300301::Drivechain,
+    mainchain: M,
     env: heed::Env,
+    /// Number of recent block bodies to keep, `None` disables pruning.
+    prune_depth: Option<u32>,
+    /// Number of blocks after which unmined transactions are evicted from the mempool, `None`
+    /// keeps them until they are mined or conflict.
+    mempool_ttl: Option<u32>,
+    /// Confirmations after which a block is final and is never disconnected, `None` allows
+    /// reorgs of any depth.
+    finality_depth: Option<u32>,
+    /// Rate at which each peer may push transactions, excess transactions are dropped and count
+    /// towards the peer's misbehavior score.
+    push_transaction_limit: crate::net::RateLimit,
+    /// Download blocks from peers as compact blocks, fetching only the transactions that aren't
+    /// in our mempool.
+    compact_blocks: bool,
+    /// Address to serve health checks on, see `crate::health`. Replaced with the bound address
+    /// when the node is run.
+    health_addr: Option<SocketAddr>,
+    /// Networking tasks spawned by `run`, the node is alive while none of them has finished.
+    tasks: Arc<std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+    /// Held while a write transaction is open. LMDB allows a single writer per environment and
+    /// blocks the thread of any other, so writers wait here instead of blocking the runtime.
+    write_lock: Arc<tokio::sync::Mutex<()>>,
+    events: tokio::sync::broadcast::Sender<ChainEvent>,
+    /// Advisory lock on the datadir, released when the last clone of the node is dropped.
+    _datadir_lock: Arc<std::fs::File>,
 }
 
 impl Node {
@@ -29,41 +198,223 @@ impl Node {
         main_addr: SocketAddr,
         user: &str,
         password: &str,
+        config: NodeConfig,
+    ) -> Result<Self, Error> {
+        let drivechain = bip300301::Drivechain::new(THIS_SIDECHAIN, main_addr, user, password)?;
+        Self::with_mainchain(datadir, bind_addr, drivechain, config)
+    }
+}
+
+impl<M: Mainchain> Node<M> {
+    /// The node is considered synced when it is at most this many blocks behind its best peer.
+    pub const SYNC_THRESHOLD: u32 = 1;
+    /// Number of chain events kept for subscribers that fall behind.
+    pub const EVENTS_CAPACITY: usize = 256;
+    /// Maximum number of headers sent in response to `Request::GetHeadersFromLocator`,
+    /// small enough for the response to fit in [`crate::net::RESPONSE_READ_LIMIT`].
+    pub const MAX_HEADERS: usize = 2000;
+    /// Every sidechain block is BMMed in its own mainchain block, so this is also roughly the
+    /// mainchain depth past which a block is final.
+    pub const DEFAULT_FINALITY_DEPTH: u32 = 100;
+
+    pub fn with_mainchain(
+        datadir: &Path,
+        bind_addr: SocketAddr,
+        mainchain: M,
+        config: NodeConfig,
     ) -> Result<Self, Error> {
+        let datadir_lock = Self::lock_datadir(datadir)?;
         let env_path = datadir.join("data.mdb");
-        // let _ = std::fs::remove_dir_all(&env_path);
         std::fs::create_dir_all(&env_path)?;
         let env = heed::EnvOpenOptions::new()
             .map_size(10 * 1024 * 1024) // 10MB
             .max_dbs(
                 crate::state::State::NUM_DBS
                     + crate::archive::Archive::NUM_DBS
-                    + crate::mempool::MemPool::NUM_DBS,
+                    + crate::mempool::MemPool::NUM_DBS
+                    + crate::versioned::NUM_DBS,
             )
             .open(env_path)?;
-        let state = crate::state::State::new(&env)?;
-        let archive = crate::archive::Archive::new(&env)?;
-        let mempool = crate::mempool::MemPool::new(&env)?;
-        let drivechain = bip300301::Drivechain::new(THIS_SIDECHAIN, main_addr, user, password)?;
+        crate::versioned::check_schema(&env)?;
+        let state = crate::state::State::new(&env, crate::consensus::ConsensusParams::default())?;
+        let archive = crate::archive::Archive::new(&env, config.compression_level)?;
+        let mempool = crate::mempool::MemPool::new(&env, config.min_relay_fee_rate)?;
+        {
+            let mut txn = env.write_txn()?;
+            let indexed = archive.backfill_txid_index(&mut txn)?;
+            let removed = mempool.reconcile(&mut txn, &state)?;
+            txn.commit()?;
+            if indexed > 0 {
+                println!("indexed {indexed} archived transactions");
+            }
+            if !removed.is_empty() {
+                println!(
+                    "removed {} invalid transactions from mempool",
+                    removed.len()
+                );
+            }
+        }
         let net = crate::net::Net::new(bind_addr)?;
+        let (events, _) = tokio::sync::broadcast::channel(Self::EVENTS_CAPACITY);
         Ok(Self {
             net,
             state,
             archive,
             mempool,
-            drivechain,
+            mainchain,
             env,
+            prune_depth: config.prune_depth,
+            mempool_ttl: config.mempool_ttl,
+            finality_depth: Some(Self::DEFAULT_FINALITY_DEPTH),
+            push_transaction_limit: crate::net::RateLimit::DEFAULT_PUSH_TRANSACTION,
+            compact_blocks: false,
+            health_addr: None,
+            tasks: Arc::new(std::sync::Mutex::new(vec![])),
+            write_lock: Arc::new(tokio::sync::Mutex::new(())),
+            events,
+            _datadir_lock: Arc::new(datadir_lock),
         })
     }
 
-    pub fn get_height(&self) -> Result<u32, Error> {
+    /// Verify signatures in a dedicated pool of `num_threads` threads instead of the global rayon
+    /// pool, which uses every core. Must be called before the node is cloned or run.
+    pub fn set_verification_threads(&mut self, num_threads: usize) -> Result<(), Error> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("verification-{index}"))
+            .build()?;
+        self.state.set_verification_pool(Arc::new(pool));
+        Ok(())
+    }
+
+    /// Confirmations after which blocks are final, `None` allows reorgs of any depth. Must be
+    /// called before the node is cloned or run.
+    pub fn set_finality_depth(&mut self, finality_depth: Option<u32>) {
+        self.finality_depth = finality_depth;
+    }
+
+    /// Time peers have to respond to requests. Must be called before the node is cloned or run.
+    pub fn set_request_timeout(&mut self, request_timeout: Duration) {
+        self.net.request_timeout = request_timeout;
+    }
+
+    /// Rate at which each peer may push transactions. Must be called before the node is cloned or
+    /// run.
+    pub fn set_push_transaction_limit(&mut self, limit: crate::net::RateLimit) {
+        self.push_transaction_limit = limit;
+    }
+
+    /// Download blocks as compact blocks. Must be called before the node is cloned or run.
+    pub fn set_compact_blocks(&mut self, compact_blocks: bool) {
+        self.compact_blocks = compact_blocks;
+    }
+
+    /// Serve health checks on `health_addr` once the node is run, `None` disables them. Must be
+    /// called before the node is cloned or run.
+    pub fn set_health_addr(&mut self, health_addr: Option<SocketAddr>) {
+        self.health_addr = health_addr;
+    }
+
+    /// Address health checks are served on, the bound address after the node is run.
+    pub fn health_addr(&self) -> Option<SocketAddr> {
+        self.health_addr
+    }
+
+    /// Whether the node was run, none of its networking tasks has stopped and the database can
+    /// be read.
+    pub fn is_alive(&self) -> bool {
+        let tasks = self.tasks.lock().unwrap();
+        !tasks.is_empty()
+            && tasks.iter().all(|task| !task.is_finished())
+            && self.env.read_txn().is_ok()
+    }
+
+    /// Whether the node is alive and synced to within `SYNC_THRESHOLD` blocks of its peers.
+    pub async fn is_ready(&self) -> bool {
+        self.is_alive() && matches!(self.is_syncing().await, Ok(false))
+    }
+
+    /// Height of the most recent final block, blocks up to it are never disconnected.
+    pub fn get_finalized_height(&self) -> Result<u32, Error> {
         let txn = self.env.read_txn()?;
-        Ok(self.archive.get_height(&txn)?)
+        Ok(self.finalized_height(&txn)?)
+    }
+
+    fn finalized_height(&self, txn: &RoTxn) -> Result<u32, crate::archive::Error> {
+        let height = self.archive.get_height(txn)?;
+        Ok(match self.finality_depth {
+            Some(finality_depth) => height.saturating_sub(finality_depth),
+            None => 0,
+        })
+    }
+
+    /// Take an exclusive lock on `datadir/.lock`, so that only one instance uses the datadir.
+    fn lock_datadir(datadir: &Path) -> Result<std::fs::File, Error> {
+        use fs2::FileExt as _;
+        std::fs::create_dir_all(datadir)?;
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(datadir.join(".lock"))?;
+        if lock.try_lock_exclusive().is_err() {
+            return Err(Error::AlreadyRunning {
+                datadir: datadir.to_path_buf(),
+            });
+        }
+        Ok(lock)
+    }
+
+    /// Receive blocks connected to and disconnected from the best chain from now on. A receiver
+    /// that falls more than `EVENTS_CAPACITY` events behind gets `RecvError::Lagged`.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    /// Address the node accepts peer connections on.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.net.server.local_addr()?)
+    }
+
+    /// Read-only view of the chain as of now, unaffected by blocks connected while it is held.
+    ///
+    /// A thread can only have one transaction open, so don't call other node methods on the
+    /// same thread while holding a snapshot.
+    pub fn snapshot(&self) -> Result<Snapshot<'_, M>, Error> {
+        Ok(Snapshot {
+            node: self,
+            txn: self.env.read_txn()?,
+        })
+    }
+
+    pub fn get_height(&self) -> Result<u32, Error> {
+        self.snapshot()?.get_height()
     }
 
     pub fn get_best_hash(&self) -> Result<BlockHash, Error> {
-        let txn = self.env.read_txn()?;
-        Ok(self.archive.get_best_hash(&txn)?)
+        self.snapshot()?.get_best_hash()
+    }
+
+    /// Highest block height reported by a connected peer.
+    pub async fn get_peer_count(&self) -> usize {
+        self.net.peers.read().await.len()
+    }
+
+    pub async fn get_best_peer_height(&self) -> Option<u32> {
+        let mut best_peer_height = None;
+        for peer in self.net.peers.read().await.values() {
+            if let Some(state) = peer.state.read().await.as_ref() {
+                best_peer_height = best_peer_height.max(Some(state.block_height));
+            }
+        }
+        best_peer_height
+    }
+
+    /// Whether the node is still catching up with its peers, blocks shouldn't be mined while it
+    /// is.
+    pub async fn is_syncing(&self) -> Result<bool, Error> {
+        let height = self.get_height()?;
+        let best_peer_height = self.get_best_peer_height().await.unwrap_or(0);
+        Ok(best_peer_height > height + Self::SYNC_THRESHOLD)
     }
 
     pub fn validate_transaction(
@@ -71,7 +422,22 @@ impl Node {
         txn: &RoTxn,
         transaction: &AuthorizedTransaction,
     ) -> Result<u64, Error> {
+        // Authorizations are zipped with inputs below, a mismatch would go unnoticed there.
+        let inputs = transaction.transaction.inputs.len();
+        let authorizations = transaction.authorizations.len();
+        if inputs != authorizations {
+            return Err(crate::state::Error::WrongAuthorizationCount {
+                txid: transaction.transaction.txid(),
+                inputs,
+                authorizations,
+            }
+            .into());
+        }
         let filled_transaction = self.state.fill_transaction(txn, &transaction.transaction)?;
+        // Checked against the next block, the earliest one the transaction can be included in.
+        let height = self.archive.get_height(txn)? + 1;
+        self.state
+            .validate_coinbase_maturity(txn, &transaction.transaction, height)?;
         for (authorization, spent_utxo) in transaction
             .authorizations
             .iter()
@@ -95,48 +461,270 @@ impl Node {
         transaction: &AuthorizedTransaction,
     ) -> Result<(), Error> {
         {
+            let _write_guard = self.write_lock.lock().await;
             let mut txn = self.env.write_txn()?;
-            self.validate_transaction(&txn, &transaction)?;
-            self.mempool.put(&mut txn, &transaction)?;
+            let fee = self.validate_transaction(&txn, &transaction)?;
+            let height = self.archive.get_height(&txn)?;
+            self.mempool.put(&mut txn, &transaction, fee, height)?;
             txn.commit()?;
         }
-        for peer in self.net.peers.read().await.values() {
-            peer.request(&Request::PushTransaction {
-                transaction: transaction.clone(),
-            })
-            .await?;
-        }
+        // The transaction is in our mempool regardless of how peers respond.
+        self.broadcast_transaction(transaction, None).await;
         Ok(())
     }
 
-    pub fn get_spent_utxos(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
-        let txn = self.env.read_txn()?;
-        let mut spent = vec![];
-        for outpoint in outpoints {
-            if self.state.utxos.get(&txn, outpoint)?.is_none() {
-                spent.push(*outpoint);
+    /// Send a transaction to every peer except the one with stable id `except`. Requests are
+    /// sent concurrently so that a slow peer doesn't hold up the rest. Returns the number of peers
+    /// that accepted the transaction.
+    async fn broadcast_transaction(
+        &self,
+        transaction: &AuthorizedTransaction,
+        except: Option<usize>,
+    ) -> usize {
+        let peers: Vec<_> = self
+            .net
+            .peers
+            .read()
+            .await
+            .values()
+            .filter(|peer| Some(peer.connection.stable_id()) != except)
+            .cloned()
+            .collect();
+        let request = Request::PushTransaction {
+            transaction: transaction.clone(),
+        };
+        let responses =
+            futures::future::join_all(peers.iter().map(|peer| peer.request(&request))).await;
+        let mut accepted = 0;
+        for (peer, response) in peers.iter().zip(responses) {
+            match response {
+                Ok(Response::TransactionAccepted) => accepted += 1,
+                Ok(_) => {}
+                Err(err) => println!(
+                    "failed to send transaction to {}: {err:?}",
+                    peer.connection.remote_address()
+                ),
             }
         }
-        Ok(spent)
+        accepted
+    }
+
+    /// Submit an already signed transaction and return its txid.
+    pub async fn send_raw_transaction(
+        &self,
+        transaction: &AuthorizedTransaction,
+    ) -> Result<Txid, Error> {
+        self.submit_transaction(transaction).await?;
+        Ok(transaction.transaction.txid())
+    }
+
+    pub fn get_spent_utxos(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        self.snapshot()?.get_spent_utxos(outpoints)
+    }
+
+    /// Heights of the blocks in which unspent outpoints were created, spent outpoints are left
+    /// out.
+    pub fn get_utxo_heights(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<HashMap<OutPoint, u32>, Error> {
+        self.snapshot()?.get_utxo_heights(outpoints)
+    }
+
+    /// Value for the `commitment` of a header on top of the current tip.
+    pub fn get_utxo_set_hash(&self) -> Result<Hash, Error> {
+        self.snapshot()?.get_utxo_set_hash()
+    }
+
+    /// Outputs spent by `transaction`, fails with `Error::MissingInputs` listing every input that
+    /// isn't an unspent output on the current chain.
+    pub fn fill_transaction(&self, transaction: &Transaction) -> Result<FilledTransaction, Error> {
+        self.snapshot()?.fill_transaction(transaction)
     }
 
     pub fn get_utxos_by_addresses(
         &self,
         addresses: &HashSet<Address>,
     ) -> Result<HashMap<OutPoint, Output>, Error> {
-        let txn = self.env.read_txn()?;
-        let utxos = self.state.get_utxos_by_addresses(&txn, addresses)?;
-        Ok(utxos)
+        self.snapshot()?.get_utxos_by_addresses(addresses)
     }
 
     pub fn get_header(&self, height: u32) -> Result<Option<Header>, Error> {
-        let txn = self.env.read_txn()?;
-        Ok(self.archive.get_header(&txn, height)?)
+        self.snapshot()?.get_header(height)
     }
 
     pub fn get_body(&self, height: u32) -> Result<Option<Body>, Error> {
+        self.snapshot()?.get_body(height)
+    }
+
+    pub fn get_header_by_hash(&self, hash: &BlockHash) -> Result<Option<Header>, Error> {
         let txn = self.env.read_txn()?;
-        Ok(self.archive.get_body(&txn, height)?)
+        let header = match self.archive.get_height_by_hash(&txn, hash)? {
+            Some(height) => self.archive.get_header(&txn, height)?,
+            None => None,
+        };
+        Ok(header)
+    }
+
+    /// Get a confirmed transaction together with the height of the block that includes it.
+    pub fn get_transaction(&self, txid: &Txid) -> Result<Option<(Transaction, u32)>, Error> {
+        let txn = self.env.read_txn()?;
+        let Some(height) = self.archive.get_height_by_txid(&txn, txid)? else {
+            return Ok(None);
+        };
+        let transaction = self.archive.get_body(&txn, height)?.and_then(|body| {
+            body.transactions
+                .into_iter()
+                .find(|transaction| transaction.txid() == *txid)
+        });
+        Ok(transaction.map(|transaction| (transaction, height)))
+    }
+
+    /// Header of the block that includes the transaction and a Merkle path proving it, checked
+    /// with `types::verify_tx_inclusion`.
+    pub fn get_tx_inclusion_proof(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Header, MerklePath)>, Error> {
+        let txn = self.env.read_txn()?;
+        let Some(height) = self.archive.get_height_by_txid(&txn, txid)? else {
+            return Ok(None);
+        };
+        let (Some(header), Some(body)) = (
+            self.archive.get_header(&txn, height)?,
+            self.archive.get_body(&txn, height)?,
+        ) else {
+            return Ok(None);
+        };
+        Ok(body.merkle_path(txid).map(|path| (header, path)))
+    }
+
+    /// Get an output whether it is unspent or was spent by a confirmed transaction. Spent coinbase
+    /// and deposit outputs can't be looked up.
+    pub fn get_output(&self, outpoint: &OutPoint) -> Result<Option<Output>, Error> {
+        Ok(self.get_outputs(&[*outpoint])?.remove(outpoint))
+    }
+
+    /// `get_output` for several outpoints, outpoints that can't be looked up are left out. Every
+    /// archived body is decompressed at most once, however many of the outpoints it created.
+    pub fn get_outputs(&self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, Output>, Error> {
+        let txn = self.env.read_txn()?;
+        let mut outputs = HashMap::with_capacity(outpoints.len());
+        let mut bodies = HashMap::new();
+        for outpoint in outpoints {
+            if let Some(output) = self.state.get_utxo(&txn, outpoint)? {
+                outputs.insert(*outpoint, output);
+                continue;
+            }
+            let OutPoint::Regular { txid, vout } = outpoint else {
+                continue;
+            };
+            let Some(height) = self.archive.get_height_by_txid(&txn, txid)? else {
+                continue;
+            };
+            let body = match bodies.entry(height) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(self.archive.get_body(&txn, height)?)
+                }
+            };
+            let output = body
+                .iter()
+                .flat_map(|body| &body.transactions)
+                .find(|transaction| transaction.txid() == *txid)
+                .and_then(|transaction| transaction.outputs.get(*vout as usize));
+            if let Some(output) = output {
+                outputs.insert(*outpoint, output.clone());
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// See `Archive::get_block_locator`.
+    pub fn get_block_locator(&self) -> Result<Vec<BlockHash>, Error> {
+        let txn = self.env.read_txn()?;
+        Ok(self.archive.get_block_locator(&txn)?)
+    }
+
+    pub fn get_block(&self, height: u32) -> Result<Option<BlockInfo>, Error> {
+        self.snapshot()?.get_block(height)
+    }
+
+    /// Pretty printed JSON of the block at `height`, see `types::json`.
+    pub fn get_block_json(&self, height: u32) -> Result<Option<String>, Error> {
+        let Some(block) = self.get_block(height)? else {
+            return Ok(None);
+        };
+        let block = crate::types::json::BlockJson::from(&block);
+        Ok(Some(serde_json::to_string_pretty(&block)?))
+    }
+
+    /// Walk the whole archive and check that stored headers and bodies are consistent, returns an
+    /// error for the first block that fails.
+    pub fn verify_chain(&self) -> Result<(), VerifyError> {
+        let txn = self.env.read_txn()?;
+        let best_height = self.archive.get_height(&txn)?;
+        let pruned_height = self.archive.get_pruned_height(&txn)?;
+        let mut prev_side_hash = crate::consensus::genesis_hash();
+        let mut bodies = self
+            .archive
+            .iter_bodies(&txn, pruned_height + 1..=best_height)?;
+        let mut expected_height = 1;
+        for item in self.archive.iter_headers(&txn, 1..=best_height)? {
+            let (height, header) = item?;
+            if height != expected_height {
+                return Err(VerifyError::MissingHeader {
+                    height: expected_height,
+                });
+            }
+            expected_height += 1;
+            if header.height != height {
+                return Err(VerifyError::InvalidHeight { height });
+            }
+            if header.prev_side_hash != prev_side_hash {
+                return Err(VerifyError::InvalidPrevSideHash { height });
+            }
+            prev_side_hash = header.hash();
+            // Only headers are kept for pruned blocks.
+            if height <= pruned_height {
+                continue;
+            }
+            let body = match bodies.next().transpose()? {
+                Some((body_height, body)) if body_height == height => body,
+                _ => return Err(VerifyError::MissingBody { height }),
+            };
+            if header.merkle_root != body.compute_merkle_root() {
+                return Err(VerifyError::InvalidMerkleRoot { height });
+            }
+            self.state
+                .in_verification_pool(|| Authorization::verify_body(&body))
+                .map_err(|source| VerifyError::Authorization { height, source })?;
+        }
+        Ok(())
+    }
+
+    async fn expire_mempool(&self, ttl: u32) -> Result<(), Error> {
+        let _write_guard = self.write_lock.lock().await;
+        let mut txn = self.env.write_txn()?;
+        let height = self.archive.get_height(&txn)?;
+        let expired = self.mempool.expire(&mut txn, height, ttl)?;
+        txn.commit()?;
+        metrics::counter!(
+            "sidechain_mempool_expired_transactions",
+            expired.len() as u64
+        );
+        Ok(())
+    }
+
+    pub fn get_mempool_info(&self) -> Result<crate::mempool::MempoolInfo, Error> {
+        let txn = self.env.read_txn()?;
+        Ok(self.mempool.get_info(&txn)?)
+    }
+
+    /// Txids of the mempool transactions, with their size, fee and inputs if `verbose` is set.
+    pub fn get_raw_mempool(&self, verbose: bool) -> Result<crate::mempool::RawMempool, Error> {
+        let txn = self.env.read_txn()?;
+        Ok(self.mempool.get_raw(&txn, verbose)?)
     }
 
     pub fn get_all_transactions(&self) -> Result<Vec<AuthorizedTransaction>, Error> {
@@ -149,7 +737,7 @@ impl Node {
         &self,
         number: usize,
     ) -> Result<(Vec<AuthorizedTransaction>, u64), Error> {
-        let mut txn = self.env.write_txn()?;
+        let txn = self.env.read_txn()?;
         let transactions = self.mempool.take(&txn, number)?;
         let mut fee: u64 = 0;
         let mut returned_transactions = vec![];
@@ -158,13 +746,9 @@ impl Node {
             let inputs: HashSet<_> = transaction.transaction.inputs.iter().copied().collect();
             if !spent_utxos.is_disjoint(&inputs) {
                 println!("UTXO double spent");
-                self.mempool
-                    .delete(&mut txn, &transaction.transaction.txid())?;
                 continue;
             }
             if self.validate_transaction(&txn, transaction).is_err() {
-                self.mempool
-                    .delete(&mut txn, &transaction.transaction.txid())?;
                 continue;
             }
             let filled_transaction = self
@@ -185,51 +769,246 @@ impl Node {
             returned_transactions.push(transaction.clone());
             spent_utxos.extend(transaction.transaction.inputs.clone());
         }
-        txn.commit()?;
         Ok((returned_transactions, fee))
     }
 
+    /// Total fee a block would collect from up to `max_transactions` mempool transactions, picked
+    /// the same way as by `get_transactions`. Doesn't modify the mempool.
+    pub fn get_available_fees(&self, max_transactions: usize) -> Result<u64, Error> {
+        let (_, fee) = self.get_transactions(max_transactions)?;
+        Ok(fee)
+    }
+
     pub fn get_pending_withdrawal_bundle(&self) -> Result<Option<WithdrawalBundle>, Error> {
         let txn = self.env.read_txn()?;
         Ok(self.state.get_pending_withdrawal_bundle(&txn)?)
     }
 
+    /// Deposits that were dropped because their destination isn't a valid address.
+    pub fn get_unclaimed_deposits(
+        &self,
+    ) -> Result<Vec<(bip300301::bitcoin::OutPoint, UnclaimedDeposit)>, Error> {
+        let txn = self.env.read_txn()?;
+        Ok(self.state.get_unclaimed_deposits(&txn)?)
+    }
+
+    /// Connect a block on top of the current tip.
+    ///
+    /// Mainchain I/O happens strictly outside of LMDB transactions: two way peg data is fetched
+    /// before `connect_block` opens its write transaction, and the withdrawal bundle is broadcast
+    /// after it is committed.
     pub async fn submit_block(&self, header: &Header, body: &Body) -> Result<(), Error> {
+        // A block anchored to a reorged mainchain block would carry stale two way peg data.
+        let confirmations = self
+            .mainchain
+            .get_block_confirmations(header.prev_main_hash)
+            .await?;
+        if confirmations.is_none() {
+            return Err(Error::StaleMainchainBlock {
+                prev_main_hash: header.prev_main_hash,
+            });
+        }
         let last_deposit_block_hash = {
             let txn = self.env.read_txn()?;
             self.state.get_last_deposit_block_hash(&txn)?
         };
+        let two_way_peg_data = self
+            .mainchain
+            .get_two_way_peg_data(header.prev_main_hash, last_deposit_block_hash)
+            .await?;
         let bundle = {
-            let two_way_peg_data = self
-                .drivechain
-                .get_two_way_peg_data(header.prev_main_hash, last_deposit_block_hash)
-                .await?;
-            let mut txn = self.env.write_txn()?;
-            self.state.validate_body(&txn, &body)?;
-            self.state.connect_body(&mut txn, &body)?;
-            let height = self.archive.get_height(&txn)?;
-            self.state
-                .connect_two_way_peg_data(&mut txn, &two_way_peg_data, height)?;
-            let bundle = self.state.get_pending_withdrawal_bundle(&txn)?;
-            self.archive.append_header(&mut txn, &header)?;
-            self.archive.put_body(&mut txn, &header, &body)?;
-            for transaction in &body.transactions {
-                self.mempool.delete(&mut txn, &transaction.txid())?;
-            }
-            txn.commit()?;
-            bundle
+            let _write_guard = self.write_lock.lock().await;
+            self.connect_block(header, body, &two_way_peg_data)?
         };
+        // Sending only fails when there are no subscribers.
+        let _ = self.events.send(ChainEvent::Connect {
+            header: header.clone(),
+            body: body.clone(),
+        });
         if let Some(bundle) = bundle {
             let _ = self
-                .drivechain
+                .mainchain
                 .broadcast_withdrawal_bundle(bundle.transaction)
                 .await;
         }
         Ok(())
     }
 
+    /// Validate and connect a block from a local source, such as a trusted dump, exactly like a
+    /// block received from a peer.
+    pub async fn import_block(&self, header: &Header, body: &Body) -> Result<(), Error> {
+        self.submit_block(header, body).await
+    }
+
+    /// Import the blocks of a file written by `export_blocks`, blocks that are already known are
+    /// skipped. Returns the number of imported blocks.
+    pub async fn import_blocks(&self, path: &Path) -> Result<u32, Error> {
+        use std::io::BufRead as _;
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut imported = 0;
+        while !reader.fill_buf()?.is_empty() {
+            let (header, body): (Header, Body) = bincode::deserialize_from(&mut reader)?;
+            if self.get_header_by_hash(&header.hash())?.is_some() {
+                continue;
+            }
+            self.import_block(&header, &body).await?;
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// Write the blocks in `heights` to a file that `import_blocks` reads, as consecutive
+    /// bincode encoded `(Header, Body)` pairs. Pruned blocks are skipped.
+    pub fn export_blocks(&self, path: &Path, heights: RangeInclusive<u32>) -> Result<(), Error> {
+        use std::io::Write as _;
+        let txn = self.env.read_txn()?;
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        // Every block above the pruned height has both a header and a body.
+        let start = std::cmp::max(*heights.start(), self.archive.get_pruned_height(&txn)? + 1);
+        let heights = start..=*heights.end();
+        let headers = self.archive.iter_headers(&txn, heights.clone())?;
+        let bodies = self.archive.iter_bodies(&txn, heights)?;
+        for (header, body) in headers.zip(bodies) {
+            let ((_, header), (_, body)) = (header?, body?);
+            bincode::serialize_into(&mut writer, &(header, body))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write a block and its two way peg data to the database, returns the pending withdrawal
+    /// bundle.
+    ///
+    /// This is deliberately not async, so the write transaction can't be held across an
+    /// `.await`. Callers must hold `write_lock`.
+    fn connect_block(
+        &self,
+        header: &Header,
+        body: &Body,
+        two_way_peg_data: &bip300301::TwoWayPegData,
+    ) -> Result<Option<WithdrawalBundle>, Error> {
+        let mut txn = self.env.write_txn()?;
+        self.state.validate_header(&txn, header)?;
+        self.state.validate_body(&txn, &body)?;
+        for transaction in &body.transactions {
+            self.state
+                .validate_coinbase_maturity(&txn, transaction, header.height)?;
+        }
+        self.state.connect_body(&mut txn, &body, header.height)?;
+        let height = self.archive.get_height(&txn)?;
+        self.state
+            .connect_two_way_peg_data(&mut txn, two_way_peg_data, height)?;
+        let bundle = self.state.get_pending_withdrawal_bundle(&txn)?;
+        self.archive.append_header(&mut txn, &header)?;
+        self.archive.put_body(&mut txn, &header, &body)?;
+        for transaction in &body.transactions {
+            self.mempool.delete(&mut txn, &transaction.txid())?;
+        }
+        self.mempool.remove_conflicts(&mut txn, &self.state)?;
+        if let Some(prune_depth) = self.prune_depth {
+            self.archive.prune_bodies(&mut txn, prune_depth)?;
+        }
+        // Final blocks are never disconnected, so their undo data isn't needed anymore.
+        let finalized_height = self.finalized_height(&txn)?;
+        self.state.prune_undo(&mut txn, finalized_height)?;
+        let utxo_set_size = self.state.utxos.len(&txn)?;
+        txn.commit()?;
+        self.state.refill_utxo_cache(&self.env);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        metrics::gauge!("sidechain_utxo_set_size", utxo_set_size as f64);
+        metrics::gauge!("sidechain_last_block_time_seconds", now.as_secs_f64());
+        metrics::gauge!(
+            "sidechain_pending_withdrawal_bundle",
+            if bundle.is_some() { 1.0 } else { 0.0 }
+        );
+        Ok(bundle)
+    }
+
+    /// Disconnect blocks from the tip down to `height`, for recovering from a bad block. Fails if
+    /// any of the blocks is final, was connected before undo data was recorded or has a pruned
+    /// body.
+    pub async fn rollback_to_height(&self, height: u32) -> Result<(), Error> {
+        let events = {
+            let _write_guard = self.write_lock.lock().await;
+            self.disconnect_blocks(height)?
+        };
+        for event in events {
+            let _ = self.events.send(event);
+        }
+        Ok(())
+    }
+
+    /// Delete all blocks, chain state and mempool transactions, leaving the node as if it was just
+    /// created. The databases are cleared in a single transaction so the environment stays open.
+    pub async fn reset_chain(&self) -> Result<(), Error> {
+        let _write_guard = self.write_lock.lock().await;
+        let mut txn = self.env.write_txn()?;
+        self.state.clear(&mut txn)?;
+        self.archive.clear(&mut txn)?;
+        self.mempool.clear(&mut txn)?;
+        txn.commit()?;
+        self.state.refill_utxo_cache(&self.env);
+        Ok(())
+    }
+
+    /// Callers must hold `write_lock`.
+    fn disconnect_blocks(&self, target: u32) -> Result<Vec<ChainEvent>, Error> {
+        let mut txn = self.env.write_txn()?;
+        let finalized_height = self.finalized_height(&txn)?;
+        if target < finalized_height {
+            return Err(Error::ReorgTooDeep {
+                target,
+                finalized_height,
+            });
+        }
+        let mut events = vec![];
+        for (height, undo) in self.state.rollback_to_height(&mut txn, target)? {
+            let (header, body) = self
+                .archive
+                .disconnect_tip(&mut txn)?
+                .ok_or(crate::state::Error::NoUndoData { height })?;
+            let mut spent_utxos = HashMap::new();
+            for input in body.transactions.iter().flat_map(|t| t.inputs.iter()) {
+                if let Some(Some((output, _))) = undo.utxos.get(input) {
+                    spent_utxos.insert(*input, output.clone());
+                }
+            }
+            events.push(ChainEvent::Disconnect {
+                header,
+                body,
+                spent_utxos,
+            });
+        }
+        let height = self.archive.get_height(&txn)?;
+        if height > target {
+            return Err(crate::state::Error::NoUndoData { height }.into());
+        }
+        self.mempool.remove_conflicts(&mut txn, &self.state)?;
+        txn.commit()?;
+        self.state.refill_utxo_cache(&self.env);
+        Ok(events)
+    }
+
     pub async fn connect(&self, addr: SocketAddr) -> Result<(), Error> {
         let peer = self.net.connect(addr).await?;
+        if let Err(err) = self.handshake(&peer).await {
+            self.net.disconnect(peer.connection.stable_id()).await?;
+            return Err(err);
+        }
+        self.net.outbound.write().await.insert(addr);
+        self.spawn_peer_tasks(peer).await;
+        Ok(())
+    }
+
+    /// Spawn the request and heart beat listeners for a connection. This is the only place they
+    /// are spawned, and it does nothing if they are already running for the connection.
+    async fn spawn_peer_tasks(&self, peer: crate::net::Peer) {
+        let stable_id = peer.connection.stable_id();
+        if !self.net.listening.write().await.insert(stable_id) {
+            return;
+        }
         let peer0 = peer.clone();
         let node0 = self.clone();
         tokio::spawn(async move {
@@ -242,8 +1021,9 @@ impl Node {
                     }
                 }
             }
+            node0.net.listening.write().await.remove(&stable_id);
         });
-        let peer0 = peer.clone();
+        let peer0 = peer;
         let node0 = self.clone();
         tokio::spawn(async move {
             loop {
@@ -256,6 +1036,159 @@ impl Node {
                 }
             }
         });
+    }
+
+    /// Download the block at `height` from `peer`, as a compact block if enabled. `None` if the
+    /// peer doesn't have it.
+    async fn download_block(
+        &self,
+        peer: &crate::net::Peer,
+        height: u32,
+    ) -> Result<Option<(Header, Body)>, Error> {
+        if !self.compact_blocks {
+            return match peer.request(&Request::GetBlock { height }).await? {
+                Response::Block { header, body } => Ok(Some((header, body))),
+                _ => Ok(None),
+            };
+        }
+        let (header, coinbase, txids) =
+            match peer.request(&Request::GetCompactBlock { height }).await? {
+                Response::CompactBlock {
+                    header,
+                    coinbase,
+                    txids,
+                } => (header, coinbase, txids),
+                _ => return Ok(None),
+            };
+        let mut transactions = {
+            let txn = self.env.read_txn()?;
+            txids
+                .iter()
+                .map(|txid| self.mempool.get(&txn, txid))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let missing_indices: Vec<u32> = transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| transaction.is_none())
+            .map(|(index, _)| index as u32)
+            .collect();
+        if !missing_indices.is_empty() {
+            let request = Request::GetBlockTxs {
+                height,
+                missing_indices: missing_indices.clone(),
+            };
+            let fetched = match peer.request(&request).await? {
+                Response::BlockTxs { transactions } => transactions,
+                _ => return Ok(None),
+            };
+            if fetched.len() != missing_indices.len() {
+                return Err(crate::net::Error::InvalidBlockTxs.into());
+            }
+            for (index, transaction) in missing_indices.into_iter().zip(fetched) {
+                if transaction.transaction.txid() != txids[index as usize] {
+                    return Err(crate::net::Error::InvalidBlockTxs.into());
+                }
+                transactions[index as usize] = Some(transaction);
+            }
+        }
+        let transactions = transactions.into_iter().flatten().collect();
+        Ok(Some((
+            header,
+            Body::with_transaction_order(transactions, coinbase),
+        )))
+    }
+
+    /// Download and connect the block at `height` from `peer`, penalizing the peer if the block
+    /// is invalid.
+    async fn sync_block(&self, peer: &crate::net::Peer, height: u32) {
+        let addr = peer.connection.remote_address();
+        let (header, body) = match self.download_block(peer, height).await {
+            Ok(Some(block)) => block,
+            Ok(None) => return,
+            // Already penalized by `Peer::request`.
+            Err(Error::Net(crate::net::Error::RequestTimeout { .. })) => return,
+            Err(Error::Net(
+                err @ (crate::net::Error::InvalidBlockTxs | crate::net::Error::Bincode(_)),
+            )) => {
+                println!("invalid block {height} from {addr}: {err:?}");
+                peer.misbehaving(crate::net::INVALID_BLOCK_PENALTY, "sent an invalid block");
+                return;
+            }
+            Err(err) => {
+                println!("failed to download block {height} from {addr}: {err:?}");
+                return;
+            }
+        };
+        if let Err(err) = self.submit_block(&header, &body).await {
+            println!("failed to connect block {height} from {addr}: {err:?}");
+            if err.is_invalid_block() {
+                peer.misbehaving(crate::net::INVALID_BLOCK_PENALTY, "sent an invalid block");
+            }
+        }
+    }
+
+    /// Exchange `Hello` with a peer we dialed, closing the connection if its protocol version is
+    /// incompatible.
+    async fn handshake(&self, peer: &crate::net::Peer) -> Result<(), Error> {
+        let hello = Request::Hello {
+            version: crate::net::PROTOCOL_VERSION,
+            best_height: self.get_height()?,
+        };
+        let (version, best_height) = match peer.request(&hello).await? {
+            Response::Hello {
+                version,
+                best_height,
+            } => (version, best_height),
+            _ => return Err(crate::net::Error::UnexpectedHandshakeResponse.into()),
+        };
+        if version != crate::net::PROTOCOL_VERSION {
+            peer.connection.close(
+                crate::net::quinn::VarInt::from_u32(2),
+                b"incompatible protocol version",
+            );
+            return Err(crate::net::Error::IncompatibleVersion { version }.into());
+        }
+        self.set_node_id(peer).await?;
+        *peer.version.write().await = Some(version);
+        *peer.state.write().await = Some(PeerState {
+            version,
+            block_height: best_height,
+            mempool_size: 0,
+        });
+        Ok(())
+    }
+
+    /// Record the node id of a peer's certificate after `Hello`, closing the connection if
+    /// another one to the same node is already open.
+    async fn set_node_id(&self, peer: &crate::net::Peer) -> Result<(), Error> {
+        let node_id = peer.certified_node_id()?;
+        if let Err(err) = self.net.set_node_id(peer, node_id).await {
+            peer.connection
+                .close(crate::net::quinn::VarInt::from_u32(1), b"already connected");
+            self.net.disconnect(peer.connection.stable_id()).await?;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Close the connection to a peer and stop redialing it.
+    pub async fn disconnect(&self, addr: SocketAddr) -> Result<(), Error> {
+        self.net.outbound.write().await.remove(&addr);
+        let stable_id = self
+            .net
+            .peers
+            .read()
+            .await
+            .values()
+            .find(|peer| peer.connection.remote_address() == addr)
+            .map(|peer| peer.connection.stable_id());
+        if let Some(stable_id) = stable_id {
+            if let Some(peer) = self.net.disconnect(stable_id).await? {
+                peer.connection
+                    .close(crate::net::quinn::VarInt::from_u32(0), b"disconnected");
+            }
+        }
         Ok(())
     }
 
@@ -273,7 +1206,21 @@ impl Node {
                 return Err(crate::net::Error::from(err).into());
             }
         };
-        let state: PeerState = bincode::deserialize(&message)?;
+        let state: PeerState = match crate::net::deserialize_message(&message) {
+            Ok(state) => state,
+            Err(err) => {
+                println!("ignoring malformed heart beat: {err}");
+                return Ok(());
+            }
+        };
+        if state.version != crate::net::PROTOCOL_VERSION {
+            println!(
+                "ignoring heart beat with protocol version {} != {}",
+                state.version,
+                crate::net::PROTOCOL_VERSION
+            );
+            return Ok(());
+        }
         *peer.state.write().await = Some(state);
         Ok(())
     }
@@ -284,19 +1231,70 @@ impl Node {
             .accept_bi()
             .await
             .map_err(crate::net::Error::from)?;
-        let data = recv
-            .read_to_end(crate::net::READ_LIMIT)
-            .await
-            .map_err(crate::net::Error::from)?;
-        let message: Request = bincode::deserialize(&data)?;
+        let data = match recv.read_to_end(crate::net::READ_LIMIT).await {
+            Ok(data) => data,
+            Err(crate::net::quinn::ReadToEndError::TooLong) => {
+                peer.misbehaving(crate::net::INVALID_REQUEST_PENALTY, "request too large");
+                return Ok(());
+            }
+            Err(err) => return Err(crate::net::Error::from(err).into()),
+        };
+        let message: Request = match crate::net::deserialize_message(&data) {
+            Ok(message) => message,
+            Err(err) => {
+                println!(
+                    "malformed request from {}: {err}",
+                    peer.connection.remote_address()
+                );
+                peer.misbehaving(crate::net::INVALID_REQUEST_PENALTY, "malformed request");
+                return Ok(());
+            }
+        };
+        // Peers have to say hello before anything else.
+        if !matches!(message, Request::Hello { .. }) && peer.version.read().await.is_none() {
+            peer.connection.close(
+                crate::net::quinn::VarInt::from_u32(4),
+                b"handshake required",
+            );
+            return Err(crate::net::Error::HandshakeRequired.into());
+        }
         match message {
+            Request::Hello {
+                version,
+                best_height,
+            } => {
+                let response = Response::Hello {
+                    version: crate::net::PROTOCOL_VERSION,
+                    best_height: self.get_height()?,
+                };
+                let response = bincode::serialize(&response)?;
+                send.write_all(&response)
+                    .await
+                    .map_err(crate::net::Error::from)?;
+                send.finish().await.map_err(crate::net::Error::from)?;
+                if version != crate::net::PROTOCOL_VERSION {
+                    peer.connection.close(
+                        crate::net::quinn::VarInt::from_u32(2),
+                        b"incompatible protocol version",
+                    );
+                    return Err(crate::net::Error::IncompatibleVersion { version }.into());
+                }
+                self.set_node_id(peer).await?;
+                *peer.version.write().await = Some(version);
+                *peer.state.write().await = Some(PeerState {
+                    version,
+                    block_height: best_height,
+                    mempool_size: 0,
+                });
+            }
             Request::GetBlock { height } => {
                 let (header, body) = {
                     let txn = self.env.read_txn()?;
-                    (
-                        self.archive.get_header(&txn, height)?,
-                        self.archive.get_body(&txn, height)?,
-                    )
+                    let body = match self.archive.get_body(&txn, height) {
+                        Err(crate::archive::Error::Pruned { .. }) => None,
+                        body => body?,
+                    };
+                    (self.archive.get_header(&txn, height)?, body)
                 };
                 let response = match (header, body) {
                     (Some(header), Some(body)) => Response::Block { header, body },
@@ -308,43 +1306,142 @@ impl Node {
                     .map_err(crate::net::Error::from)?;
                 send.finish().await.map_err(crate::net::Error::from)?;
             }
-            Request::PushTransaction { transaction } => {
-                let valid = {
+            Request::GetCompactBlock { height } => {
+                let (header, body) = {
                     let txn = self.env.read_txn()?;
-                    self.validate_transaction(&txn, &transaction)
+                    let body = match self.archive.get_body(&txn, height) {
+                        Err(crate::archive::Error::Pruned { .. }) => None,
+                        body => body?,
+                    };
+                    (self.archive.get_header(&txn, height)?, body)
                 };
-                match valid {
-                    Err(err) => {
-                        let response = Response::TransactionRejected;
-                        let response = bincode::serialize(&response)?;
-                        send.write_all(&response)
-                            .await
-                            .map_err(crate::net::Error::from)?;
-                        return Err(err.into());
+                let response = match (header, body) {
+                    (Some(header), Some(body)) => Response::CompactBlock {
+                        header,
+                        txids: body.transactions.iter().map(Transaction::txid).collect(),
+                        coinbase: body.coinbase,
+                    },
+                    (_, _) => Response::NoBlock,
+                };
+                let response = bincode::serialize(&response)?;
+                send.write_all(&response)
+                    .await
+                    .map_err(crate::net::Error::from)?;
+                send.finish().await.map_err(crate::net::Error::from)?;
+            }
+            Request::GetBlockTxs {
+                height,
+                missing_indices,
+            } => {
+                let body = {
+                    let txn = self.env.read_txn()?;
+                    match self.archive.get_body(&txn, height) {
+                        Err(crate::archive::Error::Pruned { .. }) => None,
+                        body => body?,
                     }
-                    Ok(_) => {
-                        {
-                            let mut txn = self.env.write_txn()?;
+                };
+                let transactions = body.and_then(|body| {
+                    let transactions = body.authorized_transactions();
+                    missing_indices
+                        .iter()
+                        .map(|index| transactions.get(*index as usize).cloned())
+                        .collect::<Option<Vec<_>>>()
+                });
+                let response = match transactions {
+                    Some(transactions) => Response::BlockTxs { transactions },
+                    None => Response::NoBlock,
+                };
+                let response = bincode::serialize(&response)?;
+                send.write_all(&response)
+                    .await
+                    .map_err(crate::net::Error::from)?;
+                send.finish().await.map_err(crate::net::Error::from)?;
+            }
+            Request::GetHeadersFromLocator { locator, stop } => {
+                let headers = {
+                    let txn = self.env.read_txn()?;
+                    let fork_height = self.archive.find_fork_point(&txn, &locator)?;
+                    self.archive
+                        .get_headers_after(&txn, fork_height, stop, Self::MAX_HEADERS)?
+                };
+                let response = Response::Headers { headers };
+                let response = bincode::serialize(&response)?;
+                send.write_all(&response)
+                    .await
+                    .map_err(crate::net::Error::from)?;
+                send.finish().await.map_err(crate::net::Error::from)?;
+            }
+            Request::PushTransaction { transaction } => {
+                let allowed = peer
+                    .push_transaction_bucket
+                    .lock()
+                    .unwrap()
+                    .try_take(&self.push_transaction_limit, Instant::now());
+                if !allowed {
+                    let response = Response::TransactionRejected;
+                    let response = bincode::serialize(&response)?;
+                    send.write_all(&response)
+                        .await
+                        .map_err(crate::net::Error::from)?;
+                    send.finish().await.map_err(crate::net::Error::from)?;
+                    if peer.misbehaving(
+                        crate::net::THROTTLE_PENALTY,
+                        "transaction rate limit exceeded",
+                    ) {
+                        return Err(crate::net::Error::RateLimited.into());
+                    }
+                    return Ok(());
+                }
+                let txid = transaction.transaction.txid();
+                // Peers relay transactions to all of their peers, so transactions we already have
+                // keep arriving.
+                let known = {
+                    let txn = self.env.read_txn()?;
+                    self.mempool
+                        .transactions
+                        .get(&txn, (&txid).into())?
+                        .is_some()
+                };
+                let result = if known {
+                    Ok(false)
+                } else {
+                    let _write_guard = self.write_lock.lock().await;
+                    let mut txn = self.env.write_txn()?;
+                    let result = self
+                        .validate_transaction(&txn, &transaction)
+                        .and_then(|fee| {
                             println!("adding transaction to mempool: {:?}", &transaction);
-                            self.mempool.put(&mut txn, &transaction)?;
-                            txn.commit()?;
-                        }
-                        for peer0 in self.net.peers.read().await.values() {
-                            if peer0.connection.stable_id() == peer.connection.stable_id() {
-                                continue;
-                            }
-                            peer0
-                                .request(&Request::PushTransaction {
-                                    transaction: transaction.clone(),
-                                })
-                                .await?;
+                            let height = self.archive.get_height(&txn)?;
+                            Ok(self.mempool.put(&mut txn, &transaction, fee, height)?)
+                        });
+                    if result.is_ok() {
+                        txn.commit()?;
+                    }
+                    result.map(|()| true)
+                };
+                let response = match result {
+                    Ok(_) => Response::TransactionAccepted,
+                    Err(_) => Response::TransactionRejected,
+                };
+                let response = bincode::serialize(&response)?;
+                send.write_all(&response)
+                    .await
+                    .map_err(crate::net::Error::from)?;
+                send.finish().await.map_err(crate::net::Error::from)?;
+                match result {
+                    Ok(true) => {
+                        self.broadcast_transaction(&transaction, Some(peer.connection.stable_id()))
+                            .await;
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        println!(
+                            "rejected transaction {txid} from {}: {err:?}",
+                            peer.connection.remote_address()
+                        );
+                        if let Some(penalty) = err.rejected_transaction_penalty() {
+                            peer.misbehaving(penalty, "sent an invalid transaction");
                         }
-                        let response = Response::TransactionAccepted;
-                        let response = bincode::serialize(&response)?;
-                        send.write_all(&response)
-                            .await
-                            .map_err(crate::net::Error::from)?;
-                        return Ok(());
                     }
                 }
             }
@@ -353,9 +1450,20 @@ impl Node {
     }
 
     pub fn run(&mut self) -> Result<(), Error> {
+        // Bound here so that a bad address fails `run` instead of a background task.
+        let health_listener = match self.health_addr {
+            Some(health_addr) => {
+                let listener = std::net::TcpListener::bind(health_addr)?;
+                listener.set_nonblocking(true)?;
+                self.health_addr = Some(listener.local_addr()?);
+                Some(tokio::net::TcpListener::from_std(listener)?)
+            }
+            None => None,
+        };
+
         // Listening to connections.
         let node = self.clone();
-        tokio::spawn(async move {
+        let accept_task = tokio::spawn(async move {
             loop {
                 let incoming_conn = node.net.server.accept().await.unwrap();
                 let connection = incoming_conn.await.unwrap();
@@ -377,63 +1485,124 @@ impl Node {
                     connection.remote_address(),
                     connection.stable_id(),
                 );
-                let peer = crate::net::Peer {
-                    state: Arc::new(RwLock::new(None)),
-                    connection,
-                };
-                let node0 = node.clone();
-                let peer0 = peer.clone();
-                tokio::spawn(async move {
-                    loop {
-                        match node0.peer_listen(&peer0).await {
-                            Ok(_) => {}
-                            Err(err) => {
-                                println!("{:?}", err);
-                                break;
-                            }
-                        }
-                    }
-                });
-                let node0 = node.clone();
-                let peer0 = peer.clone();
-                tokio::spawn(async move {
-                    loop {
-                        match node0.heart_beat_listen(&peer0).await {
-                            Ok(_) => {}
-                            Err(err) => {
-                                println!("{:?}", err);
-                                break;
-                            }
-                        }
-                    }
-                });
+                let peer = crate::net::Peer::new(connection, node.net.request_timeout);
                 node.net
                     .peers
                     .write()
                     .await
-                    .insert(peer.connection.stable_id(), peer);
+                    .insert(peer.connection.stable_id(), peer.clone());
+                node.spawn_peer_tasks(peer).await;
             }
         });
 
-        // Heart beat.
+        // Redial outbound peers whose connection dropped, with exponential backoff.
         let node = self.clone();
-        tokio::spawn(async move {
+        let redial_task = tokio::spawn(async move {
+            const MIN_BACKOFF: Duration = Duration::from_secs(1);
+            const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+            // addr -> (next attempt, backoff)
+            let mut backoffs = HashMap::<SocketAddr, (Instant, Duration)>::new();
             loop {
-                for peer in node.net.peers.read().await.values() {
-                    let block_height = {
-                        let txn = node.env.read_txn().unwrap();
-                        node.archive.get_height(&txn).unwrap()
-                    };
-                    let state = PeerState { block_height };
-                    peer.heart_beat(&state).unwrap();
+                let outbound: HashSet<SocketAddr> =
+                    node.net.outbound.read().await.iter().copied().collect();
+                let connected: HashSet<SocketAddr> = node
+                    .net
+                    .peers
+                    .read()
+                    .await
+                    .values()
+                    .map(|peer| peer.connection.remote_address())
+                    .collect();
+                backoffs.retain(|addr, _| outbound.contains(addr) && !connected.contains(addr));
+                for addr in outbound.difference(&connected) {
+                    let (next_attempt, backoff) = backoffs
+                        .entry(*addr)
+                        .or_insert((Instant::now(), MIN_BACKOFF));
+                    if Instant::now() < *next_attempt {
+                        continue;
+                    }
+                    match node.connect(*addr).await {
+                        Ok(()) => {
+                            println!("reconnected to {addr}");
+                            backoffs.remove(addr);
+                        }
+                        Err(err) => {
+                            println!("failed to reconnect to {addr}: {err:?}");
+                            *next_attempt = Instant::now() + *backoff;
+                            *backoff = std::cmp::min(*backoff * 2, MAX_BACKOFF);
+                        }
+                    }
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+
+        // Heart beat, only sent when our state changed. Datagrams can be lost, so the state is
+        // also resent periodically.
+        let node = self.clone();
+        let heart_beat_task = tokio::spawn(async move {
+            const HEART_BEAT_REFRESH: Duration = Duration::from_secs(30);
+            // stable_id -> (last sent state, time it was sent)
+            let mut last_sent = HashMap::<usize, (PeerState, Instant)>::new();
+            loop {
+                let state = {
+                    let txn = node.env.read_txn().unwrap();
+                    PeerState {
+                        version: crate::net::PROTOCOL_VERSION,
+                        block_height: node.archive.get_height(&txn).unwrap(),
+                        mempool_size: node.mempool.len(&txn).unwrap(),
+                    }
+                };
+                metrics::gauge!("sidechain_height", state.block_height as f64);
+                metrics::gauge!("sidechain_mempool_size", state.mempool_size as f64);
+                let peers = node.net.peers.read().await;
+                metrics::gauge!("sidechain_peers", peers.len() as f64);
+                last_sent.retain(|stable_id, _| peers.contains_key(stable_id));
+                let mut failed = vec![];
+                for (stable_id, peer) in peers.iter() {
+                    if let Some((last_state, sent_at)) = last_sent.get(stable_id) {
+                        if *last_state == state && sent_at.elapsed() < HEART_BEAT_REFRESH {
+                            continue;
+                        }
+                    }
+                    if let Err(err) = peer.heart_beat(&state) {
+                        println!(
+                            "failed to send heart beat to {}: {err}",
+                            peer.connection.remote_address()
+                        );
+                        failed.push(*stable_id);
+                        continue;
+                    }
+                    last_sent.insert(*stable_id, (state.clone(), Instant::now()));
+                }
+                drop(peers);
+                for stable_id in failed {
+                    if let Ok(Some(peer)) = node.net.disconnect(stable_id).await {
+                        peer.connection
+                            .close(crate::net::quinn::VarInt::from_u32(0), b"heart beat failed");
+                    }
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
             }
         });
 
+        // Evict transactions that stayed in the mempool for too long.
+        if let Some(mempool_ttl) = self.mempool_ttl {
+            let node = self.clone();
+            tokio::spawn(async move {
+                const EXPIRE_INTERVAL: Duration = Duration::from_secs(10);
+                loop {
+                    if let Err(err) = node.expire_mempool(mempool_ttl).await {
+                        println!("failed to expire mempool transactions: {err:?}");
+                    }
+                    tokio::time::sleep(EXPIRE_INTERVAL).await;
+                }
+            });
+        }
+
         // Request missing headers.
         let node = self.clone();
-        tokio::spawn(async move {
+        let sync_task = tokio::spawn(async move {
             loop {
                 for peer in node.net.peers.read().await.values() {
                     if let Some(state) = &peer.state.read().await.as_ref() {
@@ -442,31 +1611,63 @@ impl Node {
                             node.archive.get_height(&txn).unwrap()
                         };
                         if state.block_height > height {
-                            let response = peer
-                                .request(&Request::GetBlock { height: height + 1 })
-                                .await
-                                .unwrap();
-                            match response {
-                                Response::Block { header, body } => {
-                                    println!("got new header {:?}", &header);
-                                    node.submit_block(&header, &body).await.unwrap();
-                                }
-                                Response::NoBlock => {}
-                                Response::TransactionAccepted => {}
-                                Response::TransactionRejected => {}
-                            };
+                            node.sync_block(peer, height + 1).await;
                         }
                     }
                 }
                 tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             }
         });
+        self.tasks
+            .lock()
+            .unwrap()
+            .extend([accept_task, redial_task, heart_beat_task, sync_task]);
+
+        if let Some(listener) = health_listener {
+            tokio::spawn(crate::health::serve(self.clone(), listener));
+        }
         Ok(())
     }
 }
 
 pub trait CustomError {}
 
+impl Error {
+    /// Misbehavior for a peer whose pushed transaction was rejected with this error, `None` if the
+    /// peer isn't at fault.
+    fn rejected_transaction_penalty(&self) -> Option<u32> {
+        match self {
+            // The peer may be on another tip, or have seen a conflicting transaction first.
+            Self::State(
+                crate::state::Error::NoUtxo { .. }
+                | crate::state::Error::UtxoDoubleSpent
+                | crate::state::Error::ImmatureCoinbase { .. },
+            )
+            | Self::MemPool(crate::mempool::Error::UtxoDoubleSpent) => None,
+            // Relay policy differs between nodes.
+            Self::MemPool(
+                crate::mempool::Error::FeeTooLow { .. }
+                | crate::mempool::Error::MinFeeOverflow { .. },
+            ) => Some(crate::net::POLICY_PENALTY),
+            Self::State(crate::state::Error::Heed(_))
+            | Self::MemPool(crate::mempool::Error::Heed(_)) => None,
+            Self::State(_) | Self::MemPool(_) => Some(crate::net::INVALID_TRANSACTION_PENALTY),
+            _ => None,
+        }
+    }
+
+    /// Whether connecting a block failed because of the block itself rather than this node, so
+    /// that the peer that sent it is at fault.
+    fn is_invalid_block(&self) -> bool {
+        match self {
+            Self::State(crate::state::Error::Heed(_))
+            | Self::Archive(crate::archive::Error::Heed(_)) => false,
+            Self::State(_) | Self::Archive(_) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("heed error")]
@@ -481,10 +1682,51 @@ pub enum Error {
     Archive(#[from] crate::archive::Error),
     #[error("drivechain error")]
     Drivechain(#[from] bip300301::Error),
+    #[error("mainchain error")]
+    Mainchain(#[from] crate::mainchain::Error),
     #[error("mempool error")]
     MemPool(#[from] crate::mempool::Error),
     #[error("state error")]
     State(#[from] crate::state::Error),
+    #[error("database version error")]
+    Versioned(#[from] crate::versioned::Error),
     #[error("bincode error")]
     Bincode(#[from] bincode::Error),
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to build thread pool")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("mainchain block {prev_main_hash} is not on the mainchain best chain")]
+    StaleMainchainBlock {
+        prev_main_hash: bip300301::bitcoin::BlockHash,
+    },
+    #[error("can't disconnect blocks down to height {target}, blocks up to {finalized_height} are final")]
+    ReorgTooDeep { target: u32, finalized_height: u32 },
+    #[error("another instance is running with datadir {}", .datadir.display())]
+    AlreadyRunning { datadir: std::path::PathBuf },
+    #[error("transaction {txid} spends {} outputs that don't exist or are spent", .missing.len())]
+    MissingInputs { txid: Txid, missing: Vec<OutPoint> },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("heed error")]
+    Heed(#[from] heed::Error),
+    #[error("archive error")]
+    Archive(#[from] crate::archive::Error),
+    #[error("header at height {height} is missing")]
+    MissingHeader { height: u32 },
+    #[error("body at height {height} is missing")]
+    MissingBody { height: u32 },
+    #[error("header at height {height} has a wrong height")]
+    InvalidHeight { height: u32 },
+    #[error("invalid previous side hash at height {height}")]
+    InvalidPrevSideHash { height: u32 },
+    #[error("invalid merkle root at height {height}")]
+    InvalidMerkleRoot { height: u32 },
+    #[error("invalid authorization at height {height}")]
+    Authorization {
+        height: u32,
+        source: crate::authorization::Error,
+    },
 }
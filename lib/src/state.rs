@@ -1,41 +1,342 @@
 use crate::authorization::Authorization;
+use crate::consensus::ConsensusParams;
 use crate::types::*;
+use crate::versioned::SerdeVersioned;
 use bip300301::TwoWayPegData;
 use bip300301::{bitcoin, WithdrawalBundleStatus};
 pub use heed;
 use heed::types::*;
 use heed::{Database, RoTxn, RwTxn};
-use std::collections::{HashMap, HashSet};
+use lru::LruCache;
+use std::collections::{btree_map, BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+/// State overwritten by connecting a block, enough to disconnect it again.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BlockUndo {
+    /// Output and height every outpoint touched by the block had before, `None` if it didn't
+    /// exist.
+    pub utxos: BTreeMap<OutPoint, Option<(Output, u32)>>,
+    /// Peg state before the block's two way peg data was connected.
+    pub peg: Option<PegUndo>,
+    /// Unclaimed deposits recorded by the block.
+    pub unclaimed_deposits: Vec<bitcoin::OutPoint>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PegUndo {
+    pub pending_withdrawal_bundle: Option<WithdrawalBundle>,
+    pub pending_withdrawal_bundle_height: Option<u32>,
+    pub last_withdrawal_bundle_failure_height: Option<u32>,
+    pub last_deposit_block: Option<bitcoin::BlockHash>,
+}
+
+/// Order independent hash of a set of UTXOs, so that UTXOs can be added and removed without
+/// rehashing the whole set.
+///
+/// This is a lattice hash: every UTXO is expanded to `UTXO_SET_LANES` 16 bit lanes with the blake3
+/// XOF, and the set is the lane-wise wrapping sum of its UTXOs. The lanes are wide enough that
+/// finding two sets with the same sum is infeasible.
+#[derive(Clone, PartialEq, Eq)]
+struct UtxoSetAccumulator([u16; UTXO_SET_LANES]);
+
+const UTXO_SET_LANES: usize = 1024;
+
+impl UtxoSetAccumulator {
+    fn empty() -> Self {
+        Self([0; UTXO_SET_LANES])
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 2 * UTXO_SET_LANES {
+            return None;
+        }
+        let mut lanes = [0; UTXO_SET_LANES];
+        for (lane, bytes) in lanes.iter_mut().zip(bytes.chunks_exact(2)) {
+            *lane = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Some(Self(lanes))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|lane| lane.to_le_bytes()).collect()
+    }
+
+    fn utxo_lanes(outpoint: &OutPoint, output: &Output) -> [u16; UTXO_SET_LANES] {
+        let serialized = bincode::serialize(&(outpoint, output))
+            .expect("failed to serialize a type to compute a hash");
+        let mut bytes = [0; 2 * UTXO_SET_LANES];
+        blake3::Hasher::new()
+            .update(&serialized)
+            .finalize_xof()
+            .fill(&mut bytes);
+        let mut lanes = [0; UTXO_SET_LANES];
+        for (lane, bytes) in lanes.iter_mut().zip(bytes.chunks_exact(2)) {
+            *lane = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        lanes
+    }
+
+    fn insert(&mut self, outpoint: &OutPoint, output: &Output) {
+        let lanes = Self::utxo_lanes(outpoint, output);
+        for (lane, utxo_lane) in self.0.iter_mut().zip(lanes) {
+            *lane = lane.wrapping_add(utxo_lane);
+        }
+    }
+
+    fn remove(&mut self, outpoint: &OutPoint, output: &Output) {
+        let lanes = Self::utxo_lanes(outpoint, output);
+        for (lane, utxo_lane) in self.0.iter_mut().zip(lanes) {
+            *lane = lane.wrapping_sub(utxo_lane);
+        }
+    }
+
+    fn hash(&self) -> Hash {
+        blake3::hash(&self.to_bytes()).into()
+    }
+}
+
+/// UTXOs as they are in one version of the UTXO set.
+struct UtxoCache {
+    /// Accumulator of the UTXO set the entries were read from, `None` while they are refilled.
+    /// Transactions that see another set, such as snapshots opened before the latest block was
+    /// connected, bypass the cache.
+    accumulator: Option<Vec<u8>>,
+    utxos: LruCache<OutPoint, Output>,
+}
 
 #[derive(Clone)]
 pub struct State {
-    pub utxos: Database<SerdeBincode<OutPoint>, SerdeBincode<Output>>,
-    pub pending_withdrawal_bundle: Database<OwnedType<u32>, SerdeBincode<WithdrawalBundle>>,
+    pub utxos: Database<SerdeBincode<OutPoint>, SerdeVersioned<Output>>,
+    pub pending_withdrawal_bundle: Database<OwnedType<u32>, SerdeVersioned<WithdrawalBundle>>,
+    /// Height at which the pending withdrawal bundle was collected, which its inputs commitment
+    /// commits to.
+    pub pending_withdrawal_bundle_height: Database<OwnedType<u32>, OwnedType<u32>>,
     pub last_withdrawal_bundle_failure_height: Database<OwnedType<u32>, OwnedType<u32>>,
-    pub last_deposit_block: Database<OwnedType<u32>, SerdeBincode<bitcoin::BlockHash>>,
+    pub last_deposit_block: Database<OwnedType<u32>, SerdeVersioned<bitcoin::BlockHash>>,
+    /// Height of the block in which each UTXO was created.
+    pub utxo_heights: Database<SerdeBincode<OutPoint>, OwnedType<u32>>,
+    /// Deposits with a destination that doesn't parse as an address.
+    pub unclaimed_deposits:
+        Database<SerdeBincode<bitcoin::OutPoint>, SerdeVersioned<UnclaimedDeposit>>,
+    /// What each connected block overwrote, keyed by big endian height.
+    pub undo: Database<OwnedType<[u8; 4]>, SerdeVersioned<BlockUndo>>,
+    /// `UtxoSetAccumulator` of `utxos`, updated with every write to it.
+    utxo_set_accumulator: Database<OwnedType<u32>, ByteSlice>,
+    /// Recently written UTXOs. Entries are evicted whenever the `utxos` database is written to,
+    /// and only filled from committed state by `refill_utxo_cache`, so the cache never serves a
+    /// spent UTXO or one from an aborted transaction.
+    utxo_cache: Arc<Mutex<UtxoCache>>,
+    /// Outpoints written since the last `refill_utxo_cache`.
+    written_utxos: Arc<Mutex<HashSet<OutPoint>>>,
+    /// Pool that signatures are verified in, `None` uses the global rayon pool.
+    verification_pool: Option<Arc<rayon::ThreadPool>>,
+    consensus_params: ConsensusParams,
 }
 
 impl State {
-    pub const NUM_DBS: u32 = 4;
-    pub const WITHDRAWAL_BUNDLE_FAILURE_GAP: u32 = 4;
+    pub const NUM_DBS: u32 = 9;
+    pub const UTXO_CACHE_SIZE: usize = 100_000;
+    /// Maximum size of a single UTXO read by `import_snapshot`.
+    pub const SNAPSHOT_ENTRY_LIMIT: u64 = 1024;
 
-    pub fn new(env: &heed::Env) -> Result<Self, Error> {
+    pub fn new(env: &heed::Env, consensus_params: ConsensusParams) -> Result<Self, Error> {
         let utxos = env.create_database(Some("utxos"))?;
 
         let pending_withdrawal_bundle = env.create_database(Some("pending_withdrawal_bundle"))?;
+        let pending_withdrawal_bundle_height =
+            env.create_database(Some("pending_withdrawal_bundle_height"))?;
         let last_withdrawal_bundle_failure_height =
             env.create_database(Some("last_withdrawal_bundle_failure_height"))?;
         let last_deposit_block = env.create_database(Some("last_deposit_block"))?;
-        Ok(Self {
+        let utxo_heights = env.create_database(Some("utxo_heights"))?;
+        let unclaimed_deposits = env.create_database(Some("unclaimed_deposits"))?;
+        let undo = env.create_database(Some("undo"))?;
+        let utxo_set_accumulator = env.create_database(Some("utxo_set_accumulator"))?;
+        let utxo_cache_size =
+            NonZeroUsize::new(Self::UTXO_CACHE_SIZE).expect("utxo cache size must be non zero");
+        let utxo_cache = Arc::new(Mutex::new(UtxoCache {
+            accumulator: None,
+            utxos: LruCache::new(utxo_cache_size),
+        }));
+        let state = Self {
             utxos,
             pending_withdrawal_bundle,
+            pending_withdrawal_bundle_height,
             last_withdrawal_bundle_failure_height,
             last_deposit_block,
-        })
+            utxo_heights,
+            unclaimed_deposits,
+            undo,
+            utxo_set_accumulator,
+            utxo_cache,
+            written_utxos: Arc::new(Mutex::new(HashSet::new())),
+            verification_pool: None,
+            consensus_params,
+        };
+        // UTXO sets written before the accumulator existed are hashed once.
+        let mut txn = env.write_txn()?;
+        if state.utxo_set_accumulator.get(&txn, &0)?.is_none() {
+            let mut accumulator = UtxoSetAccumulator::empty();
+            for item in state.utxos.iter(&txn)? {
+                let (outpoint, output) = item?;
+                accumulator.insert(&outpoint, &output);
+            }
+            state.put_utxo_set_accumulator(&mut txn, &accumulator)?;
+        }
+        txn.commit()?;
+        Ok(state)
+    }
+
+    /// Verify signatures in `pool` instead of the global rayon pool.
+    pub fn set_verification_pool(&mut self, pool: Arc<rayon::ThreadPool>) {
+        self.verification_pool = Some(pool);
+    }
+
+    /// Run `op` in the verification pool, rayon parallel iterators inside it use its threads.
+    pub fn in_verification_pool<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match &self.verification_pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    pub fn get_utxo(&self, txn: &RoTxn, outpoint: &OutPoint) -> Result<Option<Output>, Error> {
+        let accumulator = self.utxo_set_accumulator.get(txn, &0)?;
+        {
+            let mut utxo_cache = self.utxo_cache.lock().unwrap();
+            if accumulator.is_some() && utxo_cache.accumulator.as_deref() == accumulator {
+                if let Some(output) = utxo_cache.utxos.get(outpoint) {
+                    return Ok(Some(output.clone()));
+                }
+            }
+        }
+        // Not cached here, `txn` may be a write transaction that is aborted.
+        let output = self.utxos.get(txn, outpoint)?;
+        Ok(output)
+    }
+
+    fn put_utxo(
+        &self,
+        txn: &mut RwTxn,
+        undo: &mut BlockUndo,
+        outpoint: &OutPoint,
+        output: &Output,
+        height: u32,
+    ) -> Result<(), Error> {
+        self.record_utxo(txn, undo, outpoint)?;
+        self.write_utxo(txn, outpoint, Some((output, height)))
     }
 
-    pub fn get_utxos(&self, txn: &RoTxn) -> Result<HashMap<OutPoint, Output>, Error> {
+    fn delete_utxo(
+        &self,
+        txn: &mut RwTxn,
+        undo: &mut BlockUndo,
+        outpoint: &OutPoint,
+    ) -> Result<(), Error> {
+        self.record_utxo(txn, undo, outpoint)?;
+        self.write_utxo(txn, outpoint, None)
+    }
+
+    /// Save the value an outpoint had before the block first touched it.
+    fn record_utxo(
+        &self,
+        txn: &RoTxn,
+        undo: &mut BlockUndo,
+        outpoint: &OutPoint,
+    ) -> Result<(), Error> {
+        if let btree_map::Entry::Vacant(entry) = undo.utxos.entry(*outpoint) {
+            let output = self.utxos.get(txn, outpoint)?;
+            let height = self.utxo_heights.get(txn, outpoint)?;
+            entry.insert(output.map(|output| (output, height.unwrap_or(0))));
+        }
+        Ok(())
+    }
+
+    fn write_utxo(
+        &self,
+        txn: &mut RwTxn,
+        outpoint: &OutPoint,
+        utxo: Option<(&Output, u32)>,
+    ) -> Result<(), Error> {
+        self.utxo_cache.lock().unwrap().utxos.pop(outpoint);
+        self.written_utxos.lock().unwrap().insert(*outpoint);
+        let mut accumulator = self.get_utxo_set_accumulator(txn)?;
+        if let Some(previous) = self.utxos.get(txn, outpoint)? {
+            accumulator.remove(outpoint, &previous);
+        }
+        if let Some((output, _)) = utxo {
+            accumulator.insert(outpoint, output);
+        }
+        self.put_utxo_set_accumulator(txn, &accumulator)?;
+        match utxo {
+            Some((output, height)) => {
+                self.utxos.put(txn, outpoint, output)?;
+                self.utxo_heights.put(txn, outpoint, &height)?;
+            }
+            None => {
+                self.utxos.delete(txn, outpoint)?;
+                self.utxo_heights.delete(txn, outpoint)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Cache the UTXOs written since the last call as they are in the latest committed state.
+    /// Should be called after a write transaction that touched the `utxos` database is committed
+    /// or aborted, before another one is started.
+    pub fn refill_utxo_cache(&self, env: &heed::Env) {
+        let written = std::mem::take(&mut *self.written_utxos.lock().unwrap());
+        let refill = || -> Result<(), Error> {
+            let txn = env.read_txn()?;
+            // Entries are from the new set while they are refilled, so they can't be served to
+            // transactions that see the old one.
+            self.utxo_cache.lock().unwrap().accumulator = None;
+            for outpoint in &written {
+                let output = self.utxos.get(&txn, outpoint)?;
+                let mut utxo_cache = self.utxo_cache.lock().unwrap();
+                match output {
+                    Some(output) => utxo_cache.utxos.put(*outpoint, output),
+                    None => utxo_cache.utxos.pop(outpoint),
+                };
+            }
+            let accumulator = self.utxo_set_accumulator.get(&txn, &0)?;
+            self.utxo_cache.lock().unwrap().accumulator = accumulator.map(<[u8]>::to_vec);
+            Ok(())
+        };
+        // Without knowing what was written, nothing cached can be trusted.
+        if refill().is_err() {
+            self.clear_utxo_cache();
+        }
+    }
+
+    /// Drop all cached UTXOs.
+    pub fn clear_utxo_cache(&self) {
+        self.utxo_cache.lock().unwrap().utxos.clear();
+    }
+
+    /// Look up several UTXOs at once, `None` means the outpoint is spent or doesn't exist.
+    pub fn get_utxos(
+        &self,
+        txn: &RoTxn,
+        outpoints: &[OutPoint],
+    ) -> Result<HashMap<OutPoint, Option<Output>>, Error> {
+        let mut utxos = HashMap::with_capacity(outpoints.len());
+        for outpoint in outpoints {
+            let output = self.get_utxo(txn, outpoint)?;
+            utxos.insert(*outpoint, output);
+        }
+        Ok(utxos)
+    }
+
+    /// Height of the block in which a UTXO was created, `None` if it is spent or doesn't exist.
+    pub fn get_utxo_height(&self, txn: &RoTxn, outpoint: &OutPoint) -> Result<Option<u32>, Error> {
+        Ok(self.utxo_heights.get(txn, outpoint)?)
+    }
+
+    pub fn get_all_utxos(&self, txn: &RoTxn) -> Result<HashMap<OutPoint, Output>, Error> {
         let mut utxos = HashMap::new();
         for item in self.utxos.iter(txn)? {
             let (outpoint, output) = item?;
@@ -44,6 +345,87 @@ impl State {
         Ok(utxos)
     }
 
+    fn get_utxo_set_accumulator(&self, txn: &RoTxn) -> Result<UtxoSetAccumulator, Error> {
+        match self.utxo_set_accumulator.get(txn, &0)? {
+            Some(bytes) => UtxoSetAccumulator::from_bytes(bytes).ok_or(Error::CorruptUtxoSetHash),
+            None => Ok(UtxoSetAccumulator::empty()),
+        }
+    }
+
+    fn put_utxo_set_accumulator(
+        &self,
+        txn: &mut RwTxn,
+        accumulator: &UtxoSetAccumulator,
+    ) -> Result<(), Error> {
+        self.utxo_set_accumulator
+            .put(txn, &0, &accumulator.to_bytes())?;
+        Ok(())
+    }
+
+    /// Hash of the UTXO set, committed to by `Header::commitment`. It is maintained as UTXOs are
+    /// written, so this doesn't read the set.
+    pub fn utxo_set_hash(&self, txn: &RoTxn) -> Result<Hash, Error> {
+        Ok(self.get_utxo_set_accumulator(txn)?.hash())
+    }
+
+    /// Write every UTXO and the height it was created at to `writer`, in the format read by
+    /// `import_snapshot`.
+    pub fn export_snapshot(
+        &self,
+        txn: &RoTxn,
+        mut writer: impl std::io::Write,
+    ) -> Result<(), Error> {
+        bincode::serialize_into(&mut writer, &self.utxos.len(txn)?)?;
+        for item in self.utxos.iter(txn)? {
+            let (outpoint, output) = item?;
+            // UTXOs created before heights were recorded have none.
+            let height = self.utxo_heights.get(txn, &outpoint)?.unwrap_or(0);
+            bincode::serialize_into(&mut writer, &(outpoint, output, height))?;
+        }
+        Ok(())
+    }
+
+    /// Replace the UTXO set with one written by `export_snapshot`, which must be the UTXO set
+    /// that the trusted `header` is connected on top of.
+    ///
+    /// Fails with `Error::WrongCommitment` if the snapshot doesn't hash to the header's
+    /// commitment, the transaction must then be aborted. Peg state and undo data are left
+    /// untouched.
+    pub fn import_snapshot(
+        &self,
+        txn: &mut RwTxn,
+        mut reader: impl std::io::Read,
+        header: &Header,
+    ) -> Result<(), Error> {
+        use bincode::Options as _;
+        let commitment = header.commitment.ok_or(Error::NoCommitment)?;
+        // Entries come from a peer, so every one is decoded with a size limit. The length is
+        // read from the snapshot too, so UTXOs are decoded one at a time instead of allocated
+        // for up front.
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(Self::SNAPSHOT_ENTRY_LIMIT);
+        self.utxos.clear(txn)?;
+        self.utxo_heights.clear(txn)?;
+        self.put_utxo_set_accumulator(txn, &UtxoSetAccumulator::empty())?;
+        self.clear_utxo_cache();
+        let len: u64 = options.deserialize_from(&mut reader)?;
+        for _ in 0..len {
+            let (outpoint, output, height): (OutPoint, Output, u32) =
+                options.deserialize_from(&mut reader)?;
+            self.write_utxo(txn, &outpoint, Some((&output, height)))?;
+        }
+        let utxo_set_hash = self.utxo_set_hash(txn)?;
+        if commitment != utxo_set_hash {
+            return Err(Error::WrongCommitment {
+                commitment: hex::encode(commitment),
+                utxo_set_hash: hex::encode(utxo_set_hash),
+            });
+        }
+        Ok(())
+    }
+
     pub fn get_utxos_by_addresses(
         &self,
         txn: &RoTxn,
@@ -67,8 +449,7 @@ impl State {
         let mut spent_utxos = vec![];
         for input in &transaction.inputs {
             let utxo = self
-                .utxos
-                .get(txn, input)?
+                .get_utxo(txn, input)?
                 .ok_or(Error::NoUtxo { outpoint: *input })?;
             spent_utxos.push(utxo);
         }
@@ -78,20 +459,37 @@ impl State {
         })
     }
 
+    /// Whether a status the mainchain reported for the bundle with `txid` in the block at
+    /// `block_height` can apply to the pending `bundle`. The reported bundle must be the pending
+    /// one, which has to have been collected in an earlier block, at the height its inputs
+    /// commitment commits to, so that failing it restores exactly the UTXOs the mainchain saw
+    /// committed to.
+    fn bundle_status_applies(
+        &self,
+        txn: &RoTxn,
+        bundle: &WithdrawalBundle,
+        txid: &bitcoin::Txid,
+        block_height: u32,
+    ) -> Result<bool, Error> {
+        // The txid covers every output of the reported bundle, so a bundle with any other inputs
+        // commitment doesn't match.
+        if bundle.transaction.txid() != *txid {
+            return Ok(false);
+        }
+        match self.pending_withdrawal_bundle_height.get(txn, &0)? {
+            Some(height) => Ok(height < block_height && bundle.verify_commitment(height)),
+            // Bundles collected before heights were recorded can only be matched by txid.
+            None => Ok(true),
+        }
+    }
+
     fn collect_withdrawal_bundle(
         &self,
         txn: &RoTxn,
         block_height: u32,
     ) -> Result<Option<WithdrawalBundle>, Error> {
         use bitcoin::blockdata::{opcodes, script};
-        // Weight of a bundle with 0 outputs.
-        const BUNDLE_0_WEIGHT: u64 = 504;
-        // Weight of a single output.
-        const OUTPUT_WEIGHT: u64 = 128;
-        // Turns out to be 3121.
-        const MAX_BUNDLE_OUTPUTS: usize = ((bitcoin::policy::MAX_STANDARD_TX_WEIGHT as u64
-            - BUNDLE_0_WEIGHT)
-            / OUTPUT_WEIGHT) as usize;
+        let max_bundle_outputs = self.consensus_params.max_bundle_outputs();
 
         // Aggregate all outputs by destination.
         // destination -> (value, mainchain fee, spent_utxos)
@@ -101,12 +499,8 @@ impl State {
         >::new();
         for item in self.utxos.iter(txn)? {
             let (outpoint, output) = item?;
-            if let Content::Withdrawal {
-                value,
-                ref main_address,
-                main_fee,
-            } = output.content
-            {
+            if let Some((main_address, main_fee)) = output.content.withdrawal() {
+                let value = output.get_value();
                 let aggregated = address_to_aggregated_withdrawal
                     .entry(main_address.clone())
                     .or_insert(AggregatedWithdrawal {
@@ -134,7 +528,7 @@ impl State {
         let mut spent_utxos = HashMap::<OutPoint, Output>::new();
         let mut bundle_outputs = vec![];
         for aggregated in &aggregated_withdrawals {
-            if bundle_outputs.len() > MAX_BUNDLE_OUTPUTS {
+            if bundle_outputs.len() > max_bundle_outputs {
                 break;
             }
             let bundle_output = bitcoin::TxOut {
@@ -172,17 +566,8 @@ impl State {
             script_pubkey: script,
         };
         // Create inputs commitment.
-        let inputs: Vec<OutPoint> = [
-            // Commit to inputs.
-            spent_utxos.keys().copied().collect(),
-            // Commit to block height.
-            vec![OutPoint::Regular {
-                txid: [0; 32].into(),
-                vout: block_height,
-            }],
-        ]
-        .concat();
-        let commitment = hash(&inputs);
+        let commitment =
+            WithdrawalBundle::compute_inputs_commitment(spent_utxos.keys(), block_height);
         let script = script::Builder::new()
             .push_opcode(opcodes::all::OP_RETURN)
             .push_slice(&commitment)
@@ -205,10 +590,10 @@ impl State {
             ]
             .concat(),
         };
-        if transaction.weight().to_wu() > bitcoin::policy::MAX_STANDARD_TX_WEIGHT as u64 {
+        if transaction.weight().to_wu() > self.consensus_params.max_bundle_weight {
             Err(Error::BundleTooHeavy {
                 weight: transaction.weight().to_wu(),
-                max_weight: bitcoin::policy::MAX_STANDARD_TX_WEIGHT as u64,
+                max_weight: self.consensus_params.max_bundle_weight,
             })?;
         }
         Ok(Some(WithdrawalBundle {
@@ -224,13 +609,39 @@ impl State {
         Ok(self.pending_withdrawal_bundle.get(txn, &0)?)
     }
 
+    pub fn get_unclaimed_deposits(
+        &self,
+        txn: &RoTxn,
+    ) -> Result<Vec<(bitcoin::OutPoint, UnclaimedDeposit)>, Error> {
+        let mut unclaimed_deposits = vec![];
+        for item in self.unclaimed_deposits.iter(txn)? {
+            unclaimed_deposits.push(item?);
+        }
+        Ok(unclaimed_deposits)
+    }
+
     pub fn validate_filled_transaction(
         &self,
         transaction: &FilledTransaction,
     ) -> Result<u64, Error> {
+        let mut inputs = HashSet::with_capacity(transaction.transaction.inputs.len());
+        for input in &transaction.transaction.inputs {
+            if !inputs.insert(input) {
+                return Err(Error::DuplicateInput { outpoint: *input });
+            }
+        }
         let mut value_in: u64 = 0;
         let mut value_out: u64 = 0;
-        for utxo in &transaction.spent_utxos {
+        for (input, utxo) in transaction
+            .transaction
+            .inputs
+            .iter()
+            .zip(&transaction.spent_utxos)
+        {
+            // Locked outputs are only consumed by withdrawal bundles.
+            if utxo.content.is_locked() {
+                return Err(Error::SpendsLockedOutput { outpoint: *input });
+            }
             value_in += utxo.get_value();
         }
         for output in &transaction.transaction.outputs {
@@ -242,9 +653,65 @@ impl State {
         Ok(value_in - value_out)
     }
 
+    /// Check that the coinbase outputs `transaction` spends are mature in the block at `height`.
+    pub fn validate_coinbase_maturity(
+        &self,
+        txn: &RoTxn,
+        transaction: &Transaction,
+        height: u32,
+    ) -> Result<(), Error> {
+        for input in &transaction.inputs {
+            if let OutPoint::Coinbase { .. } = input {
+                // UTXOs created before heights were recorded are old enough to be mature.
+                let utxo_height = self.utxo_heights.get(txn, input)?.unwrap_or(0);
+                if !crate::consensus::is_coinbase_mature(utxo_height, height) {
+                    return Err(Error::ImmatureCoinbase {
+                        outpoint: *input,
+                        utxo_height,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the header's commitment, if any, against the UTXO set it is connected on top of.
+    pub fn validate_header(&self, txn: &RoTxn, header: &Header) -> Result<(), Error> {
+        let Some(commitment) = header.commitment else {
+            return Ok(());
+        };
+        let utxo_set_hash = self.utxo_set_hash(txn)?;
+        if commitment != utxo_set_hash {
+            return Err(Error::WrongCommitment {
+                commitment: hex::encode(commitment),
+                utxo_set_hash: hex::encode(utxo_set_hash),
+            });
+        }
+        Ok(())
+    }
+
     pub fn validate_body(&self, txn: &RoTxn, body: &Body) -> Result<u64, Error> {
+        let max_coinbase_outputs = self.consensus_params.max_coinbase_outputs;
+        if body.coinbase.len() > max_coinbase_outputs {
+            return Err(Error::TooManyCoinbaseOutputs {
+                outputs: body.coinbase.len(),
+                max_outputs: max_coinbase_outputs,
+            });
+        }
+        // Authorizations are zipped with the spent UTXOs below, a mismatch would go unnoticed
+        // there.
+        let inputs = body.get_inputs().len();
+        if inputs != body.authorizations.len() {
+            return Err(Error::WrongBodyAuthorizationCount {
+                inputs,
+                authorizations: body.authorizations.len(),
+            });
+        }
         let mut coinbase_value: u64 = 0;
-        for output in &body.coinbase {
+        for (vout, output) in body.coinbase.iter().enumerate() {
+            if output.content.is_locked() {
+                return Err(Error::WithdrawalInCoinbase { vout });
+            }
             coinbase_value += output.get_value();
         }
         let mut total_fees: u64 = 0;
@@ -274,9 +741,7 @@ impl State {
                 return Err(Error::WrongPubKeyForAddress);
             }
         }
-        if Authorization::verify_body(body).is_err() {
-            return Err(Error::AuthorizationError);
-        }
+        self.in_verification_pool(|| Authorization::verify_body(body))?;
         Ok(total_fees)
     }
 
@@ -293,18 +758,65 @@ impl State {
         two_way_peg_data: &TwoWayPegData,
         block_height: u32,
     ) -> Result<(), Error> {
+        let key = (block_height + 1).to_be_bytes();
+        let mut undo = self.undo.get(txn, &key)?.unwrap_or_default();
+        if undo.peg.is_none() {
+            undo.peg = Some(PegUndo {
+                pending_withdrawal_bundle: self.pending_withdrawal_bundle.get(txn, &0)?,
+                pending_withdrawal_bundle_height: self
+                    .pending_withdrawal_bundle_height
+                    .get(txn, &0)?,
+                last_withdrawal_bundle_failure_height: self
+                    .last_withdrawal_bundle_failure_height
+                    .get(txn, &0)?,
+                last_deposit_block: self.last_deposit_block.get(txn, &0)?,
+            });
+        }
         // Handle deposits.
         if let Some(deposit_block_hash) = two_way_peg_data.deposit_block_hash {
             self.last_deposit_block.put(txn, &0, &deposit_block_hash)?;
         }
         for (outpoint, deposit) in &two_way_peg_data.deposits {
-            if let Ok(address) = deposit.address.parse() {
-                let outpoint = OutPoint::Deposit(*outpoint);
-                let output = Output {
-                    address,
-                    content: Content::Value(deposit.value),
-                };
-                self.utxos.put(txn, &outpoint, &output)?;
+            // The mainchain node computes deposit values from burn totals, so a value no deposit
+            // can have, or a deposit that was already connected, would mint coins that were
+            // never burnt.
+            if deposit.value == 0 || deposit.value > bitcoin::Amount::MAX_MONEY.to_sat() {
+                println!(
+                    "ignoring deposit {outpoint} with invalid value {}",
+                    deposit.value
+                );
+                continue;
+            }
+            if self.get_utxo(txn, &OutPoint::Deposit(*outpoint))?.is_some()
+                || self.unclaimed_deposits.get(txn, outpoint)?.is_some()
+            {
+                println!("ignoring deposit {outpoint}, it was already connected");
+                continue;
+            }
+            match deposit.address.parse() {
+                Ok(address) => {
+                    let output = Output {
+                        address,
+                        content: Content::Value(deposit.value),
+                    };
+                    self.put_utxo(
+                        txn,
+                        &mut undo,
+                        &OutPoint::Deposit(*outpoint),
+                        &output,
+                        block_height + 1,
+                    )?;
+                }
+                // Recorded for operators, see `get_unclaimed_deposits`.
+                Err(_) => {
+                    let unclaimed = UnclaimedDeposit {
+                        address: deposit.address.clone(),
+                        value: deposit.value,
+                        height: block_height + 1,
+                    };
+                    undo.unclaimed_deposits.push(*outpoint);
+                    self.unclaimed_deposits.put(txn, outpoint, &unclaimed)?;
+                }
             }
         }
 
@@ -314,19 +826,21 @@ impl State {
             .get(txn, &0)?
             .unwrap_or(0);
         if (block_height + 1) - last_withdrawal_bundle_failure_height
-            > Self::WITHDRAWAL_BUNDLE_FAILURE_GAP
+            > self.consensus_params.withdrawal_bundle_failure_gap
             && self.pending_withdrawal_bundle.get(txn, &0)?.is_none()
         {
             if let Some(bundle) = self.collect_withdrawal_bundle(txn, block_height + 1)? {
                 for outpoint in bundle.spent_utxos.keys() {
-                    self.utxos.delete(txn, outpoint)?;
+                    self.delete_utxo(txn, &mut undo, outpoint)?;
                 }
                 self.pending_withdrawal_bundle.put(txn, &0, &bundle)?;
+                self.pending_withdrawal_bundle_height
+                    .put(txn, &0, &(block_height + 1))?;
             }
         }
         for (txid, status) in &two_way_peg_data.bundle_statuses {
             if let Some(bundle) = self.pending_withdrawal_bundle.get(txn, &0)? {
-                if bundle.transaction.txid() != *txid {
+                if !self.bundle_status_applies(txn, &bundle, txid, block_height + 1)? {
                     continue;
                 }
                 match status {
@@ -337,61 +851,198 @@ impl State {
                             &(block_height + 1),
                         )?;
                         self.pending_withdrawal_bundle.delete(txn, &0)?;
+                        self.pending_withdrawal_bundle_height.delete(txn, &0)?;
                         for (outpoint, output) in &bundle.spent_utxos {
-                            self.utxos.put(txn, outpoint, output)?;
+                            self.put_utxo(txn, &mut undo, outpoint, output, block_height + 1)?;
                         }
                     }
                     WithdrawalBundleStatus::Confirmed => {
                         self.pending_withdrawal_bundle.delete(txn, &0)?;
+                        self.pending_withdrawal_bundle_height.delete(txn, &0)?;
                     }
                 }
             }
         }
+        self.undo.put(txn, &key, &undo)?;
         Ok(())
     }
 
-    pub fn connect_body(&self, txn: &mut RwTxn, body: &Body) -> Result<(), Error> {
+    pub fn connect_body(&self, txn: &mut RwTxn, body: &Body, height: u32) -> Result<(), Error> {
+        let key = height.to_be_bytes();
+        let mut undo = self.undo.get(txn, &key)?.unwrap_or_default();
         let merkle_root = body.compute_merkle_root();
         for (vout, output) in body.coinbase.iter().enumerate() {
             let outpoint = OutPoint::Coinbase {
                 merkle_root,
                 vout: vout as u32,
             };
-            self.utxos.put(txn, &outpoint, output)?;
+            self.put_utxo(txn, &mut undo, &outpoint, output, height)?;
         }
         for transaction in &body.transactions {
             let txid = transaction.txid();
             for input in &transaction.inputs {
-                self.utxos.delete(txn, input)?;
+                self.delete_utxo(txn, &mut undo, input)?;
             }
             for (vout, output) in transaction.outputs.iter().enumerate() {
                 let outpoint = OutPoint::Regular {
                     txid,
                     vout: vout as u32,
                 };
-                self.utxos.put(txn, &outpoint, output)?;
+                self.put_utxo(txn, &mut undo, &outpoint, output, height)?;
             }
         }
+        self.undo.put(txn, &key, &undo)?;
         Ok(())
     }
+
+    /// Undo the body and two way peg data connected at `height`, which must be the tip.
+    pub fn disconnect_block(&self, txn: &mut RwTxn, height: u32) -> Result<BlockUndo, Error> {
+        let key = height.to_be_bytes();
+        let undo = self
+            .undo
+            .get(txn, &key)?
+            .ok_or(Error::NoUndoData { height })?;
+        for (outpoint, utxo) in &undo.utxos {
+            let utxo = utxo.as_ref().map(|(output, height)| (output, *height));
+            self.write_utxo(txn, outpoint, utxo)?;
+        }
+        if let Some(peg) = &undo.peg {
+            match &peg.pending_withdrawal_bundle {
+                Some(bundle) => self.pending_withdrawal_bundle.put(txn, &0, bundle)?,
+                None => {
+                    self.pending_withdrawal_bundle.delete(txn, &0)?;
+                }
+            }
+            match peg.pending_withdrawal_bundle_height {
+                Some(bundle_height) => {
+                    self.pending_withdrawal_bundle_height
+                        .put(txn, &0, &bundle_height)?
+                }
+                None => {
+                    self.pending_withdrawal_bundle_height.delete(txn, &0)?;
+                }
+            }
+            match peg.last_withdrawal_bundle_failure_height {
+                Some(failure_height) => {
+                    self.last_withdrawal_bundle_failure_height
+                        .put(txn, &0, &failure_height)?
+                }
+                None => {
+                    self.last_withdrawal_bundle_failure_height.delete(txn, &0)?;
+                }
+            }
+            match &peg.last_deposit_block {
+                Some(block_hash) => self.last_deposit_block.put(txn, &0, block_hash)?,
+                None => {
+                    self.last_deposit_block.delete(txn, &0)?;
+                }
+            }
+        }
+        for outpoint in &undo.unclaimed_deposits {
+            self.unclaimed_deposits.delete(txn, outpoint)?;
+        }
+        self.undo.delete(txn, &key)?;
+        Ok(undo)
+    }
+
+    /// Delete all UTXOs, peg state and undo data.
+    pub fn clear(&self, txn: &mut RwTxn) -> Result<(), Error> {
+        self.utxos.clear(txn)?;
+        self.pending_withdrawal_bundle.clear(txn)?;
+        self.pending_withdrawal_bundle_height.clear(txn)?;
+        self.last_withdrawal_bundle_failure_height.clear(txn)?;
+        self.last_deposit_block.clear(txn)?;
+        self.utxo_heights.clear(txn)?;
+        self.unclaimed_deposits.clear(txn)?;
+        self.undo.clear(txn)?;
+        self.put_utxo_set_accumulator(txn, &UtxoSetAccumulator::empty())?;
+        self.clear_utxo_cache();
+        Ok(())
+    }
+
+    /// Drop the undo data of blocks up to `height`, they can't be disconnected afterwards.
+    pub fn prune_undo(&self, txn: &mut RwTxn, height: u32) -> Result<(), Error> {
+        self.undo.delete_range(txn, &(..=height.to_be_bytes()))?;
+        Ok(())
+    }
+
+    /// Disconnect blocks from the tip down to `target`, restoring UTXOs and peg state. Returns
+    /// the undo data of every disconnected block, tip first.
+    ///
+    /// Only blocks connected since undo data started being recorded can be disconnected.
+    pub fn rollback_to_height(
+        &self,
+        txn: &mut RwTxn,
+        target: u32,
+    ) -> Result<Vec<(u32, BlockUndo)>, Error> {
+        let tip = match self.undo.last(txn)? {
+            Some((key, _)) => u32::from_be_bytes(key),
+            None => return Ok(vec![]),
+        };
+        let mut undos = vec![];
+        for height in (target + 1..=tip).rev() {
+            let undo = self.disconnect_block(txn, height)?;
+            undos.push((height, undo));
+        }
+        Ok(undos)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("failed to verify authorization")]
     AuthorizationError,
+    #[error("authorization error")]
+    Authorization(#[from] crate::authorization::Error),
     #[error("heed error")]
     Heed(#[from] heed::Error),
     #[error("utxo {outpoint} doesn't exist")]
     NoUtxo { outpoint: OutPoint },
+    #[error("no undo data for block at height {height}")]
+    NoUndoData { height: u32 },
     #[error("value in is less than value out")]
     NotEnoughValueIn,
     #[error("total fees less than coinbase value")]
     NotEnoughFees,
+    #[error("utxo {outpoint} is spent more than once by the same transaction")]
+    DuplicateInput { outpoint: OutPoint },
+    #[error("utxo {outpoint} is a withdrawal output and can't be spent by a transaction")]
+    SpendsLockedOutput { outpoint: OutPoint },
+    #[error("utxo {outpoint} is a coinbase output created at height {utxo_height} that isn't mature yet")]
+    ImmatureCoinbase {
+        outpoint: OutPoint,
+        utxo_height: u32,
+    },
     #[error("utxo double spent")]
     UtxoDoubleSpent,
     #[error("wrong public key for address")]
     WrongPubKeyForAddress,
+    #[error("transaction {txid} has {inputs} inputs but {authorizations} authorizations")]
+    WrongAuthorizationCount {
+        txid: Txid,
+        inputs: usize,
+        authorizations: usize,
+    },
+    #[error("body has {inputs} inputs but {authorizations} authorizations")]
+    WrongBodyAuthorizationCount {
+        inputs: usize,
+        authorizations: usize,
+    },
+    #[error("coinbase has {outputs} outputs, more than the maximum of {max_outputs}")]
+    TooManyCoinbaseOutputs { outputs: usize, max_outputs: usize },
+    #[error("coinbase output {vout} is a withdrawal")]
+    WithdrawalInCoinbase { vout: usize },
+    #[error("bincode error")]
+    Bincode(#[from] bincode::Error),
+    #[error("stored utxo set hash is corrupt")]
+    CorruptUtxoSetHash,
+    #[error("header has no utxo set commitment")]
+    NoCommitment,
+    #[error("header commits to utxo set {commitment} but it is {utxo_set_hash}")]
+    WrongCommitment {
+        commitment: String,
+        utxo_set_hash: String,
+    },
     #[error("bundle too heavy {weight} > {max_weight}")]
     BundleTooHeavy { weight: u64, max_weight: u64 },
 }
@@ -0,0 +1,262 @@
+//! In-memory stand-ins for external dependencies, for tests that shouldn't need a mainchain node.
+use crate::mainchain::{Deposits, Error, Mainchain, RpcMethod};
+use crate::node::{Node, NodeConfig};
+use crate::types::{hash, BlockHash};
+use bip300301::{bitcoin, TwoWayPegData, WithdrawalBundleStatus};
+use bitcoin::hashes::Hash as _;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Scriptable mainchain.
+///
+/// Deposits are served in the order they were pushed, each batch for the first mainchain block it
+/// is queried at, and for that block to every later query so that all nodes connecting a block see
+/// the same deposits. Bundle statuses accumulate and are served to every query. BMM requests are
+/// confirmed by the next call to `generate`.
+#[derive(Clone, Default)]
+pub struct MockMainchain {
+    inner: Arc<Mutex<MockMainchainState>>,
+}
+
+struct MockMainchainState {
+    /// Hashes of the blocks on the best chain, starting with genesis.
+    best_chain: Vec<bitcoin::BlockHash>,
+    /// Number of blocks ever mined, so that blocks replacing reorged ones get new hashes.
+    blocks_mined: u32,
+    /// Deposits pushed but not yet queried.
+    queued_deposits: VecDeque<Deposits>,
+    /// Deposits served for each queried mainchain block.
+    deposits: HashMap<bitcoin::BlockHash, Deposits>,
+    bundle_statuses: HashMap<bitcoin::Txid, WithdrawalBundleStatus>,
+    /// Simulated latency of two way peg data queries.
+    query_delay: Duration,
+    pending_bmm: Vec<BlockHash>,
+    /// (prev main hash, critical hash) of confirmed BMM requests.
+    confirmed_bmm: HashSet<(bitcoin::BlockHash, BlockHash)>,
+    withdrawal_bundles: Vec<bitcoin::Transaction>,
+    /// RPC methods to fail as if the mainchain node didn't know them.
+    missing_methods: HashSet<RpcMethod>,
+}
+
+impl MockMainchainState {
+    fn tip(&self) -> bitcoin::BlockHash {
+        *self
+            .best_chain
+            .last()
+            .expect("best chain always contains genesis")
+    }
+
+    fn mine_block(&mut self) {
+        self.blocks_mined += 1;
+        let block_hash = hash(&(self.tip(), self.blocks_mined));
+        self.best_chain
+            .push(bitcoin::BlockHash::from_byte_array(block_hash));
+    }
+
+    fn require(&self, method: RpcMethod) -> Result<(), Error> {
+        if self.missing_methods.contains(&method) {
+            return Err(Error::MethodNotFound(method));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MockMainchainState {
+    fn default() -> Self {
+        Self {
+            best_chain: vec![bitcoin::BlockHash::all_zeros()],
+            blocks_mined: 0,
+            queued_deposits: VecDeque::new(),
+            deposits: HashMap::new(),
+            bundle_statuses: HashMap::new(),
+            query_delay: Duration::ZERO,
+            pending_bmm: vec![],
+            confirmed_bmm: HashSet::new(),
+            withdrawal_bundles: vec![],
+            missing_methods: HashSet::new(),
+        }
+    }
+}
+
+impl MockMainchain {
+    /// Queue deposits for the next mainchain block that deposits are queried at, and set bundle
+    /// statuses from now on.
+    pub fn push_two_way_peg_data(&self, two_way_peg_data: TwoWayPegData) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.queued_deposits.push_back((
+            two_way_peg_data.deposits,
+            two_way_peg_data.deposit_block_hash,
+        ));
+        inner
+            .bundle_statuses
+            .extend(two_way_peg_data.bundle_statuses);
+    }
+
+    /// Make each deposit and bundle status query take `delay`.
+    pub fn set_query_delay(&self, delay: Duration) {
+        self.inner.lock().unwrap().query_delay = delay;
+    }
+
+    async fn query_delay(&self) {
+        let delay = self.inner.lock().unwrap().query_delay;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Replace the tip with `tip`.
+    pub fn set_tip(&self, tip: bitcoin::BlockHash) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(last) = inner.best_chain.last_mut() {
+            *last = tip;
+        }
+    }
+
+    /// Replace the last `depth` blocks of the best chain with as many new blocks. Pending BMM
+    /// requests aren't confirmed by the new blocks.
+    pub fn reorg(&self, depth: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let fork_height = inner.best_chain.len().saturating_sub(depth).max(1);
+        let depth = inner.best_chain.len() - fork_height;
+        inner.best_chain.truncate(fork_height);
+        for _ in 0..depth {
+            inner.mine_block();
+        }
+    }
+
+    /// Fail every call to `method` as if the mainchain node didn't support it.
+    pub fn remove_rpc_method(&self, method: RpcMethod) {
+        self.inner.lock().unwrap().missing_methods.insert(method);
+    }
+
+    /// Withdrawal bundles broadcast so far.
+    pub fn withdrawal_bundles(&self) -> Vec<bitcoin::Transaction> {
+        self.inner.lock().unwrap().withdrawal_bundles.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Mainchain for MockMainchain {
+    async fn get_mainchain_tip(&self) -> Result<bitcoin::BlockHash, Error> {
+        let inner = self.inner.lock().unwrap();
+        inner.require(RpcMethod::GetBestBlockHash)?;
+        Ok(inner.tip())
+    }
+
+    async fn get_deposit_outputs(
+        &self,
+        end: bitcoin::BlockHash,
+        _start: Option<bitcoin::BlockHash>,
+    ) -> Result<Deposits, Error> {
+        self.query_delay().await;
+        let mut inner = self.inner.lock().unwrap();
+        inner.require(RpcMethod::ListSidechainDepositsByBlock)?;
+        if let Some(deposits) = inner.deposits.get(&end) {
+            return Ok(deposits.clone());
+        }
+        let deposits = inner.queued_deposits.pop_front().unwrap_or_default();
+        inner.deposits.insert(end, deposits.clone());
+        Ok(deposits)
+    }
+
+    async fn get_withdrawal_bundle_statuses(
+        &self,
+    ) -> Result<HashMap<bitcoin::Txid, WithdrawalBundleStatus>, Error> {
+        self.query_delay().await;
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.bundle_statuses.clone())
+    }
+
+    async fn broadcast_withdrawal_bundle(
+        &self,
+        transaction: bitcoin::Transaction,
+    ) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.withdrawal_bundles.push(transaction);
+        Ok(())
+    }
+
+    async fn attempt_bmm(
+        &self,
+        _amount: u64,
+        _height: u32,
+        critical_hash: BlockHash,
+        _sidechain_number: u8,
+        _prev_main_hash: bitcoin::BlockHash,
+    ) -> Result<bitcoin::Txid, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.require(RpcMethod::CreateBmmCriticalDataTx)?;
+        inner.pending_bmm.push(critical_hash);
+        Ok(bitcoin::Txid::from_byte_array(hash(&critical_hash)))
+    }
+
+    async fn verify_bmm(
+        &self,
+        prev_main_hash: &bitcoin::BlockHash,
+        critical_hash: BlockHash,
+    ) -> Result<(), Error> {
+        let inner = self.inner.lock().unwrap();
+        inner.require(RpcMethod::VerifyBmm)?;
+        if !inner
+            .confirmed_bmm
+            .contains(&(*prev_main_hash, critical_hash))
+        {
+            if *prev_main_hash == inner.tip() {
+                return Err(Error::NoNextBlock {
+                    prev_main_hash: *prev_main_hash,
+                });
+            }
+            return Err(Error::NotBmmVerified {
+                prev_main_hash: *prev_main_hash,
+                critical_hash,
+            });
+        }
+        Ok(())
+    }
+
+    async fn generate(&self, blocks: u32) -> Result<(), Error> {
+        let mut inner = self.inner.lock().unwrap();
+        for _ in 0..blocks {
+            let prev_main_hash = inner.tip();
+            let pending_bmm = std::mem::take(&mut inner.pending_bmm);
+            for critical_hash in pending_bmm {
+                inner.confirmed_bmm.insert((prev_main_hash, critical_hash));
+            }
+            inner.mine_block();
+        }
+        Ok(())
+    }
+
+    async fn get_block_confirmations(
+        &self,
+        block_hash: bitcoin::BlockHash,
+    ) -> Result<Option<u32>, Error> {
+        let inner = self.inner.lock().unwrap();
+        let Some(height) = inner.best_chain.iter().position(|hash| *hash == block_hash) else {
+            return Ok(None);
+        };
+        Ok(Some((inner.best_chain.len() - height) as u32))
+    }
+}
+
+/// Node with a `MockMainchain` and default settings, listening on a random local port.
+pub fn node(datadir: &Path, mainchain: &MockMainchain) -> Node<MockMainchain> {
+    Node::with_mainchain(
+        datadir,
+        "127.0.0.1:0".parse().unwrap(),
+        mainchain.clone(),
+        NodeConfig::default(),
+    )
+    .expect("failed to open node")
+}
+
+/// Regtest mainchain address for withdrawal outputs.
+pub fn main_address() -> bitcoin::Address<bitcoin::address::NetworkUnchecked> {
+    let main_address = bitcoin::Address::new(
+        bitcoin::Network::Regtest,
+        bitcoin::address::Payload::PubkeyHash(bitcoin::PubkeyHash::all_zeros()),
+    );
+    main_address.as_unchecked().clone()
+}
@@ -8,6 +8,19 @@ impl Address {
             .with_check()
             .into_string()
     }
+
+    /// Abbreviated address for display, the first and last 4 characters of the base58 encoding
+    /// so that the checksum stays visible.
+    pub fn short(self) -> String {
+        let base58 = self.to_base58();
+        let chars: Vec<char> = base58.chars().collect();
+        if chars.len() <= 8 {
+            return base58;
+        }
+        let first: String = chars[..4].iter().collect();
+        let last: String = chars[chars.len() - 4..].iter().collect();
+        format!("{first}…{last}")
+    }
 }
 
 impl std::fmt::Display for Address {
@@ -44,7 +44,16 @@ impl std::fmt::Debug for BlockHash {
     }
 }
 
-#[derive(Default, Clone, Copy, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+impl std::str::FromStr for BlockHash {
+    type Err = hex::FromHexError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(hex::FromHex::from_hex(s)?))
+    }
+}
+
+#[derive(
+    Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, serde::Serialize, serde::Deserialize,
+)]
 pub struct MerkleRoot(Hash);
 
 impl From<Hash> for MerkleRoot {
@@ -59,6 +68,13 @@ impl From<MerkleRoot> for Hash {
     }
 }
 
+impl std::str::FromStr for MerkleRoot {
+    type Err = hex::FromHexError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(hex::FromHex::from_hex(s)?))
+    }
+}
+
 impl std::fmt::Display for MerkleRoot {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", hex::encode(self.0))
@@ -71,7 +87,9 @@ impl std::fmt::Debug for MerkleRoot {
     }
 }
 
-#[derive(Default, Clone, Copy, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+#[derive(
+    Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, serde::Serialize, serde::Deserialize,
+)]
 pub struct Txid(pub Hash);
 
 impl Txid {
@@ -110,6 +128,17 @@ impl std::fmt::Debug for Txid {
     }
 }
 
+impl std::str::FromStr for Txid {
+    type Err = hex::FromHexError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(hex::FromHex::from_hex(s)?))
+    }
+}
+
+pub fn serialized_size<T: serde::Serialize>(data: &T) -> u64 {
+    bincode::serialized_size(data).expect("failed to compute serialized size of a type")
+}
+
 pub fn hash<T: serde::Serialize>(data: &T) -> Hash {
     let data_serialized =
         bincode::serialize(data).expect("failed to serialize a type to compute a hash");
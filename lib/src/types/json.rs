@@ -0,0 +1,265 @@
+//! Human readable JSON representation of blocks for explorers and debugging.
+//!
+//! Hashes are hex, addresses are base58 and amounts are decimal strings of sats, since
+//! JavaScript numbers can't represent every `u64`.
+use super::{AddressParseError, BlockInfo, Content, Header, OutPoint, Output, Transaction};
+use crate::authorization::Authorization;
+use bip300301::bitcoin;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockJson {
+    pub hash: String,
+    pub header: HeaderJson,
+    pub coinbase: Vec<OutputJson>,
+    pub transactions: Vec<TransactionJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderJson {
+    pub merkle_root: String,
+    pub prev_side_hash: String,
+    pub prev_main_hash: String,
+    pub height: u32,
+    pub commitment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionJson {
+    pub txid: String,
+    pub inputs: Vec<OutPointJson>,
+    pub outputs: Vec<OutputJson>,
+    pub authorizations: Vec<AuthorizationJson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutPointJson {
+    Regular { txid: String, vout: u32 },
+    Coinbase { merkle_root: String, vout: u32 },
+    Deposit { txid: String, vout: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputJson {
+    pub address: String,
+    #[serde(flatten)]
+    pub content: ContentJson,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentJson {
+    Value {
+        #[serde(with = "amount")]
+        value: u64,
+    },
+    Withdrawal {
+        #[serde(with = "amount")]
+        value: u64,
+        #[serde(with = "amount")]
+        main_fee: u64,
+        main_address: String,
+    },
+}
+
+/// Amounts as decimal strings.
+mod amount {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(amount)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let amount = String::deserialize(deserializer)?;
+        amount.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizationJson {
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl From<&BlockInfo> for BlockJson {
+    fn from(block: &BlockInfo) -> Self {
+        // Authorizations are stored flat, one per input, in transaction order.
+        let mut authorizations = block.body.authorizations.iter();
+        let transactions = block
+            .body
+            .transactions
+            .iter()
+            .map(|transaction| {
+                let authorizations = authorizations
+                    .by_ref()
+                    .take(transaction.inputs.len())
+                    .map(AuthorizationJson::from)
+                    .collect();
+                TransactionJson::new(transaction, authorizations)
+            })
+            .collect();
+        Self {
+            hash: block.hash.to_string(),
+            header: (&block.header).into(),
+            coinbase: block.body.coinbase.iter().map(OutputJson::from).collect(),
+            transactions,
+        }
+    }
+}
+
+impl From<&Header> for HeaderJson {
+    fn from(header: &Header) -> Self {
+        Self {
+            merkle_root: header.merkle_root.to_string(),
+            prev_side_hash: header.prev_side_hash.to_string(),
+            prev_main_hash: header.prev_main_hash.to_string(),
+            height: header.height,
+            commitment: header.commitment.map(hex::encode),
+        }
+    }
+}
+
+impl TransactionJson {
+    pub fn new(transaction: &Transaction, authorizations: Vec<AuthorizationJson>) -> Self {
+        Self {
+            txid: transaction.txid().to_string(),
+            inputs: transaction.inputs.iter().map(OutPointJson::from).collect(),
+            outputs: transaction.outputs.iter().map(OutputJson::from).collect(),
+            authorizations,
+        }
+    }
+}
+
+impl From<&OutPoint> for OutPointJson {
+    fn from(outpoint: &OutPoint) -> Self {
+        match outpoint {
+            OutPoint::Regular { txid, vout } => Self::Regular {
+                txid: txid.to_string(),
+                vout: *vout,
+            },
+            OutPoint::Coinbase { merkle_root, vout } => Self::Coinbase {
+                merkle_root: merkle_root.to_string(),
+                vout: *vout,
+            },
+            OutPoint::Deposit(bitcoin::OutPoint { txid, vout }) => Self::Deposit {
+                txid: txid.to_string(),
+                vout: *vout,
+            },
+        }
+    }
+}
+
+impl From<&Output> for OutputJson {
+    fn from(output: &Output) -> Self {
+        Self {
+            address: output.address.to_base58(),
+            content: (&output.content).into(),
+        }
+    }
+}
+
+impl From<&Content> for ContentJson {
+    fn from(content: &Content) -> Self {
+        match content {
+            Content::Value(value) => Self::Value { value: *value },
+            Content::Withdrawal {
+                value,
+                main_fee,
+                main_address,
+            } => Self::Withdrawal {
+                value: *value,
+                main_fee: *main_fee,
+                main_address: main_address.clone().assume_checked().to_string(),
+            },
+        }
+    }
+}
+
+impl From<&Authorization> for AuthorizationJson {
+    fn from(authorization: &Authorization) -> Self {
+        Self {
+            public_key: hex::encode(authorization.public_key.to_bytes()),
+            signature: hex::encode(authorization.signature.to_bytes()),
+        }
+    }
+}
+
+impl TryFrom<&TransactionJson> for Transaction {
+    type Error = Error;
+    fn try_from(transaction: &TransactionJson) -> Result<Self, Self::Error> {
+        Ok(Self {
+            inputs: transaction
+                .inputs
+                .iter()
+                .map(OutPoint::try_from)
+                .collect::<Result<_, _>>()?,
+            outputs: transaction
+                .outputs
+                .iter()
+                .map(Output::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<&OutPointJson> for OutPoint {
+    type Error = Error;
+    fn try_from(outpoint: &OutPointJson) -> Result<Self, Self::Error> {
+        Ok(match outpoint {
+            OutPointJson::Regular { txid, vout } => Self::Regular {
+                txid: txid.parse()?,
+                vout: *vout,
+            },
+            OutPointJson::Coinbase { merkle_root, vout } => Self::Coinbase {
+                merkle_root: merkle_root.parse()?,
+                vout: *vout,
+            },
+            OutPointJson::Deposit { txid, vout } => Self::Deposit(bitcoin::OutPoint {
+                txid: txid.parse()?,
+                vout: *vout,
+            }),
+        })
+    }
+}
+
+impl TryFrom<&OutputJson> for Output {
+    type Error = Error;
+    fn try_from(output: &OutputJson) -> Result<Self, Self::Error> {
+        Ok(Self {
+            address: output.address.parse()?,
+            content: (&output.content).try_into()?,
+        })
+    }
+}
+
+impl TryFrom<&ContentJson> for Content {
+    type Error = Error;
+    fn try_from(content: &ContentJson) -> Result<Self, Self::Error> {
+        Ok(match content {
+            ContentJson::Value { value } => Self::Value(*value),
+            ContentJson::Withdrawal {
+                value,
+                main_fee,
+                main_address,
+            } => Self::Withdrawal {
+                value: *value,
+                main_fee: *main_fee,
+                main_address: main_address.parse()?,
+            },
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid hash")]
+    Hash(#[from] hex::FromHexError),
+    #[error("invalid mainchain txid")]
+    MainchainTxid(#[from] bitcoin::hashes::hex::Error),
+    #[error("invalid address")]
+    Address(#[from] AddressParseError),
+    #[error("invalid mainchain address")]
+    MainAddress(#[from] bitcoin::address::Error),
+}
@@ -0,0 +1,117 @@
+//! Merkle tree committing to a body. The leaves are the hash of the coinbase followed by the
+//! txids in block order. Nodes are the hash of their two children, a node without a sibling is
+//! carried up to the next level unchanged. Leaves and nodes are hashed with different tags, and
+//! the root commits to the number of leaves, so that a node can't be passed off as a leaf and a
+//! path can't be checked against a tree of a different size.
+use super::{hash, Hash, Header, Txid};
+use serde::{Deserialize, Serialize};
+
+const LEAF_TAG: &[u8] = b"sidechain merkle leaf";
+const NODE_TAG: &[u8] = b"sidechain merkle node";
+
+fn hash_leaf(leaf: &Hash) -> Hash {
+    hash(&(LEAF_TAG, leaf))
+}
+
+fn hash_node(left: &Hash, right: &Hash) -> Hash {
+    hash(&(NODE_TAG, left, right))
+}
+
+/// Root of a tree with `leaf_count` leaves and `top` as its topmost node.
+fn hash_root(leaf_count: u32, top: &Hash) -> Hash {
+    hash(&(leaf_count, top))
+}
+
+/// Branch from a leaf to the Merkle root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerklePath {
+    /// Position of the leaf, 0 is the coinbase and 1 is the first transaction.
+    pub index: u32,
+    /// Number of leaves in the tree, needed to tell which nodes were carried up.
+    pub leaf_count: u32,
+    /// Siblings from the leaf level up to the level below the root.
+    pub siblings: Vec<Hash>,
+}
+
+impl MerklePath {
+    /// Root of the tree the path belongs to if it has `leaf` at `index`, `None` if the path is
+    /// malformed.
+    pub fn compute_root(&self, leaf: Hash) -> Option<Hash> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+        let mut siblings = self.siblings.iter();
+        let mut node = hash_leaf(&leaf);
+        let mut index = self.index;
+        let mut width = self.leaf_count;
+        while width > 1 {
+            if index % 2 == 1 {
+                node = hash_node(siblings.next()?, &node);
+            } else if index + 1 < width {
+                node = hash_node(&node, siblings.next()?);
+            }
+            index /= 2;
+            width = (width + 1) / 2;
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(hash_root(self.leaf_count, &node))
+    }
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [node] => *node,
+            _ => unreachable!("chunks are never empty"),
+        })
+        .collect()
+}
+
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    let mut level: Vec<Hash> = leaves.iter().map(hash_leaf).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    hash_root(
+        leaves.len() as u32,
+        &level.first().copied().unwrap_or_default(),
+    )
+}
+
+/// Path from the leaf at `index` to the root, `None` if there is no such leaf.
+pub fn merkle_path(leaves: &[Hash], index: usize) -> Option<MerklePath> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut siblings = vec![];
+    let mut level: Vec<Hash> = leaves.iter().map(hash_leaf).collect();
+    let mut position = index;
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(position ^ 1) {
+            siblings.push(*sibling);
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+    Some(MerklePath {
+        index: index as u32,
+        leaf_count: leaves.len() as u32,
+        siblings,
+    })
+}
+
+/// Check that the transaction `txid` is committed to by `header`, without access to the body.
+pub fn verify_tx_inclusion(header: &Header, txid: &Txid, path: &MerklePath) -> bool {
+    // Leaf 0 is the coinbase, not a transaction.
+    if path.index == 0 {
+        return false;
+    }
+    match path.compute_root((*txid).into()) {
+        Some(root) => root == Hash::from(header.merkle_root),
+        None => false,
+    }
+}
@@ -4,10 +4,13 @@ use std::{cmp::Ordering, collections::HashMap};
 
 mod address;
 mod hashes;
+pub mod json;
+mod merkle;
 mod types;
 
 pub use blake3;
 pub use bs58;
+pub use merkle::*;
 pub use serde;
 pub use types::*;
 
@@ -25,11 +28,79 @@ pub struct Header {
     pub merkle_root: MerkleRoot,
     pub prev_side_hash: BlockHash,
     pub prev_main_hash: bitcoin::BlockHash,
+    /// Height of this block, the first block has height 1.
+    pub height: u32,
+    /// Hash of the UTXO set the block is connected on top of, see `State::utxo_set_hash`.
+    pub commitment: Option<Hash>,
+}
+
+/// Header fields from before `commitment` was added.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderV1 {
+    pub merkle_root: MerkleRoot,
+    pub prev_side_hash: BlockHash,
+    pub prev_main_hash: bitcoin::BlockHash,
+    pub height: u32,
 }
 
 impl Header {
+    /// The critical hash BMM requests commit to, so it covers every field including `height` and
+    /// `commitment`.
     pub fn hash(&self) -> BlockHash {
-        types::hash(self).into()
+        // Headers without a commitment hash the same as before it was added, so existing chains
+        // keep their block hashes.
+        match self.commitment {
+            None => types::hash(&HeaderV1::from(self.clone())).into(),
+            Some(_) => types::hash(self).into(),
+        }
+    }
+}
+
+impl From<Header> for HeaderV1 {
+    fn from(header: Header) -> Self {
+        Self {
+            merkle_root: header.merkle_root,
+            prev_side_hash: header.prev_side_hash,
+            prev_main_hash: header.prev_main_hash,
+            height: header.height,
+        }
+    }
+}
+
+impl From<HeaderV1> for Header {
+    fn from(header: HeaderV1) -> Self {
+        Self {
+            merkle_root: header.merkle_root,
+            prev_side_hash: header.prev_side_hash,
+            prev_main_hash: header.prev_main_hash,
+            height: header.height,
+            commitment: None,
+        }
+    }
+}
+
+/// A block together with the values derived from it.
+#[derive(Debug, Clone)]
+pub struct BlockInfo {
+    pub hash: BlockHash,
+    /// Size of the serialized body in bytes.
+    pub body_size: u64,
+    pub num_transactions: usize,
+    pub coinbase_value: u64,
+    pub header: Header,
+    pub body: Body,
+}
+
+impl BlockInfo {
+    pub fn new(header: Header, body: Body) -> Self {
+        Self {
+            hash: header.hash(),
+            body_size: body.serialized_size(),
+            num_transactions: body.transactions.len(),
+            coinbase_value: body.get_coinbase_value(),
+            header,
+            body,
+        }
     }
 }
 
@@ -45,6 +116,81 @@ pub struct WithdrawalBundle {
     pub transaction: bitcoin::Transaction,
 }
 
+impl WithdrawalBundle {
+    /// Outputs of the bundle transaction that carry metadata rather than withdrawals.
+    const RETURN_DEST_VOUT: usize = 0;
+    const MAINCHAIN_FEE_VOUT: usize = 1;
+    const INPUTS_COMMITMENT_VOUT: usize = 2;
+
+    /// Commitment to the spent UTXOs and to the height at which the bundle was collected.
+    ///
+    /// Outpoints are sorted first, so the commitment doesn't depend on the order they're passed
+    /// in, which for a `HashMap` differs between nodes.
+    pub fn compute_inputs_commitment<'a>(
+        outpoints: impl IntoIterator<Item = &'a OutPoint>,
+        block_height: u32,
+    ) -> Hash {
+        let mut inputs: Vec<OutPoint> = outpoints.into_iter().copied().collect();
+        inputs.sort_unstable();
+        // Commit to block height.
+        inputs.push(OutPoint::Regular {
+            txid: [0; 32].into(),
+            vout: block_height,
+        });
+        hash(&inputs)
+    }
+
+    /// Data pushed by the `OP_RETURN` script of an output.
+    fn op_return_data(&self, vout: usize) -> Option<&[u8]> {
+        use bitcoin::blockdata::{opcodes, script::Instruction};
+        let script = &self.transaction.output.get(vout)?.script_pubkey;
+        let mut instructions = script.instructions();
+        match instructions.next()? {
+            Ok(Instruction::Op(op)) if op == opcodes::all::OP_RETURN => {}
+            _ => return None,
+        }
+        match (instructions.next()?, instructions.next()) {
+            (Ok(Instruction::PushBytes(data)), None) => Some(data.as_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Destination for the change of the bundle.
+    pub fn return_dest(&self) -> Option<&[u8]> {
+        self.op_return_data(Self::RETURN_DEST_VOUT)
+    }
+
+    /// Total mainchain fee paid by the bundle.
+    pub fn mainchain_fee(&self) -> Option<u64> {
+        let data = self.op_return_data(Self::MAINCHAIN_FEE_VOUT)?;
+        Some(u64::from_le_bytes(data.try_into().ok()?))
+    }
+
+    pub fn inputs_commitment(&self) -> Option<Hash> {
+        self.op_return_data(Self::INPUTS_COMMITMENT_VOUT)?
+            .try_into()
+            .ok()
+    }
+
+    /// Whether the inputs commitment matches the spent UTXOs for a bundle collected at
+    /// `block_height`.
+    pub fn verify_commitment(&self, block_height: u32) -> bool {
+        let commitment = Self::compute_inputs_commitment(self.spent_utxos.keys(), block_height);
+        self.inputs_commitment() == Some(commitment)
+    }
+}
+
+/// Deposit whose destination isn't a valid sidechain address, kept so that operators can see
+/// it and possibly recover the funds.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UnclaimedDeposit {
+    /// Destination string as it appeared in the deposit.
+    pub address: String,
+    pub value: u64,
+    /// Height of the block that would have included the deposit.
+    pub height: u32,
+}
+
 #[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TwoWayPegData {
     pub deposits: HashMap<types::OutPoint, types::Output>,
@@ -73,17 +219,14 @@ pub struct AggregatedWithdrawal {
 }
 
 impl Ord for AggregatedWithdrawal {
+    /// Lexicographic by mainchain fee, then value, then address, so that sorting is a total
+    /// order and every node puts bundle outputs in the same order.
     fn cmp(&self, other: &Self) -> Ordering {
-        if self == other {
-            Ordering::Equal
-        } else if self.main_fee > other.main_fee
-            || self.value > other.value
-            || self.main_address > other.main_address
-        {
-            Ordering::Greater
-        } else {
-            Ordering::Less
-        }
+        (self.main_fee, self.value, &self.main_address).cmp(&(
+            other.main_fee,
+            other.value,
+            &other.main_address,
+        ))
     }
 }
 
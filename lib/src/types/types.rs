@@ -2,10 +2,54 @@ use crate::authorization::Authorization;
 pub use crate::types::address::*;
 pub use crate::types::hashes::*;
 use bip300301::bitcoin;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Most inputs, outputs or authorizations a transaction may claim when it is
+/// decoded. Larger counts are rejected before anything is allocated for them.
+pub const MAX_TRANSACTION_ITEMS: usize = 1 << 16;
+
+/// Deserialize a vector, rejecting a length prefix above
+/// [`MAX_TRANSACTION_ITEMS`] before allocating.
+fn deserialize_bounded_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct BoundedVecVisitor<T>(std::marker::PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> serde::de::Visitor<'de> for BoundedVecVisitor<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a sequence of at most {MAX_TRANSACTION_ITEMS} items")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::SeqAccess<'de>,
+        {
+            use serde::de::Error as _;
+            let len = seq.size_hint().unwrap_or(0);
+            if len > MAX_TRANSACTION_ITEMS {
+                return Err(A::Error::invalid_length(len, &self));
+            }
+            // The length is claimed by the peer, only allocate for it as items arrive.
+            let mut items = Vec::with_capacity(len.min(1024));
+            while let Some(item) = seq.next_element()? {
+                if items.len() == MAX_TRANSACTION_ITEMS {
+                    return Err(A::Error::invalid_length(items.len() + 1, &self));
+                }
+                items.push(item);
+            }
+            Ok(items)
+        }
+    }
+
+    deserializer.deserialize_seq(BoundedVecVisitor(std::marker::PhantomData))
+}
+
+#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum OutPoint {
     // Created by transactions.
     Regular { txid: Txid, vout: u32 },
@@ -15,16 +59,62 @@ pub enum OutPoint {
     Deposit(bitcoin::OutPoint),
 }
 
+/// Formats as `regular:{txid}:{vout}`, `coinbase:{merkle_root}:{vout}` or
+/// `deposit:{mainchain txid}:{vout}`, the form parsed by `FromStr`.
 impl std::fmt::Display for OutPoint {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Regular { txid, vout } => write!(f, "regular {txid} {vout}"),
-            Self::Coinbase { merkle_root, vout } => write!(f, "coinbase {merkle_root} {vout}"),
-            Self::Deposit(bitcoin::OutPoint { txid, vout }) => write!(f, "deposit {txid} {vout}"),
+            Self::Regular { txid, vout } => write!(f, "regular:{txid}:{vout}"),
+            Self::Coinbase { merkle_root, vout } => write!(f, "coinbase:{merkle_root}:{vout}"),
+            Self::Deposit(bitcoin::OutPoint { txid, vout }) => write!(f, "deposit:{txid}:{vout}"),
         }
     }
 }
 
+impl std::str::FromStr for OutPoint {
+    type Err = OutPointParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (Some(kind), Some(hash), Some(vout), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(OutPointParseError::Format(s.to_owned()));
+        };
+        let vout = vout.parse()?;
+        match kind {
+            "regular" => Ok(Self::Regular {
+                txid: hash.parse()?,
+                vout,
+            }),
+            "coinbase" => Ok(Self::Coinbase {
+                merkle_root: hash.parse()?,
+                vout,
+            }),
+            "deposit" => {
+                let txid = hash
+                    .parse()
+                    .map_err(|_| OutPointParseError::MainchainTxid(hash.to_owned()))?;
+                Ok(Self::Deposit(bitcoin::OutPoint { txid, vout }))
+            }
+            _ => Err(OutPointParseError::Kind(kind.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OutPointParseError {
+    #[error("invalid outpoint {0}, expected <kind>:<hash>:<vout>")]
+    Format(String),
+    #[error("unknown outpoint kind {0}, expected regular, coinbase or deposit")]
+    Kind(String),
+    #[error("invalid hash")]
+    Hash(#[from] hex::FromHexError),
+    #[error("invalid mainchain txid {0}")]
+    MainchainTxid(String),
+    #[error("invalid vout")]
+    Vout(#[from] std::num::ParseIntError),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Output {
     pub address: Address,
@@ -48,6 +138,43 @@ impl Content {
     pub fn is_withdrawal(&self) -> bool {
         matches!(self, Self::Withdrawal { .. })
     }
+
+    // Content is classified through the methods below so that adding a variant only requires
+    // updating them, not every place that handles outputs.
+
+    /// Value that can be spent by an ordinary transaction input, `None` if the output is locked.
+    pub fn spendable_value(&self) -> Option<u64> {
+        match self {
+            Self::Value(value) => Some(*value),
+            Self::Withdrawal { .. } => None,
+        }
+    }
+
+    /// Whether the output is locked for a peg-out and can only be consumed by a withdrawal bundle.
+    pub fn is_locked(&self) -> bool {
+        self.spendable_value().is_none()
+    }
+
+    /// Mainchain destination and mainchain fee of a pending withdrawal.
+    pub fn withdrawal(
+        &self,
+    ) -> Option<(&bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64)> {
+        match self {
+            Self::Value(_) => None,
+            Self::Withdrawal {
+                main_address,
+                main_fee,
+                ..
+            } => Some((main_address, *main_fee)),
+        }
+    }
+}
+
+impl Output {
+    /// Whether an ordinary input authorized by `address` can spend the output.
+    pub fn is_spendable_by(&self, address: &Address) -> bool {
+        self.address == *address && !self.content.is_locked()
+    }
 }
 
 impl GetValue for Output {
@@ -69,7 +196,9 @@ impl GetValue for Content {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
+    #[serde(deserialize_with = "deserialize_bounded_vec")]
     pub inputs: Vec<OutPoint>,
+    #[serde(deserialize_with = "deserialize_bounded_vec")]
     pub outputs: Vec<Output>,
 }
 
@@ -77,6 +206,11 @@ impl Transaction {
     pub fn txid(&self) -> Txid {
         hash(self).into()
     }
+
+    /// Size of the serialized transaction in bytes, without authorizations.
+    pub fn serialized_size(&self) -> u64 {
+        serialized_size(self)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,15 +241,121 @@ impl FilledTransaction {
             Some(value_in - value_out)
         }
     }
+
+    /// Fee in sats per byte of a transaction of `size` bytes, rounded down. `None` if the inputs
+    /// don't cover the outputs or the size is zero.
+    pub fn fee_rate(&self, size: u64) -> Option<u64> {
+        self.get_fee()?.checked_div(size)
+    }
+
+    pub fn fee_display(&self, size: u64) -> FeeDisplay {
+        FeeDisplay::new(self.get_value_in(), self.get_value_out(), size)
+    }
+}
+
+/// Fee of a transaction as shown to users, displayed as the fee and its rate or as the value
+/// missing from the inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeDisplay {
+    Fee { fee: u64, fee_rate: u64 },
+    NotEnoughValueIn { missing: u64 },
+}
+
+impl FeeDisplay {
+    /// The fee rate is in sats per byte of `size`, rounded down, and zero if `size` is zero.
+    pub fn new(value_in: u64, value_out: u64, size: u64) -> Self {
+        match value_in.checked_sub(value_out) {
+            Some(fee) => Self::Fee {
+                fee,
+                fee_rate: fee.checked_div(size).unwrap_or(0),
+            },
+            None => Self::NotEnoughValueIn {
+                missing: value_out - value_in,
+            },
+        }
+    }
+
+    pub fn is_sufficient(&self) -> bool {
+        matches!(self, Self::Fee { .. })
+    }
+}
+
+impl std::fmt::Display for FeeDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fee { fee, fee_rate } => {
+                let fee = bitcoin::Amount::from_sat(*fee);
+                write!(f, "{fee} ({fee_rate} sat/byte)")
+            }
+            Self::NotEnoughValueIn { missing } => {
+                let missing = bitcoin::Amount::from_sat(*missing);
+                write!(f, "not enough value in, {missing} missing")
+            }
+        }
+    }
+}
+
+/// A transaction together with the outputs it spends, for signing on an offline wallet.
+///
+/// The online wallet creates it with `Wallet::create_unsigned_transaction` and exports it with
+/// `to_base64`, the offline wallet imports it with `from_base64`, shows the spent outputs to the
+/// user and signs it with `Wallet::authorize_unsigned`. The resulting `AuthorizedTransaction` is
+/// then submitted by the online node.
+///
+/// `spent_utxos` are only a claim of whoever created the blob. Signatures commit to the
+/// transaction but not to the outputs it spends, so the offline wallet signs for whatever
+/// addresses and values `spent_utxos` claim, and a tampered blob can make the user approve
+/// spending outputs other than the ones shown. Only sign blobs from a trusted online wallet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsignedTransaction {
+    pub transaction: Transaction,
+    pub spent_utxos: Vec<Output>,
+}
+
+impl UnsignedTransaction {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("failed to serialize unsigned transaction")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, UnsignedTransactionDecodeError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    pub fn to_base64(&self) -> String {
+        use base64::Engine as _;
+        base64::engine::general_purpose::STANDARD.encode(self.to_bytes())
+    }
+
+    pub fn from_base64(s: &str) -> Result<Self, UnsignedTransactionDecodeError> {
+        use base64::Engine as _;
+        let bytes = base64::engine::general_purpose::STANDARD.decode(s)?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UnsignedTransactionDecodeError {
+    #[error("base64 error")]
+    Base64(#[from] base64::DecodeError),
+    #[error("bincode error")]
+    Bincode(#[from] bincode::Error),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthorizedTransaction {
     pub transaction: Transaction,
     /// Authorization is called witness in Bitcoin.
+    #[serde(deserialize_with = "deserialize_bounded_vec")]
     pub authorizations: Vec<Authorization>,
 }
 
+impl AuthorizedTransaction {
+    /// Size of the serialized transaction in bytes, including authorizations.
+    pub fn serialized_size(&self) -> u64 {
+        serialized_size(self)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Body {
     pub coinbase: Vec<Output>,
@@ -124,7 +364,18 @@ pub struct Body {
 }
 
 impl Body {
+    /// Body with the transactions in canonical order, see `Body::canonical_order`. Any two nodes
+    /// packing the same transactions build the same body.
     pub fn new(authorized_transactions: Vec<AuthorizedTransaction>, coinbase: Vec<Output>) -> Self {
+        Self::with_transaction_order(Self::canonical_order(authorized_transactions), coinbase)
+    }
+
+    /// Body with the transactions in the given order, for rebuilding a body received from a peer
+    /// exactly as it was mined.
+    pub fn with_transaction_order(
+        authorized_transactions: Vec<AuthorizedTransaction>,
+        coinbase: Vec<Output>,
+    ) -> Self {
         let mut authorizations = Vec::with_capacity(
             authorized_transactions
                 .iter()
@@ -143,9 +394,101 @@ impl Body {
         }
     }
 
+    /// Sort transactions by txid, except that a transaction always comes after the transactions of
+    /// the same body whose outputs it spends.
+    pub fn canonical_order(transactions: Vec<AuthorizedTransaction>) -> Vec<AuthorizedTransaction> {
+        let txids: Vec<Txid> = transactions
+            .iter()
+            .map(|transaction| transaction.transaction.txid())
+            .collect();
+        let indices: HashMap<Txid, usize> = txids
+            .iter()
+            .enumerate()
+            .map(|(index, txid)| (*txid, index))
+            .collect();
+        // Number of unplaced transactions each transaction spends from, and the transactions
+        // spending from each transaction.
+        let mut num_parents = vec![0; transactions.len()];
+        let mut children = vec![vec![]; transactions.len()];
+        for (index, transaction) in transactions.iter().enumerate() {
+            let parents: HashSet<usize> = transaction
+                .transaction
+                .inputs
+                .iter()
+                .filter_map(|input| match input {
+                    OutPoint::Regular { txid, .. } => indices.get(txid).copied(),
+                    OutPoint::Coinbase { .. } | OutPoint::Deposit(_) => None,
+                })
+                .filter(|parent| *parent != index)
+                .collect();
+            num_parents[index] = parents.len();
+            for parent in parents {
+                children[parent].push(index);
+            }
+        }
+        let mut ready: BTreeSet<(Txid, usize)> = (0..transactions.len())
+            .filter(|index| num_parents[*index] == 0)
+            .map(|index| (txids[index], index))
+            .collect();
+        let mut order = Vec::with_capacity(transactions.len());
+        while let Some((_, index)) = ready.pop_first() {
+            order.push(index);
+            for child in &children[index] {
+                num_parents[*child] -= 1;
+                if num_parents[*child] == 0 {
+                    ready.insert((txids[*child], *child));
+                }
+            }
+        }
+        // Transactions spending from each other in a cycle can't be valid, they go last.
+        if order.len() < transactions.len() {
+            let placed: HashSet<usize> = order.iter().copied().collect();
+            let mut rest: Vec<usize> = (0..transactions.len())
+                .filter(|index| !placed.contains(index))
+                .collect();
+            rest.sort_by_key(|index| (txids[*index], *index));
+            order.extend(rest);
+        }
+        let mut transactions: Vec<Option<AuthorizedTransaction>> =
+            transactions.into_iter().map(Some).collect();
+        order
+            .into_iter()
+            .filter_map(|index| transactions[index].take())
+            .collect()
+    }
+
+    /// Transactions with their authorizations, the inverse of `Body::new`. Authorizations that
+    /// don't match the inputs, which only happens in invalid bodies, are dropped.
+    pub fn authorized_transactions(&self) -> Vec<AuthorizedTransaction> {
+        let mut authorizations = self.authorizations.iter();
+        self.transactions
+            .iter()
+            .map(|transaction| AuthorizedTransaction {
+                transaction: transaction.clone(),
+                authorizations: authorizations
+                    .by_ref()
+                    .take(transaction.inputs.len())
+                    .cloned()
+                    .collect(),
+            })
+            .collect()
+    }
+
     pub fn compute_merkle_root(&self) -> MerkleRoot {
-        // FIXME: Compute actual merkle root instead of just a hash.
-        hash(&(&self.coinbase, &self.transactions)).into()
+        super::merkle_root(&self.merkle_leaves()).into()
+    }
+
+    /// Hash of the coinbase followed by the txids, see `types::merkle_root`.
+    pub fn merkle_leaves(&self) -> Vec<Hash> {
+        std::iter::once(hash(&self.coinbase))
+            .chain(self.transactions.iter().map(|t| t.txid().into()))
+            .collect()
+    }
+
+    /// Proof that the transaction `txid` is in this body, checked with `verify_tx_inclusion`.
+    pub fn merkle_path(&self, txid: &Txid) -> Option<super::MerklePath> {
+        let index = self.transactions.iter().position(|t| t.txid() == *txid)?;
+        super::merkle_path(&self.merkle_leaves(), index + 1)
     }
 
     pub fn get_inputs(&self) -> Vec<OutPoint> {
@@ -175,6 +518,10 @@ impl Body {
         outputs
     }
 
+    pub fn serialized_size(&self) -> u64 {
+        serialized_size(self)
+    }
+
     pub fn get_coinbase_value(&self) -> u64 {
         self.coinbase.iter().map(|output| output.get_value()).sum()
     }
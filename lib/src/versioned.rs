@@ -0,0 +1,161 @@
+//! Versioned encoding of database values.
+//!
+//! Values are stored as a version byte followed by their bincode encoding, so that a change to a
+//! stored type can ship with a migration from the old encoding instead of breaking existing
+//! databases. Every environment also records `SCHEMA_VERSION`, databases written before values
+//! were versioned are rejected by `check_schema`.
+use crate::types::{
+    Address, AuthorizedTransaction, Header, HeaderV1, Output, UnclaimedDeposit, WithdrawalBundle,
+};
+use bip300301::bitcoin;
+use heed::types::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+/// Version of the database layout as a whole.
+pub const SCHEMA_VERSION: u32 = 1;
+/// Databases used by `check_schema`, on top of the ones of each store.
+pub const NUM_DBS: u32 = 1;
+const SCHEMA_VERSION_DB: &str = "schema_version";
+
+/// Type stored with `SerdeVersioned`.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Bump whenever the serialized form of the type changes, and handle the previous version in
+    /// `migrate`.
+    const VERSION: u8;
+
+    /// Decode a value serialized by an older `version`, `None` if it can't be migrated.
+    fn migrate(version: u8, bytes: &[u8]) -> Option<Self> {
+        let _ = (version, bytes);
+        None
+    }
+}
+
+/// heed codec for `Versioned` values, values of unknown versions fail to decode.
+pub struct SerdeVersioned<T>(PhantomData<T>);
+
+impl<'a, T: Versioned + 'a> heed::BytesEncode<'a> for SerdeVersioned<T> {
+    type EItem = T;
+
+    fn bytes_encode(item: &'a Self::EItem) -> Option<Cow<'a, [u8]>> {
+        let mut bytes = vec![T::VERSION];
+        bincode::serialize_into(&mut bytes, item).ok()?;
+        Some(Cow::Owned(bytes))
+    }
+}
+
+impl<'a, T: Versioned + 'a> heed::BytesDecode<'a> for SerdeVersioned<T> {
+    type DItem = T;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Option<Self::DItem> {
+        match bytes.split_first()? {
+            (&version, serialized) if version == T::VERSION => {
+                bincode::deserialize(serialized).ok()
+            }
+            (&version, serialized) if version < T::VERSION => T::migrate(version, serialized),
+            _ => None,
+        }
+    }
+}
+
+/// Record the schema version of a new environment, or check the version of an existing one.
+///
+/// Must be called before any other database of the environment is created, so that environments
+/// written before values were versioned can be told apart from new ones.
+pub fn check_schema(env: &heed::Env) -> Result<(), Error> {
+    let schema = env.open_database::<OwnedType<u8>, OwnedType<u32>>(Some(SCHEMA_VERSION_DB))?;
+    let version = match schema {
+        Some(schema) => schema.get(&env.read_txn()?, &0)?,
+        None => None,
+    };
+    match version {
+        Some(SCHEMA_VERSION) => return Ok(()),
+        Some(version) => {
+            return Err(Error::UnsupportedSchema {
+                version,
+                supported: SCHEMA_VERSION,
+            })
+        }
+        None => {}
+    }
+    // The unnamed database lists the names of all the other ones.
+    if let Some(names) = env.open_database::<Str, DecodeIgnore>(None)? {
+        let txn = env.read_txn()?;
+        for item in names.iter(&txn)? {
+            let (name, ()) = item?;
+            if name != SCHEMA_VERSION_DB {
+                return Err(Error::Unversioned);
+            }
+        }
+    }
+    let schema = env.create_database::<OwnedType<u8>, OwnedType<u32>>(Some(SCHEMA_VERSION_DB))?;
+    let mut txn = env.write_txn()?;
+    schema.put(&mut txn, &0, &SCHEMA_VERSION)?;
+    txn.commit()?;
+    Ok(())
+}
+
+impl Versioned for Output {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for Header {
+    const VERSION: u8 = 2;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Option<Self> {
+        match version {
+            1 => bincode::deserialize::<HeaderV1>(bytes)
+                .ok()
+                .map(Header::from),
+            _ => None,
+        }
+    }
+}
+
+impl Versioned for AuthorizedTransaction {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for WithdrawalBundle {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for UnclaimedDeposit {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for Address {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for bitcoin::BlockHash {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for crate::state::BlockUndo {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for crate::wallet::AccountPath {
+    const VERSION: u8 = 1;
+}
+
+impl Versioned for crate::wallet::WalletEvent {
+    const VERSION: u8 = 1;
+}
+
+/// Fee and serialized size of a mempool transaction.
+impl Versioned for (u64, u64) {
+    const VERSION: u8 = 1;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("heed error")]
+    Heed(#[from] heed::Error),
+    #[error("database was written before values were versioned, delete it and sync again")]
+    Unversioned,
+    #[error("database schema version {version} is not supported, expected {supported}")]
+    UnsupportedSchema { version: u32, supported: u32 },
+}
@@ -1,60 +1,229 @@
+use crate::authorization::Signer;
 pub use crate::authorization::{get_address, Authorization};
+use crate::consensus::is_coinbase_mature;
 use crate::types::{
-    Address, AuthorizedTransaction, Content, GetValue, OutPoint, Output, Transaction,
+    blake3, Address, AuthorizedTransaction, Body, Content, GetValue, Hash, OutPoint, Output,
+    Transaction, UnsignedTransaction,
 };
+use crate::versioned::SerdeVersioned;
 use bip300301::bitcoin;
 use byteorder::{BigEndian, ByteOrder};
 use ed25519_dalek_bip32::*;
 use heed::types::*;
-use heed::{Database, RoTxn};
+use heed::{Database, RoTxn, RwTxn};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 
 #[derive(Clone)]
 pub struct Wallet {
     env: heed::Env,
     // FIXME: Don't store the seed in plaintext.
     seed: Database<OwnedType<u8>, OwnedType<[u8; 64]>>,
+    /// Imported master key, secret key followed by chain code. Used instead of the seed, only one
+    /// of the two is ever set.
+    xprv: Database<OwnedType<u8>, OwnedType<[u8; 64]>>,
     pub address_to_index: Database<SerdeBincode<Address>, OwnedType<[u8; 4]>>,
-    pub index_to_address: Database<OwnedType<[u8; 4]>, SerdeBincode<Address>>,
-    pub utxos: Database<SerdeBincode<OutPoint>, SerdeBincode<Output>>,
+    pub index_to_address: Database<OwnedType<[u8; 4]>, SerdeVersioned<Address>>,
+    pub utxos: Database<SerdeBincode<OutPoint>, SerdeVersioned<Output>>,
+    /// Height of the block in which each UTXO was created.
+    pub utxo_heights: Database<SerdeBincode<OutPoint>, OwnedType<u32>>,
+    /// Current map size of `env`, grown by `write` whenever the map fills up.
+    map_size: Arc<AtomicUsize>,
+    /// Held for reading by every open transaction and for writing while the map is resized, which
+    /// LMDB only allows while the process has no transaction open. Shared by clones, so a wallet
+    /// should only be opened once per process.
+    resize_lock: Arc<RwLock<()>>,
+    /// Address that receives change and mining rewards under `AddressPolicy::Reuse`.
+    change_address: Database<OwnedType<u8>, SerdeVersioned<Address>>,
+    /// Path of the account key, persisted so that addresses stay reproducible.
+    account_path: Database<OwnedType<u8>, SerdeVersioned<AccountPath>>,
+    /// UTXOs reserved by the user, never picked by `select_coins`.
+    frozen: Database<SerdeBincode<OutPoint>, Unit>,
+    /// Append-only activity log, keyed by big endian sequence number.
+    events: Database<OwnedType<[u8; 8]>, SerdeVersioned<WalletEvent>>,
+    /// Height of the best block the wallet was updated to, coin selection skips coinbase outputs
+    /// that can't be spent in the block after it.
+    height: Database<OwnedType<u8>, OwnedType<u32>>,
+    address_policy: AddressPolicy,
+    /// Account level key derived from the seed, cached so that it isn't re-derived for every
+    /// address and signature. Tagged with `key_id` of the master key and account path it was
+    /// derived from, so a key derived in a transaction that predates `set_seed` is never served
+    /// for the new seed.
+    account_key: Arc<Mutex<Option<(Hash, Arc<ExtendedSecretKey>)>>>,
 }
 
 impl Wallet {
-    pub const NUM_DBS: u32 = 4;
+    pub const NUM_DBS: u32 = 11;
+    pub const DEFAULT_MAP_SIZE: usize = 10 * 1024 * 1024; // 10MB
+    /// Name of the wallet in `wallet.mdb`, the one used unless another is selected.
+    pub const DEFAULT_NAME: &'static str = "default";
 
-    pub fn new(path: &Path) -> Result<Self, Error> {
+    /// Path of the wallet called `name` in `datadir`, `wallet-<name>.mdb` for every wallet but
+    /// the default one. Names may only contain ASCII letters, digits, `-` and `_`.
+    pub fn path(datadir: &Path, name: &str) -> Result<PathBuf, Error> {
+        if name == Self::DEFAULT_NAME {
+            return Ok(datadir.join("wallet.mdb"));
+        }
+        let is_valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid {
+            return Err(Error::InvalidWalletName(name.to_owned()));
+        }
+        Ok(datadir.join(format!("wallet-{name}.mdb")))
+    }
+
+    /// Names of the wallets in `datadir`, sorted.
+    pub fn list(datadir: &Path) -> Result<Vec<String>, Error> {
+        let entries = match std::fs::read_dir(datadir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut names = vec![];
+        for entry in entries {
+            let file_name = entry?.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            let name = match file_name.strip_suffix(".mdb") {
+                Some("wallet") => Self::DEFAULT_NAME,
+                Some(stem) => match stem.strip_prefix("wallet-") {
+                    Some(name) => name,
+                    None => continue,
+                },
+                None => continue,
+            };
+            if Self::path(datadir, name).is_ok() {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Open the wallet at `path` with an initial map size of `map_size` bytes, the map is grown
+    /// when it fills up. Fails if the wallet already derived addresses with a different account
+    /// path, use `set_account_path` to switch paths.
+    pub fn new(
+        path: &Path,
+        address_policy: AddressPolicy,
+        account_path: AccountPath,
+        map_size: usize,
+    ) -> Result<Self, Error> {
         std::fs::create_dir_all(path)?;
         let env = heed::EnvOpenOptions::new()
-            .map_size(10 * 1024 * 1024) // 10MB
-            .max_dbs(Self::NUM_DBS)
+            .map_size(map_size)
+            .max_dbs(Self::NUM_DBS + crate::versioned::NUM_DBS)
             .open(path)?;
+        crate::versioned::check_schema(&env)?;
         let seed_db = env.create_database(Some("seed"))?;
+        let xprv = env.create_database(Some("xprv"))?;
         let address_to_index = env.create_database(Some("address_to_index"))?;
         let index_to_address = env.create_database(Some("index_to_address"))?;
         let utxos = env.create_database(Some("utxos"))?;
-        Ok(Self {
+        let utxo_heights = env.create_database(Some("utxo_heights"))?;
+        let change_address = env.create_database(Some("change_address"))?;
+        let account_path_db = env.create_database(Some("account_path"))?;
+        let frozen = env.create_database(Some("frozen"))?;
+        let events = env.create_database(Some("events"))?;
+        let height = env.create_database(Some("height"))?;
+        let wallet = Self {
             env,
             seed: seed_db,
+            xprv,
             address_to_index,
             index_to_address,
             utxos,
-        })
+            utxo_heights,
+            change_address,
+            account_path: account_path_db,
+            frozen,
+            events,
+            height,
+            address_policy,
+            account_key: Arc::new(Mutex::new(None)),
+            map_size: Arc::new(AtomicUsize::new(map_size)),
+            resize_lock: Arc::new(RwLock::new(())),
+        };
+        let mut txn = wallet.write_txn()?;
+        match wallet.account_path.get(&txn, &0)? {
+            Some(stored) if stored == account_path => {}
+            // Switching paths would orphan the funds of already derived addresses.
+            Some(stored) if !wallet.index_to_address.is_empty(&txn)? => {
+                return Err(Error::AccountPathMismatch {
+                    stored,
+                    requested: account_path,
+                });
+            }
+            _ => wallet.account_path.put(&mut txn, &0, &account_path)?,
+        }
+        txn.commit()?;
+        Ok(wallet)
     }
 
-    pub fn set_seed(&self, seed: &[u8; 64]) -> Result<(), Error> {
-        let mut txn = self.env.write_txn()?;
-        self.seed.put(&mut txn, &0, &seed)?;
+    /// Derive addresses under a different account path. Forgets the addresses and UTXOs of the
+    /// old path, so the wallet has to be rescanned afterwards.
+    pub fn set_account_path(&self, account_path: AccountPath) -> Result<(), Error> {
+        let mut txn = self.write_txn()?;
         self.address_to_index.clear(&mut txn)?;
         self.index_to_address.clear(&mut txn)?;
         self.utxos.clear(&mut txn)?;
+        self.utxo_heights.clear(&mut txn)?;
+        self.change_address.clear(&mut txn)?;
+        self.frozen.clear(&mut txn)?;
+        self.account_path.put(&mut txn, &0, &account_path)?;
         txn.commit()?;
         Ok(())
     }
 
+    pub fn get_account_path(&self) -> Result<AccountPath, Error> {
+        let txn = self.read_txn()?;
+        Ok(self.account_path.get(&txn, &0)?.unwrap_or_default())
+    }
+
+    pub fn set_seed(&self, seed: &[u8; 64]) -> Result<(), Error> {
+        self.write(|txn| {
+            self.reset(txn)?;
+            self.seed.put(txn, &0, seed)?;
+            Ok(())
+        })
+    }
+
+    /// Import a master key directly instead of a seed, addresses are derived from it the same way
+    /// they are derived from the master key of a seed.
+    pub fn set_xprv(&self, xprv: &ExtendedSecretKey) -> Result<(), Error> {
+        let mut key = [0; 64];
+        key[..32].copy_from_slice(xprv.secret_key.as_bytes());
+        key[32..].copy_from_slice(&xprv.chain_code);
+        let mut txn = self.write_txn()?;
+        self.reset(&mut txn)?;
+        self.xprv.put(&mut txn, &0, &key)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Forget the keys and everything derived from them.
+    fn reset(&self, txn: &mut RwTxn) -> Result<(), Error> {
+        self.seed.clear(txn)?;
+        self.xprv.clear(txn)?;
+        self.address_to_index.clear(txn)?;
+        self.index_to_address.clear(txn)?;
+        self.utxos.clear(txn)?;
+        self.utxo_heights.clear(txn)?;
+        self.change_address.clear(txn)?;
+        self.frozen.clear(txn)?;
+        Ok(())
+    }
+
+    /// Whether the wallet has a seed or an imported master key.
     pub fn has_seed(&self) -> Result<bool, Error> {
-        let txn = self.env.read_txn()?;
-        Ok(self.seed.get(&txn, &0)?.is_some())
+        let txn = self.read_txn()?;
+        Ok(self.seed.get(&txn, &0)?.is_some() || self.xprv.get(&txn, &0)?.is_some())
     }
 
     pub fn create_withdrawal(
@@ -64,7 +233,7 @@ impl Wallet {
         main_fee: u64,
         fee: u64,
     ) -> Result<Transaction, Error> {
-        let (total, coins) = self.select_coins(value + fee + main_fee)?;
+        let (total, coins) = self.select_coins(value + fee + main_fee, None)?;
         let change = total - value - fee;
         let inputs = coins.into_keys().collect();
         let outputs = vec![
@@ -77,7 +246,7 @@ impl Wallet {
                 },
             },
             Output {
-                address: self.get_new_address()?,
+                address: self.get_change_address()?,
                 content: Content::Value(change),
             },
         ];
@@ -90,41 +259,56 @@ impl Wallet {
         value: u64,
         fee: u64,
     ) -> Result<Transaction, Error> {
-        let (total, coins) = self.select_coins(value + fee)?;
+        let outputs = vec![Output {
+            address,
+            content: Content::Value(value),
+        }];
+        self.create_transaction_with_outputs(outputs, fee)
+    }
+
+    /// Select coins to pay for `outputs` and `fee`, and append a change output.
+    pub fn create_transaction_with_outputs(
+        &self,
+        mut outputs: Vec<Output>,
+        fee: u64,
+    ) -> Result<Transaction, Error> {
+        let value: u64 = outputs.iter().map(GetValue::get_value).sum();
+        let (total, coins) = self.select_coins(value + fee, None)?;
         let change = total - value - fee;
         let inputs = coins.into_keys().collect();
-        let outputs = vec![
-            Output {
-                address,
-                content: Content::Value(value),
-            },
-            Output {
-                address: self.get_new_address()?,
-                content: Content::Value(change),
-            },
-        ];
+        outputs.push(Output {
+            address: self.get_change_address()?,
+            content: Content::Value(change),
+        });
         Ok(Transaction { inputs, outputs })
     }
 
-    pub fn select_coins(&self, value: u64) -> Result<(u64, HashMap<OutPoint, Output>), Error> {
-        let txn = self.env.read_txn()?;
-        let mut utxos = vec![];
-        for item in self.utxos.iter(&txn)? {
-            utxos.push(item?);
-        }
-        utxos.sort_unstable_by_key(|(_, output)| output.get_value());
-
+    /// Select UTXOs worth at least `value`.
+    ///
+    /// Deposits created after `max_deposit_height`, or at an unknown height, aren't selected, so
+    /// that deposits which could still be reorged out of the mainchain aren't spent. Frozen UTXOs
+    /// are never selected.
+    pub fn select_coins(
+        &self,
+        value: u64,
+        max_deposit_height: Option<u32>,
+    ) -> Result<(u64, HashMap<OutPoint, Output>), Error> {
+        let txn = self.read_txn()?;
         let mut selected = HashMap::new();
         let mut total: u64 = 0;
-        for (outpoint, output) in &utxos {
-            if output.content.is_withdrawal() {
-                continue;
+        for (outpoint, output, output_value) in self.spendable_utxos(&txn)? {
+            if let (OutPoint::Deposit(_), Some(max_deposit_height)) = (outpoint, max_deposit_height)
+            {
+                match self.utxo_heights.get(&txn, &outpoint)? {
+                    Some(height) if height <= max_deposit_height => {}
+                    _ => continue,
+                }
             }
             if total > value {
                 break;
             }
-            total += output.get_value();
-            selected.insert(*outpoint, output.clone());
+            total += output_value;
+            selected.insert(outpoint, output);
         }
         if total < value {
             return Err(Error::NotEnoughFunds);
@@ -132,27 +316,339 @@ impl Wallet {
         return Ok((total, selected));
     }
 
-    pub fn delete_utxos(&self, outpoints: &[OutPoint]) -> Result<(), Error> {
-        let mut txn = self.env.write_txn()?;
-        for outpoint in outpoints {
-            self.utxos.delete(&mut txn, outpoint)?;
+    /// Combine up to `max_inputs` of the smallest UTXOs into a single output to a new address,
+    /// paying `fee`. Locked and frozen UTXOs are skipped, like in `select_coins`.
+    pub fn create_consolidation_transaction(
+        &self,
+        max_inputs: usize,
+        fee: u64,
+    ) -> Result<Transaction, Error> {
+        let utxos = {
+            let txn = self.read_txn()?;
+            self.spendable_utxos(&txn)?
+        };
+        let utxos = &utxos[..utxos.len().min(max_inputs)];
+        if utxos.len() < 2 {
+            return Err(Error::NothingToConsolidate);
+        }
+        let total: u64 = utxos.iter().map(|(_, _, value)| value).sum();
+        let value = total.checked_sub(fee).ok_or(Error::NotEnoughFunds)?;
+        let inputs = utxos.iter().map(|(outpoint, _, _)| *outpoint).collect();
+        let outputs = vec![Output {
+            address: self.get_new_address()?,
+            content: Content::Value(value),
+        }];
+        Ok(Transaction { inputs, outputs })
+    }
+
+    /// UTXOs that can be spent by an ordinary input and aren't frozen, with their values, in
+    /// ascending order of value.
+    fn spendable_utxos(&self, txn: &RoTxn) -> Result<Vec<(OutPoint, Output, u64)>, Error> {
+        let next_height = self.height.get(txn, &0)?.unwrap_or(0) + 1;
+        let mut utxos = vec![];
+        for item in self.utxos.iter(txn)? {
+            let (outpoint, output) = item?;
+            let Some(value) = output.content.spendable_value() else {
+                continue;
+            };
+            if self.frozen.get(txn, &outpoint)?.is_some() {
+                continue;
+            }
+            if let OutPoint::Coinbase { .. } = outpoint {
+                match self.utxo_heights.get(txn, &outpoint)? {
+                    Some(utxo_height) if is_coinbase_mature(utxo_height, next_height) => {}
+                    _ => continue,
+                }
+            }
+            utxos.push((outpoint, output, value));
         }
+        utxos.sort_unstable_by_key(|(_, _, value)| *value);
+        Ok(utxos)
+    }
+
+    /// Keep a UTXO out of coin selection, it can still be spent by adding it to a transaction
+    /// explicitly.
+    pub fn freeze(&self, outpoint: &OutPoint) -> Result<(), Error> {
+        let mut txn = self.write_txn()?;
+        if self.utxos.get(&txn, outpoint)?.is_none() {
+            return Err(Error::NoUtxo);
+        }
+        self.frozen.put(&mut txn, outpoint, &())?;
         txn.commit()?;
         Ok(())
     }
 
-    pub fn put_utxos(&self, utxos: &HashMap<OutPoint, Output>) -> Result<(), Error> {
-        let mut txn = self.env.write_txn()?;
-        for (outpoint, output) in utxos {
-            self.utxos.put(&mut txn, outpoint, output)?;
+    pub fn unfreeze(&self, outpoint: &OutPoint) -> Result<(), Error> {
+        let mut txn = self.write_txn()?;
+        self.frozen.delete(&mut txn, outpoint)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_frozen(&self) -> Result<HashSet<OutPoint>, Error> {
+        let txn = self.read_txn()?;
+        let mut frozen = HashSet::new();
+        for item in self.frozen.iter(&txn)? {
+            let (outpoint, ()) = item?;
+            frozen.insert(outpoint);
         }
+        Ok(frozen)
+    }
+
+    /// Forget all UTXOs, keeping the keys and addresses.
+    pub fn clear_utxos(&self) -> Result<(), Error> {
+        let mut txn = self.write_txn()?;
+        self.utxos.clear(&mut txn)?;
+        self.utxo_heights.clear(&mut txn)?;
+        self.frozen.clear(&mut txn)?;
         txn.commit()?;
         Ok(())
     }
 
+    pub fn delete_utxos(&self, outpoints: &[OutPoint]) -> Result<(), Error> {
+        self.write(|txn| {
+            for outpoint in outpoints {
+                self.spend_utxo(txn, outpoint, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Store UTXOs paying to the wallet, outputs to foreign addresses are skipped.
+    pub fn put_utxos(&self, utxos: &HashMap<OutPoint, Output>) -> Result<(), Error> {
+        self.write(|txn| {
+            for (outpoint, output) in utxos {
+                self.put_utxo_if_mine(txn, outpoint, output, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn put_utxo_heights(&self, heights: &HashMap<OutPoint, u32>) -> Result<(), Error> {
+        self.write(|txn| {
+            for (outpoint, height) in heights {
+                self.utxo_heights.put(txn, outpoint, height)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Apply a block connected to the best chain at `height`.
+    pub fn connect_block(&self, body: &Body, height: u32) -> Result<(), Error> {
+        self.write(|txn| {
+            // A peeked address isn't persisted until it's used, so it could be paid to before it's
+            // claimed.
+            if let Ok((index, next_address)) = self.next_address(txn) {
+                let mut outputs = body.coinbase.iter().chain(
+                    body.transactions
+                        .iter()
+                        .flat_map(|transaction| &transaction.outputs),
+                );
+                if outputs.any(|output| output.address == next_address) {
+                    self.put_address(txn, index, &next_address)?;
+                }
+            }
+            let merkle_root = body.compute_merkle_root();
+            for (vout, output) in body.coinbase.iter().enumerate() {
+                let outpoint = OutPoint::Coinbase {
+                    merkle_root,
+                    vout: vout as u32,
+                };
+                self.put_utxo_if_mine(txn, &outpoint, output, Some(height))?;
+            }
+            for transaction in &body.transactions {
+                for input in &transaction.inputs {
+                    self.spend_utxo(txn, input, Some(height))?;
+                }
+                let txid = transaction.txid();
+                for (vout, output) in transaction.outputs.iter().enumerate() {
+                    let outpoint = OutPoint::Regular {
+                        txid,
+                        vout: vout as u32,
+                    };
+                    self.put_utxo_if_mine(txn, &outpoint, output, Some(height))?;
+                }
+            }
+            self.height.put(txn, &0, &height)?;
+            Ok(())
+        })
+    }
+
+    /// Record the height of the best block, for wallets that are updated from the UTXO set
+    /// instead of `connect_block`.
+    pub fn set_height(&self, height: u32) -> Result<(), Error> {
+        self.write(|txn| Ok(self.height.put(txn, &0, &height)?))
+    }
+
+    /// Undo a block disconnected from the best chain, dropping the outputs it created and
+    /// restoring the outputs it spent.
+    pub fn disconnect_block(
+        &self,
+        body: &Body,
+        spent_utxos: &HashMap<OutPoint, Output>,
+    ) -> Result<(), Error> {
+        self.write(|txn| {
+            let merkle_root = body.compute_merkle_root();
+            for vout in 0..body.coinbase.len() {
+                let outpoint = OutPoint::Coinbase {
+                    merkle_root,
+                    vout: vout as u32,
+                };
+                self.revert_utxo(txn, &outpoint)?;
+            }
+            for transaction in &body.transactions {
+                let txid = transaction.txid();
+                for vout in 0..transaction.outputs.len() {
+                    let outpoint = OutPoint::Regular {
+                        txid,
+                        vout: vout as u32,
+                    };
+                    self.revert_utxo(txn, &outpoint)?;
+                }
+                for input in &transaction.inputs {
+                    if let Some(output) = spent_utxos.get(input) {
+                        // The height is filled in again by the next update from the node.
+                        self.put_utxo_if_mine(txn, input, output, None)?;
+                    }
+                }
+            }
+            // The disconnected block was the best one.
+            if let Some(height) = self.height.get(txn, &0)? {
+                self.height.put(txn, &0, &height.saturating_sub(1))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Forget a UTXO spent at `height`, logging it if the wallet had it.
+    fn spend_utxo(
+        &self,
+        txn: &mut RwTxn,
+        outpoint: &OutPoint,
+        height: Option<u32>,
+    ) -> Result<(), Error> {
+        if let Some(output) = self.utxos.get(txn, outpoint)? {
+            // Withdrawal outputs are only ever consumed by withdrawal bundles.
+            let event = if output.content.is_withdrawal() {
+                WalletEvent::WithdrawalBundled {
+                    outpoint: *outpoint,
+                    output,
+                }
+            } else {
+                WalletEvent::Spent {
+                    outpoint: *outpoint,
+                    output,
+                    height,
+                }
+            };
+            self.record_event(txn, &event)?;
+        }
+        self.utxos.delete(txn, outpoint)?;
+        self.utxo_heights.delete(txn, outpoint)?;
+        self.frozen.delete(txn, outpoint)?;
+        Ok(())
+    }
+
+    /// Forget a UTXO created by a disconnected block, logging it if the wallet had it.
+    fn revert_utxo(&self, txn: &mut RwTxn, outpoint: &OutPoint) -> Result<(), Error> {
+        if let Some(output) = self.utxos.get(txn, outpoint)? {
+            let event = WalletEvent::Reverted {
+                outpoint: *outpoint,
+                output,
+            };
+            self.record_event(txn, &event)?;
+        }
+        self.utxos.delete(txn, outpoint)?;
+        self.utxo_heights.delete(txn, outpoint)?;
+        Ok(())
+    }
+
+    fn record_event(&self, txn: &mut RwTxn, event: &WalletEvent) -> Result<(), Error> {
+        let id = match self.events.last(txn)? {
+            Some((last_id, _)) => u64::from_be_bytes(last_id) + 1,
+            None => 0,
+        };
+        self.events.put(txn, &id.to_be_bytes(), event)?;
+        Ok(())
+    }
+
+    /// Activity log entries with an id of at least `since`, oldest first.
+    pub fn get_events(&self, since: u64) -> Result<Vec<(u64, WalletEvent)>, Error> {
+        let txn = self.read_txn()?;
+        let mut events = vec![];
+        for item in self.events.range(&txn, &(since.to_be_bytes()..))? {
+            let (id, event) = item?;
+            events.push((u64::from_be_bytes(id), event));
+        }
+        Ok(events)
+    }
+
+    /// Whether the output pays to one of the wallet's addresses.
+    pub fn owns_output(&self, output: &Output) -> Result<bool, Error> {
+        let txn = self.read_txn()?;
+        self.owns_address(&txn, &output.address)
+    }
+
+    fn owns_address(&self, txn: &RoTxn, address: &Address) -> Result<bool, Error> {
+        Ok(self.address_to_index.get(txn, address)?.is_some())
+    }
+
+    fn put_utxo_if_mine(
+        &self,
+        txn: &mut RwTxn,
+        outpoint: &OutPoint,
+        output: &Output,
+        height: Option<u32>,
+    ) -> Result<(), Error> {
+        if !self.owns_address(txn, &output.address)? {
+            return Ok(());
+        }
+        if self.utxos.get(txn, outpoint)?.is_none() {
+            let event = WalletEvent::Received {
+                outpoint: *outpoint,
+                output: output.clone(),
+                height,
+            };
+            self.record_event(txn, &event)?;
+        }
+        self.utxos.put(txn, outpoint, output)?;
+        if let Some(height) = height {
+            self.utxo_heights.put(txn, outpoint, &height)?;
+        }
+        Ok(())
+    }
+
+    /// Balance split by whether the funds can be spent, see `Balance`.
+    pub fn get_balance_detailed(&self, height: u32) -> Result<Balance, Error> {
+        let txn = self.read_txn()?;
+        let mut balance = Balance::default();
+        for item in self.utxos.iter(&txn)? {
+            let (outpoint, output) = item?;
+            let value = output.get_value();
+            if output.content.is_locked() {
+                balance.pending_withdrawal += value;
+            } else if self.frozen.get(&txn, &outpoint)?.is_some() {
+                balance.frozen += value;
+            } else if let OutPoint::Coinbase { .. } = outpoint {
+                let mature = match self.utxo_heights.get(&txn, &outpoint)? {
+                    Some(utxo_height) => is_coinbase_mature(utxo_height, height + 1),
+                    None => false,
+                };
+                if mature {
+                    balance.available += value;
+                } else {
+                    balance.immature += value;
+                }
+            } else {
+                balance.available += value;
+            }
+        }
+        Ok(balance)
+    }
+
     pub fn get_balance(&self) -> Result<u64, Error> {
         let mut balance: u64 = 0;
-        let txn = self.env.read_txn()?;
+        let txn = self.read_txn()?;
         for item in self.utxos.iter(&txn)? {
             let (_, utxo) = item?;
             balance += utxo.get_value();
@@ -161,7 +657,7 @@ impl Wallet {
     }
 
     pub fn get_utxos(&self) -> Result<HashMap<OutPoint, Output>, Error> {
-        let txn = self.env.read_txn()?;
+        let txn = self.read_txn()?;
         let mut utxos = HashMap::new();
         for item in self.utxos.iter(&txn)? {
             let (outpoint, output) = item?;
@@ -170,8 +666,21 @@ impl Wallet {
         Ok(utxos)
     }
 
+    /// UTXOs along with the height of the block that created them. The height is `None` for UTXOs
+    /// stored before heights were tracked, until the wallet is next updated from the node.
+    pub fn get_utxos_with_height(&self) -> Result<HashMap<OutPoint, (Output, Option<u32>)>, Error> {
+        let txn = self.read_txn()?;
+        let mut utxos = HashMap::new();
+        for item in self.utxos.iter(&txn)? {
+            let (outpoint, output) = item?;
+            let height = self.utxo_heights.get(&txn, &outpoint)?;
+            utxos.insert(outpoint, (output, height));
+        }
+        Ok(utxos)
+    }
+
     pub fn get_addresses(&self) -> Result<HashSet<Address>, Error> {
-        let txn = self.env.read_txn()?;
+        let txn = self.read_txn()?;
         let mut addresses = HashSet::new();
         for item in self.index_to_address.iter(&txn)? {
             let (_, address) = item?;
@@ -181,23 +690,68 @@ impl Wallet {
     }
 
     pub fn authorize(&self, transaction: Transaction) -> Result<AuthorizedTransaction, Error> {
-        let txn = self.env.read_txn()?;
-        let mut authorizations = vec![];
+        let unsigned_transaction = self.create_unsigned_transaction(transaction)?;
+        self.authorize_unsigned(unsigned_transaction)
+    }
+
+    /// Attach the spent outputs from the wallet's UTXOs so the transaction can be signed by a
+    /// wallet that doesn't track them.
+    pub fn create_unsigned_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<UnsignedTransaction, Error> {
+        let txn = self.read_txn()?;
+        let mut spent_utxos = vec![];
         for input in &transaction.inputs {
             let spent_utxo = self.utxos.get(&txn, input)?.ok_or(Error::NoUtxo)?;
+            spent_utxos.push(spent_utxo);
+        }
+        Ok(UnsignedTransaction {
+            transaction,
+            spent_utxos,
+        })
+    }
+
+    /// Sign the inputs of `unsigned_transaction` with the keys of the addresses its spent
+    /// outputs claim, which aren't checked, see `UnsignedTransaction`.
+    pub fn authorize_unsigned(
+        &self,
+        unsigned_transaction: UnsignedTransaction,
+    ) -> Result<AuthorizedTransaction, Error> {
+        let UnsignedTransaction {
+            transaction,
+            spent_utxos,
+        } = unsigned_transaction;
+        if spent_utxos.len() != transaction.inputs.len() {
+            return Err(Error::WrongNumberOfSpentUtxos {
+                inputs: transaction.inputs.len(),
+                spent_utxos: spent_utxos.len(),
+            });
+        }
+        let txn = self.read_txn()?;
+        let message = crate::authorization::signing_message(&transaction)?;
+        // Every input signs the same message, so inputs spending from the same address share a
+        // single authorization.
+        let mut address_authorizations: HashMap<Address, Authorization> = HashMap::new();
+        let mut authorizations = Vec::with_capacity(spent_utxos.len());
+        for spent_utxo in &spent_utxos {
+            let address = spent_utxo.address;
+            if let Some(authorization) = address_authorizations.get(&address) {
+                authorizations.push(authorization.clone());
+                continue;
+            }
             let index = self
                 .address_to_index
-                .get(&txn, &spent_utxo.address)?
-                .ok_or(Error::NoIndex {
-                    address: spent_utxo.address,
-                })?;
+                .get(&txn, &address)?
+                .ok_or(Error::NoIndex { address })?;
             let index = BigEndian::read_u32(&index);
             let keypair = self.get_keypair(&txn, index)?;
-            let signature = crate::authorization::sign(&keypair, &transaction)?;
-            authorizations.push(Authorization {
+            let authorization = Authorization {
                 public_key: keypair.public,
-                signature,
-            });
+                signature: keypair.sign(&message),
+            };
+            address_authorizations.insert(address, authorization.clone());
+            authorizations.push(authorization);
         }
         Ok(AuthorizedTransaction {
             authorizations,
@@ -205,25 +759,158 @@ impl Wallet {
         })
     }
 
+    /// Sign a message with the key of one of the wallet's addresses.
+    pub fn sign_message(
+        &self,
+        address: &Address,
+        message: &[u8],
+    ) -> Result<crate::authorization::SignedMessage, Error> {
+        let txn = self.read_txn()?;
+        let index = self
+            .address_to_index
+            .get(&txn, address)?
+            .ok_or(Error::NoIndex { address: *address })?;
+        let index = BigEndian::read_u32(&index);
+        let keypair = self.get_keypair(&txn, index)?;
+        Ok(crate::authorization::sign_message(&keypair, message))
+    }
+
     pub fn get_new_address(&self) -> Result<Address, Error> {
-        let mut txn = self.env.write_txn()?;
+        self.write(|txn| {
+            let (index, address) = self.next_address(txn)?;
+            self.put_address(txn, index, &address)?;
+            Ok(address)
+        })
+    }
+
+    /// The address `get_new_address` would return, without persisting it. Showing it doesn't
+    /// use up an index, call `claim_address` once it's actually used.
+    pub fn peek_next_address(&self) -> Result<Address, Error> {
+        let txn = self.read_txn()?;
+        let (_, address) = self.next_address(&txn)?;
+        Ok(address)
+    }
+
+    /// Persist `address` if it's the next address, returns whether it was claimed.
+    pub fn claim_address(&self, address: &Address) -> Result<bool, Error> {
+        let mut txn = self.write_txn()?;
+        let (index, next_address) = self.next_address(&txn)?;
+        if next_address != *address {
+            return Ok(false);
+        }
+        self.put_address(&mut txn, index, address)?;
+        txn.commit()?;
+        Ok(true)
+    }
+
+    fn next_address(&self, txn: &RoTxn) -> Result<(u32, Address), Error> {
         let (last_index, _) = self
             .index_to_address
-            .last(&txn)?
+            .last(txn)?
             .unwrap_or(([0; 4], [0; 20].into()));
-        let last_index = BigEndian::read_u32(&last_index);
-        let index = last_index + 1;
-        let keypair = self.get_keypair(&txn, index)?;
-        let address = get_address(&keypair.public);
+        let index = BigEndian::read_u32(&last_index) + 1;
+        let keypair = self.get_keypair(txn, index)?;
+        Ok((index, get_address(&keypair.public)))
+    }
+
+    fn put_address(&self, txn: &mut RwTxn, index: u32, address: &Address) -> Result<(), Error> {
         let index = index.to_be_bytes();
-        self.index_to_address.put(&mut txn, &index, &address)?;
-        self.address_to_index.put(&mut txn, &address, &index)?;
-        txn.commit()?;
-        Ok(address)
+        self.index_to_address.put(txn, &index, address)?;
+        self.address_to_index.put(txn, address, &index)?;
+        Ok(())
     }
 
-    pub fn get_num_addresses(&self) -> Result<u32, Error> {
+    /// Address for change and mining rewards, fresh or reused depending on the address policy.
+    pub fn get_change_address(&self) -> Result<Address, Error> {
+        match self.address_policy {
+            AddressPolicy::Fresh => self.get_new_address(),
+            AddressPolicy::Reuse => {
+                let txn = self.read_txn()?;
+                if let Some(address) = self.change_address.get(&txn, &0)? {
+                    return Ok(address);
+                }
+                drop(txn);
+                let address = self.get_new_address()?;
+                let mut txn = self.write_txn()?;
+                self.change_address.put(&mut txn, &0, &address)?;
+                txn.commit()?;
+                Ok(address)
+            }
+        }
+    }
+
+    /// Generate `count` new addresses in a single write transaction.
+    pub fn get_new_addresses(&self, count: u32) -> Result<Vec<Address>, Error> {
+        self.write(|txn| {
+            let (last_index, _) = self
+                .index_to_address
+                .last(txn)?
+                .unwrap_or(([0; 4], [0; 20].into()));
+            let last_index = BigEndian::read_u32(&last_index);
+            let account_key = self.get_account_key(txn)?;
+            let mut addresses = Vec::with_capacity(count as usize);
+            for index in (last_index + 1)..=(last_index + count) {
+                let keypair = Self::derive_keypair(&account_key, index)?;
+                let address = get_address(&keypair.public);
+                let index = index.to_be_bytes();
+                self.index_to_address.put(txn, &index, &address)?;
+                self.address_to_index.put(txn, &address, &index)?;
+                addresses.push(address);
+            }
+            Ok(addresses)
+        })
+    }
+
+    /// Current map size of the wallet's environment in bytes.
+    pub fn map_size(&self) -> usize {
+        self.map_size.load(Ordering::SeqCst)
+    }
+
+    /// Run `f` in a write transaction and commit it. If the map is full, the map size is doubled
+    /// and `f` is retried in a new transaction.
+    ///
+    /// Resizing requires that no other transaction of this process is open on the wallet, so it
+    /// waits for every transaction opened through `read_txn` and `write_txn` to finish.
+    fn write<T>(&self, f: impl Fn(&mut RwTxn) -> Result<T, Error>) -> Result<T, Error> {
+        loop {
+            let map_size = self.map_size();
+            let result = {
+                let mut txn = self.write_txn()?;
+                match f(&mut txn) {
+                    Ok(value) => txn.commit().map(|()| value).map_err(Error::from),
+                    Err(err) => Err(err),
+                }
+            };
+            match result {
+                Err(Error::Heed(heed::Error::Mdb(heed::MdbError::MapFull))) => {
+                    let _resize = self.resize_lock.write().unwrap();
+                    // Another writer may have grown the map while this one waited.
+                    if self.map_size() == map_size {
+                        self.env.resize(map_size * 2)?;
+                        self.map_size.store(map_size * 2, Ordering::SeqCst);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Open a read transaction that holds off `write` from resizing the map while it is open.
+    fn read_txn(&self) -> Result<Txn<'_, RoTxn<'_>>, Error> {
+        let guard = self.resize_lock.read().unwrap();
         let txn = self.env.read_txn()?;
+        Ok(Txn { txn, _guard: guard })
+    }
+
+    /// Open a write transaction that holds off `write` from resizing the map while it is open.
+    fn write_txn(&self) -> Result<Txn<'_, RwTxn<'_, '_>>, Error> {
+        let guard = self.resize_lock.read().unwrap();
+        let txn = self.env.write_txn()?;
+        Ok(Txn { txn, _guard: guard })
+    }
+
+    pub fn get_num_addresses(&self) -> Result<u32, Error> {
+        let txn = self.read_txn()?;
         let (last_index, _) = self
             .index_to_address
             .last(&txn)?
@@ -233,33 +920,279 @@ impl Wallet {
     }
 
     fn get_keypair(&self, txn: &RoTxn, index: u32) -> Result<ed25519_dalek::Keypair, Error> {
-        let seed = self.seed.get(txn, &0)?.ok_or(Error::NoSeed)?;
-        let xpriv = ExtendedSecretKey::from_seed(&seed)?;
-        let derivation_path = DerivationPath::new([
-            ChildIndex::Hardened(1),
-            ChildIndex::Hardened(0),
-            ChildIndex::Hardened(0),
-            ChildIndex::Hardened(index),
-        ]);
-        let child = xpriv.derive(&derivation_path)?;
+        let account_key = self.get_account_key(txn)?;
+        Self::derive_keypair(&account_key, index)
+    }
+
+    /// Key at the account path, `m/1'/0'/0'` by default, address keys are its hardened children.
+    ///
+    /// The cache lock is only taken while `txn` is open and never across opening a transaction,
+    /// so it can't deadlock with a writer waiting for the cache.
+    fn get_account_key(&self, txn: &RoTxn) -> Result<Arc<ExtendedSecretKey>, Error> {
+        let key_id = self.key_id(txn)?;
+        if let Some((cached_id, account_key)) = self.account_key.lock().unwrap().as_ref() {
+            if *cached_id == key_id {
+                return Ok(account_key.clone());
+            }
+        }
+        let xpriv = self.get_master_key(txn)?;
+        let derivation_path = self
+            .account_path
+            .get(txn, &0)?
+            .unwrap_or_default()
+            .derivation_path();
+        let xpriv = Arc::new(xpriv.derive(&derivation_path)?);
+        *self.account_key.lock().unwrap() = Some((key_id, xpriv.clone()));
+        Ok(xpriv)
+    }
+
+    /// Hash of the master key material and account path visible in `txn`, identifies the account
+    /// key derived from them.
+    fn key_id(&self, txn: &RoTxn) -> Result<Hash, Error> {
+        let mut hasher = blake3::Hasher::new();
+        match (self.seed.get(txn, &0)?, self.xprv.get(txn, &0)?) {
+            (Some(seed), _) => hasher.update(b"seed").update(&seed),
+            (None, Some(key)) => hasher.update(b"xprv").update(&key),
+            (None, None) => return Err(Error::NoSeed),
+        };
+        let account_path = self.account_path.get(txn, &0)?.unwrap_or_default();
+        hasher.update(account_path.to_string().as_bytes());
+        Ok(hasher.finalize().into())
+    }
+
+    fn get_master_key(&self, txn: &RoTxn) -> Result<ExtendedSecretKey, Error> {
+        if let Some(seed) = self.seed.get(txn, &0)? {
+            return Ok(ExtendedSecretKey::from_seed(&seed)?);
+        }
+        let key = self.xprv.get(txn, &0)?.ok_or(Error::NoSeed)?;
+        let secret_key = ed25519_dalek::SecretKey::from_bytes(&key[..32])
+            .map_err(crate::authorization::Error::from)?;
+        let mut chain_code = [0; 32];
+        chain_code.copy_from_slice(&key[32..]);
+        Ok(ExtendedSecretKey {
+            depth: 0,
+            child_index: ChildIndex::Normal(0),
+            secret_key,
+            chain_code,
+        })
+    }
+
+    fn derive_keypair(
+        account_key: &ExtendedSecretKey,
+        index: u32,
+    ) -> Result<ed25519_dalek::Keypair, Error> {
+        let child = account_key.derive_child(ChildIndex::Hardened(index))?;
         let public = child.public_key();
         let secret = child.secret_key;
         Ok(ed25519_dalek::Keypair { secret, public })
     }
 }
 
+/// Transaction opened by `Wallet::read_txn` or `Wallet::write_txn`, the guard is released after
+/// the transaction is closed.
+struct Txn<'a, T> {
+    txn: T,
+    _guard: RwLockReadGuard<'a, ()>,
+}
+
+impl<T> std::ops::Deref for Txn<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.txn
+    }
+}
+
+impl<T> std::ops::DerefMut for Txn<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.txn
+    }
+}
+
+impl<'e, 'p> Txn<'_, RwTxn<'e, 'p>> {
+    fn commit(self) -> Result<(), heed::Error> {
+        self.txn.commit()
+    }
+}
+
+/// Entry of the wallet's activity log, see `Wallet::get_events`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalletEvent {
+    /// An output paying to the wallet, including credited deposits. `height` is `None` if the
+    /// output was picked up by a scan of the UTXO set instead of a connected block.
+    Received {
+        outpoint: OutPoint,
+        output: Output,
+        height: Option<u32>,
+    },
+    Spent {
+        outpoint: OutPoint,
+        output: Output,
+        height: Option<u32>,
+    },
+    /// A withdrawal output was included in a withdrawal bundle.
+    WithdrawalBundled { outpoint: OutPoint, output: Output },
+    /// An output was undone by a block disconnected in a reorg.
+    Reverted { outpoint: OutPoint, output: Output },
+}
+
+impl std::fmt::Display for WalletEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let in_block = |height: &Option<u32>| match height {
+            Some(height) => format!(" in block {height}"),
+            None => String::new(),
+        };
+        let amount = |output: &Output| bitcoin::Amount::from_sat(output.get_value());
+        match self {
+            Self::Received {
+                outpoint: OutPoint::Deposit(_),
+                output,
+                height,
+            } => write!(
+                f,
+                "deposit of {} credited to {}{}",
+                amount(output),
+                output.address,
+                in_block(height)
+            ),
+            Self::Received { output, height, .. } => write!(
+                f,
+                "received {} at {}{}",
+                amount(output),
+                output.address,
+                in_block(height)
+            ),
+            Self::Spent { output, height, .. } => write!(
+                f,
+                "spent {} from {}{}",
+                amount(output),
+                output.address,
+                in_block(height)
+            ),
+            Self::WithdrawalBundled { output, .. } => {
+                write!(f, "withdrawal of {} bundled", amount(output))
+            }
+            Self::Reverted { output, .. } => write!(
+                f,
+                "{} at {} reverted by a reorg",
+                amount(output),
+                output.address
+            ),
+        }
+    }
+}
+
+/// How the wallet picks addresses for change and mining rewards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressPolicy {
+    /// Generate a new address every time.
+    #[default]
+    Fresh,
+    /// Send everything to a single designated address, which keeps backups simple at the cost
+    /// of privacy.
+    Reuse,
+}
+
+/// Hardened `m/purpose'/coin'/account'` path of the key that addresses are derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountPath {
+    pub purpose: u32,
+    pub coin: u32,
+    pub account: u32,
+}
+
+impl AccountPath {
+    /// First hardened child index, components are given without the hardened bit so they have
+    /// to be below it.
+    pub const HARDENED_OFFSET: u32 = 1 << 31;
+
+    pub fn derivation_path(&self) -> DerivationPath {
+        DerivationPath::new([
+            ChildIndex::Hardened(self.purpose),
+            ChildIndex::Hardened(self.coin),
+            ChildIndex::Hardened(self.account),
+        ])
+    }
+}
+
+impl Default for AccountPath {
+    fn default() -> Self {
+        Self {
+            purpose: 1,
+            coin: 0,
+            account: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for AccountPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "m/{}'/{}'/{}'", self.purpose, self.coin, self.account)
+    }
+}
+
+impl std::str::FromStr for AccountPath {
+    type Err = AccountPathParseError;
+    /// Parses `m/purpose'/coin'/account'`, every component has to be hardened.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AccountPathParseError(s.to_string());
+        let mut components = s.split('/');
+        if components.next() != Some("m") {
+            return Err(invalid());
+        }
+        let indexes = components
+            .map(|component| {
+                let index = component.strip_suffix('\'').ok_or_else(invalid)?;
+                match index.parse::<u32>() {
+                    Ok(index) if index < Self::HARDENED_OFFSET => Ok(index),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<Vec<u32>, _>>()?;
+        match indexes[..] {
+            [purpose, coin, account] => Ok(Self {
+                purpose,
+                coin,
+                account,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid account path {0}, expected m/purpose'/coin'/account'")]
+pub struct AccountPathParseError(String);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Value that can be spent right away.
+    pub available: u64,
+    /// Value in withdrawal outputs that haven't been included in a bundle yet.
+    pub pending_withdrawal: u64,
+    /// Value in coinbase outputs that can't be spent in the next block, see
+    /// `consensus::COINBASE_MATURITY`.
+    pub immature: u64,
+    /// Value in UTXOs frozen with `Wallet::freeze`.
+    pub frozen: u64,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("heed error")]
     Heed(#[from] heed::Error),
     #[error("bip32 error")]
     Bip32(#[from] ed25519_dalek_bip32::Error),
+    #[error("database version error")]
+    Versioned(#[from] crate::versioned::Error),
     #[error("address {address} does not exist")]
     AddressDoesNotExist { address: crate::types::Address },
     #[error("utxo doesn't exist")]
     NoUtxo,
     #[error("wallet doesn't have a seed")]
     NoSeed,
+    #[error("invalid wallet name {0:?}")]
+    InvalidWalletName(String),
     #[error("no index for address {address}")]
     NoIndex { address: Address },
     #[error("authorization error")]
@@ -268,4 +1201,13 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("not enough funds")]
     NotEnoughFunds,
+    #[error("fewer than two UTXOs to consolidate")]
+    NothingToConsolidate,
+    #[error("wallet derives addresses at {stored}, can't open it with {requested}")]
+    AccountPathMismatch {
+        stored: AccountPath,
+        requested: AccountPath,
+    },
+    #[error("transaction has {inputs} inputs but {spent_utxos} spent utxos")]
+    WrongNumberOfSpentUtxos { inputs: usize, spent_utxos: usize },
 }
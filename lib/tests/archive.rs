@@ -0,0 +1,291 @@
+use {{crate_name}}::{
+    archive::{self, Archive},
+    bip300301::bitcoin,
+    consensus::genesis_hash,
+    types::{Address, AuthorizedTransaction, Body, Content, Header, Output, Transaction},
+};
+use bitcoin::hashes::Hash as _;
+
+fn archive(dir: &tempfile::TempDir) -> (heed::Env, Archive) {
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(Archive::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let archive = Archive::new(&env, None).unwrap();
+    (env, archive)
+}
+
+/// Append `count` headers, `branch` makes the headers differ between chains.
+fn extend(env: &heed::Env, archive: &Archive, count: u32, branch: u64) {
+    let mut txn = env.write_txn().unwrap();
+    for _ in 0..count {
+        let height = archive.get_height(&txn).unwrap() + 1;
+        let body = Body::new(vec![], vec![]);
+        let mut merkle_root: [u8; 32] = body.compute_merkle_root().into();
+        merkle_root[..8].copy_from_slice(&branch.to_le_bytes());
+        let header = Header {
+            merkle_root: merkle_root.into(),
+            prev_side_hash: archive.get_best_hash(&txn).unwrap(),
+            prev_main_hash: bitcoin::BlockHash::all_zeros(),
+            height,
+            commitment: None,
+        };
+        archive.append_header(&mut txn, &header).unwrap();
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn block_locator_finds_fork_point() {
+    const FORK_HEIGHT: u32 = 57;
+    let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let (env_a, archive_a) = archive(&dir_a);
+    let (env_b, archive_b) = archive(&dir_b);
+    extend(&env_a, &archive_a, FORK_HEIGHT, 0);
+    extend(&env_b, &archive_b, FORK_HEIGHT, 0);
+    extend(&env_a, &archive_a, 100, 1);
+    extend(&env_b, &archive_b, 30, 2);
+
+    let txn_a = env_a.read_txn().unwrap();
+    let txn_b = env_b.read_txn().unwrap();
+    let locator = archive_b.get_block_locator(&txn_b).unwrap();
+    // The locator of the shorter branch skips heights, the fork point is the most recent shared
+    // block it contains.
+    let fork_height = archive_a.find_fork_point(&txn_a, &locator).unwrap();
+    assert!(fork_height <= FORK_HEIGHT);
+    let fork_hash = archive_a
+        .get_header(&txn_a, fork_height)
+        .unwrap()
+        .unwrap()
+        .hash();
+    assert_eq!(
+        archive_b.get_height_by_hash(&txn_b, &fork_hash).unwrap(),
+        Some(fork_height)
+    );
+    // Headers after the fork point lead back onto A's chain.
+    let headers = archive_a
+        .get_headers_after(&txn_a, fork_height, None, 2000)
+        .unwrap();
+    assert_eq!(headers.first().unwrap().height, fork_height + 1);
+    assert_eq!(headers.last().unwrap().height, FORK_HEIGHT + 100);
+
+    // Without any shared block only genesis is in common.
+    let dir_c = tempfile::tempdir().unwrap();
+    let (env_c, archive_c) = archive(&dir_c);
+    extend(&env_c, &archive_c, 20, 3);
+    let txn_c = env_c.read_txn().unwrap();
+    let locator = archive_c.get_block_locator(&txn_c).unwrap();
+    assert_eq!(archive_a.find_fork_point(&txn_a, &locator).unwrap(), 0);
+}
+
+#[test]
+fn iterate_height_range() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, archive) = archive(&dir);
+    let mut txn = env.write_txn().unwrap();
+    for height in 1..=20u32 {
+        // A distinct coinbase per block, so that bodies differ.
+        let body = Body::new(
+            vec![],
+            vec![Output {
+                address: Address([height as u8; 20]),
+                content: Content::Value(height as u64),
+            }],
+        );
+        let header = Header {
+            merkle_root: body.compute_merkle_root(),
+            prev_side_hash: archive.get_best_hash(&txn).unwrap(),
+            prev_main_hash: bitcoin::BlockHash::all_zeros(),
+            height,
+            commitment: None,
+        };
+        archive.append_header(&mut txn, &header).unwrap();
+        archive.put_body(&mut txn, &header, &body).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = env.read_txn().unwrap();
+    let headers: Vec<_> = archive
+        .iter_headers(&txn, 5..=12)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let bodies: Vec<_> = archive
+        .iter_bodies(&txn, 5..=12)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(headers.len(), 8);
+    assert_eq!(bodies.len(), 8);
+    for ((height, header), (body_height, body)) in headers.iter().zip(&bodies) {
+        assert_eq!(height, body_height);
+        let expected_header = archive.get_header(&txn, *height).unwrap().unwrap();
+        assert_eq!(header.hash(), expected_header.hash());
+        let expected_body = archive.get_body(&txn, *height).unwrap().unwrap();
+        assert_eq!(
+            body.compute_merkle_root(),
+            expected_body.compute_merkle_root()
+        );
+    }
+    assert_eq!(headers.first().unwrap().0, 5);
+    assert_eq!(headers.last().unwrap().0, 12);
+    // Ranges past the tip stop at the tip.
+    assert_eq!(archive.iter_headers(&txn, 18..=100).unwrap().count(), 3);
+}
+
+/// Append a block with `body` on top of the tip.
+fn push_block(txn: &mut heed::RwTxn, archive: &Archive, body: &Body) -> Header {
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: archive.get_best_hash(txn).unwrap(),
+        prev_main_hash: bitcoin::BlockHash::all_zeros(),
+        height: archive.get_height(txn).unwrap() + 1,
+        commitment: None,
+    };
+    archive.append_header(txn, &header).unwrap();
+    archive.put_body(txn, &header, body).unwrap();
+    header
+}
+
+fn coinbase_body(value: u64) -> Body {
+    Body::new(
+        vec![],
+        vec![Output {
+            address: Address([value as u8; 20]),
+            content: Content::Value(value),
+        }],
+    )
+}
+
+#[test]
+fn first_header_builds_on_genesis() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, archive) = archive(&dir);
+    let mut txn = env.write_txn().unwrap();
+    assert_eq!(archive.get_best_hash(&txn).unwrap(), genesis_hash());
+    let body = coinbase_body(1);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: [0; 32].into(),
+        prev_main_hash: bitcoin::BlockHash::all_zeros(),
+        height: 1,
+        commitment: None,
+    };
+    assert!(matches!(
+        archive.append_header(&mut txn, &header),
+        Err(archive::Error::InvalidPrevSideHash)
+    ));
+    let header = push_block(&mut txn, &archive, &body);
+    assert_eq!(header.prev_side_hash, genesis_hash());
+    assert_eq!(archive.get_best_hash(&txn).unwrap(), header.hash());
+}
+
+#[test]
+fn reject_header_with_wrong_height() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, archive) = archive(&dir);
+    let mut txn = env.write_txn().unwrap();
+    push_block(&mut txn, &archive, &coinbase_body(1));
+    let body = coinbase_body(2);
+    for height in [1, 3] {
+        let header = Header {
+            merkle_root: body.compute_merkle_root(),
+            prev_side_hash: archive.get_best_hash(&txn).unwrap(),
+            prev_main_hash: bitcoin::BlockHash::all_zeros(),
+            height,
+            commitment: None,
+        };
+        assert!(matches!(
+            archive.append_header(&mut txn, &header),
+            Err(archive::Error::InvalidHeight { expected: 2, height: h }) if h == height
+        ));
+    }
+    assert_eq!(archive.get_height(&txn).unwrap(), 1);
+}
+
+#[test]
+fn pruned_bodies_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, archive) = archive(&dir);
+    let mut txn = env.write_txn().unwrap();
+    for value in 1..=10 {
+        push_block(&mut txn, &archive, &coinbase_body(value));
+    }
+    archive.prune_bodies(&mut txn, 3).unwrap();
+    assert_eq!(archive.get_pruned_height(&txn).unwrap(), 6);
+    assert!(matches!(
+        archive.get_body(&txn, 6),
+        Err(archive::Error::Pruned { height: 6 })
+    ));
+    assert!(archive.get_body(&txn, 7).unwrap().is_some());
+    // Headers are kept, and pruning to a shallower depth is a no-op.
+    assert!(archive.get_header(&txn, 1).unwrap().is_some());
+    archive.prune_bodies(&mut txn, 5).unwrap();
+    assert_eq!(archive.get_pruned_height(&txn).unwrap(), 6);
+    assert_eq!(archive.iter_bodies(&txn, 1..=10).unwrap().count(), 4);
+}
+
+#[test]
+fn lookup_height_by_hash_and_txid() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, archive) = archive(&dir);
+    let mut txn = env.write_txn().unwrap();
+    push_block(&mut txn, &archive, &coinbase_body(1));
+    let transaction = AuthorizedTransaction {
+        transaction: Transaction {
+            inputs: vec![],
+            outputs: vec![Output {
+                address: Address([2; 20]),
+                content: Content::Value(2),
+            }],
+        },
+        authorizations: vec![],
+    };
+    let txid = transaction.transaction.txid();
+    let header = push_block(&mut txn, &archive, &Body::new(vec![transaction], vec![]));
+    assert_eq!(
+        archive.get_height_by_hash(&txn, &header.hash()).unwrap(),
+        Some(2)
+    );
+    assert_eq!(archive.get_height_by_txid(&txn, &txid).unwrap(), Some(2));
+
+    // Disconnecting the block removes it from both indexes.
+    archive.disconnect_tip(&mut txn).unwrap().unwrap();
+    assert_eq!(
+        archive.get_height_by_hash(&txn, &header.hash()).unwrap(),
+        None
+    );
+    assert_eq!(archive.get_height_by_txid(&txn, &txid).unwrap(), None);
+}
+
+#[test]
+fn backfill_missing_txid_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, archive) = archive(&dir);
+    let txid_to_height: heed::Database<
+        heed::types::OwnedType<[u8; 32]>,
+        heed::types::OwnedType<[u8; 4]>,
+    > = env.open_database(Some("txid_to_height")).unwrap().unwrap();
+    let mut txn = env.write_txn().unwrap();
+    let transaction = AuthorizedTransaction {
+        transaction: Transaction {
+            inputs: vec![],
+            outputs: vec![Output {
+                address: Address([2; 20]),
+                content: Content::Value(2),
+            }],
+        },
+        authorizations: vec![],
+    };
+    let txid = transaction.transaction.txid();
+    push_block(&mut txn, &archive, &coinbase_body(1));
+    push_block(&mut txn, &archive, &Body::new(vec![transaction], vec![]));
+    assert_eq!(archive.backfill_txid_index(&mut txn).unwrap(), 0);
+
+    // Drop the index, as in a database written before it existed.
+    txid_to_height.clear(&mut txn).unwrap();
+    assert_eq!(archive.get_height_by_txid(&txn, &txid).unwrap(), None);
+    assert_eq!(archive.backfill_txid_index(&mut txn).unwrap(), 1);
+    assert_eq!(archive.get_height_by_txid(&txn, &txid).unwrap(), Some(2));
+}
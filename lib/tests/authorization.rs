@@ -0,0 +1,169 @@
+use {{crate_name}}::{
+    authorization::{
+        self, authorize, get_address, sign_message, verify_authorizations,
+        verify_authorized_transaction, verify_message, Authorization,
+    },
+    consensus::chain_id,
+    types::{AuthorizedTransaction, Body, Content, OutPoint, Output, Transaction},
+};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer as _};
+
+fn keypair(index: u32) -> Keypair {
+    let mut bytes = [0; 32];
+    bytes[..4].copy_from_slice(&index.to_be_bytes());
+    let secret = SecretKey::from_bytes(&bytes).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// Body of transactions with `inputs_per_transaction` inputs each, every input authorized by a
+/// different keypair.
+fn body(num_transactions: u32, inputs_per_transaction: u32) -> Body {
+    let transactions: Vec<AuthorizedTransaction> = (0..num_transactions)
+        .map(|i| {
+            let seed = i * inputs_per_transaction;
+            let keypairs: Vec<_> = (0..inputs_per_transaction)
+                .map(|j| keypair(seed + j))
+                .collect();
+            let addresses_keypairs: Vec<_> = keypairs
+                .iter()
+                .map(|keypair| (get_address(&keypair.public), keypair))
+                .collect();
+            let transaction = Transaction {
+                inputs: (0..inputs_per_transaction)
+                    .map(|j| OutPoint::Regular {
+                        txid: [0; 32].into(),
+                        vout: seed + j,
+                    })
+                    .collect(),
+                outputs: vec![Output {
+                    address: addresses_keypairs[0].0,
+                    content: Content::Value(seed as u64),
+                }],
+            };
+            authorize(&addresses_keypairs, transaction).unwrap()
+        })
+        .collect();
+    Body::new(transactions, vec![])
+}
+
+#[test]
+fn verify_in_single_thread_pool() {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    // Fewer signatures than a chunk, and enough for several chunks.
+    for body in [body(4, 2), body(50, 3)] {
+        pool.install(|| verify_authorizations(&body)).unwrap();
+
+        let mut tampered = body.clone();
+        let last = tampered.authorizations.len() - 1;
+        tampered.authorizations[last].signature = tampered.authorizations[0].signature;
+        let result = pool.install(|| verify_authorizations(&tampered));
+        let txid = body.transactions.last().unwrap().txid();
+        let input = body.transactions.last().unwrap().inputs.len() - 1;
+        assert!(matches!(
+            result,
+            Err(authorization::Error::InvalidSignature { txid: t, input: i }) if t == txid && i == input
+        ));
+    }
+}
+
+#[test]
+fn identify_invalid_signature_across_threads() {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+    // Empty bodies have no chunks to verify.
+    pool.install(|| verify_authorizations(&body(0, 0))).unwrap();
+
+    let body = body(200, 2);
+    pool.install(|| verify_authorizations(&body)).unwrap();
+    let mut tampered = body.clone();
+    // First input of the 101st transaction, in the middle of a chunk.
+    tampered.authorizations[200].signature = tampered.authorizations[201].signature;
+    let txid = body.transactions[100].txid();
+    let result = pool.install(|| verify_authorizations(&tampered));
+    assert!(matches!(
+        result,
+        Err(authorization::Error::InvalidSignature { txid: t, input: 0 }) if t == txid
+    ));
+}
+
+#[test]
+fn reject_signature_without_domain_tag() {
+    let keypair = keypair(0);
+    let address = get_address(&keypair.public);
+    let transaction = Transaction {
+        inputs: vec![OutPoint::Regular {
+            txid: [0; 32].into(),
+            vout: 0,
+        }],
+        outputs: vec![Output {
+            address,
+            content: Content::Value(1),
+        }],
+    };
+    let authorized = authorize(&[(address, &keypair)], transaction.clone()).unwrap();
+    verify_authorized_transaction(&authorized).unwrap();
+
+    // The signed message commits to the domain tag and the chain id, so a signature over the
+    // bare transaction is not valid.
+    let signing_message = authorization::signing_message(&transaction).unwrap();
+    assert_ne!(signing_message, bincode::serialize(&transaction).unwrap());
+    let chain_id = bincode::serialize(&chain_id()).unwrap();
+    assert!(signing_message
+        .windows(chain_id.len())
+        .any(|window| window == chain_id));
+    let bare = AuthorizedTransaction {
+        authorizations: vec![Authorization {
+            public_key: keypair.public,
+            signature: keypair.sign(&bincode::serialize(&transaction).unwrap()),
+        }],
+        transaction,
+    };
+    assert!(verify_authorized_transaction(&bare).is_err());
+}
+
+#[test]
+fn sign_and_verify_message() {
+    let keypair = keypair(0);
+    let address = get_address(&keypair.public);
+    let message = b"I control this address";
+    let signed = sign_message(&keypair, message);
+    verify_message(&address, message, &signed.public_key, &signed.signature).unwrap();
+    assert!(verify_message(
+        &address,
+        b"something else",
+        &signed.public_key,
+        &signed.signature
+    )
+    .is_err());
+
+    // The public key has to hash to the address.
+    let other = keypair(1);
+    assert!(matches!(
+        verify_message(&address, message, &other.public, &signed.signature),
+        Err(authorization::Error::WrongKeypairForAddress { .. })
+    ));
+
+    // A message signature can't authorize a transaction that serializes to the same bytes.
+    let transaction = Transaction {
+        inputs: vec![],
+        outputs: vec![],
+    };
+    let signed = sign_message(
+        &keypair,
+        &authorization::signing_message(&transaction).unwrap(),
+    );
+    let authorized = AuthorizedTransaction {
+        authorizations: vec![Authorization {
+            public_key: signed.public_key,
+            signature: signed.signature,
+        }],
+        transaction,
+    };
+    assert!(verify_authorized_transaction(&authorized).is_err());
+}
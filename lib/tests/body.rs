@@ -0,0 +1,143 @@
+use {{crate_name}}::{
+    authorization::{authorize, get_address},
+    compression::{self, deserialize_body, serialize_body, MAX_BODY_SIZE},
+    types::{
+        AuthorizedTransaction, Body, Content, MerkleRoot, OutPoint, Output, Transaction, Txid,
+    },
+};
+
+fn keypair() -> ed25519_dalek::Keypair {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    ed25519_dalek::Keypair { secret, public }
+}
+
+fn transaction(inputs: Vec<OutPoint>, value: u64) -> AuthorizedTransaction {
+    let keypair = keypair();
+    let address = get_address(&keypair.public);
+    let transaction = Transaction {
+        inputs,
+        outputs: vec![Output {
+            address,
+            content: Content::Value(value),
+        }],
+    };
+    authorize(&[(address, &keypair)], transaction).unwrap()
+}
+
+fn txids(body: &Body) -> Vec<Txid> {
+    body.transactions.iter().map(Transaction::txid).collect()
+}
+
+#[test]
+fn same_transactions_give_same_merkle_root() {
+    let transactions: Vec<_> = (0..5)
+        .map(|vout| {
+            let input = OutPoint::Regular {
+                txid: [vout as u8; 32].into(),
+                vout,
+            };
+            transaction(vec![input], 1_000)
+        })
+        .collect();
+    let mut reversed = transactions.clone();
+    reversed.reverse();
+    let body = Body::new(transactions, vec![]);
+    let reversed_body = Body::new(reversed, vec![]);
+    assert_eq!(
+        body.compute_merkle_root(),
+        reversed_body.compute_merkle_root()
+    );
+    let txids = txids(&body);
+    assert!(txids.windows(2).all(|pair| pair[0] < pair[1]));
+    // Authorizations stay with their transactions.
+    for transaction in body.authorized_transactions() {
+        assert_eq!(transaction.authorizations.len(), 1);
+        assert!(transaction
+            .authorizations
+            .iter()
+            .all(|authorization| authorization.public_key == keypair().public));
+    }
+}
+
+#[test]
+fn spent_transactions_come_first() {
+    // Find a parent whose txid sorts after its child's, so that txid order alone would be wrong.
+    let (parent, child) = (0..)
+        .map(|vout| {
+            let coinbase_output = OutPoint::Coinbase {
+                merkle_root: MerkleRoot::from([0; 32]),
+                vout,
+            };
+            let parent = transaction(vec![coinbase_output], 1_000);
+            let input = OutPoint::Regular {
+                txid: parent.transaction.txid(),
+                vout: 0,
+            };
+            let child = transaction(vec![input], 900);
+            (parent, child)
+        })
+        .find(|(parent, child)| parent.transaction.txid() > child.transaction.txid())
+        .unwrap();
+    let expected = vec![parent.transaction.txid(), child.transaction.txid()];
+    let body = Body::new(vec![child.clone(), parent.clone()], vec![]);
+    assert_eq!(txids(&body), expected);
+    let body = Body::new(vec![parent.clone(), child.clone()], vec![]);
+    assert_eq!(txids(&body), expected);
+    // Bodies received from peers keep their order.
+    let body = Body::with_transaction_order(vec![child.clone(), parent], vec![]);
+    assert_eq!(body.transactions[0].txid(), child.transaction.txid());
+}
+
+#[test]
+fn body_round_trips_through_compression() {
+    let coinbase_output = OutPoint::Coinbase {
+        merkle_root: MerkleRoot::from([0; 32]),
+        vout: 0,
+    };
+    let body = Body::new(vec![transaction(vec![coinbase_output], 1_000)], vec![]);
+    for compression_level in [None, Some(compression::DEFAULT_COMPRESSION_LEVEL)] {
+        let bytes = serialize_body(&body, compression_level).unwrap();
+        let decoded = deserialize_body(&bytes).unwrap();
+        assert_eq!(txids(&decoded), txids(&body));
+        assert_eq!(decoded.compute_merkle_root(), body.compute_merkle_root());
+    }
+}
+
+#[test]
+fn oversized_compressed_body_is_rejected() {
+    // Zeroes compress to a few bytes, but would inflate past the limit.
+    let serialized = vec![0; MAX_BODY_SIZE + 1];
+    let compressed = zstd::encode_all(serialized.as_slice(), 3).unwrap();
+    let bytes = [vec![0xff], compressed].concat();
+    assert!(matches!(
+        deserialize_body(&bytes),
+        Err(compression::Error::TooLarge { .. })
+    ));
+}
+
+#[test]
+fn serialized_sizes_match_encoding() {
+    let transaction = transaction(
+        vec![OutPoint::Regular {
+            txid: [1; 32].into(),
+            vout: 0,
+        }],
+        100,
+    );
+    assert_eq!(
+        transaction.transaction.serialized_size(),
+        bincode::serialize(&transaction.transaction).unwrap().len() as u64
+    );
+    // Authorizations are counted for authorized transactions only.
+    assert_eq!(
+        transaction.serialized_size(),
+        bincode::serialize(&transaction).unwrap().len() as u64
+    );
+    assert!(transaction.serialized_size() > transaction.transaction.serialized_size());
+    let body = Body::new(vec![transaction], vec![]);
+    assert_eq!(
+        body.serialized_size(),
+        bincode::serialize(&body).unwrap().len() as u64
+    );
+}
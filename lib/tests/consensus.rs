@@ -0,0 +1,46 @@
+use {{crate_name}}::{
+    bip300301::bitcoin,
+    consensus::{self, ConsensusParams},
+    node::THIS_SIDECHAIN,
+    types::hash,
+};
+
+#[test]
+fn selected_network_determines_params_and_chain_id() {
+    let regtest_chain_id = hash(&(THIS_SIDECHAIN, bitcoin::Network::Regtest.to_string()));
+    consensus::select_network(bitcoin::Network::Signet).unwrap();
+    assert_eq!(consensus::network(), bitcoin::Network::Signet);
+    assert_ne!(consensus::chain_id(), regtest_chain_id);
+    assert_eq!(
+        ConsensusParams::default().withdrawal_bundle_failure_gap,
+        ConsensusParams::signet().withdrawal_bundle_failure_gap
+    );
+    // The chain id can't change once it is in use.
+    assert_eq!(
+        consensus::select_network(bitcoin::Network::Regtest),
+        Err(bitcoin::Network::Signet)
+    );
+    assert!(consensus::select_network(bitcoin::Network::Signet).is_ok());
+}
+
+#[test]
+fn presets_by_network() {
+    let presets = [
+        (bitcoin::Network::Bitcoin, ConsensusParams::mainnet()),
+        (bitcoin::Network::Testnet, ConsensusParams::testnet()),
+        (bitcoin::Network::Signet, ConsensusParams::signet()),
+        (bitcoin::Network::Regtest, ConsensusParams::regtest()),
+    ];
+    for (network, preset) in presets {
+        let params = ConsensusParams::for_network(network);
+        assert_eq!(
+            params.withdrawal_bundle_failure_gap,
+            preset.withdrawal_bundle_failure_gap
+        );
+        assert_eq!(params.max_bundle_outputs(), preset.max_bundle_outputs());
+    }
+    assert!(
+        ConsensusParams::mainnet().withdrawal_bundle_failure_gap
+            > ConsensusParams::regtest().withdrawal_bundle_failure_gap
+    );
+}
@@ -0,0 +1,36 @@
+use {{crate_name}}::{
+    bip300301::bitcoin,
+    testutil::main_address,
+    types::{Content, GetValue as _},
+};
+
+fn withdrawal() -> Content {
+    Content::Withdrawal {
+        value: 1000,
+        main_fee: 10,
+        main_address: main_address(),
+    }
+}
+
+#[test]
+fn value_is_spendable() {
+    let content = Content::Value(1000);
+    assert_eq!(content.spendable_value(), Some(1000));
+    assert!(!content.is_locked());
+    assert!(content.withdrawal().is_none());
+    assert_eq!(content.get_value(), 1000);
+}
+
+#[test]
+fn withdrawal_is_locked() {
+    let content = withdrawal();
+    assert_eq!(content.spendable_value(), None);
+    assert!(content.is_locked());
+    let (main_address, main_fee) = content.withdrawal().unwrap();
+    assert_eq!(main_fee, 10);
+    assert_eq!(
+        main_address.clone().assume_checked().network,
+        bitcoin::Network::Regtest
+    );
+    assert_eq!(content.get_value(), 1000);
+}
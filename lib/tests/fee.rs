@@ -0,0 +1,68 @@
+use {{crate_name}}::{
+    bip300301::bitcoin::Amount,
+    types::{Address, Content, FeeDisplay, FilledTransaction, OutPoint, Output, Transaction},
+};
+
+fn filled_transaction(value_in: u64, value_out: u64) -> FilledTransaction {
+    let output = |value| Output {
+        address: Address([1; 20]),
+        content: Content::Value(value),
+    };
+    FilledTransaction {
+        transaction: Transaction {
+            inputs: vec![OutPoint::Regular {
+                txid: [1; 32].into(),
+                vout: 0,
+            }],
+            outputs: vec![output(value_out)],
+        },
+        spent_utxos: vec![output(value_in)],
+    }
+}
+
+#[test]
+fn normal_fee() {
+    let transaction = filled_transaction(10_000, 7_500);
+    assert_eq!(transaction.fee_rate(100), Some(25));
+    // Rounded down.
+    assert_eq!(transaction.fee_rate(1_000), Some(2));
+    let fee = transaction.fee_display(100);
+    assert_eq!(
+        fee,
+        FeeDisplay::Fee {
+            fee: 2_500,
+            fee_rate: 25
+        }
+    );
+    assert!(fee.is_sufficient());
+    assert_eq!(
+        fee.to_string(),
+        format!("{} (25 sat/byte)", Amount::from_sat(2_500))
+    );
+}
+
+#[test]
+fn zero_fee() {
+    let transaction = filled_transaction(10_000, 10_000);
+    assert_eq!(transaction.fee_rate(100), Some(0));
+    assert_eq!(transaction.fee_rate(0), None);
+    let fee = transaction.fee_display(0);
+    assert_eq!(
+        fee,
+        FeeDisplay::Fee {
+            fee: 0,
+            fee_rate: 0
+        }
+    );
+    assert!(fee.is_sufficient());
+}
+
+#[test]
+fn negative_fee() {
+    let transaction = filled_transaction(7_500, 10_000);
+    assert_eq!(transaction.fee_rate(100), None);
+    let fee = transaction.fee_display(100);
+    assert_eq!(fee, FeeDisplay::NotEnoughValueIn { missing: 2_500 });
+    assert!(!fee.is_sufficient());
+    assert_eq!(fee.to_string(), "not enough value in, 0.000025 BTC missing");
+}
@@ -0,0 +1,32 @@
+use {{crate_name}}::{
+    health::{HEALTH_PATH, READY_PATH},
+    testutil::{self, MockMainchain},
+};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn health_checks_on_running_node() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &MockMainchain::default());
+    node.set_health_addr(Some("127.0.0.1:0".parse().unwrap()));
+    assert!(!node.is_alive());
+    node.run().unwrap();
+    assert!(node.is_alive());
+    let addr = node.health_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+
+    assert!(get(addr, HEALTH_PATH).await.starts_with("HTTP/1.1 200"));
+    // Without peers there is nothing to sync with.
+    assert!(get(addr, READY_PATH).await.starts_with("HTTP/1.1 200"));
+    assert!(get(addr, "/unknown").await.starts_with("HTTP/1.1 404"));
+}
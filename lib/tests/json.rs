@@ -0,0 +1,119 @@
+use {{crate_name}}::{
+    bip300301::bitcoin,
+    testutil::main_address,
+    types::{
+        json::{BlockJson, TransactionJson},
+        Address, AuthorizedTransaction, BlockInfo, Body, Content, Header, OutPoint, Output,
+        Transaction,
+    },
+};
+use bitcoin::hashes::Hash as _;
+
+#[test]
+fn block_json_snapshot() {
+    let transaction = Transaction {
+        inputs: vec![OutPoint::Deposit(bitcoin::OutPoint {
+            txid: bitcoin::Txid::all_zeros(),
+            vout: 1,
+        })],
+        outputs: vec![Output {
+            address: Address([1; 20]),
+            content: Content::Withdrawal {
+                value: 900,
+                main_fee: 50,
+                main_address: main_address(),
+            },
+        }],
+    };
+    let txid = transaction.txid();
+    let coinbase = vec![Output {
+        address: Address([0; 20]),
+        content: Content::Value(50),
+    }];
+    let body = Body::new(
+        vec![AuthorizedTransaction {
+            transaction,
+            authorizations: vec![],
+        }],
+        coinbase,
+    );
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: [0; 32].into(),
+        prev_main_hash: bitcoin::BlockHash::all_zeros(),
+        height: 1,
+        commitment: None,
+    };
+    let block = BlockInfo::new(header.clone(), body);
+    let json = serde_json::to_value(BlockJson::from(&block)).unwrap();
+    let zeros = "0".repeat(64);
+    let expected = serde_json::json!({
+        "hash": header.hash().to_string(),
+        "header": {
+            "merkle_root": header.merkle_root.to_string(),
+            "prev_side_hash": zeros,
+            "prev_main_hash": zeros,
+            "height": 1,
+            "commitment": null,
+        },
+        "coinbase": [{
+            "address": "111111111111111111117K4nzc",
+            "type": "value",
+            "value": "50",
+        }],
+        "transactions": [{
+            "txid": txid.to_string(),
+            "inputs": [{
+                "type": "deposit",
+                "txid": zeros,
+                "vout": 1,
+            }],
+            "outputs": [{
+                "address": "6Jswqk47s9PUcyCc88MMVwzgvHR4tFVH",
+                "type": "withdrawal",
+                "value": "900",
+                "main_fee": "50",
+                "main_address": "mfWxJ45yp2SFn7UciZyNpvDKrzbhyfKrY8",
+            }],
+            "authorizations": [],
+        }],
+    });
+    assert_eq!(json, expected);
+}
+
+#[test]
+fn transaction_json_round_trip() {
+    // Not representable as a JavaScript number.
+    const VALUE: u64 = (1 << 53) + 1;
+    let transaction = Transaction {
+        inputs: vec![
+            OutPoint::Regular {
+                txid: [2; 32].into(),
+                vout: 0,
+            },
+            OutPoint::Coinbase {
+                merkle_root: [3; 32].into(),
+                vout: 1,
+            },
+        ],
+        outputs: vec![
+            Output {
+                address: Address([1; 20]),
+                content: Content::Value(VALUE),
+            },
+            Output {
+                address: Address([1; 20]),
+                content: Content::Withdrawal {
+                    value: VALUE,
+                    main_fee: VALUE,
+                    main_address: main_address(),
+                },
+            },
+        ],
+    };
+    let json = serde_json::to_string(&TransactionJson::new(&transaction, vec![])).unwrap();
+    assert!(json.contains(&format!("\"{VALUE}\"")));
+    let transaction_json: TransactionJson = serde_json::from_str(&json).unwrap();
+    let round_tripped = Transaction::try_from(&transaction_json).unwrap();
+    assert_eq!(round_tripped.txid(), transaction.txid());
+}
@@ -0,0 +1,55 @@
+use {{crate_name}}::{
+    bip300301::{bitcoin, TwoWayPegData, WithdrawalBundleStatus},
+    mainchain::{self, Check, Mainchain as _, RpcMethod},
+    testutil::MockMainchain,
+};
+use bitcoin::hashes::Hash as _;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn two_way_peg_data_queries_overlap() {
+    const QUERY_DELAY: Duration = Duration::from_millis(500);
+    let mainchain = MockMainchain::default();
+    mainchain.set_query_delay(QUERY_DELAY);
+    let bundle_txid = bitcoin::Txid::all_zeros();
+    mainchain.push_two_way_peg_data(TwoWayPegData {
+        bundle_statuses: HashMap::from([(bundle_txid, WithdrawalBundleStatus::Confirmed)]),
+        ..Default::default()
+    });
+    let start = Instant::now();
+    let two_way_peg_data = mainchain
+        .get_two_way_peg_data(bitcoin::BlockHash::all_zeros(), None)
+        .await
+        .unwrap();
+    // Sequential queries would take at least twice the delay.
+    assert!(start.elapsed() < QUERY_DELAY * 2);
+    assert!(two_way_peg_data.deposits.is_empty());
+    assert!(two_way_peg_data.bundle_statuses.contains_key(&bundle_txid));
+}
+
+#[tokio::test]
+async fn doctor_reports_missing_method() {
+    let mainchain = MockMainchain::default();
+    let checks = mainchain::doctor(&mainchain).await;
+    let methods: Vec<RpcMethod> = checks.iter().map(|(method, _)| *method).collect();
+    assert_eq!(methods, RpcMethod::ALL);
+    assert!(checks.iter().all(|(_, check)| check.is_supported()));
+
+    mainchain.remove_rpc_method(RpcMethod::VerifyBmm);
+    for (method, check) in mainchain::doctor(&mainchain).await {
+        if method == RpcMethod::VerifyBmm {
+            assert!(matches!(check, Check::Missing));
+        } else {
+            assert!(check.is_supported(), "{method}: {check:?}");
+        }
+    }
+
+    mainchain.remove_rpc_method(RpcMethod::GetBestBlockHash);
+    let checks = mainchain::doctor(&mainchain).await;
+    assert_eq!(checks.len(), 1);
+    assert!(matches!(
+        checks[0],
+        (RpcMethod::GetBestBlockHash, Check::Missing)
+    ));
+}
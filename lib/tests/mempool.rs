@@ -0,0 +1,292 @@
+use {{crate_name}}::{
+    consensus::ConsensusParams,
+    mempool::{self, MemPool, MempoolInfo, RawMempool},
+    state::State,
+    types::{Address, AuthorizedTransaction, Body, Content, OutPoint, Output, Transaction},
+};
+
+fn transaction(inputs: Vec<OutPoint>) -> AuthorizedTransaction {
+    AuthorizedTransaction {
+        transaction: Transaction {
+            inputs,
+            outputs: vec![Output {
+                address: Address([0; 20]),
+                content: Content::Value(0),
+            }],
+        },
+        authorizations: vec![],
+    }
+}
+
+#[test]
+fn reconcile_clears_stale_markers() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(State::NUM_DBS + MemPool::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let state = State::new(&env, ConsensusParams::regtest()).unwrap();
+    let mempool = MemPool::new(&env, 0).unwrap();
+    let mut txn = env.write_txn().unwrap();
+    let funding = transaction(vec![]);
+    let utxo = OutPoint::Regular {
+        txid: funding.transaction.txid(),
+        vout: 0,
+    };
+    state
+        .connect_body(&mut txn, &Body::new(vec![funding], vec![]), 1)
+        .unwrap();
+
+    let valid = transaction(vec![utxo]);
+    mempool.put(&mut txn, &valid, 0, 1).unwrap();
+    // A marker without a transaction, and a transaction spending a missing output without a
+    // marker, as a crash between writes could leave them.
+    let stale = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout: 0,
+    };
+    mempool.spent_utxos.put(&mut txn, &stale, &()).unwrap();
+    let missing = OutPoint::Regular {
+        txid: [2; 32].into(),
+        vout: 0,
+    };
+    let orphan = transaction(vec![missing]);
+    let orphan_txid = orphan.transaction.txid();
+    mempool
+        .transactions
+        .put(&mut txn, &orphan_txid.into(), &orphan)
+        .unwrap();
+
+    let removed = mempool.reconcile(&mut txn, &state).unwrap();
+    assert_eq!(removed, vec![orphan_txid]);
+    let markers: Vec<_> = mempool
+        .spent_utxos
+        .iter(&txn)
+        .unwrap()
+        .map(|item| item.unwrap().0)
+        .collect();
+    assert_eq!(markers, vec![utxo]);
+    assert_eq!(mempool.len(&txn).unwrap(), 1);
+}
+
+#[test]
+fn raw_mempool_modes_agree() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(MemPool::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let mempool = MemPool::new(&env, 0).unwrap();
+    let mut txn = env.write_txn().unwrap();
+    let transactions: Vec<_> = (0..3u8)
+        .map(|i| {
+            transaction(vec![OutPoint::Regular {
+                txid: [i; 32].into(),
+                vout: 0,
+            }])
+        })
+        .collect();
+    for (i, transaction) in transactions.iter().enumerate() {
+        let fee = 1_000 * (i as u64 + 1);
+        mempool
+            .put(&mut txn, transaction, fee, 10 + i as u32)
+            .unwrap();
+    }
+
+    let txids = match mempool.get_raw(&txn, false).unwrap() {
+        RawMempool::Txids(txids) => txids,
+        RawMempool::Verbose(_) => panic!("expected txids"),
+    };
+    let entries = match mempool.get_raw(&txn, true).unwrap() {
+        RawMempool::Verbose(entries) => entries,
+        RawMempool::Txids(_) => panic!("expected entries"),
+    };
+    assert_eq!(txids.len(), transactions.len());
+    assert!(txids.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(
+        entries.iter().map(|entry| entry.txid).collect::<Vec<_>>(),
+        txids
+    );
+    for (i, transaction) in transactions.iter().enumerate() {
+        let txid = transaction.transaction.txid();
+        let entry = entries.iter().find(|entry| entry.txid == txid).unwrap();
+        let fee = 1_000 * (i as u64 + 1);
+        assert_eq!(entry.size, transaction.serialized_size());
+        assert_eq!(entry.fee, fee);
+        assert_eq!(entry.fee_rate, fee / entry.size);
+        assert_eq!(entry.inputs, transaction.transaction.inputs);
+        assert_eq!(entry.height, Some(10 + i as u32));
+    }
+}
+
+fn open(dir: &tempfile::TempDir) -> (heed::Env, MemPool) {
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(State::NUM_DBS + MemPool::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let mempool = MemPool::new(&env, 0).unwrap();
+    (env, mempool)
+}
+
+fn outpoint(i: u8) -> OutPoint {
+    OutPoint::Regular {
+        txid: [i; 32].into(),
+        vout: 0,
+    }
+}
+
+#[test]
+fn reject_double_spends() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, mempool) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    assert!(matches!(
+        mempool.put(&mut txn, &transaction(vec![outpoint(1), outpoint(1)]), 0, 0),
+        Err(mempool::Error::DuplicateInput { outpoint: duplicate }) if duplicate == outpoint(1)
+    ));
+    // The rejected transaction left no markers behind.
+    mempool
+        .put(&mut txn, &transaction(vec![outpoint(1)]), 0, 0)
+        .unwrap();
+    assert!(matches!(
+        mempool.put(&mut txn, &transaction(vec![outpoint(2), outpoint(1)]), 0, 0),
+        Err(mempool::Error::UtxoDoubleSpent)
+    ));
+    assert_eq!(mempool.len(&txn).unwrap(), 1);
+}
+
+#[test]
+fn remove_transactions_conflicting_with_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, mempool) = open(&dir);
+    let state = State::new(&env, ConsensusParams::regtest()).unwrap();
+    let mut txn = env.write_txn().unwrap();
+    let funding = AuthorizedTransaction {
+        transaction: Transaction {
+            inputs: vec![],
+            outputs: vec![
+                Output {
+                    address: Address([0; 20]),
+                    content: Content::Value(1),
+                };
+                2
+            ],
+        },
+        authorizations: vec![],
+    };
+    let txid = funding.transaction.txid();
+    state
+        .connect_body(&mut txn, &Body::new(vec![funding], vec![]), 1)
+        .unwrap();
+    let (first, second) = (
+        OutPoint::Regular { txid, vout: 0 },
+        OutPoint::Regular { txid, vout: 1 },
+    );
+    let kept = transaction(vec![first]);
+    let conflicting = transaction(vec![second]);
+    mempool.put(&mut txn, &kept, 0, 1).unwrap();
+    mempool.put(&mut txn, &conflicting, 0, 1).unwrap();
+    assert!(mempool
+        .remove_conflicts(&mut txn, &state)
+        .unwrap()
+        .is_empty());
+
+    // A block spends the second output with a different transaction.
+    let mut confirmed = transaction(vec![second]);
+    confirmed.transaction.outputs[0].content = Content::Value(1);
+    state
+        .connect_body(&mut txn, &Body::new(vec![confirmed], vec![]), 2)
+        .unwrap();
+    assert_eq!(
+        mempool.remove_conflicts(&mut txn, &state).unwrap(),
+        vec![conflicting.transaction.txid()]
+    );
+    assert_eq!(mempool.len(&txn).unwrap(), 1);
+    assert!(mempool
+        .get(&txn, &kept.transaction.txid())
+        .unwrap()
+        .is_some());
+    assert!(mempool.spent_utxos.get(&txn, &second).unwrap().is_none());
+}
+
+#[test]
+fn info_summarizes_fees() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, mempool) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    assert_eq!(mempool.get_info(&txn).unwrap(), MempoolInfo::default());
+    let transactions: Vec<_> = (0..3).map(|i| transaction(vec![outpoint(i)])).collect();
+    // All transactions have the same size.
+    let size = transactions[0].serialized_size();
+    for (i, transaction) in transactions.iter().enumerate() {
+        mempool
+            .put(&mut txn, transaction, size * (i as u64 + 1), 0)
+            .unwrap();
+    }
+    assert_eq!(
+        mempool.get_info(&txn).unwrap(),
+        MempoolInfo {
+            count: 3,
+            total_fees: size * 6,
+            total_size: size * 3,
+            min_fee_rate: Some(1),
+            max_fee_rate: Some(3),
+            median_fee_rate: Some(2),
+        }
+    );
+}
+
+#[test]
+fn expire_old_transactions() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, mempool) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let old = transaction(vec![outpoint(1)]);
+    let recent = transaction(vec![outpoint(2)]);
+    mempool.put(&mut txn, &old, 0, 10).unwrap();
+    mempool.put(&mut txn, &recent, 0, 15).unwrap();
+    assert!(mempool.expire(&mut txn, 20, 10).unwrap().is_empty());
+    assert_eq!(
+        mempool.expire(&mut txn, 21, 10).unwrap(),
+        vec![old.transaction.txid()]
+    );
+    assert_eq!(mempool.len(&txn).unwrap(), 1);
+    // The expired transaction's input can be spent again.
+    assert!(mempool
+        .spent_utxos
+        .get(&txn, &outpoint(1))
+        .unwrap()
+        .is_none());
+    mempool
+        .put(&mut txn, &transaction(vec![outpoint(1)]), 0, 21)
+        .unwrap();
+}
+
+#[test]
+fn enforce_min_relay_fee() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(MemPool::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let mempool = MemPool::new(&env, 2).unwrap();
+    // A fee rate so high that the minimum fee doesn't fit in a `u64` rejects every transaction.
+    let overflowing = MemPool::new(&env, u64::MAX).unwrap();
+    let mut txn = env.write_txn().unwrap();
+    let cheap = transaction(vec![outpoint(1)]);
+    let min_fee = 2 * cheap.serialized_size();
+    assert!(matches!(
+        mempool.put(&mut txn, &cheap, min_fee - 1, 0),
+        Err(mempool::Error::FeeTooLow { fee, min_fee: min }) if fee == min_fee - 1 && min == min_fee
+    ));
+    mempool.put(&mut txn, &cheap, min_fee, 0).unwrap();
+    let expensive = transaction(vec![outpoint(2)]);
+    assert!(matches!(
+        overflowing.put(&mut txn, &expensive, u64::MAX, 0),
+        Err(mempool::Error::MinFeeOverflow { size }) if size == expensive.serialized_size()
+    ));
+}
@@ -0,0 +1,91 @@
+use {{crate_name}}::{
+    mainchain::Mainchain as _,
+    testutil::{self, MockMainchain},
+    types::{Address, Body, Content, Header, Output},
+};
+use metrics::{Counter, Gauge, GaugeFn, Histogram, Key, KeyName, Metadata, SharedString, Unit};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Records the last value of every gauge.
+#[derive(Default)]
+struct GaugeRecorder {
+    gauges: Mutex<HashMap<String, Arc<GaugeValue>>>,
+}
+
+#[derive(Default)]
+struct GaugeValue(Mutex<f64>);
+
+impl GaugeFn for GaugeValue {
+    fn increment(&self, value: f64) {
+        *self.0.lock().unwrap() += value;
+    }
+
+    fn decrement(&self, value: f64) {
+        *self.0.lock().unwrap() -= value;
+    }
+
+    fn set(&self, value: f64) {
+        *self.0.lock().unwrap() = value;
+    }
+}
+
+impl GaugeRecorder {
+    fn get(&self, name: &str) -> Option<f64> {
+        let gauges = self.gauges.lock().unwrap();
+        gauges.get(name).map(|value| *value.0.lock().unwrap())
+    }
+}
+
+impl metrics::Recorder for GaugeRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, _: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::noop()
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        let mut gauges = self.gauges.lock().unwrap();
+        let value = gauges.entry(key.name().to_owned()).or_default();
+        Gauge::from_arc(value.clone())
+    }
+
+    fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::noop()
+    }
+}
+
+#[tokio::test]
+async fn connecting_block_updates_gauges() {
+    let recorder: &'static GaugeRecorder = Box::leak(Box::default());
+    metrics::set_recorder(recorder).unwrap();
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    mainchain.generate(1).await.unwrap();
+    let body = Body::new(
+        vec![],
+        vec![Output {
+            address: Address([1; 20]),
+            content: Content::Value(0),
+        }],
+    );
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: 1,
+        commitment: None,
+    };
+    node.submit_block(&header, &body).await.unwrap();
+    assert_eq!(recorder.get("sidechain_utxo_set_size"), Some(1.0));
+    assert_eq!(
+        recorder.get("sidechain_pending_withdrawal_bundle"),
+        Some(0.0)
+    );
+    assert!(recorder.get("sidechain_last_block_time_seconds").unwrap() > 0.0);
+}
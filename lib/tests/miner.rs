@@ -0,0 +1,123 @@
+use {{crate_name}}::{
+    mainchain::{self, Mainchain as _},
+    miner::Miner,
+    node::THIS_SIDECHAIN,
+    testutil::MockMainchain,
+    types::{Body, Header},
+};
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+async fn attempt_bmm(miner: &mut Miner<MockMainchain>) -> Header {
+    let body = Body::new(vec![], vec![]);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: [0; 32].into(),
+        prev_main_hash: miner.mainchain.get_mainchain_tip().await.unwrap(),
+        height: 1,
+        commitment: None,
+    };
+    miner.attempt_bmm(0, 0, header.clone(), body).await.unwrap();
+    header
+}
+
+#[tokio::test]
+async fn wait_for_bmm_delayed_confirmation() {
+    let mainchain = MockMainchain::default();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+    let header = attempt_bmm(&mut miner).await;
+    // Nothing confirms the request until a mainchain block is mined a while later.
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        mainchain.generate(1).await.unwrap();
+    });
+    let (confirmed, _) = miner
+        .wait_for_bmm(POLL_INTERVAL, Duration::from_secs(10))
+        .await
+        .unwrap()
+        .expect("BMM request should land once a mainchain block is mined");
+    assert_eq!(confirmed.hash(), header.hash());
+}
+
+#[tokio::test]
+async fn wait_for_bmm_times_out() {
+    let mainchain = MockMainchain::default();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain);
+    attempt_bmm(&mut miner).await;
+    let block = miner
+        .wait_for_bmm(POLL_INTERVAL, Duration::from_millis(300))
+        .await
+        .unwrap();
+    assert!(block.is_none());
+}
+
+#[tokio::test]
+async fn abandon_block() {
+    let mainchain = MockMainchain::default();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+    attempt_bmm(&mut miner).await;
+    assert!(miner.has_pending_block());
+    miner.abandon_block();
+    assert!(!miner.has_pending_block());
+    mainchain.generate(1).await.unwrap();
+    assert!(miner.confirm_bmm().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn abandon_block_on_stale_mainchain_tip() {
+    let mainchain = MockMainchain::default();
+    mainchain.generate(1).await.unwrap();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+    attempt_bmm(&mut miner).await;
+    // The block the request was built on is replaced.
+    mainchain.reorg(1);
+    assert!(miner.confirm_bmm().await.unwrap().is_none());
+    assert!(!miner.has_pending_block());
+}
+
+#[tokio::test]
+async fn confirm_bmm_before_next_mainchain_block() {
+    let mainchain = MockMainchain::default();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+    let header = attempt_bmm(&mut miner).await;
+    // Nothing has been mined on top of the request's mainchain block yet.
+    assert!(matches!(
+        mainchain
+            .verify_bmm(&header.prev_main_hash, header.hash())
+            .await,
+        Err(mainchain::Error::NoNextBlock { .. })
+    ));
+    assert!(miner.confirm_bmm().await.unwrap().is_none());
+    assert!(miner.has_pending_block());
+    mainchain.generate(1).await.unwrap();
+    let (confirmed, _) = miner.confirm_bmm().await.unwrap().unwrap();
+    assert_eq!(confirmed.hash(), header.hash());
+}
+
+#[tokio::test]
+async fn bmm_commits_to_height_and_commitment() {
+    let mainchain = MockMainchain::default();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+    let header = attempt_bmm(&mut miner).await;
+    mainchain.generate(1).await.unwrap();
+    mainchain
+        .verify_bmm(&header.prev_main_hash, header.hash())
+        .await
+        .unwrap();
+    // The same block at another height, or with a commitment, wasn't BMMed.
+    let moved = Header {
+        height: 2,
+        ..header.clone()
+    };
+    let committed = Header {
+        commitment: Some([1; 32]),
+        ..header.clone()
+    };
+    for other in [moved, committed] {
+        assert!(mainchain
+            .verify_bmm(&header.prev_main_hash, other.hash())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,879 @@
+use {{crate_name}}::{
+    archive,
+    authorization::{authorize, get_address},
+    bip300301::{self, bitcoin},
+    compression,
+    mainchain::Mainchain as _,
+    mempool,
+    node::{self, ChainEvent, Node, NodeConfig},
+    state,
+    testutil::{self, MockMainchain},
+    types::{
+        verify_tx_inclusion, Address, AuthorizedTransaction, Body, Content, GetValue as _, Header,
+        OutPoint, Output, Transaction,
+    },
+    versioned,
+    wallet::{AccountPath, AddressPolicy, Wallet},
+};
+use bitcoin::hashes::Hash as _;
+use std::collections::{HashMap, HashSet};
+
+#[tokio::test]
+async fn reject_block_on_stale_mainchain_block() {
+    let mainchain = MockMainchain::default();
+    mainchain.generate(1).await.unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let body = Body::new(vec![], vec![]);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: node.get_height().unwrap() + 1,
+        commitment: None,
+    };
+    mainchain.reorg(1);
+    let result = node.submit_block(&header, &body).await;
+    assert!(matches!(
+        result,
+        Err(node::Error::StaleMainchainBlock { prev_main_hash }) if prev_main_hash == header.prev_main_hash
+    ));
+    assert_eq!(node.get_height().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn verify_tx_inclusion_proof() {
+    let mainchain = MockMainchain::default();
+    mainchain.generate(1).await.unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    // Transactions without inputs or value, distinguished by their output address.
+    let transactions: Vec<_> = (0..5)
+        .map(|i| AuthorizedTransaction {
+            transaction: Transaction {
+                inputs: vec![],
+                outputs: vec![Output {
+                    address: Address([i; 20]),
+                    content: Content::Value(0),
+                }],
+            },
+            authorizations: vec![],
+        })
+        .collect();
+    let txids: Vec<_> = transactions.iter().map(|t| t.transaction.txid()).collect();
+    let body = Body::new(transactions, vec![]);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: node.get_height().unwrap() + 1,
+        commitment: None,
+    };
+    node.submit_block(&header, &body).await.unwrap();
+
+    for txid in &txids {
+        let (proof_header, path) = node.get_tx_inclusion_proof(txid).unwrap().unwrap();
+        assert_eq!(proof_header.hash(), header.hash());
+        assert!(verify_tx_inclusion(&header, txid, &path));
+        // A valid path doesn't prove inclusion of a different transaction.
+        let other = txids.iter().find(|other| *other != txid).unwrap();
+        assert!(!verify_tx_inclusion(&header, other, &path));
+    }
+
+    let (_, path) = node.get_tx_inclusion_proof(&txids[2]).unwrap().unwrap();
+    let mut tampered = path.clone();
+    tampered.siblings[0][0] ^= 1;
+    assert!(!verify_tx_inclusion(&header, &txids[2], &tampered));
+    let mut tampered = path.clone();
+    tampered.index += 1;
+    assert!(!verify_tx_inclusion(&header, &txids[2], &tampered));
+    // Seven leaves take the same path as the six in the block, the root commits to the count.
+    let mut tampered = path.clone();
+    tampered.leaf_count += 1;
+    assert!(!verify_tx_inclusion(&header, &txids[2], &tampered));
+    let mut tampered = path;
+    tampered.siblings.pop();
+    assert!(!verify_tx_inclusion(&header, &txids[2], &tampered));
+}
+
+async fn submit_empty_block(node: &Node<MockMainchain>, mainchain: &MockMainchain) {
+    mainchain.generate(1).await.unwrap();
+    let body = Body::new(vec![], vec![]);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: node.get_height().unwrap() + 1,
+        commitment: None,
+    };
+    node.submit_block(&header, &body).await.unwrap();
+}
+
+#[tokio::test]
+async fn refuse_reorg_past_finality_depth() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.set_finality_depth(Some(2));
+    for _ in 0..5 {
+        submit_empty_block(&node, &mainchain).await;
+    }
+    assert_eq!(node.get_finalized_height().unwrap(), 3);
+
+    let result = node.rollback_to_height(2).await;
+    assert!(matches!(
+        result,
+        Err(node::Error::ReorgTooDeep {
+            target: 2,
+            finalized_height: 3
+        })
+    ));
+    assert_eq!(node.get_height().unwrap(), 5);
+
+    node.rollback_to_height(3).await.unwrap();
+    assert_eq!(node.get_height().unwrap(), 3);
+}
+
+#[tokio::test]
+async fn import_exported_chain() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let node_a = testutil::node(dir_a.path(), &mainchain);
+    for _ in 0..4 {
+        submit_empty_block(&node_a, &mainchain).await;
+    }
+    let fixture = dir_a.path().join("blocks.bin");
+    node_a.export_blocks(&fixture, 1..=4).unwrap();
+
+    let node_b = testutil::node(dir_b.path(), &mainchain);
+    assert_eq!(node_b.import_blocks(&fixture).await.unwrap(), 4);
+    assert_eq!(node_b.get_height().unwrap(), 4);
+    assert_eq!(
+        node_b.get_best_hash().unwrap(),
+        node_a.get_best_hash().unwrap()
+    );
+    // Importing again skips the known blocks.
+    assert_eq!(node_b.import_blocks(&fixture).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn reject_transaction_with_wrong_authorization_count() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+    let input = |vout| OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout,
+    };
+    let transaction = Transaction {
+        inputs: vec![input(0), input(1)],
+        outputs: vec![],
+    };
+    let address = get_address(&public);
+    let authorized = authorize(&[(address, &keypair), (address, &keypair)], transaction).unwrap();
+
+    let mut too_few = authorized.clone();
+    too_few.authorizations.pop();
+    let mut too_many = authorized;
+    too_many.transaction.inputs.pop();
+    for (transaction, expected) in [(too_few, (2, 1)), (too_many, (1, 2))] {
+        let result = node.submit_transaction(&transaction).await;
+        assert!(matches!(
+            result,
+            Err(node::Error::State(state::Error::WrongAuthorizationCount {
+                inputs,
+                authorizations,
+                ..
+            })) if (inputs, authorizations) == expected
+        ));
+    }
+}
+
+#[tokio::test]
+async fn reset_empties_node_databases() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+    let address = get_address(&public);
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: HashMap::from([(
+            bitcoin::OutPoint {
+                txid: bitcoin::Txid::from_byte_array([1; 32]),
+                vout: 0,
+            },
+            bip300301::Output {
+                address: format!("{address}"),
+                value: 100_000,
+            },
+        )]),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+    submit_empty_block(&node, &mainchain).await;
+    let addresses: HashSet<_> = [address].into();
+    let utxos = node.get_utxos_by_addresses(&addresses).unwrap();
+    assert_eq!(utxos.len(), 1);
+    let transaction = Transaction {
+        inputs: utxos.into_keys().collect(),
+        outputs: vec![Output {
+            address,
+            content: Content::Value(50_000),
+        }],
+    };
+    let transaction = authorize(&[(address, &keypair)], transaction).unwrap();
+    node.submit_transaction(&transaction).await.unwrap();
+    submit_empty_block(&node, &mainchain).await;
+    let tip = node.get_best_hash().unwrap();
+    assert_eq!(node.get_all_transactions().unwrap().len(), 1);
+
+    node.reset_chain().await.unwrap();
+    assert_eq!(node.get_height().unwrap(), 0);
+    assert!(node.get_header(1).unwrap().is_none());
+    assert!(node.get_body(1).unwrap().is_none());
+    assert!(node.get_header_by_hash(&tip).unwrap().is_none());
+    assert!(node.get_utxos_by_addresses(&addresses).unwrap().is_empty());
+    assert!(node.get_all_transactions().unwrap().is_empty());
+    assert!(node.get_pending_withdrawal_bundle().unwrap().is_none());
+    assert!(node.get_unclaimed_deposits().unwrap().is_empty());
+
+    // The node syncs from scratch afterwards.
+    submit_empty_block(&node, &mainchain).await;
+    assert_eq!(node.get_height().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn available_fees_leave_mempool_untouched() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+    let address = get_address(&public);
+    let deposits = (0..2).map(|vout| {
+        let outpoint = bitcoin::OutPoint {
+            txid: bitcoin::Txid::from_byte_array([1; 32]),
+            vout,
+        };
+        let output = bip300301::Output {
+            address: format!("{address}"),
+            value: 100_000,
+        };
+        (outpoint, output)
+    });
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: deposits.collect(),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+    submit_empty_block(&node, &mainchain).await;
+    assert_eq!(node.get_available_fees(10).unwrap(), 0);
+
+    let addresses: HashSet<_> = [address].into();
+    let utxos = node.get_utxos_by_addresses(&addresses).unwrap();
+    assert_eq!(utxos.len(), 2);
+    for (outpoint, fee) in utxos.into_keys().zip([1_000, 3_000]) {
+        let transaction = Transaction {
+            inputs: vec![outpoint],
+            outputs: vec![Output {
+                address,
+                content: Content::Value(100_000 - fee),
+            }],
+        };
+        let transaction = authorize(&[(address, &keypair)], transaction).unwrap();
+        node.submit_transaction(&transaction).await.unwrap();
+    }
+
+    assert_eq!(node.get_available_fees(10).unwrap(), 4_000);
+    let (_, first_fee) = node.get_transactions(1).unwrap();
+    assert_eq!(node.get_available_fees(1).unwrap(), first_fee);
+    assert_eq!(node.get_all_transactions().unwrap().len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot_is_consistent() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    submit_empty_block(&node, &mainchain).await;
+
+    let snapshot = node.snapshot().unwrap();
+    // Connect a block on another thread while the snapshot is held, a thread can only have one
+    // transaction open.
+    tokio::spawn({
+        let (node, mainchain) = (node.clone(), mainchain.clone());
+        async move { submit_empty_block(&node, &mainchain).await }
+    })
+    .await
+    .unwrap();
+    assert_eq!(snapshot.get_height().unwrap(), 1);
+    let header = snapshot.get_header(1).unwrap().unwrap();
+    assert_eq!(snapshot.get_best_hash().unwrap(), header.hash());
+    assert!(snapshot.get_block(1).unwrap().is_some());
+    assert!(snapshot.get_header(2).unwrap().is_none());
+    assert!(snapshot.get_body(2).unwrap().is_none());
+    drop(snapshot);
+
+    assert_eq!(node.get_height().unwrap(), 2);
+    assert!(node.get_header(2).unwrap().is_some());
+}
+
+#[tokio::test]
+async fn reject_wrong_utxo_set_commitment() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    mainchain.generate(1).await.unwrap();
+    let coinbase = vec![Output {
+        address: Address([1; 20]),
+        content: Content::Value(0),
+    }];
+    let body = Body::new(vec![], coinbase);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: 1,
+        commitment: Some(node.get_utxo_set_hash().unwrap()),
+    };
+    node.submit_block(&header, &body).await.unwrap();
+
+    mainchain.generate(1).await.unwrap();
+    let body = Body::new(vec![], vec![]);
+    let mut header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: 2,
+        // The UTXO set before the first block.
+        commitment: Some(header.commitment.unwrap()),
+    };
+    let result = node.submit_block(&header, &body).await;
+    assert!(matches!(
+        result,
+        Err(node::Error::State(state::Error::WrongCommitment { .. }))
+    ));
+    assert_eq!(node.get_height().unwrap(), 1);
+
+    header.commitment = Some(node.get_utxo_set_hash().unwrap());
+    node.submit_block(&header, &body).await.unwrap();
+    assert_eq!(node.get_best_hash().unwrap(), header.hash());
+}
+
+#[tokio::test]
+async fn fill_mempool_transaction_with_foreign_inputs() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    // Not derived from any wallet's seed.
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[2; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+    let address = get_address(&public);
+    let outpoint = bitcoin::OutPoint {
+        txid: bitcoin::Txid::from_byte_array([2; 32]),
+        vout: 0,
+    };
+    let deposit = bip300301::Output {
+        address: format!("{address}"),
+        value: 100_000,
+    };
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: [(outpoint, deposit)].into(),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+    submit_empty_block(&node, &mainchain).await;
+
+    let transaction = Transaction {
+        inputs: vec![OutPoint::Deposit(outpoint)],
+        outputs: vec![Output {
+            address,
+            content: Content::Value(98_000),
+        }],
+    };
+    let transaction = authorize(&[(address, &keypair)], transaction).unwrap();
+    node.submit_transaction(&transaction).await.unwrap();
+    let transactions = node.get_all_transactions().unwrap();
+    assert_eq!(transactions.len(), 1);
+    let filled = node.fill_transaction(&transactions[0].transaction).unwrap();
+    assert_eq!(filled.spent_utxos.len(), 1);
+    assert_eq!(filled.spent_utxos[0].address, address);
+    assert_eq!(filled.get_value_in(), 100_000);
+    assert_eq!(filled.get_fee(), Some(2_000));
+}
+
+#[tokio::test]
+async fn fill_transaction_reports_missing_inputs() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let coinbase = vec![Output {
+        address: Address([1; 20]),
+        content: Content::Value(0),
+    }];
+    let body = Body::new(vec![], coinbase);
+    mainchain.generate(1).await.unwrap();
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: 1,
+        commitment: None,
+    };
+    node.submit_block(&header, &body).await.unwrap();
+    let present = OutPoint::Coinbase {
+        merkle_root: header.merkle_root,
+        vout: 0,
+    };
+    let missing = OutPoint::Coinbase {
+        merkle_root: header.merkle_root,
+        vout: 1,
+    };
+
+    let transaction = Transaction {
+        inputs: vec![present],
+        outputs: vec![],
+    };
+    let filled = node.fill_transaction(&transaction).unwrap();
+    assert_eq!(filled.spent_utxos, body.coinbase);
+
+    let transaction = Transaction {
+        inputs: vec![present, missing],
+        outputs: vec![],
+    };
+    let result = node.fill_transaction(&transaction);
+    assert!(matches!(
+        &result,
+        Err(node::Error::MissingInputs { txid, missing: outpoints })
+            if *txid == transaction.txid() && *outpoints == vec![missing]
+    ));
+}
+
+fn keypair(byte: u8) -> ed25519_dalek::Keypair {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[byte; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    ed25519_dalek::Keypair { secret, public }
+}
+
+/// Deposit `count` outputs of 100000 sats to `address` with the next block.
+fn push_deposits(mainchain: &MockMainchain, address: Address, count: u32) {
+    let deposits = (0..count).map(|vout| {
+        let outpoint = bitcoin::OutPoint {
+            txid: bitcoin::Txid::from_byte_array([3; 32]),
+            vout,
+        };
+        let output = bip300301::Output {
+            address: format!("{address}"),
+            value: 100_000,
+        };
+        (outpoint, output)
+    });
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: deposits.collect(),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+}
+
+/// Connect a block with `transactions` and no coinbase.
+async fn submit_transactions(
+    node: &Node<MockMainchain>,
+    mainchain: &MockMainchain,
+    transactions: Vec<AuthorizedTransaction>,
+) -> (Header, Body) {
+    mainchain.generate(1).await.unwrap();
+    let body = Body::new(transactions, vec![]);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: node.get_height().unwrap() + 1,
+        commitment: None,
+    };
+    node.submit_block(&header, &body).await.unwrap();
+    (header, body)
+}
+
+#[tokio::test]
+async fn verify_chain_detects_corrupt_body() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let node = testutil::node(dir.path(), &mainchain);
+        for _ in 0..3 {
+            submit_empty_block(&node, &mainchain).await;
+        }
+        node.verify_chain().unwrap();
+    }
+
+    // Overwrite the body at height 2 with one that doesn't match its header.
+    {
+        let env = heed::EnvOpenOptions::new()
+            .map_size(10 * 1024 * 1024)
+            .max_dbs(
+                state::State::NUM_DBS
+                    + archive::Archive::NUM_DBS
+                    + mempool::MemPool::NUM_DBS
+                    + versioned::NUM_DBS,
+            )
+            .open(dir.path().join("data.mdb"))
+            .unwrap();
+        let bodies: heed::Database<heed::types::OwnedType<[u8; 4]>, heed::types::ByteSlice> =
+            env.open_database(Some("bodies")).unwrap().unwrap();
+        let corrupt = Body::new(
+            vec![],
+            vec![Output {
+                address: Address([1; 20]),
+                content: Content::Value(0),
+            }],
+        );
+        let corrupt = compression::serialize_body(&corrupt, None).unwrap();
+        let mut txn = env.write_txn().unwrap();
+        bodies.put(&mut txn, &2u32.to_be_bytes(), &corrupt).unwrap();
+        txn.commit().unwrap();
+    }
+
+    let node = testutil::node(dir.path(), &mainchain);
+    assert!(matches!(
+        node.verify_chain(),
+        Err(node::VerifyError::InvalidMerkleRoot { height: 2 })
+    ));
+}
+
+#[tokio::test]
+async fn send_wallet_transaction() {
+    let mainchain = MockMainchain::default();
+    let (dir, wallet_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let node = testutil::node(dir.path(), &mainchain);
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    push_deposits(&mainchain, wallet.get_new_address().unwrap(), 1);
+    submit_empty_block(&node, &mainchain).await;
+    let utxos = node
+        .get_utxos_by_addresses(&wallet.get_addresses().unwrap())
+        .unwrap();
+    wallet.put_utxos(&utxos).unwrap();
+
+    let transaction = wallet
+        .create_transaction(Address([2; 20]), 50_000, 1_000)
+        .unwrap();
+    let transaction = wallet.authorize(transaction).unwrap();
+    let txid = node.send_raw_transaction(&transaction).await.unwrap();
+    assert_eq!(txid, transaction.transaction.txid());
+    let transactions = node.get_all_transactions().unwrap();
+    assert_eq!(transactions.len(), 1);
+    assert_eq!(transactions[0].transaction.txid(), txid);
+}
+
+#[tokio::test]
+async fn connected_block_evicts_conflicting_mempool_transactions() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let keypair = keypair(1);
+    let address = get_address(&keypair.public);
+    push_deposits(&mainchain, address, 1);
+    submit_empty_block(&node, &mainchain).await;
+    let deposit = OutPoint::Deposit(bitcoin::OutPoint {
+        txid: bitcoin::Txid::from_byte_array([3; 32]),
+        vout: 0,
+    });
+    let spend = |value| {
+        let transaction = Transaction {
+            inputs: vec![deposit],
+            outputs: vec![Output {
+                address,
+                content: Content::Value(value),
+            }],
+        };
+        authorize(&[(address, &keypair)], transaction).unwrap()
+    };
+    node.submit_transaction(&spend(99_000)).await.unwrap();
+    assert_eq!(node.get_all_transactions().unwrap().len(), 1);
+    // A block spends the deposit with another transaction.
+    submit_transactions(&node, &mainchain, vec![spend(98_000)]).await;
+    assert!(node.get_all_transactions().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn block_info_matches_header_and_body() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let keypair = keypair(1);
+    let address = get_address(&keypair.public);
+    push_deposits(&mainchain, address, 1);
+    submit_empty_block(&node, &mainchain).await;
+    let outpoint = OutPoint::Deposit(bitcoin::OutPoint {
+        txid: bitcoin::Txid::from_byte_array([3; 32]),
+        vout: 0,
+    });
+    let transaction = Transaction {
+        inputs: vec![outpoint],
+        outputs: vec![Output {
+            address,
+            content: Content::Value(99_000),
+        }],
+    };
+    let transaction = authorize(&[(address, &keypair)], transaction).unwrap();
+    mainchain.generate(1).await.unwrap();
+    let coinbase = vec![Output {
+        address,
+        content: Content::Value(600),
+    }];
+    let body = Body::new(vec![transaction], coinbase);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: 2,
+        commitment: None,
+    };
+    node.submit_block(&header, &body).await.unwrap();
+
+    let block = node.get_block(2).unwrap().unwrap();
+    assert_eq!(block.hash, header.hash());
+    assert_eq!(block.header.hash(), header.hash());
+    assert_eq!(
+        block.body_size,
+        bincode::serialize(&body).unwrap().len() as u64
+    );
+    assert_eq!(block.num_transactions, 1);
+    assert_eq!(block.coinbase_value, 600);
+    assert_eq!(block.body.compute_merkle_root(), body.compute_merkle_root());
+    assert!(node.get_block(3).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn look_up_confirmed_blocks_transactions_and_outputs() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let keypair = keypair(1);
+    let address = get_address(&keypair.public);
+    push_deposits(&mainchain, address, 1);
+    submit_empty_block(&node, &mainchain).await;
+    let deposit = OutPoint::Deposit(bitcoin::OutPoint {
+        txid: bitcoin::Txid::from_byte_array([3; 32]),
+        vout: 0,
+    });
+    let output = Output {
+        address,
+        content: Content::Value(99_000),
+    };
+    let first = Transaction {
+        inputs: vec![deposit],
+        outputs: vec![output.clone()],
+    };
+    let first = authorize(&[(address, &keypair)], first).unwrap();
+    let first_txid = first.transaction.txid();
+    let (header, _) = submit_transactions(&node, &mainchain, vec![first]).await;
+    assert_eq!(
+        node.get_header_by_hash(&header.hash())
+            .unwrap()
+            .unwrap()
+            .hash(),
+        header.hash()
+    );
+    let (transaction, height) = node.get_transaction(&first_txid).unwrap().unwrap();
+    assert_eq!(transaction.txid(), first_txid);
+    assert_eq!(height, 2);
+
+    // Spend the output, it can still be looked up from the transaction that created it.
+    let created = OutPoint::Regular {
+        txid: first_txid,
+        vout: 0,
+    };
+    assert_eq!(node.get_utxo_heights(&[created]).unwrap()[&created], 2);
+    let second = Transaction {
+        inputs: vec![created],
+        outputs: vec![Output {
+            address,
+            content: Content::Value(98_000),
+        }],
+    };
+    let second = authorize(&[(address, &keypair)], second).unwrap();
+    submit_transactions(&node, &mainchain, vec![second]).await;
+    assert!(node.get_utxo_heights(&[created]).unwrap().is_empty());
+    assert_eq!(node.get_spent_utxos(&[created]).unwrap(), vec![created]);
+    assert_eq!(node.get_output(&created).unwrap(), Some(output.clone()));
+    // Spent deposits aren't archived.
+    assert_eq!(node.get_output(&deposit).unwrap(), None);
+    assert_eq!(
+        node.get_outputs(&[created, deposit]).unwrap(),
+        HashMap::from([(created, output)])
+    );
+    assert!(node.get_transaction(&[0; 32].into()).unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_block_submissions() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    mainchain.generate(1).await.unwrap();
+    let body = Body::new(vec![], vec![]);
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: mainchain.get_mainchain_tip().await.unwrap(),
+        height: 1,
+        commitment: None,
+    };
+    let submissions: Vec<_> = (0..16)
+        .map(|_| {
+            let (node, header, body) = (node.clone(), header.clone(), body.clone());
+            tokio::spawn(async move { node.submit_block(&header, &body).await })
+        })
+        .collect();
+    let mut connected = 0;
+    for submission in submissions {
+        match submission.await.unwrap() {
+            Ok(()) => connected += 1,
+            // Every other submission finds the block already connected.
+            Err(err) => assert!(
+                matches!(
+                    err,
+                    node::Error::Archive(archive::Error::InvalidPrevSideHash)
+                ),
+                "{err:?}"
+            ),
+        }
+    }
+    assert_eq!(connected, 1);
+    assert_eq!(node.get_height().unwrap(), 1);
+    node.verify_chain().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_transaction_submissions() {
+    const NUM_TRANSACTIONS: u32 = 16;
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let node = testutil::node(dir.path(), &mainchain);
+    let keypair = keypair(1);
+    let address = get_address(&keypair.public);
+    push_deposits(&mainchain, address, NUM_TRANSACTIONS);
+    submit_empty_block(&node, &mainchain).await;
+    let utxos = node.get_utxos_by_addresses(&[address].into()).unwrap();
+    assert_eq!(utxos.len(), NUM_TRANSACTIONS as usize);
+    let submissions: Vec<_> = utxos
+        .into_keys()
+        .map(|outpoint| {
+            let transaction = Transaction {
+                inputs: vec![outpoint],
+                outputs: vec![Output {
+                    address,
+                    content: Content::Value(99_000),
+                }],
+            };
+            let transaction = authorize(&[(address, &keypair)], transaction).unwrap();
+            let node = node.clone();
+            tokio::spawn(async move { node.submit_transaction(&transaction).await })
+        })
+        .collect();
+    for submission in submissions {
+        submission.await.unwrap().unwrap();
+    }
+    assert_eq!(
+        node.get_all_transactions().unwrap().len(),
+        NUM_TRANSACTIONS as usize
+    );
+}
+
+#[tokio::test]
+async fn datadir_is_locked() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let first = testutil::node(dir.path(), &mainchain);
+    let second = Node::with_mainchain(
+        dir.path(),
+        "127.0.0.1:0".parse().unwrap(),
+        mainchain.clone(),
+        NodeConfig::default(),
+    );
+    assert!(matches!(
+        second,
+        Err(node::Error::AlreadyRunning { datadir }) if datadir == dir.path()
+    ));
+    // The lock is released with the last clone of the node.
+    let clone = first.clone();
+    drop(first);
+    assert!(Node::with_mainchain(
+        dir.path(),
+        "127.0.0.1:0".parse().unwrap(),
+        mainchain.clone(),
+        NodeConfig::default(),
+    )
+    .is_err());
+    drop(clone);
+    testutil::node(dir.path(), &mainchain);
+}
+
+#[tokio::test]
+async fn wallet_follows_reorg_events() {
+    let mainchain = MockMainchain::default();
+    let (dir, wallet_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let node = testutil::node(dir.path(), &mainchain);
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let mut events = node.subscribe();
+    push_deposits(&mainchain, address, 1);
+    submit_empty_block(&node, &mainchain).await;
+    let utxos = node.get_utxos_by_addresses(&[address].into()).unwrap();
+    wallet.put_utxos(&utxos).unwrap();
+    let transaction = wallet
+        .create_transaction(Address([2; 20]), 50_000, 1_000)
+        .unwrap();
+    let transaction = wallet.authorize(transaction).unwrap();
+    // Pays change back to the wallet.
+    submit_transactions(&node, &mainchain, vec![transaction]).await;
+
+    for height in 1..=2 {
+        match events.recv().await.unwrap() {
+            ChainEvent::Connect { header, body } => {
+                assert_eq!(header.height, height);
+                wallet.connect_block(&body, height).unwrap();
+            }
+            ChainEvent::Disconnect { .. } => panic!("unexpected disconnect"),
+        }
+    }
+    let funded = wallet.get_utxos().unwrap();
+    assert_eq!(funded.len(), 1);
+    assert!(funded.values().all(|output| output.get_value() == 49_000));
+
+    // Reorg the funding transaction away, the deposit it spent is restored.
+    node.rollback_to_height(1).await.unwrap();
+    match events.recv().await.unwrap() {
+        ChainEvent::Disconnect {
+            header,
+            body,
+            spent_utxos,
+        } => {
+            assert_eq!(header.height, 2);
+            wallet.disconnect_block(&body, &spent_utxos).unwrap();
+        }
+        ChainEvent::Connect { .. } => panic!("unexpected connect"),
+    }
+    assert_eq!(wallet.get_utxos().unwrap(), utxos);
+}
@@ -0,0 +1,80 @@
+use {{crate_name}}::{
+    bip300301::bitcoin,
+    types::{Address, MerkleRoot, OutPoint, Txid},
+};
+use bitcoin::hashes::Hash as _;
+
+fn round_trip(outpoint: OutPoint) {
+    let s = outpoint.to_string();
+    assert_eq!(s.parse::<OutPoint>().unwrap(), outpoint, "{s}");
+}
+
+#[test]
+fn regular_outpoint_round_trip() {
+    let outpoint = OutPoint::Regular {
+        txid: Txid::from([1; 32]),
+        vout: 7,
+    };
+    assert_eq!(
+        outpoint.to_string(),
+        format!("regular:{}:7", "01".repeat(32))
+    );
+    round_trip(outpoint);
+}
+
+#[test]
+fn coinbase_outpoint_round_trip() {
+    round_trip(OutPoint::Coinbase {
+        merkle_root: MerkleRoot::from([2; 32]),
+        vout: 0,
+    });
+}
+
+#[test]
+fn deposit_outpoint_round_trip() {
+    let mut txid = [0; 32];
+    txid[0] = 3;
+    round_trip(OutPoint::Deposit(bitcoin::OutPoint {
+        txid: bitcoin::Txid::from_byte_array(txid),
+        vout: u32::MAX,
+    }));
+}
+
+#[test]
+fn malformed_outpoints_are_rejected() {
+    let txid = "01".repeat(32);
+    for s in [
+        String::new(),
+        format!("regular:{txid}"),
+        format!("regular:{txid}:0:0"),
+        format!("spent:{txid}:0"),
+        format!("regular:{}:0", &txid[2..]),
+        format!("regular:{txid}:-1"),
+        "deposit:xyz:0".to_owned(),
+    ] {
+        assert!(s.parse::<OutPoint>().is_err(), "{s}");
+    }
+}
+
+#[test]
+fn address_round_trip() {
+    let address = Address([4; 20]);
+    assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+    let mut corrupted = address.to_string();
+    corrupted.pop();
+    assert!(corrupted.parse::<Address>().is_err());
+}
+
+#[test]
+fn short_address_keeps_both_ends() {
+    let address = Address([4; 20]);
+    let full = address.to_string();
+    let short = address.short();
+    let (first, last) = short.split_once('…').unwrap();
+    assert_eq!(first.chars().count(), 4);
+    assert_eq!(last.chars().count(), 4);
+    assert!(full.starts_with(first));
+    // The checksum is at the end of the base58 encoding.
+    assert!(full.ends_with(last));
+    assert_ne!(Address([5; 20]).short(), short);
+}
@@ -0,0 +1,863 @@
+use {{crate_name}}::{
+    authorization::{authorize, get_address},
+    bip300301::{self, bitcoin},
+    mainchain::Mainchain as _,
+    miner::Miner,
+    net::{self, Net, PeerState, RateLimit, Request, Response, TokenBucket},
+    node::{Node, THIS_SIDECHAIN},
+    testutil::{self, MockMainchain},
+    types::{
+        Address, AuthorizedTransaction, Body, Content, Header, OutPoint, Output, Transaction, Txid,
+        MAX_TRANSACTION_ITEMS,
+    },
+    wallet::{AccountPath, AddressPolicy, Wallet},
+};
+use bitcoin::hashes::Hash as _;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn hello() -> Request {
+    Request::Hello {
+        version: net::PROTOCOL_VERSION,
+        best_height: 0,
+    }
+}
+
+/// BMM a block on the mock mainchain and connect it to `node`.
+async fn mine(
+    node: &Node<MockMainchain>,
+    miner: &mut Miner<MockMainchain>,
+    body: Body,
+) -> (Header, Body) {
+    let header = Header {
+        merkle_root: body.compute_merkle_root(),
+        prev_side_hash: node.get_best_hash().unwrap(),
+        prev_main_hash: miner.mainchain.get_mainchain_tip().await.unwrap(),
+        height: node.get_height().unwrap() + 1,
+        commitment: None,
+    };
+    miner.attempt_bmm(0, 0, header, body).await.unwrap();
+    miner.generate().await.unwrap();
+    let (header, body) = miner.confirm_bmm().await.unwrap().unwrap();
+    node.submit_block(&header, &body).await.unwrap();
+    (header, body)
+}
+
+/// Wait for chain events until `node` reaches `height`.
+async fn wait_for_height(node: &Node<MockMainchain>, height: u32) {
+    // Subscribed before the height is checked, so a block connected in between isn't missed.
+    let mut events = node.subscribe();
+    let synced = async {
+        while node.get_height().unwrap() < height {
+            if let Err(RecvError::Closed) = events.recv().await {
+                panic!("node stopped before reaching height {height}");
+            }
+        }
+    };
+    tokio::time::timeout(SYNC_TIMEOUT, synced)
+        .await
+        .unwrap_or_else(|_| panic!("node didn't sync to height {height}"));
+}
+
+/// Deposit 100000 sats to each of `addresses` with the next block.
+fn push_deposits(mainchain: &MockMainchain, addresses: &[Address]) {
+    let deposits = addresses.iter().enumerate().map(|(vout, address)| {
+        let outpoint = bitcoin::OutPoint {
+            txid: bitcoin::Txid::from_byte_array([2; 32]),
+            vout: vout as u32,
+        };
+        let deposit = bip300301::Output {
+            address: format!("{address}"),
+            value: 100_000,
+        };
+        (outpoint, deposit)
+    });
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: deposits.collect(),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+}
+
+/// Wait until `txid` is in the mempool of `node`.
+async fn wait_for_transaction(node: &Node<MockMainchain>, txid: Txid) {
+    let relayed = async {
+        while !node
+            .get_all_transactions()
+            .unwrap()
+            .iter()
+            .any(|transaction| transaction.transaction.txid() == txid)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(SYNC_TIMEOUT, relayed)
+        .await
+        .expect("transaction wasn't relayed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn block_propagates_between_nodes() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b, wallet_dir) = (
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+    );
+    let mut node_a = testutil::node(dir_a.path(), &mainchain);
+    let mut node_b = testutil::node(dir_b.path(), &mainchain);
+    node_a.run().unwrap();
+    node_b.run().unwrap();
+    node_b.connect(node_a.local_addr().unwrap()).await.unwrap();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+
+    // Fund the wallet with a deposit.
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let deposit_outpoint = bitcoin::OutPoint {
+        txid: bitcoin::Txid::from_byte_array([1; 32]),
+        vout: 0,
+    };
+    let deposit = bip300301::Output {
+        address: format!("{address}"),
+        value: 100_000,
+    };
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: HashMap::from([(deposit_outpoint, deposit)]),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+    mine(&node_a, &mut miner, Body::new(vec![], vec![])).await;
+
+    // Spend it.
+    let addresses: HashSet<_> = [address].into();
+    let utxos = node_a.get_utxos_by_addresses(&addresses).unwrap();
+    wallet.put_utxos(&utxos).unwrap();
+    let recipient = wallet.get_new_address().unwrap();
+    let transaction = wallet.create_transaction(recipient, 50_000, 1_000).unwrap();
+    let transaction = wallet.authorize(transaction).unwrap();
+    node_a.submit_transaction(&transaction).await.unwrap();
+
+    let (transactions, fee) = node_a.get_transactions(1).unwrap();
+    assert_eq!(transactions.len(), 1);
+    let coinbase = vec![Output {
+        address: wallet.get_new_address().unwrap(),
+        content: Content::Value(fee),
+    }];
+    let (header, _) = mine(&node_a, &mut miner, Body::new(transactions, coinbase)).await;
+
+    wait_for_height(&node_b, 2).await;
+    assert_eq!(node_b.get_best_hash().unwrap(), header.hash());
+    let addresses = wallet.get_addresses().unwrap();
+    assert_eq!(
+        node_b.get_utxos_by_addresses(&addresses).unwrap(),
+        node_a.get_utxos_by_addresses(&addresses).unwrap(),
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn second_connection_to_same_node_is_closed() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let mut node_a = testutil::node(dir_a.path(), &mainchain);
+    let mut node_b = testutil::node(dir_b.path(), &mainchain);
+    node_a.run().unwrap();
+    node_b.run().unwrap();
+    node_a.connect(node_b.local_addr().unwrap()).await.unwrap();
+    // Dialed from a different address, but to a node that is already connected.
+    assert!(node_b.connect(node_a.local_addr().unwrap()).await.is_err());
+    let deduplicated = async {
+        while node_a.get_peer_count().await != 1 || node_b.get_peer_count().await != 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(SYNC_TIMEOUT, deduplicated)
+        .await
+        .expect("duplicate connection wasn't closed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn node_ids_are_bound_to_certificates() {
+    let server = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    assert_ne!(server.node_id, client.node_id);
+    let accepted = tokio::spawn({
+        let server = server.server.clone();
+        async move { server.accept().await.unwrap().await.unwrap() }
+    });
+    let peer = client
+        .connect(server.server.local_addr().unwrap())
+        .await
+        .unwrap();
+    // Each end sees the id of the certificate the other end proved it holds.
+    assert_eq!(peer.certified_node_id().unwrap(), server.node_id);
+    let accepted = net::Peer::new(accepted.await.unwrap(), net::DEFAULT_REQUEST_TIMEOUT);
+    assert_eq!(accepted.certified_node_id().unwrap(), client.node_id);
+}
+
+#[test]
+fn token_bucket_refills() {
+    let limit = RateLimit {
+        per_second: 1.0,
+        burst: 2,
+    };
+    let mut bucket = TokenBucket::default();
+    let start = Instant::now();
+    assert!(bucket.try_take(&limit, start));
+    assert!(bucket.try_take(&limit, start));
+    assert!(!bucket.try_take(&limit, start));
+    assert!(bucket.try_take(&limit, start + Duration::from_secs(1)));
+    assert!(!bucket.try_take(&limit, start + Duration::from_secs(1)));
+}
+
+#[test]
+fn huge_authorization_count_is_rejected() {
+    let message = Request::PushTransaction {
+        transaction: AuthorizedTransaction {
+            transaction: Transaction {
+                inputs: vec![],
+                outputs: vec![],
+            },
+            authorizations: vec![],
+        },
+    };
+    let bytes = bincode::serialize(&message).unwrap();
+    assert!(net::deserialize_message::<Request>(&bytes).is_ok());
+    // The authorization count is the last field of the message.
+    let count_at = bytes.len() - std::mem::size_of::<u64>();
+    for count in [MAX_TRANSACTION_ITEMS as u64 + 1, u64::MAX] {
+        let mut bytes = bytes.clone();
+        bytes[count_at..].copy_from_slice(&count.to_le_bytes());
+        assert!(net::deserialize_message::<Request>(&bytes).is_err());
+        // Rejected by the count itself, not only by the message size limit.
+        assert!(bincode::deserialize::<Request>(&bytes).is_err());
+    }
+}
+
+#[test]
+fn maximal_transaction_fits_read_limit() {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+    let transaction = Transaction {
+        inputs: vec![OutPoint::Deposit(bitcoin::OutPoint {
+            txid: bitcoin::Txid::from_byte_array([0xff; 32]),
+            vout: u32::MAX,
+        })],
+        outputs: vec![Output {
+            address: Address([0xff; 20]),
+            content: Content::Withdrawal {
+                value: u64::MAX,
+                main_fee: u64::MAX,
+                main_address: bitcoin::Address::p2wsh(
+                    &bitcoin::ScriptBuf::new(),
+                    bitcoin::Network::Testnet,
+                )
+                .as_unchecked()
+                .clone(),
+            },
+        }],
+    };
+    let authorized = authorize(&[(get_address(&keypair.public), &keypair)], transaction).unwrap();
+    let transaction = AuthorizedTransaction {
+        transaction: Transaction {
+            inputs: vec![authorized.transaction.inputs[0]; MAX_TRANSACTION_ITEMS],
+            outputs: vec![authorized.transaction.outputs[0].clone(); MAX_TRANSACTION_ITEMS],
+        },
+        authorizations: vec![authorized.authorizations[0].clone(); MAX_TRANSACTION_ITEMS],
+    };
+    let bytes = bincode::serialize(&Request::PushTransaction { transaction }).unwrap();
+    assert!(bytes.len() <= net::READ_LIMIT);
+    assert!(net::deserialize_message::<Request>(&bytes).is_ok());
+}
+
+#[test]
+fn full_headers_response_fits_response_limit() {
+    let headers = (1..=Node::<MockMainchain>::MAX_HEADERS as u32)
+        .map(|height| Header {
+            merkle_root: [0xff; 32].into(),
+            prev_side_hash: [0xff; 32].into(),
+            prev_main_hash: bitcoin::BlockHash::all_zeros(),
+            height,
+            commitment: Some([0xff; 32]),
+        })
+        .collect();
+    let bytes = bincode::serialize(&Response::Headers { headers }).unwrap();
+    let response = net::deserialize_response(&bytes).unwrap();
+    assert!(matches!(
+        response,
+        Response::Headers { headers } if headers.len() == Node::<MockMainchain>::MAX_HEADERS
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_exceeding_transaction_rate_is_disconnected() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.set_push_transaction_limit(RateLimit {
+        per_second: 0.0,
+        burst: 0,
+    });
+    node.run().unwrap();
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let peer = client.connect(node.local_addr().unwrap()).await.unwrap();
+    peer.request(&hello()).await.unwrap();
+    let request = Request::PushTransaction {
+        transaction: AuthorizedTransaction {
+            transaction: Transaction {
+                inputs: vec![],
+                outputs: vec![],
+            },
+            authorizations: vec![],
+        },
+    };
+    let throttled_pushes = net::MAX_MISBEHAVIOR / net::THROTTLE_PENALTY;
+    for _ in 1..throttled_pushes {
+        let response = peer.request(&request).await.unwrap();
+        assert!(matches!(response, Response::TransactionRejected));
+    }
+    // The push that reaches the misbehavior limit gets the connection closed.
+    let _ = peer.request(&request).await;
+    let reason = tokio::time::timeout(SYNC_TIMEOUT, peer.connection.closed())
+        .await
+        .expect("peer wasn't disconnected");
+    assert!(matches!(
+        reason,
+        net::quinn::ConnectionError::ApplicationClosed(_)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn request_before_hello_is_rejected() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.run().unwrap();
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let peer = client.connect(node.local_addr().unwrap()).await.unwrap();
+    let result = peer.request(&Request::GetBlock { height: 1 }).await;
+    assert!(!matches!(
+        result,
+        Ok(Response::Block { .. } | Response::NoBlock)
+    ));
+    let reason = tokio::time::timeout(SYNC_TIMEOUT, peer.connection.closed())
+        .await
+        .expect("peer wasn't disconnected");
+    assert!(matches!(
+        reason,
+        net::quinn::ConnectionError::ApplicationClosed(close) if &close.reason[..] == b"handshake required"
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peer_sending_malformed_request_is_disconnected() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.run().unwrap();
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let peer = client.connect(node.local_addr().unwrap()).await.unwrap();
+    peer.request(&hello()).await.unwrap();
+    let (mut send, _recv) = peer.connection.open_bi().await.unwrap();
+    send.write_all(&[0xff; 16]).await.unwrap();
+    send.finish().await.unwrap();
+    let reason = tokio::time::timeout(SYNC_TIMEOUT, peer.connection.closed())
+        .await
+        .expect("peer wasn't disconnected");
+    assert!(matches!(
+        reason,
+        net::quinn::ConnectionError::ApplicationClosed(close) if &close.reason[..] == b"malformed request"
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn request_to_unresponsive_peer_times_out() {
+    let unresponsive = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let server = unresponsive.server.clone();
+    // Accepts the request stream but never responds.
+    tokio::spawn(async move {
+        let connection = server.accept().await.unwrap().await.unwrap();
+        let _streams = connection.accept_bi().await.unwrap();
+        std::future::pending::<()>().await;
+    });
+    let mut client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.request_timeout = Duration::from_millis(200);
+    let peer = client
+        .connect(unresponsive.server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let start = Instant::now();
+    let result = peer.request(&Request::GetBlock { height: 1 }).await;
+    assert!(matches!(result, Err(net::Error::RequestTimeout { .. })));
+    assert!(start.elapsed() < SYNC_TIMEOUT);
+    assert_eq!(
+        peer.misbehavior.load(std::sync::atomic::Ordering::SeqCst),
+        net::TIMEOUT_PENALTY
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transaction_broadcast_is_concurrent() {
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+    let mainchain = MockMainchain::default();
+    let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+    let mut node_a = testutil::node(dirs[0].path(), &mainchain);
+    node_a.set_request_timeout(REQUEST_TIMEOUT);
+    node_a.run().unwrap();
+    let mut receivers = vec![
+        testutil::node(dirs[1].path(), &mainchain),
+        testutil::node(dirs[2].path(), &mainchain),
+    ];
+    for receiver in &mut receivers {
+        receiver.run().unwrap();
+        receiver
+            .connect(node_a.local_addr().unwrap())
+            .await
+            .unwrap();
+    }
+    // Peers that never accept the request streams, each of them holds up its request until it
+    // times out.
+    let mut unresponsive = vec![];
+    for _ in 0..2 {
+        let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.connect(node_a.local_addr().unwrap()).await.unwrap();
+        unresponsive.push(client);
+    }
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+
+    let wallet = Wallet::new(
+        dirs[3].path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: HashMap::from([(
+            bitcoin::OutPoint {
+                txid: bitcoin::Txid::from_byte_array([1; 32]),
+                vout: 0,
+            },
+            bip300301::Output {
+                address: format!("{address}"),
+                value: 100_000,
+            },
+        )]),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+    mine(&node_a, &mut miner, Body::new(vec![], vec![])).await;
+    for receiver in &receivers {
+        wait_for_height(receiver, 1).await;
+    }
+
+    let addresses: HashSet<_> = [address].into();
+    wallet
+        .put_utxos(&node_a.get_utxos_by_addresses(&addresses).unwrap())
+        .unwrap();
+    let recipient = wallet.get_new_address().unwrap();
+    let transaction = wallet.create_transaction(recipient, 50_000, 1_000).unwrap();
+    let transaction = wallet.authorize(transaction).unwrap();
+    let start = Instant::now();
+    node_a.submit_transaction(&transaction).await.unwrap();
+    // Sequential requests would wait for both unresponsive peers in turn.
+    assert!(start.elapsed() < 2 * REQUEST_TIMEOUT);
+    for receiver in &receivers {
+        let transactions = receiver.get_all_transactions().unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(
+            transactions[0].transaction.txid(),
+            transaction.transaction.txid()
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn compact_block_is_reconstructed_from_mempool() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b, wallet_dir) = (
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+    );
+    let mut node_a = testutil::node(dir_a.path(), &mainchain);
+    let mut node_b = testutil::node(dir_b.path(), &mainchain);
+    node_b.set_compact_blocks(true);
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+
+    // Two deposits, so that two independent transactions can be made.
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let addresses: HashSet<_> = (0..2).map(|_| wallet.get_new_address().unwrap()).collect();
+    let deposits = addresses.iter().enumerate().map(|(vout, address)| {
+        let outpoint = bitcoin::OutPoint {
+            txid: bitcoin::Txid::from_byte_array([1; 32]),
+            vout: vout as u32,
+        };
+        let deposit = bip300301::Output {
+            address: format!("{address}"),
+            value: 100_000,
+        };
+        (outpoint, deposit)
+    });
+    mainchain.push_two_way_peg_data(bip300301::TwoWayPegData {
+        deposits: deposits.collect(),
+        deposit_block_hash: None,
+        bundle_statuses: HashMap::new(),
+    });
+    // The nodes aren't connected yet, node B gets the block directly.
+    let (header, body) = mine(&node_a, &mut miner, Body::new(vec![], vec![])).await;
+    node_b.submit_block(&header, &body).await.unwrap();
+
+    let utxos = node_a.get_utxos_by_addresses(&addresses).unwrap();
+    let transactions: Vec<_> = utxos
+        .into_iter()
+        .map(|(outpoint, output)| {
+            let transaction = Transaction {
+                inputs: vec![outpoint],
+                outputs: vec![Output {
+                    address: output.address,
+                    content: Content::Value(90_000),
+                }],
+            };
+            wallet.authorize(transaction).unwrap()
+        })
+        .collect();
+    for transaction in &transactions {
+        node_a.submit_transaction(transaction).await.unwrap();
+    }
+    // Node B has all but one of the block's transactions.
+    node_b.submit_transaction(&transactions[0]).await.unwrap();
+    let (header, _) = mine(&node_a, &mut miner, Body::new(transactions, vec![])).await;
+
+    node_a.run().unwrap();
+    node_b.run().unwrap();
+    node_b.connect(node_a.local_addr().unwrap()).await.unwrap();
+    wait_for_height(&node_b, 2).await;
+    assert_eq!(node_b.get_best_hash().unwrap(), header.hash());
+    assert!(node_b.get_all_transactions().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn node_behind_peer_is_syncing() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.run().unwrap();
+    assert!(!node.is_syncing().await.unwrap());
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let peer = client.connect(node.local_addr().unwrap()).await.unwrap();
+    // Within the threshold the node counts as synced.
+    let hello = Request::Hello {
+        version: net::PROTOCOL_VERSION,
+        best_height: Node::<MockMainchain>::SYNC_THRESHOLD,
+    };
+    peer.request(&hello).await.unwrap();
+    assert!(!node.is_syncing().await.unwrap());
+
+    let state = PeerState {
+        version: net::PROTOCOL_VERSION,
+        block_height: 5,
+        mempool_size: 0,
+    };
+    let syncing = async {
+        while !node.is_syncing().await.unwrap() {
+            peer.heart_beat(&state).unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(SYNC_TIMEOUT, syncing)
+        .await
+        .expect("node didn't notice it is behind");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_outbound_peer_is_redialed() {
+    let server = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let (accepted_tx, mut accepted_rx) = tokio::sync::mpsc::unbounded_channel();
+    // Answers the handshake of every connection and hands the connection to the test.
+    tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Some(connecting) = server.server.accept().await {
+                let connection = connecting.await.unwrap();
+                let (mut send, mut recv) = connection.accept_bi().await.unwrap();
+                recv.read_to_end(net::READ_LIMIT).await.unwrap();
+                let response = Response::Hello {
+                    version: net::PROTOCOL_VERSION,
+                    best_height: 0,
+                };
+                send.write_all(&bincode::serialize(&response).unwrap())
+                    .await
+                    .unwrap();
+                send.finish().await.unwrap();
+                accepted_tx.send(connection).unwrap();
+            }
+        }
+    });
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.run().unwrap();
+    node.connect(server.server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let connection = accepted_rx.recv().await.unwrap();
+    connection.close(net::quinn::VarInt::from_u32(0), b"dropped");
+    tokio::time::timeout(SYNC_TIMEOUT, accepted_rx.recv())
+        .await
+        .expect("peer wasn't redialed")
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transaction_with_many_inputs_is_relayed() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b, wallet_dir) = (
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+    );
+    let mut node_a = testutil::node(dir_a.path(), &mainchain);
+    let mut node_b = testutil::node(dir_b.path(), &mainchain);
+    node_a.run().unwrap();
+    node_b.run().unwrap();
+    node_b.connect(node_a.local_addr().unwrap()).await.unwrap();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    push_deposits(&mainchain, &[address; 50]);
+    mine(&node_a, &mut miner, Body::new(vec![], vec![])).await;
+    wait_for_height(&node_b, 1).await;
+
+    let utxos = node_a.get_utxos_by_addresses(&[address].into()).unwrap();
+    assert_eq!(utxos.len(), 50);
+    let transaction = Transaction {
+        inputs: utxos.into_keys().collect(),
+        outputs: vec![Output {
+            address,
+            content: Content::Value(50 * 100_000 - 10_000),
+        }],
+    };
+    let transaction = wallet.authorize(transaction).unwrap();
+    node_a.submit_transaction(&transaction).await.unwrap();
+    wait_for_transaction(&node_b, transaction.transaction.txid()).await;
+    // Both nodes are still listening to each other.
+    assert_eq!(node_a.get_peer_count().await, 1);
+    assert_eq!(node_b.get_peer_count().await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transaction_from_several_addresses_is_relayed() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b, wallet_dir) = (
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+    );
+    let mut node_a = testutil::node(dir_a.path(), &mainchain);
+    let mut node_b = testutil::node(dir_b.path(), &mainchain);
+    node_a.run().unwrap();
+    node_b.run().unwrap();
+    node_b.connect(node_a.local_addr().unwrap()).await.unwrap();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let addresses = wallet.get_new_addresses(2).unwrap();
+    let deposit_addresses: Vec<_> = addresses.iter().copied().cycle().take(50).collect();
+    push_deposits(&mainchain, &deposit_addresses);
+    mine(&node_a, &mut miner, Body::new(vec![], vec![])).await;
+    wait_for_height(&node_b, 1).await;
+
+    let utxos = node_a
+        .get_utxos_by_addresses(&addresses.iter().copied().collect())
+        .unwrap();
+    assert_eq!(utxos.len(), 50);
+    let transaction = Transaction {
+        inputs: utxos.into_keys().collect(),
+        outputs: vec![Output {
+            address: addresses[0],
+            content: Content::Value(50 * 100_000 - 10_000),
+        }],
+    };
+    let transaction = wallet.authorize(transaction).unwrap();
+    assert_eq!(transaction.authorizations.len(), 50);
+    node_a.submit_transaction(&transaction).await.unwrap();
+    wait_for_transaction(&node_b, transaction.transaction.txid()).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn consolidation_transaction_is_relayed() {
+    let mainchain = MockMainchain::default();
+    let (dir_a, dir_b, wallet_dir) = (
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+        tempfile::tempdir().unwrap(),
+    );
+    let mut node_a = testutil::node(dir_a.path(), &mainchain);
+    let mut node_b = testutil::node(dir_b.path(), &mainchain);
+    node_a.run().unwrap();
+    node_b.run().unwrap();
+    node_b.connect(node_a.local_addr().unwrap()).await.unwrap();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    push_deposits(&mainchain, &[address; 10]);
+    mine(&node_a, &mut miner, Body::new(vec![], vec![])).await;
+    wait_for_height(&node_b, 1).await;
+
+    wallet
+        .put_utxos(&node_a.get_utxos_by_addresses(&[address].into()).unwrap())
+        .unwrap();
+    let transaction = wallet.create_consolidation_transaction(10, 10_000).unwrap();
+    assert_eq!(transaction.inputs.len(), 10);
+    let transaction = wallet.authorize(transaction).unwrap();
+    node_a.submit_transaction(&transaction).await.unwrap();
+    wait_for_transaction(&node_b, transaction.transaction.txid()).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn known_transaction_is_accepted_again() {
+    let mainchain = MockMainchain::default();
+    let (dir, wallet_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.run().unwrap();
+    let mut miner = Miner::with_mainchain(THIS_SIDECHAIN, mainchain.clone());
+    let wallet = Wallet::new(
+        wallet_dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&[1; 64]).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    push_deposits(&mainchain, &[address]);
+    mine(&node, &mut miner, Body::new(vec![], vec![])).await;
+    wallet
+        .put_utxos(&node.get_utxos_by_addresses(&[address].into()).unwrap())
+        .unwrap();
+    let transaction = wallet.create_transaction(address, 50_000, 1_000).unwrap();
+    let transaction = wallet.authorize(transaction).unwrap();
+
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let peer = client.connect(node.local_addr().unwrap()).await.unwrap();
+    peer.request(&hello()).await.unwrap();
+    let request = Request::PushTransaction { transaction };
+    // Relayed back by another peer, or pushed again by the same one.
+    for _ in 0..3 {
+        let response = peer.request(&request).await.unwrap();
+        assert!(matches!(response, Response::TransactionAccepted));
+    }
+    assert_eq!(node.get_all_transactions().unwrap().len(), 1);
+    assert_eq!(node.get_peer_count().await, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_transactions_are_penalized_by_kind() {
+    let mainchain = MockMainchain::default();
+    let dir = tempfile::tempdir().unwrap();
+    let mut node = testutil::node(dir.path(), &mainchain);
+    node.run().unwrap();
+    let client = Net::new("127.0.0.1:0".parse().unwrap()).unwrap();
+    let peer = client.connect(node.local_addr().unwrap()).await.unwrap();
+    peer.request(&hello()).await.unwrap();
+    let missing = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout: 0,
+    };
+    let keypair = {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        ed25519_dalek::Keypair { secret, public }
+    };
+    let spends_missing = Transaction {
+        inputs: vec![missing],
+        outputs: vec![],
+    };
+    let spends_missing =
+        authorize(&[(get_address(&keypair.public), &keypair)], spends_missing).unwrap();
+    // Spending an output this node doesn't know yet, and not paying our minimum relay fee, aren't
+    // reasons to disconnect.
+    let no_fee = AuthorizedTransaction {
+        transaction: Transaction {
+            inputs: vec![],
+            outputs: vec![],
+        },
+        authorizations: vec![],
+    };
+    for transaction in [spends_missing.clone(), no_fee] {
+        let response = peer
+            .request(&Request::PushTransaction { transaction })
+            .await
+            .unwrap();
+        assert!(matches!(response, Response::TransactionRejected));
+    }
+    let response = peer
+        .request(&Request::GetBlock { height: 1 })
+        .await
+        .unwrap();
+    assert!(matches!(response, Response::NoBlock));
+
+    // A transaction without its authorizations can't be valid anywhere.
+    let unauthorized = AuthorizedTransaction {
+        authorizations: vec![],
+        ..spends_missing
+    };
+    let response = peer
+        .request(&Request::PushTransaction {
+            transaction: unauthorized,
+        })
+        .await
+        .unwrap();
+    assert!(matches!(response, Response::TransactionRejected));
+    let reason = tokio::time::timeout(SYNC_TIMEOUT, peer.connection.closed())
+        .await
+        .expect("peer wasn't disconnected");
+    assert!(matches!(
+        reason,
+        net::quinn::ConnectionError::ApplicationClosed(close) if &close.reason[..] == b"sent an invalid transaction"
+    ));
+}
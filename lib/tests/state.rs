@@ -0,0 +1,812 @@
+use {{crate_name}}::{
+    authorization::{authorize, get_address, Authorization},
+    bip300301::{self, bitcoin, TwoWayPegData, WithdrawalBundleStatus},
+    consensus::ConsensusParams,
+    state::{self, State},
+    testutil::main_address,
+    types::{
+        Address, AuthorizedTransaction, Body, Content, FilledTransaction, Header, OutPoint, Output,
+        Transaction, UnclaimedDeposit, WithdrawalBundle,
+    },
+};
+use bitcoin::hashes::Hash as _;
+use std::collections::HashMap;
+
+fn open(dir: &tempfile::TempDir) -> (heed::Env, State) {
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(State::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let state = State::new(&env, ConsensusParams::regtest()).unwrap();
+    (env, state)
+}
+
+fn state(dir: &tempfile::TempDir) -> State {
+    open(dir).1
+}
+
+fn withdrawal_output() -> Output {
+    Output {
+        address: Address([0; 20]),
+        content: Content::Withdrawal {
+            value: 1000,
+            main_fee: 10,
+            main_address: main_address(),
+        },
+    }
+}
+
+fn bundle_statuses(txid: bitcoin::Txid, status: WithdrawalBundleStatus) -> TwoWayPegData {
+    TwoWayPegData {
+        bundle_statuses: HashMap::from([(txid, status)]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn reject_spending_withdrawal_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir);
+    let withdrawal = withdrawal_output();
+    let outpoint = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout: 0,
+    };
+    let transaction = FilledTransaction {
+        transaction: Transaction {
+            inputs: vec![outpoint],
+            outputs: vec![Output {
+                address: Address([0; 20]),
+                content: Content::Value(900),
+            }],
+        },
+        spent_utxos: vec![withdrawal],
+    };
+    let result = state.validate_filled_transaction(&transaction);
+    assert!(matches!(
+        result,
+        Err(state::Error::SpendsLockedOutput { outpoint: spent }) if spent == outpoint
+    ));
+}
+
+/// Connect a withdrawal and collect it into a bundle, returns the bundle and the height of the
+/// block that collected it.
+fn collect_bundle(state: &State, txn: &mut heed::RwTxn) -> (WithdrawalBundle, u32) {
+    let transaction = AuthorizedTransaction {
+        transaction: Transaction {
+            inputs: vec![],
+            outputs: vec![withdrawal_output()],
+        },
+        authorizations: vec![],
+    };
+    state
+        .connect_body(txn, &Body::new(vec![transaction], vec![]), 1)
+        .unwrap();
+    // Far enough past genesis for a bundle to be collected.
+    let height = ConsensusParams::regtest().withdrawal_bundle_failure_gap + 1;
+    state
+        .connect_two_way_peg_data(txn, &TwoWayPegData::default(), height)
+        .unwrap();
+    let bundle = state.get_pending_withdrawal_bundle(txn).unwrap().unwrap();
+    assert!(bundle.verify_commitment(height + 1));
+    (bundle, height)
+}
+
+#[test]
+fn ignore_status_of_unknown_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let (bundle, height) = collect_bundle(&state, &mut txn);
+
+    let unknown_txid = bitcoin::Txid::all_zeros();
+    let two_way_peg_data = bundle_statuses(unknown_txid, WithdrawalBundleStatus::Failed);
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, height + 1)
+        .unwrap();
+    assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_some());
+    assert_eq!(state.utxos.len(&txn).unwrap(), 0);
+
+    let two_way_peg_data =
+        bundle_statuses(bundle.transaction.txid(), WithdrawalBundleStatus::Confirmed);
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, height + 2)
+        .unwrap();
+    assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_none());
+}
+
+#[test]
+fn ignore_status_of_bundle_with_mismatched_height() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let (bundle, height) = collect_bundle(&state, &mut txn);
+    // The bundle commits to height + 1.
+    state
+        .pending_withdrawal_bundle_height
+        .put(&mut txn, &0, &height)
+        .unwrap();
+    let two_way_peg_data =
+        bundle_statuses(bundle.transaction.txid(), WithdrawalBundleStatus::Failed);
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, height + 1)
+        .unwrap();
+    assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_some());
+    assert_eq!(state.utxos.len(&txn).unwrap(), 0);
+}
+
+#[test]
+fn ignore_status_of_bundle_with_mismatched_commitment() {
+    use bitcoin::blockdata::{opcodes, script};
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let (bundle, height) = collect_bundle(&state, &mut txn);
+    // The same bundle, but committing to other inputs.
+    let mut reported = bundle.transaction.clone();
+    reported.output[2].script_pubkey = script::Builder::new()
+        .push_opcode(opcodes::all::OP_RETURN)
+        .push_slice([1; 32])
+        .into_script();
+    assert_ne!(reported.txid(), bundle.transaction.txid());
+    for status in [
+        WithdrawalBundleStatus::Failed,
+        WithdrawalBundleStatus::Confirmed,
+    ] {
+        let two_way_peg_data = bundle_statuses(reported.txid(), status);
+        state
+            .connect_two_way_peg_data(&mut txn, &two_way_peg_data, height + 1)
+            .unwrap();
+        assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_some());
+        assert_eq!(state.utxos.len(&txn).unwrap(), 0);
+    }
+}
+
+#[test]
+fn apply_status_of_bundle_pending_before_upgrade() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let (bundle, height) = collect_bundle(&state, &mut txn);
+    // Bundles collected before the height was recorded don't have one.
+    state
+        .pending_withdrawal_bundle_height
+        .delete(&mut txn, &0)
+        .unwrap();
+    let two_way_peg_data =
+        bundle_statuses(bundle.transaction.txid(), WithdrawalBundleStatus::Failed);
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, height + 1)
+        .unwrap();
+    assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_none());
+    assert_eq!(state.utxos.len(&txn).unwrap(), 1);
+}
+
+#[test]
+fn record_deposit_with_invalid_destination() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let outpoint = bitcoin::OutPoint {
+        txid: bitcoin::Txid::all_zeros(),
+        vout: 0,
+    };
+    let deposit = bip300301::Output {
+        address: "not an address".into(),
+        value: 100_000,
+    };
+    let two_way_peg_data = TwoWayPegData {
+        deposits: HashMap::from([(outpoint, deposit)]),
+        ..Default::default()
+    };
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, 1)
+        .unwrap();
+    assert_eq!(state.utxos.len(&txn).unwrap(), 0);
+    let unclaimed = UnclaimedDeposit {
+        address: "not an address".into(),
+        value: 100_000,
+        height: 2,
+    };
+    assert_eq!(
+        state.get_unclaimed_deposits(&txn).unwrap(),
+        vec![(outpoint, unclaimed)]
+    );
+}
+
+#[test]
+fn ignore_deposits_with_invalid_value() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let address = Address([2; 20]).to_string();
+    let deposits = [0, bitcoin::Amount::MAX_MONEY.to_sat() + 1]
+        .into_iter()
+        .enumerate()
+        .map(|(vout, value)| {
+            let outpoint = bitcoin::OutPoint {
+                txid: bitcoin::Txid::all_zeros(),
+                vout: vout as u32,
+            };
+            let deposit = bip300301::Output {
+                address: address.clone(),
+                value,
+            };
+            (outpoint, deposit)
+        })
+        .collect();
+    let two_way_peg_data = TwoWayPegData {
+        deposits,
+        ..Default::default()
+    };
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, 1)
+        .unwrap();
+    assert_eq!(state.utxos.len(&txn).unwrap(), 0);
+    assert!(state.get_unclaimed_deposits(&txn).unwrap().is_empty());
+}
+
+#[test]
+fn ignore_deposit_connected_twice() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let two_way_peg_data = deposit(0, &Address([2; 20]).to_string());
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, 1)
+        .unwrap();
+    // The mainchain reports the same deposit again for the next block.
+    state
+        .connect_two_way_peg_data(&mut txn, &two_way_peg_data, 2)
+        .unwrap();
+    let utxos = state.get_all_utxos(&txn).unwrap();
+    assert_eq!(utxos.len(), 1);
+    let outpoint = utxos.keys().next().unwrap();
+    assert_eq!(state.get_utxo_height(&txn, outpoint).unwrap(), Some(2));
+    // Disconnecting the second report leaves the deposit in place.
+    state.disconnect_block(&mut txn, 3).unwrap();
+    assert_eq!(state.get_all_utxos(&txn).unwrap(), utxos);
+}
+
+/// Connect a block the way `Node` does, two way peg data is connected on top of the previous
+/// height.
+fn connect_block(
+    state: &State,
+    txn: &mut heed::RwTxn,
+    body: &Body,
+    two_way_peg_data: &TwoWayPegData,
+    height: u32,
+) {
+    state.connect_body(txn, body, height).unwrap();
+    state
+        .connect_two_way_peg_data(txn, two_way_peg_data, height - 1)
+        .unwrap();
+}
+
+fn deposit(vout: u32, address: &str) -> TwoWayPegData {
+    let outpoint = bitcoin::OutPoint {
+        txid: bitcoin::Txid::all_zeros(),
+        vout,
+    };
+    let deposit = bip300301::Output {
+        address: address.into(),
+        value: 100_000,
+    };
+    TwoWayPegData {
+        deposits: HashMap::from([(outpoint, deposit)]),
+        deposit_block_hash: Some(bitcoin::BlockHash::from_byte_array([vout as u8; 32])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn rollback_restores_historical_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let address = Address([2; 20]);
+    let value_output = |value| Output {
+        address,
+        content: Content::Value(value),
+    };
+    let transaction = |inputs, outputs| AuthorizedTransaction {
+        transaction: Transaction { inputs, outputs },
+        authorizations: vec![],
+    };
+
+    connect_block(
+        &state,
+        &mut txn,
+        &Body::new(vec![], vec![]),
+        &deposit(1, &format!("{address}")),
+        1,
+    );
+    let utxos = state.get_all_utxos(&txn).unwrap();
+    let heights: Vec<_> = utxos
+        .keys()
+        .map(|outpoint| state.get_utxo_height(&txn, outpoint).unwrap())
+        .collect();
+    let last_deposit_block_hash = state.get_last_deposit_block_hash(&txn).unwrap();
+    let utxo_set_hash = state.utxo_set_hash(&txn).unwrap();
+    assert_eq!(utxos.len(), 1);
+
+    // Spend the deposit into a withdrawal and a regular output.
+    let deposit_outpoint = *utxos.keys().next().unwrap();
+    let spend = transaction(
+        vec![deposit_outpoint],
+        vec![withdrawal_output(), value_output(50_000)],
+    );
+    let spend_txid = spend.transaction.txid();
+    connect_block(
+        &state,
+        &mut txn,
+        &Body::new(vec![spend], vec![]),
+        &deposit(2, &format!("{address}")),
+        2,
+    );
+    let respend = transaction(
+        vec![OutPoint::Regular {
+            txid: spend_txid,
+            vout: 1,
+        }],
+        vec![value_output(40_000)],
+    );
+    connect_block(
+        &state,
+        &mut txn,
+        &Body::new(vec![respend], vec![]),
+        &deposit(3, "not an address"),
+        3,
+    );
+    connect_block(
+        &state,
+        &mut txn,
+        &Body::new(vec![], vec![]),
+        &TwoWayPegData::default(),
+        4,
+    );
+    // Far enough past genesis for the withdrawal to be collected into a bundle.
+    connect_block(
+        &state,
+        &mut txn,
+        &Body::new(vec![], vec![]),
+        &TwoWayPegData::default(),
+        5,
+    );
+    assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_some());
+    assert_eq!(state.get_unclaimed_deposits(&txn).unwrap().len(), 1);
+
+    let undos = state.rollback_to_height(&mut txn, 1).unwrap();
+    let disconnected: Vec<_> = undos.iter().map(|(height, _)| *height).collect();
+    assert_eq!(disconnected, vec![5, 4, 3, 2]);
+    assert_eq!(state.get_all_utxos(&txn).unwrap(), utxos);
+    let rolled_back_heights: Vec<_> = utxos
+        .keys()
+        .map(|outpoint| state.get_utxo_height(&txn, outpoint).unwrap())
+        .collect();
+    assert_eq!(rolled_back_heights, heights);
+    assert_eq!(state.utxo_set_hash(&txn).unwrap(), utxo_set_hash);
+    assert_eq!(
+        state.get_last_deposit_block_hash(&txn).unwrap(),
+        last_deposit_block_hash
+    );
+    assert!(state.get_pending_withdrawal_bundle(&txn).unwrap().is_none());
+    assert!(state.get_unclaimed_deposits(&txn).unwrap().is_empty());
+
+    // Undo data of the disconnected blocks is gone.
+    let result = state.disconnect_block(&mut txn, 2);
+    assert!(matches!(
+        result,
+        Err(state::Error::NoUndoData { height: 2 })
+    ));
+}
+
+/// Valid authorization of an unrelated transaction, only its count matters.
+fn authorization() -> Authorization {
+    let secret = ed25519_dalek::SecretKey::from_bytes(&[1; 32]).unwrap();
+    let public = ed25519_dalek::PublicKey::from(&secret);
+    let keypair = ed25519_dalek::Keypair { secret, public };
+    let transaction = Transaction {
+        inputs: vec![OutPoint::Regular {
+            txid: [1; 32].into(),
+            vout: 0,
+        }],
+        outputs: vec![],
+    };
+    let mut authorized = authorize(&[(get_address(&public), &keypair)], transaction).unwrap();
+    authorized.authorizations.pop().unwrap()
+}
+
+#[test]
+fn reject_body_with_wrong_authorization_count() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let txn = env.read_txn().unwrap();
+    let input = OutPoint::Regular {
+        txid: [2; 32].into(),
+        vout: 0,
+    };
+    let cases = [
+        (vec![input], vec![]),
+        (vec![input], vec![authorization(), authorization()]),
+        (vec![], vec![authorization()]),
+    ];
+    for (inputs, authorizations) in cases {
+        let expected = (inputs.len(), authorizations.len());
+        let transaction = AuthorizedTransaction {
+            transaction: Transaction {
+                inputs,
+                outputs: vec![],
+            },
+            authorizations,
+        };
+        let result = state.validate_body(&txn, &Body::new(vec![transaction], vec![]));
+        assert!(matches!(
+            result,
+            Err(state::Error::WrongBodyAuthorizationCount { inputs, authorizations })
+                if (inputs, authorizations) == expected
+        ));
+    }
+}
+
+#[test]
+fn utxo_snapshot_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    for height in 1..=3 {
+        let coinbase = vec![Output {
+            address: Address([height as u8; 20]),
+            content: Content::Value(height as u64 * 100),
+        }];
+        state
+            .connect_body(&mut txn, &Body::new(vec![], coinbase), height)
+            .unwrap();
+    }
+    connect_block(
+        &state,
+        &mut txn,
+        &Body::new(vec![], vec![]),
+        &deposit(0, &Address([9; 20]).to_string()),
+        4,
+    );
+    let mut snapshot = Vec::new();
+    state.export_snapshot(&txn, &mut snapshot).unwrap();
+    let utxos = state.get_all_utxos(&txn).unwrap();
+    assert_eq!(utxos.len(), 4);
+
+    let hash = state.utxo_set_hash(&txn).unwrap();
+    // The header of the next block commits to the snapshot.
+    let header = |commitment| Header {
+        merkle_root: Body::new(vec![], vec![]).compute_merkle_root(),
+        prev_side_hash: [0; 32].into(),
+        prev_main_hash: bitcoin::BlockHash::all_zeros(),
+        height: 5,
+        commitment,
+    };
+
+    let other_dir = tempfile::tempdir().unwrap();
+    let (other_env, other) = open(&other_dir);
+    let mut other_txn = other_env.write_txn().unwrap();
+    assert!(matches!(
+        other.import_snapshot(&mut other_txn, snapshot.as_slice(), &header(Some([0; 32]))),
+        Err(state::Error::WrongCommitment { .. })
+    ));
+    assert!(matches!(
+        other.import_snapshot(&mut other_txn, snapshot.as_slice(), &header(None)),
+        Err(state::Error::NoCommitment)
+    ));
+    other
+        .import_snapshot(&mut other_txn, snapshot.as_slice(), &header(Some(hash)))
+        .unwrap();
+    assert_eq!(hash, other.utxo_set_hash(&other_txn).unwrap());
+    assert_eq!(other.get_all_utxos(&other_txn).unwrap(), utxos);
+    for outpoint in utxos.keys() {
+        assert_eq!(
+            other.get_utxo_height(&other_txn, outpoint).unwrap(),
+            state.get_utxo_height(&txn, outpoint).unwrap(),
+        );
+    }
+}
+
+#[test]
+fn utxo_cache_only_serves_committed_utxos() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let body = Body::new(
+        vec![],
+        vec![Output {
+            address: Address([0; 20]),
+            content: Content::Value(1000),
+        }],
+    );
+    let outpoint = OutPoint::Coinbase {
+        merkle_root: body.compute_merkle_root(),
+        vout: 0,
+    };
+
+    let mut txn = env.write_txn().unwrap();
+    state.connect_body(&mut txn, &body, 1).unwrap();
+    txn.abort();
+    state.refill_utxo_cache(&env);
+    let txn = env.read_txn().unwrap();
+    assert!(state.get_utxo(&txn, &outpoint).unwrap().is_none());
+    drop(txn);
+
+    let mut txn = env.write_txn().unwrap();
+    state.connect_body(&mut txn, &body, 1).unwrap();
+    txn.commit().unwrap();
+    state.refill_utxo_cache(&env);
+    let txn = env.read_txn().unwrap();
+    assert!(state.get_utxo(&txn, &outpoint).unwrap().is_some());
+    drop(txn);
+
+    let mut txn = env.write_txn().unwrap();
+    state.rollback_to_height(&mut txn, 0).unwrap();
+    txn.commit().unwrap();
+    state.refill_utxo_cache(&env);
+    let txn = env.read_txn().unwrap();
+    assert!(state.get_utxo(&txn, &outpoint).unwrap().is_none());
+}
+
+#[test]
+fn utxo_cache_respects_snapshots() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let body = Body::new(
+        vec![],
+        vec![Output {
+            address: Address([0; 20]),
+            content: Content::Value(1000),
+        }],
+    );
+    let outpoint = OutPoint::Coinbase {
+        merkle_root: body.compute_merkle_root(),
+        vout: 0,
+    };
+
+    // Held while the block is connected and its UTXOs are cached.
+    let snapshot = env.read_txn().unwrap();
+    let mut txn = env.write_txn().unwrap();
+    state.connect_body(&mut txn, &body, 1).unwrap();
+    txn.commit().unwrap();
+    state.refill_utxo_cache(&env);
+    let txn = env.read_txn().unwrap();
+    assert!(state.get_utxo(&txn, &outpoint).unwrap().is_some());
+    assert!(state.get_utxo(&snapshot, &outpoint).unwrap().is_none());
+    assert_eq!(
+        state.get_utxos(&snapshot, &[outpoint]).unwrap(),
+        HashMap::from([(outpoint, None)])
+    );
+}
+
+#[test]
+fn reject_spending_immature_coinbase() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let body = Body::new(
+        vec![],
+        vec![Output {
+            address: Address([0; 20]),
+            content: Content::Value(1000),
+        }],
+    );
+    state.connect_body(&mut txn, &body, 1).unwrap();
+    let outpoint = OutPoint::Coinbase {
+        merkle_root: body.compute_merkle_root(),
+        vout: 0,
+    };
+    let transaction = Transaction {
+        inputs: vec![outpoint],
+        outputs: vec![],
+    };
+    let maturity = {{crate_name}}::consensus::COINBASE_MATURITY;
+    assert!(matches!(
+        state.validate_coinbase_maturity(&txn, &transaction, maturity),
+        Err(state::Error::ImmatureCoinbase { utxo_height: 1, .. })
+    ));
+    state
+        .validate_coinbase_maturity(&txn, &transaction, 1 + maturity)
+        .unwrap();
+}
+
+#[test]
+fn batch_lookup_reports_missing_utxos() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let output = Output {
+        address: Address([0; 20]),
+        content: Content::Value(1000),
+    };
+    let body = Body::new(vec![], vec![output.clone()]);
+    state.connect_body(&mut txn, &body, 3).unwrap();
+    let existing = OutPoint::Coinbase {
+        merkle_root: body.compute_merkle_root(),
+        vout: 0,
+    };
+    let missing = OutPoint::Coinbase {
+        merkle_root: body.compute_merkle_root(),
+        vout: 1,
+    };
+    assert_eq!(
+        state.get_utxo(&txn, &existing).unwrap(),
+        Some(output.clone())
+    );
+    assert_eq!(state.get_utxo(&txn, &missing).unwrap(), None);
+    let utxos = state.get_utxos(&txn, &[existing, missing]).unwrap();
+    assert_eq!(
+        utxos,
+        HashMap::from([(existing, Some(output)), (missing, None)])
+    );
+    // The height of the creating block is recorded alongside.
+    assert_eq!(state.get_utxo_height(&txn, &existing).unwrap(), Some(3));
+    assert_eq!(state.get_utxo_height(&txn, &missing).unwrap(), None);
+    state.rollback_to_height(&mut txn, 2).unwrap();
+    assert_eq!(state.get_utxo_height(&txn, &existing).unwrap(), None);
+}
+
+#[test]
+fn reject_duplicate_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir);
+    let outpoint = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout: 0,
+    };
+    let spent_utxo = Output {
+        address: Address([0; 20]),
+        content: Content::Value(1000),
+    };
+    // Counting the input twice would make the output look covered.
+    let transaction = FilledTransaction {
+        transaction: Transaction {
+            inputs: vec![outpoint, outpoint],
+            outputs: vec![Output {
+                address: Address([0; 20]),
+                content: Content::Value(1500),
+            }],
+        },
+        spent_utxos: vec![spent_utxo.clone(), spent_utxo],
+    };
+    let result = state.validate_filled_transaction(&transaction);
+    assert!(matches!(
+        result,
+        Err(state::Error::DuplicateInput { outpoint: duplicate }) if duplicate == outpoint
+    ));
+}
+
+#[test]
+fn reject_invalid_coinbase() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let txn = env.read_txn().unwrap();
+    let result = state.validate_body(&txn, &Body::new(vec![], vec![withdrawal_output()]));
+    assert!(matches!(
+        result,
+        Err(state::Error::WithdrawalInCoinbase { vout: 0 })
+    ));
+    let max_outputs = ConsensusParams::regtest().max_coinbase_outputs;
+    let coinbase = vec![
+        Output {
+            address: Address([0; 20]),
+            content: Content::Value(0),
+        };
+        max_outputs + 1
+    ];
+    let result = state.validate_body(&txn, &Body::new(vec![], coinbase));
+    assert!(matches!(
+        result,
+        Err(state::Error::TooManyCoinbaseOutputs { outputs, max_outputs: max })
+            if outputs == max_outputs + 1 && max == max_outputs
+    ));
+}
+
+#[test]
+fn consensus_params_are_applied() {
+    assert_eq!(ConsensusParams::regtest().max_bundle_outputs(), 3121);
+    let dir = tempfile::tempdir().unwrap();
+    let env = heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(State::NUM_DBS)
+        .open(dir.path())
+        .unwrap();
+    let consensus_params = ConsensusParams {
+        max_coinbase_outputs: 1,
+        ..ConsensusParams::regtest()
+    };
+    let state = State::new(&env, consensus_params).unwrap();
+    let txn = env.read_txn().unwrap();
+    let output = Output {
+        address: Address([0; 20]),
+        content: Content::Value(0),
+    };
+    state
+        .validate_body(&txn, &Body::new(vec![], vec![output.clone()]))
+        .unwrap();
+    let result = state.validate_body(&txn, &Body::new(vec![], vec![output.clone(), output]));
+    assert!(matches!(
+        result,
+        Err(state::Error::TooManyCoinbaseOutputs {
+            outputs: 2,
+            max_outputs: 1
+        })
+    ));
+}
+
+#[test]
+fn utxo_set_hash_only_depends_on_the_set() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let empty = state.utxo_set_hash(&txn).unwrap();
+    let coinbase = |value| {
+        vec![Output {
+            address: Address([1; 20]),
+            content: Content::Value(value),
+        }]
+    };
+    state
+        .connect_body(&mut txn, &Body::new(vec![], coinbase(100)), 1)
+        .unwrap();
+    state
+        .connect_body(&mut txn, &Body::new(vec![], coinbase(200)), 2)
+        .unwrap();
+    let hash = state.utxo_set_hash(&txn).unwrap();
+    assert_ne!(hash, empty);
+    txn.commit().unwrap();
+
+    // The same blocks connected in the other order give the same set.
+    let other_dir = tempfile::tempdir().unwrap();
+    let (other_env, other) = open(&other_dir);
+    let mut other_txn = other_env.write_txn().unwrap();
+    other
+        .connect_body(&mut other_txn, &Body::new(vec![], coinbase(200)), 2)
+        .unwrap();
+    other
+        .connect_body(&mut other_txn, &Body::new(vec![], coinbase(100)), 1)
+        .unwrap();
+    assert_eq!(other.utxo_set_hash(&other_txn).unwrap(), hash);
+    other.clear(&mut other_txn).unwrap();
+    assert_eq!(other.utxo_set_hash(&other_txn).unwrap(), empty);
+    other_txn.commit().unwrap();
+
+    // The hash is stored, so a new `State` on the same environment has it.
+    let state = State::new(&env, ConsensusParams::regtest()).unwrap();
+    assert_eq!(state.utxo_set_hash(&env.read_txn().unwrap()).unwrap(), hash);
+}
+
+#[test]
+fn reject_oversized_snapshot_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let (env, state) = open(&dir);
+    let mut txn = env.write_txn().unwrap();
+    let outpoint = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout: 0,
+    };
+    let mut entry = bincode::serialize(&(outpoint, withdrawal_output(), 0u32)).unwrap();
+    // Make the withdrawal address claim to be longer than any entry may be.
+    let address = main_address().assume_checked().to_string();
+    let position = entry
+        .windows(address.len())
+        .position(|window| window == address.as_bytes())
+        .unwrap();
+    entry[position - 8..position].copy_from_slice(&u64::MAX.to_le_bytes());
+    let mut snapshot = bincode::serialize(&1u64).unwrap();
+    snapshot.extend(entry);
+    let header = Header {
+        merkle_root: Body::new(vec![], vec![]).compute_merkle_root(),
+        prev_side_hash: [0; 32].into(),
+        prev_main_hash: bitcoin::BlockHash::all_zeros(),
+        height: 1,
+        commitment: Some([0; 32]),
+    };
+    let result = state.import_snapshot(&mut txn, snapshot.as_slice(), &header);
+    assert!(matches!(
+        result,
+        Err(state::Error::Bincode(err)) if matches!(*err, bincode::ErrorKind::SizeLimit)
+    ));
+}
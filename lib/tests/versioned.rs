@@ -0,0 +1,71 @@
+use {{crate_name}}::versioned::{self, SerdeVersioned, Versioned};
+use heed::types::*;
+
+/// Stored type whose second version added a field.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Point {
+    x: u32,
+    y: u32,
+}
+
+impl Versioned for Point {
+    const VERSION: u8 = 2;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Option<Self> {
+        match version {
+            1 => {
+                let x: u32 = bincode::deserialize(bytes).ok()?;
+                Some(Self { x, y: 0 })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn open_env(dir: &tempfile::TempDir) -> heed::Env {
+    heed::EnvOpenOptions::new()
+        .map_size(10 * 1024 * 1024)
+        .max_dbs(2)
+        .open(dir.path())
+        .unwrap()
+}
+
+#[test]
+fn migrate_or_reject_old_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = open_env(&dir);
+    let raw = env
+        .create_database::<OwnedType<u8>, ByteSlice>(Some("points"))
+        .unwrap();
+    let points = raw.remap_data_type::<SerdeVersioned<Point>>();
+    let mut txn = env.write_txn().unwrap();
+    let old = [vec![1], bincode::serialize(&7u32).unwrap()].concat();
+    raw.put(&mut txn, &0, &old).unwrap();
+    let unknown = [vec![3], bincode::serialize(&Point { x: 1, y: 2 }).unwrap()].concat();
+    raw.put(&mut txn, &1, &unknown).unwrap();
+    points.put(&mut txn, &2, &Point { x: 3, y: 4 }).unwrap();
+
+    assert_eq!(points.get(&txn, &0).unwrap(), Some(Point { x: 7, y: 0 }));
+    assert!(matches!(points.get(&txn, &1), Err(heed::Error::Decoding)));
+    assert_eq!(points.get(&txn, &2).unwrap(), Some(Point { x: 3, y: 4 }));
+    assert_eq!(raw.get(&txn, &2).unwrap().unwrap()[0], Point::VERSION);
+}
+
+#[test]
+fn reject_unversioned_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let env = open_env(&dir);
+    env.create_database::<OwnedType<u8>, ByteSlice>(Some("utxos"))
+        .unwrap();
+    assert!(matches!(
+        versioned::check_schema(&env),
+        Err(versioned::Error::Unversioned)
+    ));
+
+    let dir = tempfile::tempdir().unwrap();
+    let env = open_env(&dir);
+    versioned::check_schema(&env).unwrap();
+    env.create_database::<OwnedType<u8>, ByteSlice>(Some("utxos"))
+        .unwrap();
+    versioned::check_schema(&env).unwrap();
+}
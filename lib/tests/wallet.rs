@@ -0,0 +1,732 @@
+use {{crate_name}}::{
+    authorization::{get_address, verify_authorized_transaction, verify_message},
+    bip300301::bitcoin,
+    consensus::COINBASE_MATURITY,
+    testutil::main_address,
+    types::{Address, Body, Content, OutPoint, Output, Transaction, UnsignedTransaction},
+    wallet::{self, AccountPath, AddressPolicy, Wallet, WalletEvent},
+};
+use bitcoin::hashes::Hash as _;
+use std::collections::HashMap;
+
+const SEED: [u8; 64] = [1; 64];
+
+fn wallet(dir: &tempfile::TempDir, account_path: AccountPath) -> Result<Wallet, wallet::Error> {
+    Wallet::new(
+        dir.path(),
+        AddressPolicy::Fresh,
+        account_path,
+        Wallet::DEFAULT_MAP_SIZE,
+    )
+}
+
+#[test]
+fn account_paths_derive_different_addresses() {
+    let default_dir = tempfile::tempdir().unwrap();
+    let default_wallet = wallet(&default_dir, AccountPath::default()).unwrap();
+    default_wallet.set_seed(&SEED).unwrap();
+    let other_dir = tempfile::tempdir().unwrap();
+    let other_path: AccountPath = "m/44'/0'/1'".parse().unwrap();
+    let other_wallet = wallet(&other_dir, other_path).unwrap();
+    other_wallet.set_seed(&SEED).unwrap();
+    assert_eq!(other_wallet.get_account_path().unwrap(), other_path);
+    assert_ne!(
+        default_wallet.get_new_address().unwrap(),
+        other_wallet.get_new_address().unwrap()
+    );
+}
+
+#[test]
+fn refuse_changing_account_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let address = {
+        let wallet = wallet(&dir, AccountPath::default()).unwrap();
+        wallet.set_seed(&SEED).unwrap();
+        wallet.get_new_address().unwrap()
+    };
+    let other_path: AccountPath = "m/44'/0'/1'".parse().unwrap();
+    assert!(matches!(
+        wallet(&dir, other_path),
+        Err(wallet::Error::AccountPathMismatch { .. })
+    ));
+    // Switching explicitly forgets the addresses of the old path.
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_account_path(other_path).unwrap();
+    assert_ne!(wallet.get_new_address().unwrap(), address);
+}
+
+#[test]
+fn parse_account_path() {
+    let account_path: AccountPath = "m/1'/0'/0'".parse().unwrap();
+    assert_eq!(account_path, AccountPath::default());
+    assert_eq!(account_path.to_string(), "m/1'/0'/0'");
+    assert!("m/1'/0'".parse::<AccountPath>().is_err());
+    assert!("m/1'/0/0'".parse::<AccountPath>().is_err());
+    assert!("1'/0'/0'".parse::<AccountPath>().is_err());
+}
+
+#[test]
+fn reject_account_path_index_out_of_hardened_range() {
+    let max = AccountPath::HARDENED_OFFSET - 1;
+    let account_path: AccountPath = format!("m/1'/0'/{max}'").parse().unwrap();
+    assert_eq!(account_path.account, max);
+    let overflowing = format!("m/1'/0'/{}'", AccountPath::HARDENED_OFFSET);
+    assert!(overflowing.parse::<AccountPath>().is_err());
+    assert!(format!("m/{}'/0'/0'", u32::MAX)
+        .parse::<AccountPath>()
+        .is_err());
+}
+
+#[test]
+fn peek_next_address_does_not_advance() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let peeked = wallet.peek_next_address().unwrap();
+    assert_eq!(wallet.peek_next_address().unwrap(), peeked);
+    assert_eq!(wallet.get_num_addresses().unwrap(), 0);
+    assert!(wallet.claim_address(&peeked).unwrap());
+    assert_eq!(wallet.get_num_addresses().unwrap(), 1);
+    assert!(!wallet.claim_address(&peeked).unwrap());
+    assert_ne!(wallet.peek_next_address().unwrap(), peeked);
+}
+
+#[test]
+fn frozen_utxo_is_not_selected() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let utxo = |vout, value| {
+        let outpoint = OutPoint::Regular {
+            txid: [1; 32].into(),
+            vout,
+        };
+        let output = Output {
+            address,
+            content: Content::Value(value),
+        };
+        (outpoint, output)
+    };
+    let (frozen, _) = utxo(0, 1_000);
+    let (unfrozen, _) = utxo(1, 2_000);
+    wallet
+        .put_utxos(&HashMap::from([utxo(0, 1_000), utxo(1, 2_000)]))
+        .unwrap();
+    wallet.freeze(&frozen).unwrap();
+
+    let (total, selected) = wallet.select_coins(500, None).unwrap();
+    assert_eq!(total, 2_000);
+    assert_eq!(selected.into_keys().collect::<Vec<_>>(), vec![unfrozen]);
+    assert!(matches!(
+        wallet.select_coins(2_500, None),
+        Err(wallet::Error::NotEnoughFunds)
+    ));
+    let balance = wallet.get_balance_detailed(0).unwrap();
+    assert_eq!((balance.available, balance.frozen), (2_000, 1_000));
+    assert_eq!(wallet.get_balance().unwrap(), 3_000);
+
+    wallet.unfreeze(&frozen).unwrap();
+    assert_eq!(wallet.select_coins(2_500, None).unwrap().0, 3_000);
+}
+
+#[test]
+fn immature_coinbase_is_not_selected() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let coinbase = vec![Output {
+        address: wallet.get_new_address().unwrap(),
+        content: Content::Value(1_000),
+    }];
+    wallet
+        .connect_block(&Body::new(vec![], coinbase), 1)
+        .unwrap();
+    assert!(matches!(
+        wallet.select_coins(500, None),
+        Err(wallet::Error::NotEnoughFunds)
+    ));
+    // Spendable from height 1 + COINBASE_MATURITY, the block after this one.
+    wallet.set_height(COINBASE_MATURITY - 1).unwrap();
+    assert!(wallet.select_coins(500, None).is_err());
+    wallet.set_height(COINBASE_MATURITY).unwrap();
+    assert_eq!(wallet.select_coins(500, None).unwrap().0, 1_000);
+    let balance = wallet.get_balance_detailed(COINBASE_MATURITY).unwrap();
+    assert_eq!((balance.available, balance.immature), (1_000, 0));
+}
+
+#[test]
+fn foreign_outputs_are_not_owned() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let owned = Output {
+        address: wallet.get_new_address().unwrap(),
+        content: Content::Value(1_000),
+    };
+    let foreign = Output {
+        address: Address([2; 20]),
+        content: Content::Value(2_000),
+    };
+    assert!(wallet.owns_output(&owned).unwrap());
+    assert!(!wallet.owns_output(&foreign).unwrap());
+    assert!(owned.is_spendable_by(&owned.address));
+    assert!(!foreign.is_spendable_by(&owned.address));
+
+    let outpoint = |vout| OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout,
+    };
+    wallet
+        .put_utxos(&HashMap::from([
+            (outpoint(0), owned),
+            (outpoint(1), foreign),
+        ]))
+        .unwrap();
+    let utxos = wallet.get_utxos().unwrap();
+    assert_eq!(utxos.into_keys().collect::<Vec<_>>(), vec![outpoint(0)]);
+}
+
+#[test]
+fn consolidate_utxos() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let utxo = |vout: u32, content| {
+        let outpoint = OutPoint::Regular {
+            txid: [1; 32].into(),
+            vout,
+        };
+        (outpoint, Output { address, content })
+    };
+    let mut utxos: HashMap<_, _> = (0..10)
+        .map(|vout| utxo(vout, Content::Value(1_000 + u64::from(vout))))
+        .collect();
+    // Neither frozen nor locked UTXOs are consolidated.
+    let (frozen, frozen_output) = utxo(10, Content::Value(1));
+    let (locked, locked_output) = utxo(
+        11,
+        Content::Withdrawal {
+            value: 1,
+            main_fee: 0,
+            main_address: main_address(),
+        },
+    );
+    utxos.insert(frozen, frozen_output);
+    utxos.insert(locked, locked_output);
+    wallet.put_utxos(&utxos).unwrap();
+    wallet.freeze(&frozen).unwrap();
+
+    let transaction = wallet.create_consolidation_transaction(20, 500).unwrap();
+    assert_eq!(transaction.inputs.len(), 10);
+    assert!(!transaction.inputs.contains(&frozen));
+    assert!(!transaction.inputs.contains(&locked));
+    assert_eq!(transaction.outputs.len(), 1);
+    let total: u64 = (0..10).map(|vout| 1_000 + vout).sum();
+    assert_eq!(transaction.outputs[0].content, Content::Value(total - 500));
+    assert!(wallet.owns_output(&transaction.outputs[0]).unwrap());
+
+    // The smallest UTXOs are picked first.
+    let transaction = wallet.create_consolidation_transaction(2, 500).unwrap();
+    let smallest: Vec<_> = [0, 1]
+        .into_iter()
+        .map(|vout| utxo(vout, Content::Value(0)).0)
+        .collect();
+    assert_eq!(transaction.inputs.len(), 2);
+    assert!(transaction
+        .inputs
+        .iter()
+        .all(|input| smallest.contains(input)));
+    assert!(matches!(
+        wallet.create_consolidation_transaction(1, 500),
+        Err(wallet::Error::NothingToConsolidate)
+    ));
+}
+
+#[test]
+fn named_wallets_are_isolated() {
+    let datadir = tempfile::tempdir().unwrap();
+    assert!(Wallet::list(datadir.path()).unwrap().is_empty());
+    let open = |name| {
+        let path = Wallet::path(datadir.path(), name).unwrap();
+        Wallet::new(
+            &path,
+            AddressPolicy::Fresh,
+            AccountPath::default(),
+            Wallet::DEFAULT_MAP_SIZE,
+        )
+        .unwrap()
+    };
+    let default_wallet = open(Wallet::DEFAULT_NAME);
+    let savings = open("savings");
+    assert_eq!(
+        Wallet::list(datadir.path()).unwrap(),
+        vec![Wallet::DEFAULT_NAME, "savings"]
+    );
+    assert!(Wallet::path(datadir.path(), "../savings").is_err());
+    assert!(Wallet::path(datadir.path(), "").is_err());
+
+    default_wallet.set_seed(&SEED).unwrap();
+    assert!(!savings.has_seed().unwrap());
+    savings.set_seed(&[2; 64]).unwrap();
+    let address = default_wallet.get_new_address().unwrap();
+    assert_ne!(savings.get_new_address().unwrap(), address);
+    let outpoint = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout: 0,
+    };
+    let output = Output {
+        address,
+        content: Content::Value(1_000),
+    };
+    default_wallet
+        .put_utxos(&HashMap::from([(outpoint, output)]))
+        .unwrap();
+    assert_eq!(default_wallet.get_utxos().unwrap().len(), 1);
+    assert!(savings.get_utxos().unwrap().is_empty());
+    assert!(!savings
+        .owns_output(&default_wallet.get_utxos().unwrap()[&outpoint])
+        .unwrap());
+}
+
+#[test]
+fn receiving_and_spending_are_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let output = Output {
+        address: wallet.get_new_address().unwrap(),
+        content: Content::Value(1_000),
+    };
+    let received = Body::new(vec![], vec![output.clone()]);
+    wallet.connect_block(&received, 1).unwrap();
+    let outpoint = OutPoint::Coinbase {
+        merkle_root: received.compute_merkle_root(),
+        vout: 0,
+    };
+    // Seeing the same UTXO again in a scan isn't new activity.
+    wallet
+        .put_utxos(&HashMap::from([(outpoint, output.clone())]))
+        .unwrap();
+
+    let spend = Transaction {
+        inputs: vec![outpoint],
+        outputs: vec![Output {
+            address: Address([2; 20]),
+            content: Content::Value(900),
+        }],
+    };
+    let spent = Body {
+        coinbase: vec![],
+        transactions: vec![spend],
+        authorizations: vec![],
+    };
+    wallet.connect_block(&spent, 2).unwrap();
+
+    let events = wallet.get_events(0).unwrap();
+    assert_eq!(
+        events,
+        vec![
+            (
+                0,
+                WalletEvent::Received {
+                    outpoint,
+                    output: output.clone(),
+                    height: Some(1),
+                }
+            ),
+            (
+                1,
+                WalletEvent::Spent {
+                    outpoint,
+                    output,
+                    height: Some(2),
+                }
+            ),
+        ]
+    );
+    assert_eq!(wallet.get_events(1).unwrap(), events[1..].to_vec());
+    assert!(wallet.get_events(2).unwrap().is_empty());
+}
+
+#[test]
+fn full_map_is_resized() {
+    const MAP_SIZE: usize = 256 * 1024;
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = Wallet::new(
+        dir.path(),
+        AddressPolicy::Fresh,
+        AccountPath::default(),
+        MAP_SIZE,
+    )
+    .unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    // Readers on other threads keep transactions open, the resize waits for them to close.
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let reader = std::thread::spawn({
+        let (wallet, stop) = (wallet.clone(), stop.clone());
+        move || {
+            while !stop.load(std::sync::atomic::Ordering::SeqCst) {
+                wallet.get_num_addresses().unwrap();
+                wallet.get_balance().unwrap();
+            }
+        }
+    });
+    // Two index entries of around 50 bytes each per address, well past the initial map size.
+    let addresses = wallet.get_new_addresses(5_000).unwrap();
+    stop.store(true, std::sync::atomic::Ordering::SeqCst);
+    reader.join().unwrap();
+    assert_eq!(addresses.len(), 5_000);
+    assert!(wallet.map_size() > MAP_SIZE);
+    assert_eq!(wallet.get_num_addresses().unwrap(), 5_000);
+    wallet.get_new_address().unwrap();
+}
+
+#[test]
+fn seed_changes_race_address_derivation() {
+    const OTHER_SEED: [u8; 64] = [2; 64];
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    let threads = [
+        std::thread::spawn({
+            let (wallet, done_tx) = (wallet.clone(), done_tx.clone());
+            move || {
+                for i in 0..50 {
+                    let seed = if i % 2 == 0 { OTHER_SEED } else { SEED };
+                    wallet.set_seed(&seed).unwrap();
+                }
+                done_tx.send(()).unwrap();
+            }
+        }),
+        std::thread::spawn({
+            let wallet = wallet.clone();
+            move || {
+                for _ in 0..50 {
+                    wallet.get_new_address().unwrap();
+                    wallet.peek_next_address().unwrap();
+                }
+                done_tx.send(()).unwrap();
+            }
+        }),
+    ];
+    for _ in &threads {
+        done_rx
+            .recv_timeout(std::time::Duration::from_secs(30))
+            .expect("wallet threads deadlocked");
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // The cached key of the previous seed is never used for the new one.
+    wallet.set_seed(&OTHER_SEED).unwrap();
+    let other_dir = tempfile::tempdir().unwrap();
+    let other_wallet = self::wallet(&other_dir, AccountPath::default()).unwrap();
+    other_wallet.set_seed(&OTHER_SEED).unwrap();
+    assert_eq!(
+        wallet.get_new_address().unwrap(),
+        other_wallet.get_new_address().unwrap()
+    );
+}
+
+/// Put a UTXO of `value` paying to `address`.
+fn put_utxo(wallet: &Wallet, vout: u32, address: Address, value: u64) -> OutPoint {
+    let outpoint = OutPoint::Regular {
+        txid: [1; 32].into(),
+        vout,
+    };
+    let output = Output {
+        address,
+        content: Content::Value(value),
+    };
+    wallet
+        .put_utxos(&HashMap::from([(outpoint, output)]))
+        .unwrap();
+    outpoint
+}
+
+#[test]
+fn create_transaction_pays_change() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let outpoint = put_utxo(&wallet, 0, wallet.get_new_address().unwrap(), 1_000);
+    let recipient = Address([2; 20]);
+    let transaction = wallet.create_transaction(recipient, 600, 100).unwrap();
+    assert_eq!(transaction.inputs, vec![outpoint]);
+    assert_eq!(transaction.outputs.len(), 2);
+    assert_eq!(transaction.outputs[0].address, recipient);
+    assert_eq!(transaction.outputs[0].content, Content::Value(600));
+    assert!(wallet.owns_output(&transaction.outputs[1]).unwrap());
+    assert_eq!(transaction.outputs[1].content, Content::Value(300));
+    assert!(matches!(
+        wallet.create_transaction(recipient, 1_000, 100),
+        Err(wallet::Error::NotEnoughFunds)
+    ));
+}
+
+#[test]
+fn authorize_inputs_from_several_addresses() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let (first, second) = (
+        wallet.get_new_address().unwrap(),
+        wallet.get_new_address().unwrap(),
+    );
+    let inputs = vec![
+        put_utxo(&wallet, 0, first, 1_000),
+        put_utxo(&wallet, 1, second, 1_000),
+        put_utxo(&wallet, 2, first, 1_000),
+    ];
+    let transaction = Transaction {
+        inputs,
+        outputs: vec![Output {
+            address: Address([2; 20]),
+            content: Content::Value(2_900),
+        }],
+    };
+    let authorized = wallet.authorize(transaction).unwrap();
+    verify_authorized_transaction(&authorized).unwrap();
+    let signers: Vec<_> = authorized
+        .authorizations
+        .iter()
+        .map(|authorization| get_address(&authorization.public_key))
+        .collect();
+    assert_eq!(signers, vec![first, second, first]);
+    // Inputs from the same address share an authorization.
+    assert_eq!(authorized.authorizations[0], authorized.authorizations[2]);
+
+    let inputs: Vec<_> = (10..60)
+        .map(|vout| put_utxo(&wallet, vout, first, 1_000))
+        .collect();
+    let transaction = Transaction {
+        inputs,
+        outputs: vec![],
+    };
+    let authorized = wallet.authorize(transaction).unwrap();
+    assert_eq!(authorized.authorizations.len(), 50);
+    verify_authorized_transaction(&authorized).unwrap();
+}
+
+#[test]
+fn sign_unsigned_transaction_offline() {
+    let online_dir = tempfile::tempdir().unwrap();
+    let online = wallet(&online_dir, AccountPath::default()).unwrap();
+    online.set_seed(&SEED).unwrap();
+    let address = online.get_new_address().unwrap();
+    put_utxo(&online, 0, address, 1_000);
+    let transaction = online
+        .create_transaction(Address([2; 20]), 600, 100)
+        .unwrap();
+    let unsigned = online.create_unsigned_transaction(transaction).unwrap();
+    let exported = unsigned.to_base64();
+
+    // The offline wallet has the keys but doesn't track any UTXOs.
+    let offline_dir = tempfile::tempdir().unwrap();
+    let offline = wallet(&offline_dir, AccountPath::default()).unwrap();
+    offline.set_seed(&SEED).unwrap();
+    offline.get_new_addresses(2).unwrap();
+    let imported = UnsignedTransaction::from_base64(&exported).unwrap();
+    assert_eq!(imported.spent_utxos[0].address, address);
+    assert_eq!(imported.spent_utxos[0].content, Content::Value(1_000));
+    let authorized = offline.authorize_unsigned(imported.clone()).unwrap();
+    verify_authorized_transaction(&authorized).unwrap();
+    assert_eq!(authorized.transaction.txid(), unsigned.transaction.txid());
+
+    let mut missing_utxo = imported;
+    missing_utxo.spent_utxos.clear();
+    assert!(matches!(
+        offline.authorize_unsigned(missing_utxo),
+        Err(wallet::Error::WrongNumberOfSpentUtxos { .. })
+    ));
+    assert!(UnsignedTransaction::from_base64("not base64!").is_err());
+}
+
+#[test]
+fn sign_message_with_wallet_address() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let signed = wallet.sign_message(&address, b"hello").unwrap();
+    verify_message(&address, b"hello", &signed.public_key, &signed.signature).unwrap();
+    assert!(matches!(
+        wallet.sign_message(&Address([2; 20]), b"hello"),
+        Err(wallet::Error::NoIndex { .. })
+    ));
+}
+
+#[test]
+fn batch_addresses_match_single_derivation() {
+    let batch_dir = tempfile::tempdir().unwrap();
+    let batch_wallet = wallet(&batch_dir, AccountPath::default()).unwrap();
+    batch_wallet.set_seed(&SEED).unwrap();
+    let single_dir = tempfile::tempdir().unwrap();
+    let single_wallet = wallet(&single_dir, AccountPath::default()).unwrap();
+    single_wallet.set_seed(&SEED).unwrap();
+
+    let first = single_wallet.get_new_address().unwrap();
+    batch_wallet.get_new_address().unwrap();
+    // Batches continue after the last derived address.
+    let batch = batch_wallet.get_new_addresses(3).unwrap();
+    let single: Vec<_> = (0..3)
+        .map(|_| single_wallet.get_new_address().unwrap())
+        .collect();
+    assert_eq!(batch, single);
+    assert!(!batch.contains(&first));
+    assert_eq!(batch_wallet.get_num_addresses().unwrap(), 4);
+}
+
+#[test]
+fn recent_deposits_are_not_selected() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let deposit = |vout| {
+        OutPoint::Deposit(bitcoin::OutPoint {
+            txid: bitcoin::Txid::all_zeros(),
+            vout,
+        })
+    };
+    let output = Output {
+        address,
+        content: Content::Value(1_000),
+    };
+    wallet
+        .put_utxos(&HashMap::from([
+            (deposit(0), output.clone()),
+            (deposit(1), output.clone()),
+            (deposit(2), output),
+        ]))
+        .unwrap();
+    // The deposit at vout 2 has no known height.
+    wallet
+        .put_utxo_heights(&HashMap::from([(deposit(0), 5), (deposit(1), 10)]))
+        .unwrap();
+
+    let (total, selected) = wallet.select_coins(500, Some(5)).unwrap();
+    assert_eq!(total, 1_000);
+    assert_eq!(selected.into_keys().collect::<Vec<_>>(), vec![deposit(0)]);
+    assert!(matches!(
+        wallet.select_coins(1_500, Some(9)),
+        Err(wallet::Error::NotEnoughFunds)
+    ));
+    assert_eq!(wallet.select_coins(1_500, Some(10)).unwrap().0, 2_000);
+    assert_eq!(wallet.select_coins(2_500, None).unwrap().0, 3_000);
+    let heights = wallet.get_utxos_with_height().unwrap();
+    assert_eq!(heights[&deposit(1)].1, Some(10));
+    assert_eq!(heights[&deposit(2)].1, None);
+}
+
+#[test]
+fn reuse_policy_keeps_change_address() {
+    let open = |dir: &tempfile::TempDir, address_policy| {
+        let wallet = Wallet::new(
+            dir.path(),
+            address_policy,
+            AccountPath::default(),
+            Wallet::DEFAULT_MAP_SIZE,
+        )
+        .unwrap();
+        wallet.set_seed(&SEED).unwrap();
+        wallet
+    };
+    let reuse_dir = tempfile::tempdir().unwrap();
+    let reuse = open(&reuse_dir, AddressPolicy::Reuse);
+    let change = reuse.get_change_address().unwrap();
+    assert_eq!(reuse.get_change_address().unwrap(), change);
+    assert!(reuse.get_addresses().unwrap().contains(&change));
+    assert_eq!(reuse.get_num_addresses().unwrap(), 1);
+
+    let fresh_dir = tempfile::tempdir().unwrap();
+    let fresh = open(&fresh_dir, AddressPolicy::Fresh);
+    assert_ne!(
+        fresh.get_change_address().unwrap(),
+        fresh.get_change_address().unwrap()
+    );
+}
+
+#[test]
+fn imported_master_key_derives_seed_addresses() {
+    let seed_dir = tempfile::tempdir().unwrap();
+    let seed_wallet = wallet(&seed_dir, AccountPath::default()).unwrap();
+    seed_wallet.set_seed(&SEED).unwrap();
+    let xprv_dir = tempfile::tempdir().unwrap();
+    let xprv_wallet = wallet(&xprv_dir, AccountPath::default()).unwrap();
+    assert!(!xprv_wallet.has_seed().unwrap());
+    let xprv = ed25519_dalek_bip32::ExtendedSecretKey::from_seed(&SEED).unwrap();
+    xprv_wallet.set_xprv(&xprv).unwrap();
+    assert!(xprv_wallet.has_seed().unwrap());
+    assert_eq!(
+        xprv_wallet.get_new_addresses(3).unwrap(),
+        seed_wallet.get_new_addresses(3).unwrap()
+    );
+
+    // Importing a key replaces the seed and everything derived from it.
+    seed_wallet
+        .set_xprv(&ed25519_dalek_bip32::ExtendedSecretKey::from_seed(&[2; 64]).unwrap())
+        .unwrap();
+    assert_eq!(seed_wallet.get_num_addresses().unwrap(), 0);
+    assert_ne!(
+        seed_wallet.get_new_address().unwrap(),
+        xprv_wallet
+            .get_addresses()
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+    );
+}
+
+#[test]
+fn disconnect_block_restores_spent_utxos() {
+    let dir = tempfile::tempdir().unwrap();
+    let wallet = wallet(&dir, AccountPath::default()).unwrap();
+    wallet.set_seed(&SEED).unwrap();
+    let address = wallet.get_new_address().unwrap();
+    let spent = put_utxo(&wallet, 0, address, 1_000);
+    let spent_output = wallet.get_utxos().unwrap()[&spent].clone();
+    let transaction = Transaction {
+        inputs: vec![spent],
+        outputs: vec![Output {
+            address,
+            content: Content::Value(900),
+        }],
+    };
+    let created = OutPoint::Regular {
+        txid: transaction.txid(),
+        vout: 0,
+    };
+    let body = Body {
+        coinbase: vec![],
+        transactions: vec![transaction],
+        authorizations: vec![],
+    };
+    wallet.connect_block(&body, 1).unwrap();
+    assert_eq!(
+        wallet.get_utxos().unwrap().into_keys().collect::<Vec<_>>(),
+        vec![created]
+    );
+
+    wallet
+        .disconnect_block(&body, &HashMap::from([(spent, spent_output)]))
+        .unwrap();
+    assert_eq!(
+        wallet.get_utxos().unwrap().into_keys().collect::<Vec<_>>(),
+        vec![spent]
+    );
+    let events = wallet.get_events(0).unwrap();
+    assert!(matches!(
+        events.last(),
+        Some((_, WalletEvent::Received { outpoint, height: None, .. })) if *outpoint == spent
+    ));
+    assert!(events.iter().any(|(_, event)| matches!(
+        event,
+        WalletEvent::Reverted { outpoint, .. } if *outpoint == created
+    )));
+}
@@ -0,0 +1,64 @@
+use {{crate_name}}::{
+    bip300301::bitcoin,
+    types::{AggregatedWithdrawal, OutPoint, WithdrawalBundle},
+};
+use bitcoin::hashes::Hash as _;
+use std::collections::{HashMap, HashSet};
+
+fn outpoints() -> Vec<OutPoint> {
+    (0..100)
+        .map(|vout| OutPoint::Regular {
+            txid: [vout as u8; 32].into(),
+            vout,
+        })
+        .collect()
+}
+
+#[test]
+fn inputs_commitment_is_order_independent() {
+    let outpoints = outpoints();
+    let commitment = WithdrawalBundle::compute_inputs_commitment(&outpoints, 10);
+    let reversed: Vec<_> = outpoints.iter().rev().copied().collect();
+    assert_eq!(
+        WithdrawalBundle::compute_inputs_commitment(&reversed, 10),
+        commitment
+    );
+    // Each set iterates in its own randomly seeded order.
+    for _ in 0..10 {
+        let set: HashSet<OutPoint> = outpoints.iter().copied().collect();
+        assert_eq!(
+            WithdrawalBundle::compute_inputs_commitment(&set, 10),
+            commitment
+        );
+    }
+    assert_ne!(
+        WithdrawalBundle::compute_inputs_commitment(&outpoints, 11),
+        commitment
+    );
+}
+
+#[test]
+fn aggregated_withdrawal_order_is_total() {
+    let aggregated: Vec<AggregatedWithdrawal> = (0..10u8)
+        .map(|byte| {
+            let main_address = bitcoin::Address::new(
+                bitcoin::Network::Regtest,
+                bitcoin::address::Payload::PubkeyHash(bitcoin::PubkeyHash::from_byte_array(
+                    [byte; 20],
+                )),
+            );
+            AggregatedWithdrawal {
+                spent_utxos: HashMap::new(),
+                main_address: main_address.as_unchecked().clone(),
+                // Same value and fee for every withdrawal, only the address breaks ties.
+                value: 1000,
+                main_fee: 10,
+            }
+        })
+        .collect();
+    let mut sorted = aggregated.clone();
+    sorted.sort();
+    let mut reversed: Vec<_> = aggregated.into_iter().rev().collect();
+    reversed.sort();
+    assert_eq!(sorted, reversed);
+}